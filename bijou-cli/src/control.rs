@@ -0,0 +1,219 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! The control socket of a mounted Bijou (`bijou mount
+//! --control-socket`), through which it can be managed without
+//! unmounting it.
+//!
+//! Each connection carries one request and one response, both as a
+//! line of JSON.
+
+use anyhow::{bail, Context, Result};
+use bijou::{Bijou, SecretBytes};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::Permissions,
+    io::{BufRead, BufReader, Read, Write},
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tracing::{info, warn};
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    /// See [`Bijou::change_password`]. Passwords must be valid UTF-8.
+    ChangePassword {
+        #[serde(with = "secret_str")]
+        old: SecretBytes,
+        #[serde(with = "secret_str")]
+        new: SecretBytes,
+    },
+    /// See [`Bijou::backup`]. `dest` should be absolute, as it is
+    /// resolved by the mounting process.
+    Backup { dest: PathBuf },
+}
+
+#[derive(Serialize, Deserialize)]
+struct Response {
+    error: Option<String>,
+}
+
+/// The maximum length of a request, which is read into locked memory.
+const MAX_REQUEST_LEN: usize = 16 << 10;
+
+/// (De)serializes [`SecretBytes`] as a string, without leaving copies
+/// of it behind.
+mod secret_str {
+    use bijou::SecretBytes;
+    use serde::{de, ser, Deserializer, Serializer};
+    use std::fmt;
+
+    pub fn serialize<S: Serializer>(secret: &SecretBytes, s: S) -> Result<S::Ok, S::Error> {
+        let secret = std::str::from_utf8(secret)
+            .map_err(|_| ser::Error::custom("password is not valid UTF-8"))?;
+        s.serialize_str(secret)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<SecretBytes, D::Error> {
+        struct Visitor;
+        impl de::Visitor<'_> for Visitor {
+            type Value = SecretBytes;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a string")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<SecretBytes, E> {
+                let mut secret = SecretBytes::allocate_lenient(v.len());
+                secret.copy_from_slice(v.as_bytes());
+                Ok(secret)
+            }
+
+            fn visit_string<E: de::Error>(self, v: String) -> Result<SecretBytes, E> {
+                Ok(SecretBytes::move_from(&mut v.into_bytes()))
+            }
+        }
+        d.deserialize_str(Visitor)
+    }
+}
+
+/// Serves requests for `bijou` on a socket at `path` in the
+/// background. The socket is only accessible by the current user.
+pub fn serve(path: &Path, bijou: Arc<Bijou>) -> Result<()> {
+    // left behind by an earlier mount
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    // restricted from the start, as other users could otherwise
+    // connect before `set_permissions`
+    let umask = unsafe { libc::umask(0o077) };
+    let listener = UnixListener::bind(path);
+    unsafe { libc::umask(umask) };
+    let listener = listener.context("failed to bind control socket")?;
+    std::fs::set_permissions(path, Permissions::from_mode(0o600))?;
+    info!("listening on control socket {}", path.display());
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream
+                .map_err(Into::into)
+                .and_then(|stream| handle(stream, &bijou));
+            if let Err(err) = result {
+                warn!("failed to handle control request: {err:#}");
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Reads a line from `stream` into memory that is zeroed when
+/// dropped, as it may hold passwords.
+fn read_request(mut stream: &UnixStream) -> Result<(SecretBytes, usize)> {
+    // never reallocated, unlike the buffers of `BufReader::read_line`
+    let mut buffer = SecretBytes::allocate_lenient(MAX_REQUEST_LEN);
+    let mut len = 0;
+    loop {
+        if len == buffer.len() {
+            bail!("request is too long");
+        }
+        let read = stream.read(&mut buffer[len..])?;
+        if read == 0 {
+            return Ok((buffer, len));
+        }
+        if let Some(end) = buffer[len..len + read].iter().position(|&b| b == b'\n') {
+            return Ok((buffer, len + end));
+        }
+        len += read;
+    }
+}
+
+fn handle(stream: UnixStream, bijou: &Bijou) -> Result<()> {
+    let (line, len) = read_request(&stream)?;
+    let request: Request = serde_json::from_slice(&line[..len])?;
+    drop(line);
+    let result = match request {
+        Request::ChangePassword { old, new } => bijou.change_password(old, new, None, None),
+        Request::Backup { dest } => bijou.backup(dest),
+    };
+    let response = Response {
+        error: result.err().map(|err| err.to_string()),
+    };
+    writeln!(&stream, "{}", serde_json::to_string(&response)?)?;
+    Ok(())
+}
+
+/// Sends `request` to the control socket at `path`.
+pub fn request(path: &Path, request: &Request) -> Result<()> {
+    let stream = UnixStream::connect(path).context("failed to connect to control socket")?;
+    // zeroed when dropped, as it may hold passwords
+    let line = SecretBytes::from(serde_json::to_vec(request)?);
+    (&stream).write_all(&line)?;
+    (&stream).write_all(b"\n")?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let response: Response = serde_json::from_str(&line).context("invalid response")?;
+    if let Some(error) = response.error {
+        bail!("{error}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bijou::{BijouBuilder, Limit};
+
+    #[test]
+    fn test_change_password() {
+        bijou::init().unwrap();
+        let dir = std::env::temp_dir().join(format!("bijou-control-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("bijou");
+        let mut builder = BijouBuilder::new(&path);
+        builder
+            .ops_limit(Limit::Interactive)
+            .mem_limit(Limit::Interactive);
+        builder.create(b"old".to_vec()).unwrap();
+
+        let socket = dir.join("control");
+        let bijou = Bijou::open(&path, b"old".to_vec()).unwrap();
+        serve(&socket, Arc::new(bijou)).unwrap();
+        let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let change = |old: &[u8], new: &[u8]| {
+            request(
+                &socket,
+                &Request::ChangePassword {
+                    old: old.to_vec().into(),
+                    new: new.to_vec().into(),
+                },
+            )
+        };
+        assert!(change(b"wrong", b"new").is_err());
+        // escaped in JSON
+        change(b"old", b"n\"ew").unwrap();
+        assert!(change(b"old", b"new").is_err());
+        change(b"n\"ew", b"new").unwrap();
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -13,21 +13,70 @@
 // limitations under the License.
 //
 
+#[cfg(not(windows))]
+mod control;
+#[cfg(not(windows))]
+mod mount_helper;
+mod secret;
+#[cfg(not(windows))]
+mod service;
+mod shell;
+
 use anyhow::{Context, Result};
-use bijou::{Bijou, Config, FileId, FileKind, Limit};
-use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand};
-use std::{fs::File, path::PathBuf, sync::Arc};
-use tracing::info;
+use bijou::{
+    config::FileStorage,
+    password::{self, MinimumStrength, PasswordPolicy, SecretProvider},
+    Bijou, BijouBuilder, BijouFs, CheckEvent, CheckIssue, CheckOptions, Config, FileId, FileKind,
+    Limit, QuotaInfo, QuotaLimits, ScrubEvent, ScrubOptions, TaskKind, TaskState, TreeEntry,
+    TreeOptions, UsageStats,
+};
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tracing::{error, info, warn};
 use tracing_log::LogTracer;
 use tracing_subscriber::{filter::LevelFilter, fmt, prelude::*, EnvFilter};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// the format of reports printed to stdout
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// read the password from the first line of this file (`-` for
+    /// stdin) instead of prompting for it
+    #[arg(long, global = true, conflicts_with_all = ["password_command", "password_env"])]
+    password_file: Option<PathBuf>,
+
+    /// run this shell command and use the first line of its output
+    /// as the password, e.g. to get it from a keyring
+    #[arg(long, global = true, conflicts_with = "password_env")]
+    password_command: Option<String>,
+
+    /// read the password from this environment variable
+    #[arg(long, global = true)]
+    password_env: Option<String>,
+
     #[command(subcommand)]
     command: Command,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// human-readable text
+    Text,
+    /// JSON, for use in scripts
+    Json,
+}
+
 fn limit_parser(s: &str) -> Result<Limit, &'static str> {
     Ok(match s {
         "interactive" | "i" => Limit::Interactive,
@@ -45,6 +94,79 @@ fn limit_parser(s: &str) -> Result<Limit, &'static str> {
     })
 }
 
+#[derive(Clone, ValueEnum)]
+enum Preset {
+    /// favor security over performance
+    Paranoid,
+    /// favor performance over security
+    Fast,
+    /// for data directories synchronized to remote storages
+    Remote,
+    /// for single directories of opaque files, e.g. synchronized as a blob
+    Compact,
+}
+
+#[cfg(not(windows))]
+#[derive(Clone, Copy, ValueEnum)]
+enum Offload {
+    /// lookup, getattr, setattr, open, ...
+    Metadata,
+    Read,
+    Write,
+    /// creating, linking, unlinking and renaming files
+    Namespace,
+    Xattr,
+}
+
+#[cfg(not(windows))]
+impl From<Offload> for bijou::OpClass {
+    fn from(value: Offload) -> Self {
+        match value {
+            Offload::Metadata => Self::METADATA,
+            Offload::Read => Self::READ,
+            Offload::Write => Self::WRITE,
+            Offload::Namespace => Self::NAMESPACE,
+            Offload::Xattr => Self::XATTR,
+        }
+    }
+}
+
+#[derive(Clone, ValueEnum)]
+enum MigrateSource {
+    Gocryptfs,
+    Cryfs,
+    /// a plain directory
+    Plain,
+}
+
+impl MigrateSource {
+    /// Returns why `src` cannot be migrated from, e.g. since it is
+    /// the encrypted directory rather than the mount point.
+    fn check(&self, src: &Path) -> Option<String> {
+        let (name, config) = match self {
+            Self::Gocryptfs => ("gocryptfs", "gocryptfs.conf"),
+            Self::Cryfs => ("CryFS", "cryfs.config"),
+            Self::Plain => return None,
+        };
+        if src.join(config).exists() {
+            return Some(format!(
+                "Source is an encrypted {name} directory, mount it and pass the mount point"
+            ));
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let dev = |path: &Path| std::fs::metadata(path).map(|meta| meta.dev()).ok();
+            if dev(src).is_some() && dev(src) == dev(&src.join("..")) {
+                return Some(format!(
+                    "Source is not the mount point of a {name} filesystem"
+                ));
+            }
+        }
+        None
+    }
+}
+
 #[derive(Subcommand)]
 enum Command {
     /// Create a new Bijou
@@ -56,6 +178,10 @@ enum Command {
         #[arg(short, long)]
         config: Option<PathBuf>,
 
+        /// the preset to apply on top of the config
+        #[arg(short, long, value_enum)]
+        preset: Option<Preset>,
+
         /// the operation limit of Argon2id
         #[arg(long, value_parser = limit_parser)]
         ops_limit: Option<Limit>,
@@ -63,6 +189,75 @@ enum Command {
         /// the memory limit of Argon2id
         #[arg(long, value_parser = limit_parser)]
         mem_limit: Option<Limit>,
+
+        /// reject passwords whose estimated strength (0-4) is below this
+        #[arg(long, value_parser = clap::value_parser!(u8).range(0..=4))]
+        min_strength: Option<u8>,
+
+        /// a human-readable label, which is readable without the password
+        #[arg(long)]
+        label: Option<String>,
+
+        /// also prompt for a read-only password, which only allows
+        /// opening the Bijou read-only
+        #[arg(long)]
+        read_only_password: bool,
+
+        /// delay unlock attempts exponentially after several failed
+        /// ones (enabled by the paranoid preset)
+        #[arg(long)]
+        throttle_unlock: bool,
+    },
+
+    /// Print what is known about a Bijou without unlocking it
+    ///
+    /// This includes its UUID, label and key derivation cost.
+    Info {
+        /// the path to the Bijou
+        path: PathBuf,
+    },
+
+    /// Change the password of a Bijou
+    Passwd {
+        /// the path to the Bijou
+        path: PathBuf,
+
+        /// treat PATH as the control socket of a mounted Bijou (see
+        /// `mount --control-socket`), which is needed while mounted
+        #[cfg(not(windows))]
+        #[arg(
+            long,
+            conflicts_with_all = ["read_only", "remove_read_only", "ops_limit", "mem_limit"]
+        )]
+        socket: bool,
+
+        /// set the read-only password instead, which only allows
+        /// opening the Bijou read-only
+        #[arg(long)]
+        read_only: bool,
+
+        /// remove the read-only password instead
+        #[arg(long, conflicts_with = "read_only")]
+        remove_read_only: bool,
+
+        /// the new operation limit of Argon2id, keeping the current
+        /// one if omitted
+        #[arg(long, value_parser = limit_parser, conflicts_with_all = ["read_only", "remove_read_only"])]
+        ops_limit: Option<Limit>,
+
+        /// the new memory limit of Argon2id, keeping the current one
+        /// if omitted
+        #[arg(long, value_parser = limit_parser, conflicts_with_all = ["read_only", "remove_read_only"])]
+        mem_limit: Option<Limit>,
+    },
+
+    /// Set or remove the label of a Bijou
+    Label {
+        /// the path to the Bijou
+        path: PathBuf,
+
+        /// the new label, which is removed if omitted
+        label: Option<String>,
     },
 
     #[cfg(not(windows))]
@@ -77,38 +272,495 @@ enum Command {
         /// allow other users to access the mount point
         #[arg(long)]
         allow_other: bool,
+
+        /// verify the integrity of files every time they are opened
+        #[arg(long)]
+        verify: bool,
+
+        /// keep inode numbers stable across mounts (e.g. for NFS re-export)
+        #[arg(long)]
+        stable_inodes: bool,
+
+        /// list directory entries sorted by name
+        #[arg(long)]
+        sorted: bool,
+
+        /// mount read-only, e.g. from read-only media
+        #[arg(long)]
+        read_only: bool,
+
+        /// only allow root to change the owner of files
+        #[arg(long)]
+        restrict_chown: bool,
+
+        /// treat root as the user who mounted the Bijou
+        #[arg(long)]
+        root_squash: bool,
+
+        /// number of worker threads, defaults to the number of CPUs
+        #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+        threads: Option<u16>,
+
+        /// operations run by worker threads, the others block the
+        /// FUSE session loop; defaults to all
+        #[arg(long, value_enum, value_delimiter = ',')]
+        offload: Option<Vec<Offload>>,
+
+        /// keep running in the background once mounted, until
+        /// unmounted with umount
+        #[arg(long)]
+        daemon: bool,
+
+        /// write the ID of the process serving the mount to this file
+        /// once mounted, which is removed when unmounted
+        #[arg(long)]
+        pid_file: Option<PathBuf>,
+
+        /// listen on a Unix socket at this path for commands like
+        /// `passwd --socket`
+        #[arg(long)]
+        control_socket: Option<PathBuf>,
     },
 
     /// Print the file tree of a Bijou
     Tree {
         /// the path to the Bijou
         path: PathBuf,
+
+        /// only list this many levels of directories
+        #[arg(short = 'L', long)]
+        max_depth: Option<usize>,
+
+        /// print the size of each entry
+        #[arg(short, long)]
+        size: bool,
+
+        /// print the permissions of each entry
+        #[arg(short, long)]
+        perm: bool,
+    },
+
+    /// Verify every file in a Bijou
+    ///
+    /// Every block is decrypted and authenticated, without writing
+    /// plaintext anywhere. Verified files are pinned for later quick
+    /// checks.
+    Verify {
+        /// the path to the Bijou
+        path: PathBuf,
+
+        /// the maximum IO rate in MiB/s
+        #[arg(long)]
+        rate_limit: Option<u64>,
+
+        /// only check pinned files against their pins, without
+        /// decrypting them
+        #[arg(long)]
+        quick: bool,
+
+        /// the number of threads verifying files, defaulting to one
+        /// per CPU core
+        #[arg(short = 'j', long, default_value_t = 0)]
+        threads: usize,
+    },
+
+    /// Check the consistency of a Bijou, like fsck
+    ///
+    /// Directory entries, link counts and the content of every file
+    /// are checked, and the storage is searched for content that no
+    /// file refers to. The Bijou should not be in use (e.g. mounted).
+    Check {
+        /// the path to the Bijou
+        path: PathBuf,
+
+        /// repair the issues found where possible
+        #[arg(long)]
+        repair: bool,
+
+        /// only check the metadata, without decrypting files
+        #[arg(long)]
+        skip_content: bool,
+
+        /// the number of threads verifying files, defaulting to one
+        /// per CPU core
+        #[arg(short = 'j', long, default_value_t = 0)]
+        threads: usize,
+    },
+
+    /// Dump database records of a file as JSON, for debugging
+    DumpMeta {
+        /// the path to the Bijou
+        path: PathBuf,
+
+        /// the path of the file inside the Bijou
+        file: String,
+
+        /// treat FILE as a file ID (hexadecimal) instead of a path
+        #[arg(long)]
+        id: bool,
+    },
+
+    /// Migrate files from another encrypted filesystem into a Bijou
+    ///
+    /// The source filesystem should be mounted, and SRC should be its
+    /// mount point, which is checked for gocryptfs and CryFS. Reading
+    /// encrypted data directly is not supported.
+    /// Permissions, times, symlinks, hard links and xattrs are preserved.
+    MigrateFrom {
+        /// the type of the source filesystem
+        #[arg(value_enum)]
+        kind: MigrateSource,
+
+        /// the mount point of the source filesystem
+        src: PathBuf,
+
+        /// the path to the Bijou
+        dst: PathBuf,
+
+        /// the directory inside the Bijou to import into
+        #[arg(long, default_value = "/")]
+        target: String,
+    },
+
+    /// Back up a Bijou
+    ///
+    /// A mounted Bijou can only be backed up through its control socket
+    /// (see --socket), since it cannot be opened by other processes.
+    Backup {
+        /// the path to the Bijou
+        path: PathBuf,
+
+        /// the path to store the backup, which should be empty
+        dest: PathBuf,
+
+        /// treat PATH as the control socket of a mounted Bijou (see
+        /// `mount --control-socket`), which backs itself up
+        #[cfg(not(windows))]
+        #[arg(long)]
+        socket: bool,
+    },
+
+    /// Back up only the metadata of a Bijou
+    ///
+    /// This includes the database, key store and config, but no file
+    /// contents, and is usually small enough to be taken often.
+    ExportMeta {
+        /// the path to the Bijou
+        path: PathBuf,
+
+        /// the path to store the backup, which should be empty
+        dest: PathBuf,
+    },
+
+    /// Restore the metadata of a Bijou from `export-meta`, keeping its data
+    ///
+    /// Files whose content in the data disagrees with the restored
+    /// metadata are then emptied and listed. The replaced database is
+    /// kept as `db.old`.
+    ImportMeta {
+        /// the path to the metadata backup
+        backup: PathBuf,
+
+        /// the path to the Bijou, which should not be opened elsewhere
+        path: PathBuf,
+    },
+
+    /// Open an interactive shell in a Bijou
+    ///
+    /// Supports basic commands like cd, ls, cat, put and get. Run
+    /// `help` in the shell for details.
+    Shell {
+        /// the path to the Bijou
+        path: PathBuf,
+    },
+
+    /// Decrypt a whole Bijou into a plain directory
+    ///
+    /// Every block is verified while being decrypted. If interrupted,
+    /// running again with the same destination resumes the export.
+    DecryptAll {
+        /// the path to the Bijou
+        path: PathBuf,
+
+        /// the directory to store the decrypted files
+        dest: PathBuf,
+    },
+
+    /// Set or inspect directory quotas
+    ///
+    /// With --bytes or --inodes, sets the quota of DIR, and limits not
+    /// given are unlimited. Otherwise, prints the quota of DIR and its
+    /// usage, or all quotas if DIR is omitted.
+    Quota {
+        /// the path to the Bijou
+        path: PathBuf,
+
+        /// the directory inside the Bijou
+        dir: Option<String>,
+
+        /// the maximum total size of files, in bytes
+        #[arg(long, requires = "dir")]
+        bytes: Option<u64>,
+
+        /// the maximum number of files, directories and symlinks
+        #[arg(long, requires = "dir")]
+        inodes: Option<u64>,
+
+        /// remove the quota of DIR
+        #[arg(long, requires = "dir", conflicts_with_all = ["bytes", "inodes"])]
+        remove: bool,
+    },
+
+    /// Print the usage of a Bijou and record it
+    ///
+    /// Counts files and bytes, and records them as the snapshot of
+    /// today unless the Bijou is opened read-only. Run this daily (e.g.
+    /// from cron) to keep track of how the Bijou grows.
+    Du {
+        /// the path to the Bijou
+        path: PathBuf,
+
+        /// print the recorded snapshots instead
+        #[arg(long)]
+        history: bool,
+    },
+
+    /// Manage resumable maintenance tasks
+    ///
+    /// Tasks checkpoint their progress in the Bijou, so that they can
+    /// be resumed after being interrupted.
+    Task {
+        /// the path to the Bijou
+        path: PathBuf,
+
+        #[command(subcommand)]
+        command: TaskCommand,
+    },
+
+    /// Move raw files of a Bijou to another storage
+    ///
+    /// Files are copied and verified one by one, and removed from the
+    /// old storage once all of them are moved. If interrupted, the
+    /// Bijou can still be used, and the migration can be resumed with
+    /// `task resume`.
+    MigrateStorage {
+        /// the path to the Bijou
+        path: PathBuf,
+
+        /// the path to a JSON file describing the new storage
+        storage: PathBuf,
+
+        /// the maximum IO rate in MiB/s
+        #[arg(long)]
+        rate_limit: Option<u64>,
+    },
+}
+
+#[derive(Subcommand)]
+enum TaskCommand {
+    /// List unfinished tasks
+    List,
+
+    /// Start a resumable verification of every file
+    Scrub {
+        /// only check pinned files against their pins, without
+        /// decrypting them
+        #[arg(long)]
+        quick: bool,
+
+        /// the maximum IO rate in MiB/s
+        #[arg(long)]
+        rate_limit: Option<u64>,
     },
+
+    /// Resume an interrupted task
+    Resume {
+        /// the ID of the task
+        id: u64,
+
+        /// the maximum IO rate in MiB/s
+        #[arg(long)]
+        rate_limit: Option<u64>,
+    },
+
+    /// Cancel a task
+    Cancel {
+        /// the ID of the task
+        id: u64,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct QuotaEntry {
+    id: FileId,
+    #[serde(flatten)]
+    info: QuotaInfo,
+}
+
+fn print_file_tree(entries: &[TreeEntry], depth: usize, size: bool, perm: bool) {
+    for entry in entries {
+        let mut info = Vec::new();
+        if perm {
+            info.push(entry.perms.map_or_else(
+                || "?".to_owned(),
+                |perms| format_mode(entry.kind, perms.mode),
+            ));
+        }
+        if size {
+            info.push(entry.size.unwrap_or_default().to_string());
+        }
+        let info = if info.is_empty() {
+            String::new()
+        } else {
+            format!("[{}] ", info.join(" "))
+        };
+        println!("{}| {info}{}", "  ".repeat(depth), entry.name);
+        if let Some(children) = &entry.children {
+            print_file_tree(children, depth + 1, size, perm);
+        }
+    }
 }
 
-fn print_file_tree(bijou: &Bijou, dir: FileId, depth: usize) -> Result<()> {
-    for entry in bijou.read_dir(dir)?.reset() {
-        let (name, item) = entry?;
-        if name == "." || name == ".." {
+/// Formats permissions like `ls -l`, e.g. `drwxr-xr-x`.
+fn format_mode(kind: FileKind, mode: u16) -> String {
+    let kind = match kind {
+        FileKind::File => '-',
+        FileKind::Directory => 'd',
+        FileKind::Symlink => 'l',
+    };
+    std::iter::once(kind)
+        .chain((0..9).rev().map(|bit| {
+            if mode & (1 << bit) == 0 {
+                '-'
+            } else {
+                b"xwr"[bit % 3] as char
+            }
+        }))
+        .collect()
+}
+
+fn print_quota(name: &str, info: &QuotaInfo) {
+    let limit =
+        |limit: Option<u64>| limit.map_or_else(|| "unlimited".to_owned(), |it| it.to_string());
+    println!(
+        "{name}: {}/{} bytes, {}/{} inodes",
+        info.bytes,
+        limit(info.limits.bytes),
+        info.inodes,
+        limit(info.limits.inodes)
+    );
+}
+
+fn print_usage(stats: &UsageStats) {
+    println!(
+        "{} files, {} directories, {} symlinks, {} bytes",
+        stats.files, stats.directories, stats.symlinks, stats.bytes
+    );
+    for (bucket, count) in stats.sizes.iter().enumerate() {
+        if *count == 0 {
             continue;
         }
-        println!("{}| {name}", "  ".repeat(depth));
-        if item.kind == FileKind::Directory {
-            print_file_tree(bijou, item.id, depth + 1)?;
+        match bucket {
+            0 => println!("  empty: {count} files"),
+            _ => println!(
+                "  {}-{} bytes: {count} files",
+                1u64 << (bucket - 1),
+                (1u128 << bucket) - 1
+            ),
         }
     }
+}
+
+fn print_check_issue(issue: &CheckIssue) {
+    match issue {
+        CheckIssue::DanglingEntry { path, id } => println!("DANGLING {path} ({id}) does not exist"),
+        CheckIssue::KindMismatch {
+            path,
+            id,
+            entry,
+            actual,
+        } => println!("MISMATCH {path} ({id}) is {actual:?}, not {entry:?}"),
+        CheckIssue::WrongLinkCount {
+            id,
+            recorded,
+            actual,
+        } => println!("NLINKS {id} has {actual} links, not {recorded}"),
+        CheckIssue::Damaged { path, id, error } => println!("DAMAGED {path} ({id}): {error}"),
+        CheckIssue::OrphanedContent { id } => println!("ORPHANED {id}"),
+    }
+}
+
+fn print_task(task: &TaskState) {
+    let kind = match task.kind {
+        TaskKind::Scrub { quick: false } => "scrub",
+        TaskKind::Scrub { quick: true } => "quick scrub",
+        TaskKind::MigrateStorage { .. } => "storage migration",
+    };
+    println!(
+        "#{} {kind}, started at {}, updated at {}: {} files, {} bytes, {} failures",
+        task.id,
+        task.started,
+        task.updated,
+        task.files,
+        task.bytes,
+        task.failures.len()
+    );
+}
+
+fn run_task(
+    bijou: &mut Bijou,
+    id: u64,
+    rate_limit: Option<u64>,
+    format: OutputFormat,
+) -> Result<()> {
+    let rate_limit = rate_limit.map(|limit| limit << 20);
+    let migration = bijou
+        .tasks()?
+        .iter()
+        .any(|task| task.id == id && matches!(task.kind, TaskKind::MigrateStorage { .. }));
+    let task = if migration {
+        bijou.migrate_storage(id, rate_limit)?
+    } else {
+        bijou.run_task(id, rate_limit)?
+    };
+    match format {
+        OutputFormat::Text => {
+            for failure in &task.failures {
+                println!("FAILED {}: {}", failure.id, failure.error);
+            }
+            print_task(&task);
+        }
+        OutputFormat::Json => print_json(&task)?,
+    }
+    if !task.failures.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn print_json(value: &impl Serialize) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
     Ok(())
 }
 
 fn main() -> Result<()> {
     LogTracer::init()?;
 
+    // the journal does not render colors
+    #[cfg(not(windows))]
+    let ansi = !service::logs_to_journal();
+    #[cfg(windows)]
+    let ansi = true;
     let subscriber = tracing_subscriber::registry().with(
-        fmt::layer().with_writer(std::io::stderr).with_filter(
-            EnvFilter::builder()
-                .with_default_directive(LevelFilter::INFO.into())
-                .from_env_lossy(),
-        ),
+        fmt::layer()
+            .with_writer(std::io::stderr)
+            .with_ansi(ansi)
+            .with_filter(
+                EnvFilter::builder()
+                    .with_default_directive(LevelFilter::INFO.into())
+                    .from_env_lossy(),
+            ),
     );
 
     tracing::subscriber::set_global_default(subscriber)
@@ -116,14 +768,31 @@ fn main() -> Result<()> {
 
     bijou::init()?;
 
-    let args = Args::parse();
+    #[cfg(not(windows))]
+    if mount_helper::is_helper() {
+        if let Err(err) = run(Args::parse_from(mount_helper::args())) {
+            error!("{err:?}");
+            std::process::exit(mount_helper::EXIT_FAILURE);
+        }
+        return Ok(());
+    }
 
+    run(Args::parse())
+}
+
+fn run(args: Args) -> Result<()> {
+    let secrets = secret::provider(args.password_file, args.password_command, args.password_env);
     match args.command {
         Command::Create {
             path,
             config,
+            preset,
             ops_limit,
             mem_limit,
+            min_strength,
+            label,
+            read_only_password,
+            throttle_unlock,
         } => {
             let config = match config {
                 Some(path) => {
@@ -138,27 +807,131 @@ fn main() -> Result<()> {
                     .exit();
             }
 
-            let password = rpassword::prompt_password("Enter password: ")?;
-            if rpassword::prompt_password("Repeat: ")? != password {
-                Args::command()
-                    .error(ErrorKind::InvalidValue, "Passwords do not match")
-                    .exit();
+            let password = secrets.new_secret("Enter password: ")?;
+            if let Some(strength) = password::estimate_strength(&password) {
+                if strength.score < 3 {
+                    warn!("weak password (score {}/4)", strength.score);
+                    for feedback in &strength.feedback {
+                        warn!("{feedback}");
+                    }
+                }
+            }
+            if let Some(min_strength) = min_strength {
+                if let Err(err) = MinimumStrength(min_strength).check(&password) {
+                    Args::command()
+                        .error(ErrorKind::InvalidValue, err.to_string())
+                        .exit();
+                }
+            }
+
+            let mut builder = BijouBuilder::from_config(&path, config);
+            match preset {
+                Some(Preset::Paranoid) => builder.paranoid(),
+                Some(Preset::Fast) => builder.fast(),
+                Some(Preset::Remote) => builder.remote(),
+                // applied last, as it changes the type of the builder
+                Some(Preset::Compact) | None => &mut builder,
+            };
+            if let Some(limit) = ops_limit {
+                builder.ops_limit(limit);
+            }
+            if let Some(limit) = mem_limit {
+                builder.mem_limit(limit);
+            }
+            if let Some(label) = label {
+                builder.label(label);
+            }
+            if read_only_password {
+                // always prompted for, as the options only give one password
+                builder.read_only_password(secret::Prompt.new_secret("Read-only password: ")?);
+            }
+            if throttle_unlock {
+                builder.throttle_unlock(true);
+            }
+            if let Some(Preset::Compact) = preset {
+                builder.compact().create(password)?;
+            } else {
+                builder.create(password)?;
             }
-            Bijou::create(
-                &path,
-                password.into_bytes(),
-                config,
-                ops_limit.unwrap_or(Limit::Moderate),
-                mem_limit.unwrap_or(Limit::Moderate),
-            )?;
 
             info!("Bijou created at {}", path.display());
         }
+        Command::Info { path } => {
+            let Some(info) = Bijou::probe(&path)? else {
+                Args::command()
+                    .error(ErrorKind::InvalidValue, "Not a Bijou")
+                    .exit();
+            };
+            match args.format {
+                OutputFormat::Text => {
+                    println!("UUID: {}", info.uuid.as_deref().unwrap_or("-"));
+                    println!("Label: {}", info.label.as_deref().unwrap_or("-"));
+                    println!("Version: {}", info.version);
+                    println!("Embedded: {}", info.embedded);
+                    println!("Read-only password: {}", info.has_read_only_password);
+                    println!("Unlock throttling: {}", info.throttle_unlock);
+                    println!(
+                        "Key derivation: {} ops, {} MiB",
+                        info.ops_limit,
+                        info.mem_limit >> 20
+                    );
+                }
+                OutputFormat::Json => print_json(&info)?,
+            }
+        }
+        Command::Passwd {
+            path,
+            #[cfg(not(windows))]
+            socket,
+            read_only,
+            remove_read_only,
+            ops_limit,
+            mem_limit,
+        } => {
+            let old = secrets.secret("Enter password: ")?;
+            if read_only || remove_read_only {
+                let read_only_password = if read_only {
+                    Some(secret::Prompt.new_secret("New read-only password: ")?)
+                } else {
+                    None
+                };
+                let bijou = Bijou::open(path, old.clone())?;
+                bijou.set_read_only_password(old, read_only_password)?;
+                info!("read-only password changed");
+                return Ok(());
+            }
+            // always prompted for, as the options only give the current one
+            let new = secret::Prompt.new_secret("New password: ")?;
+            #[cfg(not(windows))]
+            if socket {
+                control::request(&path, &control::Request::ChangePassword { old, new })?;
+                info!("password changed");
+                return Ok(());
+            }
+            let bijou = Bijou::open(path, old.clone())?;
+            bijou.change_password(old, new, ops_limit, mem_limit)?;
+            info!("password changed");
+        }
+        Command::Label { path, label } => {
+            let mut bijou = Bijou::open(path, secrets.secret("Enter password: ")?)?;
+            bijou.set_label(label)?;
+        }
         #[cfg(not(windows))]
         Command::Mount {
             path,
             mount_point,
             allow_other,
+            verify,
+            stable_inodes,
+            sorted,
+            read_only,
+            restrict_chown,
+            root_squash,
+            threads,
+            offload,
+            daemon,
+            pid_file,
+            control_socket,
         } => {
             if !path.is_dir() {
                 Args::command()
@@ -171,15 +944,55 @@ fn main() -> Result<()> {
                     .exit();
             }
 
-            let password = rpassword::prompt_password("Enter password: ")?;
-            let bijou = Arc::new(Bijou::open(path, password.into_bytes())?);
-            let fuse = bijou::BijouFuse::new(bijou);
+            let password = secrets.secret("Enter password: ")?;
+            if daemon && !mount_helper::is_daemon() {
+                return mount_helper::daemonize(&password);
+            }
+            let mut bijou = if read_only {
+                Bijou::open_read_only(path, password)?
+            } else {
+                Bijou::open(path, password)?
+            };
+            bijou.set_verify_on_open(verify);
+            let bijou = Arc::new(bijou);
+            // verifies files while idle, if enabled in the config
+            let _scrub = bijou.spawn_background_scrub();
+            if let Some(path) = &control_socket {
+                control::serve(path, Arc::clone(&bijou))?;
+            }
+            let mut fuse = bijou::BijouFuse::new(bijou);
+            fuse.set_stable_inodes(stable_inodes);
+            fuse.set_sorted_dirs(sorted);
+            fuse.set_owner_policy(bijou::OwnerPolicy {
+                restrict_chown,
+                root_squash,
+            });
+            if let Some(threads) = threads {
+                fuse.set_threads(threads as usize);
+            }
+            if let Some(offload) = offload {
+                fuse.set_offload(
+                    offload
+                        .into_iter()
+                        .fold(bijou::OpClass::EMPTY, |classes, it| classes | it.into()),
+                );
+            }
             let mut options = Vec::new();
             if allow_other {
                 options.push(bijou::MountOption::AllowOther);
             }
+            if daemon {
+                fuse.run(mount_point, &options, || {
+                    mount_helper::notify_ready();
+                    service::started(pid_file.as_deref());
+                })?;
+                service::stopping(pid_file.as_deref(), control_socket.as_deref());
+                return Ok(());
+            }
             let mut unmounter = fuse.mount(mount_point, &options)?;
+            service::started(pid_file.as_deref());
             ctrlc::set_handler(move || {
+                service::stopping(pid_file.as_deref(), control_socket.as_deref());
                 unmounter.unmount().expect("failed to unmount");
                 std::process::exit(0);
             })?;
@@ -188,10 +1001,383 @@ fn main() -> Result<()> {
                 std::thread::park();
             }
         }
-        Command::Tree { path } => {
-            let password = rpassword::prompt_password("Enter password: ")?;
-            let bijou = Bijou::open(path, password.into_bytes())?;
-            print_file_tree(&bijou, FileId::ROOT, 0)?;
+        Command::Tree {
+            path,
+            max_depth,
+            size,
+            perm,
+        } => {
+            let bijou = Bijou::open(path, secrets.secret("Enter password: ")?)?;
+            let tree = bijou.tree_with(
+                FileId::ROOT,
+                &TreeOptions {
+                    max_depth,
+                    // always included in JSON
+                    metadata: size || perm || matches!(args.format, OutputFormat::Json),
+                },
+            )?;
+            match args.format {
+                OutputFormat::Text => print_file_tree(&tree, 0, size, perm),
+                OutputFormat::Json => print_json(&tree)?,
+            }
+        }
+        Command::Verify {
+            path,
+            rate_limit,
+            quick,
+            threads,
+        } => {
+            let bijou = Bijou::open(path, secrets.secret("Enter password: ")?)?;
+            let options = ScrubOptions {
+                rate_limit: rate_limit.map(|limit| limit << 20),
+                quick,
+                threads,
+            };
+
+            let failed = match args.format {
+                OutputFormat::Text => {
+                    // printed as found, so that nothing piles up
+                    let failures = AtomicU64::new(0);
+                    let report = bijou.scrub_with(&options, &|event| match event {
+                        ScrubEvent::Failure(failure) => {
+                            failures.fetch_add(1, Ordering::Relaxed);
+                            println!(
+                                "FAILED {} ({}): {}",
+                                failure.path, failure.id, failure.error
+                            );
+                        }
+                        ScrubEvent::Unreachable(id) => println!("UNREACHABLE {id}"),
+                        ScrubEvent::NormalizationDuplicates(paths) => {
+                            println!("DUPLICATE names differing in normalization: {paths:?}");
+                        }
+                        ScrubEvent::Progress(progress) => info!(
+                            "{} files, {} bytes checked so far",
+                            progress.files, progress.bytes
+                        ),
+                        _ => {}
+                    });
+                    let failures = failures.into_inner();
+                    println!(
+                        "{} files, {} directories, {} symlinks, {} bytes checked, {} failures",
+                        report.files, report.directories, report.symlinks, report.bytes, failures
+                    );
+                    if report.pin_checked != 0 || report.pinned != 0 {
+                        println!(
+                            "{} files checked against pins, {} files pinned",
+                            report.pin_checked, report.pinned
+                        );
+                    }
+                    if report.unreachable_count != 0 {
+                        println!("{} unreachable files", report.unreachable_count);
+                    }
+                    failures != 0
+                }
+                OutputFormat::Json => {
+                    let report = bijou.scrub(&options);
+                    print_json(&report)?;
+                    !report.is_ok()
+                }
+            };
+            if failed {
+                std::process::exit(1);
+            }
+        }
+        Command::Check {
+            path,
+            repair,
+            skip_content,
+            threads,
+        } => {
+            let bijou = Bijou::open(path, secrets.secret("Enter password: ")?)?;
+            let options = CheckOptions {
+                repair,
+                skip_content,
+                threads,
+            };
+            let report = match args.format {
+                OutputFormat::Text => {
+                    // printed as found, so that nothing piles up
+                    let report = bijou.check_with(&options, &|event| match event {
+                        CheckEvent::Issue(issue) => print_check_issue(&issue),
+                        CheckEvent::Progress(progress) => info!(
+                            "{} files, {} bytes checked so far",
+                            progress.files, progress.bytes
+                        ),
+                        _ => {}
+                    })?;
+                    println!(
+                        "{} files, {} directories, {} symlinks checked, {} issues, {} repaired",
+                        report.files,
+                        report.directories,
+                        report.symlinks,
+                        report.issue_count,
+                        report.repaired
+                    );
+                    if !report.orphans_checked {
+                        info!("the storage can't list its files, so orphaned content was not looked for");
+                    }
+                    if report.unreachable_count != 0 {
+                        println!("{} unreachable files", report.unreachable_count);
+                    }
+                    report
+                }
+                OutputFormat::Json => {
+                    let report = bijou.check(&options)?;
+                    print_json(&report)?;
+                    report
+                }
+            };
+            if !report.is_ok() {
+                std::process::exit(1);
+            }
+        }
+        Command::DumpMeta { path, file, id } => {
+            let bijou = Bijou::open(path, secrets.secret("Enter password: ")?)?;
+            let file = if id {
+                file.parse().context("invalid file ID")?
+            } else {
+                bijou.resolve(file.as_str())?
+            };
+            // always JSON
+            print_json(&bijou.dump_records(file)?)?;
+        }
+        Command::MigrateFrom {
+            kind,
+            src,
+            dst,
+            target,
+        } => {
+            if !src.is_dir() {
+                Args::command()
+                    .error(ErrorKind::Io, "Source directory does not exist")
+                    .exit();
+            }
+            if let Some(err) = kind.check(&src) {
+                Args::command().error(ErrorKind::InvalidValue, err).exit();
+            }
+
+            let bijou = Bijou::open(dst, secrets.secret("Enter password: ")?)?;
+            let parent = bijou.resolve(target.as_str())?;
+            let stats = bijou.import(&src, parent)?;
+
+            match args.format {
+                OutputFormat::Text => info!(
+                    "imported {} files, {} directories, {} symlinks, {} hard links ({} bytes)",
+                    stats.files, stats.directories, stats.symlinks, stats.hard_links, stats.bytes
+                ),
+                OutputFormat::Json => print_json(&stats)?,
+            }
+        }
+        Command::Backup {
+            path,
+            dest,
+            #[cfg(not(windows))]
+            socket,
+        } => {
+            if dest.exists() && (!dest.is_dir() || dest.read_dir()?.next().is_some()) {
+                Args::command()
+                    .error(ErrorKind::Io, "Destination is not empty")
+                    .exit();
+            }
+
+            #[cfg(not(windows))]
+            if socket {
+                let dest = std::env::current_dir()?.join(dest);
+                control::request(&path, &control::Request::Backup { dest: dest.clone() })?;
+                info!("Backup created at {}", dest.display());
+                return Ok(());
+            }
+
+            let bijou = Bijou::open(path, secrets.secret("Enter password: ")?)?;
+            bijou.backup(&dest)?;
+
+            info!("Backup created at {}", dest.display());
+        }
+        Command::ExportMeta { path, dest } => {
+            if dest.exists() && (!dest.is_dir() || dest.read_dir()?.next().is_some()) {
+                Args::command()
+                    .error(ErrorKind::Io, "Destination is not empty")
+                    .exit();
+            }
+
+            let bijou = Bijou::open(path, secrets.secret("Enter password: ")?)?;
+            bijou.export_meta(&dest)?;
+
+            info!("Metadata backup created at {}", dest.display());
+        }
+        Command::ImportMeta { backup, path } => {
+            Bijou::import_meta(&path, &backup)?;
+            info!("Metadata imported, reconciling data");
+
+            let bijou = Bijou::open(path, secrets.secret("Enter password: ")?)?;
+            let report = bijou.reconcile()?;
+            match args.format {
+                OutputFormat::Text => {
+                    for id in &report.recreated {
+                        println!("MISSING {id}, now empty");
+                    }
+                    for id in &report.truncated {
+                        println!("DAMAGED {id}, now empty");
+                    }
+                    for failure in &report.failures {
+                        println!("FAILED {}: {}", failure.id, failure.error);
+                    }
+                    println!(
+                        "{} files checked, {} repaired, {} failures",
+                        report.files,
+                        report.recreated.len() + report.truncated.len(),
+                        report.failures.len()
+                    );
+                }
+                OutputFormat::Json => print_json(&report)?,
+            }
+        }
+        Command::Shell { path } => {
+            let bijou = Bijou::open(path, secrets.secret("Enter password: ")?)?;
+            shell::run(BijouFs::new(Arc::new(bijou)))?;
+        }
+        Command::DecryptAll { path, dest } => {
+            if dest.exists() && !dest.is_dir() {
+                Args::command()
+                    .error(ErrorKind::Io, "Destination is not a directory")
+                    .exit();
+            }
+
+            let bijou = Bijou::open(path, secrets.secret("Enter password: ")?)?;
+            let stats = bijou.export(FileId::ROOT, &dest)?;
+
+            match args.format {
+                OutputFormat::Text => info!(
+                    "exported {} files ({} skipped), {} directories, {} symlinks, {} hard links ({} bytes)",
+                    stats.files,
+                    stats.skipped,
+                    stats.directories,
+                    stats.symlinks,
+                    stats.hard_links,
+                    stats.bytes
+                ),
+                OutputFormat::Json => print_json(&stats)?,
+            }
+        }
+        Command::Quota {
+            path,
+            dir,
+            bytes,
+            inodes,
+            remove,
+        } => {
+            let bijou = Bijou::open(path, secrets.secret("Enter password: ")?)?;
+            let Some(dir) = dir else {
+                let quotas: Vec<_> = bijou
+                    .quotas()
+                    .into_iter()
+                    .map(|(id, info)| QuotaEntry { id, info })
+                    .collect();
+                match args.format {
+                    OutputFormat::Text => {
+                        for entry in &quotas {
+                            print_quota(&entry.id.to_string(), &entry.info);
+                        }
+                    }
+                    OutputFormat::Json => print_json(&quotas)?,
+                }
+                return Ok(());
+            };
+
+            let id = bijou.resolve(dir.as_str())?;
+            if remove {
+                if !bijou.remove_quota(id)? {
+                    warn!("{dir} has no quota");
+                }
+            } else if bytes.is_some() || inodes.is_some() {
+                bijou.set_quota(id, QuotaLimits { bytes, inodes })?;
+            }
+
+            let quota = bijou.quota(id);
+            match args.format {
+                OutputFormat::Text => match &quota {
+                    Some(info) => print_quota(&dir, info),
+                    None => println!("{dir}: no quota"),
+                },
+                OutputFormat::Json => print_json(&quota)?,
+            }
+        }
+        Command::Du { path, history } => {
+            let bijou = Bijou::open(path, secrets.secret("Enter password: ")?)?;
+            if history {
+                let snapshots = bijou.usage_history()?;
+                match args.format {
+                    OutputFormat::Text => {
+                        let mut last = None;
+                        for snapshot in &snapshots {
+                            let stats = &snapshot.stats;
+                            let growth = last.map_or_else(String::new, |last| {
+                                format!(" ({:+} bytes)", stats.bytes as i128 - last as i128)
+                            });
+                            println!(
+                                "{}: {} files, {} directories, {} symlinks, {} bytes{growth}",
+                                snapshot.date,
+                                stats.files,
+                                stats.directories,
+                                stats.symlinks,
+                                stats.bytes
+                            );
+                            last = Some(stats.bytes);
+                        }
+                    }
+                    OutputFormat::Json => print_json(&snapshots)?,
+                }
+                return Ok(());
+            }
+
+            let stats = if bijou.is_read_only() {
+                bijou.usage()?
+            } else {
+                bijou.record_usage()?.stats
+            };
+            match args.format {
+                OutputFormat::Text => print_usage(&stats),
+                OutputFormat::Json => print_json(&stats)?,
+            }
+        }
+        Command::Task { path, command } => {
+            let mut bijou = Bijou::open(path, secrets.secret("Enter password: ")?)?;
+            match command {
+                TaskCommand::List => {
+                    let tasks = bijou.tasks()?;
+                    match args.format {
+                        OutputFormat::Text => tasks.iter().for_each(print_task),
+                        OutputFormat::Json => print_json(&tasks)?,
+                    }
+                }
+                TaskCommand::Scrub { quick, rate_limit } => {
+                    let id = bijou.start_task(TaskKind::Scrub { quick })?;
+                    info!("started task #{id}");
+                    run_task(&mut bijou, id, rate_limit, args.format)?;
+                }
+                TaskCommand::Resume { id, rate_limit } => {
+                    run_task(&mut bijou, id, rate_limit, args.format)?;
+                }
+                TaskCommand::Cancel { id } => {
+                    if !bijou.cancel_task(id)? {
+                        warn!("task #{id} does not exist");
+                    }
+                }
+            }
+        }
+        Command::MigrateStorage {
+            path,
+            storage,
+            rate_limit,
+        } => {
+            let storage: FileStorage = File::open(storage)
+                .map_err(anyhow::Error::from)
+                .and_then(|file| Ok(serde_json::from_reader(file)?))
+                .context("failed to read storage")?;
+            let mut bijou = Bijou::open(path, secrets.secret("Enter password: ")?)?;
+            let id = bijou.start_storage_migration(storage)?;
+            info!("started task #{id}");
+            run_task(&mut bijou, id, rate_limit, args.format)?;
         }
     }
 
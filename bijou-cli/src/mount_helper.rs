@@ -0,0 +1,204 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Support for mounting from `/etc/fstab`.
+//!
+//! When invoked as `mount.bijou` (e.g. through a symlink), the
+//! arguments are parsed as those of a mount helper (see `mount(8)`)
+//! and translated into `bijou mount --daemon`:
+//!
+//! ```text
+//! /path/to/bijou  /mnt/bijou  bijou  password_file=/etc/bijou.key,allow_other  0  0
+//! ```
+//!
+//! Besides generic options like `noauto` or `x-systemd.*`, which are
+//! ignored, options correspond to flags of `bijou mount`, with commas
+//! in `offload` written as `+`.
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use std::{
+    ffi::OsString,
+    io::{BufRead, BufReader, Write},
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+use tracing::warn;
+
+/// Exit code for incorrect invocation, see `mount(8)`.
+pub const EXIT_USAGE: i32 = 1;
+/// Exit code for mount failures, see `mount(8)`.
+pub const EXIT_FAILURE: i32 = 32;
+
+/// Set for the process started in the background by
+/// [`daemonize`], which reads the password from its stdin (see
+/// [`crate::secret::provider`]).
+const DAEMON_ENV: &str = "BIJOU_DAEMON";
+/// Written to stdout by the background process once mounted.
+const READY: &str = "ready";
+
+/// Options of the Bijou passed by `mount(8)` that are not ours to
+/// handle.
+const IGNORED: &[&str] = &[
+    "defaults", "auto", "noauto", "user", "users", "nouser", "nofail", "_netdev", "rw", "exec",
+    "noexec", "suid", "nosuid", "dev", "nodev", "atime", "noatime", "relatime",
+];
+
+#[derive(Parser)]
+#[command(name = "mount.bijou", about = "Mount helper for Bijou, see mount(8)")]
+struct HelperArgs {
+    /// the path to the Bijou
+    source: PathBuf,
+
+    /// mount point
+    dir: PathBuf,
+
+    /// ignore unknown mount options
+    #[arg(short)]
+    sloppy: bool,
+
+    /// do everything except mounting
+    #[arg(short)]
+    fake: bool,
+
+    /// do not write to /etc/mtab, which is never done anyway
+    #[arg(short = 'n')]
+    #[allow(dead_code)]
+    no_mtab: bool,
+
+    /// verbose mode, which is accepted for compatibility
+    #[arg(short)]
+    #[allow(dead_code)]
+    verbose: bool,
+
+    /// comma-separated mount options
+    #[arg(short)]
+    options: Option<String>,
+
+    /// the filesystem type, which is ignored
+    #[arg(short = 't')]
+    #[allow(dead_code)]
+    kind: Option<String>,
+}
+
+/// Returns whether this process was invoked as `mount.bijou`.
+pub fn is_helper() -> bool {
+    std::env::args_os()
+        .next()
+        .as_deref()
+        .and_then(|arg0| Path::new(arg0).file_name())
+        .is_some_and(|name| name == "mount.bijou")
+}
+
+/// Translates arguments of `mount.bijou` into those of `bijou mount`,
+/// exiting on invalid ones.
+pub fn args() -> Vec<OsString> {
+    let args = match HelperArgs::try_parse() {
+        Ok(args) => args,
+        Err(err) if err.use_stderr() => {
+            let _ = err.print();
+            std::process::exit(EXIT_USAGE);
+        }
+        Err(err) => err.exit(),
+    };
+    if args.fake {
+        std::process::exit(0);
+    }
+
+    let mut result: Vec<OsString> = vec!["bijou".into(), "mount".into()];
+    result.push(args.source.into());
+    result.push(args.dir.into());
+    result.push("--daemon".into());
+    for option in args.options.iter().flat_map(|options| options.split(',')) {
+        let (name, value) = match option.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (option, None),
+        };
+        match (name, value) {
+            ("ro", None) => result.push("--read-only".into()),
+            (
+                "allow_other" | "verify" | "stable_inodes" | "sorted" | "restrict_chown"
+                | "root_squash",
+                None,
+            ) => result.push(format!("--{}", name.replace('_', "-")).into()),
+            ("threads" | "password_file" | "password_command" | "password_env", Some(value)) => {
+                result.push(format!("--{}", name.replace('_', "-")).into());
+                result.push(value.into());
+            }
+            ("offload", Some(value)) => {
+                result.push("--offload".into());
+                result.push(value.replace('+', ",").into());
+            }
+            _ if IGNORED.contains(&option)
+                || name.starts_with("x-")
+                || name == "comment"
+                || option.is_empty() => {}
+            _ if args.sloppy => {
+                warn!("ignoring unknown mount option: {option}");
+            }
+            _ => {
+                eprintln!("mount.bijou: unknown mount option: {option}");
+                std::process::exit(EXIT_USAGE);
+            }
+        }
+    }
+    result
+}
+
+/// Returns whether this is the background process started by
+/// [`daemonize`].
+pub fn is_daemon() -> bool {
+    std::env::var_os(DAEMON_ENV).is_some()
+}
+
+/// Runs this command again in the background with `password`, and
+/// returns once it has mounted the Bijou.
+///
+/// This must be done before opening the Bijou, which starts threads.
+pub fn daemonize(password: &[u8]) -> Result<()> {
+    let mut args = std::env::args_os();
+    let arg0 = args.next().unwrap_or_default();
+    let mut child = Command::new(std::env::current_exe()?)
+        .arg0(arg0)
+        .args(args)
+        .env(DAEMON_ENV, "1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        // not interrupted along with the terminal
+        .process_group(0)
+        .spawn()
+        .context("failed to start background process")?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(password)?;
+    stdin.write_all(b"\n")?;
+    drop(stdin);
+
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap()).read_line(&mut line)?;
+    if line.trim_end() == READY {
+        return Ok(());
+    }
+    let status = child.wait()?;
+    bail!("failed to mount in the background ({status})")
+}
+
+/// Tells the process that called [`daemonize`] that the Bijou is
+/// mounted.
+pub fn notify_ready() {
+    let mut stdout = std::io::stdout();
+    let _ = writeln!(stdout, "{READY}").and_then(|()| stdout.flush());
+}
@@ -0,0 +1,64 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Where passwords come from, according to the global `--password-*`
+//! options.
+
+use bijou::{
+    password::{CommandSecret, EnvSecret, FileSecret, SecretProvider},
+    Error, ErrorKind, SecretBytes,
+};
+use std::path::PathBuf;
+
+/// Prompts for passwords on the terminal.
+pub struct Prompt;
+
+impl SecretProvider for Prompt {
+    fn secret(&self, prompt: &str) -> bijou::Result<SecretBytes> {
+        let secret = rpassword::prompt_password(prompt).map_err(|err| Error::anyhow(err.into()))?;
+        Ok(secret.into_bytes().into())
+    }
+
+    fn new_secret(&self, prompt: &str) -> bijou::Result<SecretBytes> {
+        let secret = self.secret(prompt)?;
+        if self.secret("Repeat: ")?[..] != secret[..] {
+            return Err(Error::msg("passwords do not match").with_kind(ErrorKind::InvalidInput));
+        }
+        Ok(secret)
+    }
+}
+
+/// Returns the source of passwords given by the global options,
+/// prompting for them if none is given.
+pub fn provider(
+    file: Option<PathBuf>,
+    command: Option<String>,
+    env: Option<String>,
+) -> Box<dyn SecretProvider> {
+    #[cfg(not(windows))]
+    if crate::mount_helper::is_daemon() {
+        // passed by the process that started this one
+        return Box::new(FileSecret("-".into()));
+    }
+    if let Some(file) = file {
+        Box::new(FileSecret(file))
+    } else if let Some(command) = command {
+        Box::new(CommandSecret(command))
+    } else if let Some(env) = env {
+        Box::new(EnvSecret(env))
+    } else {
+        Box::new(Prompt)
+    }
+}
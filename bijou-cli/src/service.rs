@@ -0,0 +1,81 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Integration with service managers, for running `bijou mount` as a
+//! service.
+//!
+//! Readiness is reported to systemd (see `sd_notify(3)`) when
+//! `NOTIFY_SOCKET` is set, so that units can use `Type=notify`.
+
+use std::{
+    io,
+    os::unix::{ffi::OsStrExt, net::UnixDatagram},
+    path::Path,
+};
+use tracing::warn;
+
+/// Sends `state` to systemd, if started by it.
+fn notify(state: &str) {
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let result = (|| -> io::Result<()> {
+        let datagram = UnixDatagram::unbound()?;
+        match socket.as_bytes().strip_prefix(b"@") {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+                let addr = SocketAddr::from_abstract_name(name)?;
+                datagram.send_to_addr(state.as_bytes(), &addr)?;
+            }
+            #[cfg(not(target_os = "linux"))]
+            Some(_) => return Err(io::ErrorKind::Unsupported.into()),
+            None => {
+                datagram.send_to(state.as_bytes(), &socket)?;
+            }
+        }
+        Ok(())
+    })();
+    if let Err(err) = result {
+        warn!("failed to notify systemd: {err}");
+    }
+}
+
+/// Reports that the Bijou is mounted, writing the ID of this process
+/// to `pid_file` if given.
+pub fn started(pid_file: Option<&Path>) {
+    let pid = std::process::id();
+    if let Some(path) = pid_file {
+        if let Err(err) = std::fs::write(path, format!("{pid}\n")) {
+            warn!("failed to write pid file: {err}");
+        }
+    }
+    notify(&format!("READY=1\nMAINPID={pid}"));
+}
+
+/// Reports that the Bijou is being unmounted, removing `pid_file`
+/// and `control_socket` if given.
+pub fn stopping(pid_file: Option<&Path>, control_socket: Option<&Path>) {
+    notify("STOPPING=1");
+    for path in [pid_file, control_socket].into_iter().flatten() {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Whether logs are written to the journal, which adds timestamps by
+/// itself and does not render colors.
+pub fn logs_to_journal() -> bool {
+    std::env::var_os("JOURNAL_STREAM").is_some()
+}
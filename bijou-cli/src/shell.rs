@@ -0,0 +1,402 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! The interactive shell (`bijou shell`).
+
+use anyhow::{bail, Context as _, Result};
+use bijou::{
+    path::{Component, Path, PathBuf},
+    BijouFs, File, FileKind,
+};
+use rustyline::{
+    completion::{Completer, FilenameCompleter, Pair},
+    error::ReadlineError,
+    history::DefaultHistory,
+    Context, Editor, Helper, Highlighter, Hinter, Validator,
+};
+use std::{cell::RefCell, io::Write, rc::Rc};
+
+const COMMANDS: &[&str] = &[
+    "cat", "cd", "exit", "get", "help", "ls", "mkdir", "put", "pwd", "rm", "stat",
+];
+
+const HELP: &str = "\
+cd [DIR]                 change the current directory
+ls [DIR]                 list a directory
+cat FILE                 print the contents of a file
+put LOCAL [REMOTE]       copy a local file into the Bijou
+get REMOTE [LOCAL]       copy a file out of the Bijou
+mkdir DIR                create a directory
+rm [-r] PATH             remove a file or (recursively) a directory
+stat PATH                print metadata of a file
+pwd                      print the current directory
+exit                     exit the shell
+
+Arguments are split like in a POSIX shell, so names containing spaces
+can be quoted, e.g. `cat 'my notes.txt'`.";
+
+/// Lexically normalizes an absolute path, resolving `.` and `..`.
+fn normalize(path: &Path) -> PathBuf {
+    let mut parts = Vec::new();
+    for comp in path.components() {
+        match comp {
+            Component::RootDir => parts.clear(),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                parts.pop();
+            }
+            Component::Normal(name) => parts.push(name),
+        }
+    }
+    PathBuf::new(format!("/{}", parts.join("/")))
+}
+
+struct Shell {
+    fs: BijouFs,
+    cwd: RefCell<PathBuf>,
+}
+
+impl Shell {
+    /// Resolves a path relative to the current directory.
+    fn path(&self, path: &str) -> PathBuf {
+        if path.starts_with('/') {
+            normalize(Path::new(path))
+        } else {
+            normalize(&self.cwd.borrow().join(Path::new(path)))
+        }
+    }
+
+    fn kind_of(&self, path: &Path) -> Result<FileKind> {
+        Ok(self.fs.metadata(path)?.kind)
+    }
+
+    /// Runs a command, returning `false` if the shell should exit.
+    fn run(&self, line: &str) -> Result<bool> {
+        let Some(args) = shlex::split(line) else {
+            bail!("unbalanced quotes");
+        };
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let Some((&command, args)) = args.split_first() else {
+            return Ok(true);
+        };
+        match (command, args) {
+            ("cd", []) => *self.cwd.borrow_mut() = PathBuf::from("/"),
+            ("cd", [dir]) => {
+                let dir = self.path(dir);
+                if self.kind_of(&dir)? != FileKind::Directory {
+                    bail!("not a directory: {}", dir.as_str());
+                }
+                *self.cwd.borrow_mut() = dir;
+            }
+            ("ls", []) => self.ls(&self.cwd.borrow())?,
+            ("ls", [dir]) => self.ls(&self.path(dir))?,
+            ("cat", [file]) => {
+                let mut file = File::open(&self.fs, self.path(file))?;
+                let mut stdout = std::io::stdout().lock();
+                std::io::copy(&mut file, &mut stdout)?;
+                stdout.flush()?;
+            }
+            ("put", [local]) | ("put", [local, _]) => {
+                let remote = match args.get(1) {
+                    Some(remote) => self.path(remote),
+                    None => {
+                        let name = std::path::Path::new(local)
+                            .file_name()
+                            .and_then(|name| name.to_str())
+                            .context("invalid local file name")?;
+                        self.path(name)
+                    }
+                };
+                let mut input = std::fs::File::open(local)
+                    .with_context(|| format!("failed to open {local}"))?;
+                let mut output = File::create(&self.fs, &remote)?;
+                let bytes = std::io::copy(&mut input, &mut output)?;
+                println!("{bytes} bytes written to {}", remote.as_str());
+            }
+            ("get", [remote]) | ("get", [remote, _]) => {
+                let remote = self.path(remote);
+                let local = match args.get(1) {
+                    Some(local) => std::path::PathBuf::from(local),
+                    None => remote.file_name().context("not a file")?.into(),
+                };
+                let mut input = File::open(&self.fs, &remote)?;
+                let mut output = std::fs::File::create(&local)
+                    .with_context(|| format!("failed to create {}", local.display()))?;
+                let bytes = std::io::copy(&mut input, &mut output)?;
+                println!("{bytes} bytes written to {}", local.display());
+            }
+            ("mkdir", [dir]) => self.fs.create_dir(self.path(dir))?,
+            ("rm", [path]) => self.fs.remove(self.path(path))?,
+            ("rm", ["-r", path]) => self.fs.remove_all(self.path(path))?,
+            ("stat", [path]) => {
+                let meta = self.fs.metadata(self.path(path))?;
+                println!("{}", serde_json::to_string_pretty(&meta)?);
+            }
+            ("pwd", []) => println!("{}", self.cwd.borrow().as_str()),
+            ("help", []) => println!("{HELP}"),
+            ("exit" | "quit", []) => return Ok(false),
+            _ if COMMANDS.contains(&command) => bail!("invalid arguments, see `help`"),
+            _ => bail!("unknown command `{command}`, see `help`"),
+        }
+        Ok(true)
+    }
+
+    fn ls(&self, dir: &Path) -> Result<()> {
+        let mut entries = self.fs.read_dir(dir)?.collect::<bijou::Result<Vec<_>>>()?;
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, item) in entries {
+            match item.kind {
+                FileKind::Directory => println!("{name}/"),
+                FileKind::Symlink => println!("{name}@"),
+                FileKind::File => println!("{name}"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Completes a path in the Bijou, returning the start of the
+    /// completed part and candidates.
+    fn complete_path(&self, word: &str) -> (usize, Vec<Pair>) {
+        let (dir, prefix) = match word.rfind('/') {
+            Some(pos) => word.split_at(pos + 1),
+            None => ("", word),
+        };
+        let dir = self.path(if dir.is_empty() { "." } else { dir });
+        let Ok(entries) = self.fs.read_dir(&dir) else {
+            return (0, Vec::new());
+        };
+        let candidates = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|(name, _)| name.starts_with(prefix))
+            .map(|(name, item)| {
+                let replacement = if item.kind == FileKind::Directory {
+                    format!("{name}/")
+                } else {
+                    name.clone()
+                };
+                Pair {
+                    display: name,
+                    replacement,
+                }
+            })
+            .collect();
+        (word.len() - prefix.len(), candidates)
+    }
+}
+
+#[derive(Helper, Hinter, Highlighter, Validator)]
+struct ShellHelper {
+    shell: Rc<Shell>,
+    local: FilenameCompleter,
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos]
+            .rfind(char::is_whitespace)
+            .map_or(0, |it| it + 1);
+        let word = &line[start..pos];
+        let previous: Vec<&str> = line[..start].split_whitespace().collect();
+        match previous.as_slice() {
+            [] => {
+                let candidates = COMMANDS
+                    .iter()
+                    .filter(|command| command.starts_with(word))
+                    .map(|command| Pair {
+                        display: command.to_string(),
+                        replacement: format!("{command} "),
+                    })
+                    .collect();
+                Ok((start, candidates))
+            }
+            // local paths
+            ["put"] | ["get", _] => self.local.complete(line, pos, ctx),
+            _ => {
+                let (offset, candidates) = self.shell.complete_path(word);
+                Ok((start + offset, candidates))
+            }
+        }
+    }
+}
+
+/// Runs the interactive shell until `exit` or EOF.
+pub fn run(fs: BijouFs) -> Result<()> {
+    let shell = Rc::new(Shell {
+        fs,
+        cwd: RefCell::new(PathBuf::from("/")),
+    });
+    let mut editor = Editor::<ShellHelper, DefaultHistory>::new()?;
+    editor.set_helper(Some(ShellHelper {
+        shell: Rc::clone(&shell),
+        local: FilenameCompleter::new(),
+    }));
+
+    loop {
+        let prompt = format!("bijou:{}> ", shell.cwd.borrow().as_str());
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.into()),
+        };
+        editor.add_history_entry(line.as_str())?;
+        match shell.run(&line) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) => eprintln!("error: {err:#}"),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bijou::{Bijou, BijouBuilder, Limit};
+    use std::sync::Arc;
+
+    /// Runs `f` with a shell on a Bijou with encrypted file names, in
+    /// a temporary directory which is removed afterwards.
+    fn with_shell(name: &str, f: impl FnOnce(&Shell)) {
+        bijou::init().unwrap();
+        let path = std::env::temp_dir().join(format!("bijou-shell-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let mut builder = BijouBuilder::new(&path);
+        builder
+            .ops_limit(Limit::Interactive)
+            .mem_limit(Limit::Interactive)
+            .filename_encryption(true);
+        builder.create(b"password".to_vec()).unwrap();
+        let bijou = Bijou::open(&path, b"password".to_vec()).unwrap();
+
+        let shell = Shell {
+            fs: BijouFs::new(Arc::new(bijou)),
+            cwd: RefCell::new(PathBuf::from("/")),
+        };
+        f(&shell);
+        drop(shell);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    fn cwd(shell: &Shell) -> String {
+        shell.cwd.borrow().as_str().to_owned()
+    }
+
+    #[test]
+    fn test_normalize() {
+        let normalize = |path: &str| normalize(Path::new(path)).as_str().to_owned();
+        assert_eq!(normalize("/"), "/");
+        assert_eq!(normalize("/a/./b/../c/"), "/a/c");
+        assert_eq!(normalize("/.."), "/");
+        assert_eq!(normalize("/a/b/../../.."), "/");
+        assert_eq!(normalize("/a/.//b"), "/a/b");
+    }
+
+    #[test]
+    fn test_run() {
+        with_shell("run", |shell| {
+            let local = std::env::temp_dir().join(format!("bijou-shell-{}", std::process::id()));
+            std::fs::create_dir_all(&local).unwrap();
+            let input = local.join("input file");
+            std::fs::write(&input, "hello, shell").unwrap();
+            let input = shlex::quote(input.to_str().unwrap()).into_owned();
+            let output = local.join("output");
+
+            assert!(shell.run("mkdir docs").unwrap());
+            assert!(shell.run("cd docs").unwrap());
+            assert_eq!(cwd(shell), "/docs");
+            shell.run(&format!("put {input} 'my notes.txt'")).unwrap();
+            assert_eq!(
+                shell.fs.read("/docs/my notes.txt").unwrap(),
+                b"hello, shell"
+            );
+            shell
+                .run(&format!("get \"my notes.txt\" {}", output.display()))
+                .unwrap();
+            assert_eq!(std::fs::read(&output).unwrap(), b"hello, shell");
+
+            // `cd` only accepts directories, relative to the current one
+            assert!(shell.run("cd 'my notes.txt'").is_err());
+            assert!(shell.run("cd ./missing").is_err());
+            shell.run("cd ../docs/..").unwrap();
+            assert_eq!(cwd(shell), "/");
+            shell.run("cd docs").unwrap();
+            shell.run("cd").unwrap();
+            assert_eq!(cwd(shell), "/");
+
+            assert!(shell.run("rm docs").is_err());
+            shell.run("rm '/docs/my notes.txt'").unwrap();
+            shell.run("mkdir docs/sub").unwrap();
+            shell.run("rm -r docs").unwrap();
+            assert_eq!(shell.fs.read_dir("/").unwrap().count(), 0);
+
+            // blank lines are ignored
+            assert!(shell.run("  ").unwrap());
+            assert!(shell.run("cat 'unbalanced").is_err());
+            assert!(shell.run("cat").is_err());
+            assert!(shell.run("frobnicate").is_err());
+            assert!(!shell.run("exit").unwrap());
+
+            std::fs::remove_dir_all(&local).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_complete_path() {
+        with_shell("complete", |shell| {
+            shell.fs.create_dir("/docs").unwrap();
+            shell.fs.create_dir("/docs/sub").unwrap();
+            shell.fs.write("/docs/notes.txt", "").unwrap();
+            shell.fs.write("/docs/other.txt", "").unwrap();
+
+            let complete = |word: &str| {
+                let (start, candidates) = shell.complete_path(word);
+                let mut replacements: Vec<_> = candidates
+                    .into_iter()
+                    .map(|pair| pair.replacement)
+                    .collect();
+                replacements.sort();
+                (start, replacements)
+            };
+            // directories are completed with a slash
+            assert_eq!(complete("d"), (0, vec!["docs/".to_owned()]));
+            assert_eq!(complete("docs/no"), (5, vec!["notes.txt".to_owned()]));
+            assert_eq!(
+                complete("/docs/"),
+                (
+                    6,
+                    vec![
+                        "notes.txt".to_owned(),
+                        "other.txt".to_owned(),
+                        "sub/".to_owned()
+                    ]
+                )
+            );
+            assert_eq!(complete("missing/"), (0, vec![]));
+
+            // relative to the current directory
+            shell.run("cd docs/sub").unwrap();
+            assert_eq!(complete("../o"), (3, vec!["other.txt".to_owned()]));
+        });
+    }
+}
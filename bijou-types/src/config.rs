@@ -0,0 +1,499 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::serde_ext;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// File encryption algorithm.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum FileEncryption {
+    /// AES-256-GCM
+    ///
+    /// This is the default algorithm and is most commonly
+    /// used. However, this is not the safest algorithm due
+    /// to its small nonce size. For a safer alternative,
+    /// see [`XChaCha20Poly1305IETF`].
+    ///
+    /// [`XChaCha20Poly1305IETF`]: FileEncryption::XChaCha20Poly1305IETF
+    Aes256Gcm,
+
+    /// ChaCha20-Poly1305
+    ///
+    /// This is a safer alternative to [`Aes256Gcm`].
+    ///
+    /// [`Aes256Gcm`]: FileEncryption::Aes256Gcm
+    ChaCha20Poly1305,
+
+    /// XChaCha20-Poly1305-IETF
+    ///
+    /// This is the safest algorithm but can be slower than
+    /// [`Aes256Gcm`].
+    ///
+    /// [`Aes256Gcm`]: FileEncryption::Aes256Gcm
+    XChaCha20Poly1305IETF,
+
+    /// XSalsa20
+    ///
+    /// This is a stream cipher, which means it has lower
+    /// storage overhead than other algorithms, but does
+    /// not provide integrity protection.
+    XSalsa20,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OpenDALType {
+    Memory,
+}
+
+/// How `SplitFileSystem` names clusters.
+#[derive(Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ClusterNaming {
+    /// Random IDs, which are checked against existing files. This is
+    /// what Bijous created by older versions use.
+    #[default]
+    Random,
+
+    /// IDs derived from the ID of the file and the index of the
+    /// cluster with a keyed hash, so that no existence check is
+    /// needed. See [`ClusterNaming::keyed`].
+    Keyed {
+        #[serde(with = "serde_ext::base64")]
+        key: [u8; 32],
+    },
+}
+
+impl ClusterNaming {
+    /// Keyed naming with a random key.
+    pub fn keyed() -> Self {
+        Self::Keyed {
+            key: rand::random(),
+        }
+    }
+}
+
+// the key is not logged
+impl fmt::Debug for ClusterNaming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Random => write!(f, "Random"),
+            Self::Keyed { .. } => write!(f, "Keyed"),
+        }
+    }
+}
+
+/// File storage type.
+///
+/// Multiple storage types can be combined together.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum FileStorage {
+    /// Local filesystem.
+    Local,
+
+    /// Split filesystem. See `SplitFileSystem` for more details.
+    Split {
+        inner: Box<FileStorage>,
+        cluster_size: u64,
+        #[serde(default)]
+        naming: ClusterNaming,
+    },
+
+    /// Tracking filesystem. See `TrackingFileSystem` for more details.
+    Tracking { inner: Box<FileStorage> },
+
+    /// OpenDAL filesystem. See `OpenDALFileSystem` for more details.
+    ///
+    /// Files being written are held in memory in whole, so this
+    /// should be wrapped in [`FileStorage::Split`] unless files are
+    /// at most a few MB.
+    ///
+    /// This requires the `opendal` feature.
+    OpenDAL {
+        ty: OpenDALType,
+        prefix: String,
+        /// Rejects the storage instead of warning if it's not
+        /// directly wrapped in [`FileStorage::Split`] (optionally
+        /// through [`FileStorage::Tracking`]) with `cluster_size` 1,
+        /// in which case objects would be rewritten in whole.
+        #[serde(default)]
+        strict: bool,
+    },
+
+    /// RocksDB filesystem. See `RocksDBFileSystem` for more details.
+    RocksDB,
+
+    /// Storage being migrated, set by `Bijou::migrate_storage`.
+    /// See `MigratingFileSystem` for more details.
+    Migrating {
+        from: Box<FileStorage>,
+        to: Box<FileStorage>,
+    },
+
+    /// Storage injecting random failures and delays, for testing. See
+    /// `FlakyFileSystem` for more details.
+    ///
+    /// This requires the `flaky` feature.
+    Flaky {
+        inner: Box<FileStorage>,
+        /// The probability of an operation failing, between 0 and 1.
+        failure_rate: f64,
+        /// The maximum delay of an operation, in milliseconds.
+        #[serde(default)]
+        latency: u64,
+    },
+}
+
+/// Formats the storage briefly, e.g. `split(local)`, for logs.
+impl fmt::Display for FileStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Local => write!(f, "local"),
+            Self::Split { inner, .. } => write!(f, "split({inner})"),
+            Self::Tracking { inner } => write!(f, "tracking({inner})"),
+            Self::OpenDAL { ty, .. } => write!(f, "opendal({ty:?})"),
+            Self::RocksDB => write!(f, "rocksdb"),
+            Self::Migrating { from, to } => write!(f, "migrating({from} -> {to})"),
+            Self::Flaky { inner, .. } => write!(f, "flaky({inner})"),
+        }
+    }
+}
+
+/// Where times of files are taken from. See [`Config::time_source`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeSource {
+    /// The underlying storage, falling back to the times stored in
+    /// the database for those it does not report (e.g. access times
+    /// of some remote storages).
+    #[default]
+    Storage,
+
+    /// The times stored in the database, which are only updated on
+    /// creation and by `Bijou::set_times`. Useful for storages
+    /// whose times are unreliable.
+    Database,
+
+    /// The later of the two.
+    Latest,
+}
+
+/// How modification times of directories are updated when their
+/// entries change. See [`Config::dir_time_policy`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DirTimePolicy {
+    /// The metadata of the directory is written along with every
+    /// change, as POSIX requires.
+    #[default]
+    Strict,
+
+    /// When only the modification time of the directory would change
+    /// (e.g. creating or removing a file, but not a subdirectory),
+    /// the update is kept in memory and written along with a later
+    /// change to the directory, by `Bijou::flush_dir_times`, or
+    /// when the Bijou is dropped. Times returned by `Bijou::get_meta`
+    /// are always up to date.
+    ///
+    /// This halves the database writes of create-heavy workloads, at
+    /// the cost of losing recent directory times on a crash.
+    Relaxed,
+}
+
+/// What to do when a name being added to a directory only differs
+/// from an existing one in Unicode normalization (e.g. `é` in NFC
+/// and NFD), which look identical in listings. See
+/// [`Config::normalization_duplicates`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NormalizationPolicy {
+    /// Names are compared byte by byte, like most Unix filesystems.
+    #[default]
+    Allow,
+    /// The name is added, and a warning is logged.
+    Warn,
+    /// The name is rejected with `ErrorKind::AlreadyExists`.
+    Reject,
+}
+
+/// When and how fast the background scrubber walks through the
+/// Bijou. See [`Config::background_scrub`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScrubSchedule {
+    /// Seconds between the starts of two passes over the whole Bijou.
+    pub interval: u64,
+    /// The maximum number of raw bytes to read per second.
+    pub rate_limit: u64,
+    /// See `ScrubOptions::quick`.
+    pub quick: bool,
+}
+
+impl Default for ScrubSchedule {
+    fn default() -> Self {
+        Self {
+            interval: 7 * 24 * 60 * 60,
+            rate_limit: 1 << 20,
+            quick: false,
+        }
+    }
+}
+
+/// Configuration for Bijou. Used to initialize a Bijou instance.
+///
+/// See also `Bijou::create`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The version of the configuration.
+    ///
+    /// See [`Config::CURRENT_VERSION`] for the current version.
+    pub version: u32,
+
+    /// Unique ID of the Bijou, e.g. to tell Bijous apart in mount
+    /// managers. Generated when creating the Bijou if not set, and
+    /// `None` for Bijous created by older versions.
+    pub uuid: Option<String>,
+    /// Human-readable label of the Bijou. See `Bijou::set_label`.
+    ///
+    /// This is also kept unencrypted in the key store, so that it can
+    /// be read before unlocking (see `KeyStore::label`). It should
+    /// thus not contain anything secret.
+    pub label: Option<String>,
+
+    /// File encryption algorithm.
+    pub file_encryption: FileEncryption,
+    /// File encryption block size.
+    pub block_size: u64,
+
+    /// Whether to encrypt the database.
+    pub encrypt_db: bool,
+    /// Whether to encrypt the file name.
+    ///
+    /// This is somehow redundant if [`encrypt_db`] is `true`,
+    /// since the file name is stored in the database.
+    ///
+    /// [`encrypt_db`]: Config::encrypt_db
+    pub encrypt_file_name: bool,
+
+    /// Whether to use Unix permissions.
+    ///
+    /// When disabled, all files will have same default
+    /// permissions.
+    pub unix_perms: bool,
+
+    /// File storage type. See [`FileStorage`] for more details.
+    pub storage: FileStorage,
+
+    /// Whether to disable `getxattr` operations.
+    ///
+    /// xattrs can cause significant performance degration
+    /// when file access is frequent. If you don't need them,
+    /// consider disabling them.
+    ///
+    /// This will only disable `getxattr` calls. `setxattr` and
+    /// `listxattr` calls will still work.
+    pub disable_xattr_gets: bool,
+
+    /// Where times of files are taken from. See [`TimeSource`].
+    pub time_source: TimeSource,
+    /// Tolerated clock skew of the storage, in seconds.
+    ///
+    /// Times reported by the storage that are further ahead of the
+    /// local clock are clamped to the local time.
+    pub clock_skew_tolerance: u64,
+    /// How modification times of directories are updated. See
+    /// [`DirTimePolicy`].
+    pub dir_time_policy: DirTimePolicy,
+
+    /// Maximum size of files, in bytes.
+    ///
+    /// Writing or resizing beyond this fails with
+    /// `ErrorKind::FileTooLarge`. Regardless of this, sizes are
+    /// capped so that ciphertext sizes fit in an `i64`.
+    pub max_file_size: u64,
+
+    /// Whether to collect statistics of the database, which are
+    /// returned by `Bijou::db_stats`. This costs a bit of
+    /// performance.
+    pub db_statistics: bool,
+
+    /// What to do when a name being added to a directory only
+    /// differs from an existing one in Unicode normalization.
+    ///
+    /// Only the NFC and NFD forms of the name are looked up, so mixed
+    /// forms are not detected. Scrubs report every such duplicate
+    /// regardless of this (see `ScrubReport`).
+    pub normalization_duplicates: NormalizationPolicy,
+
+    /// Whether to keep the key store and the config (still encrypted)
+    /// in the database of the storage instead of separate files, so
+    /// that the Bijou directory only contains opaque database files,
+    /// e.g. to be synchronized as a blob.
+    ///
+    /// This requires [`FileStorage::RocksDB`], which cannot be
+    /// migrated away from.
+    pub embedded: bool,
+
+    /// Whether to keep an index from files to the directory entries
+    /// pointing to them, which is needed by `Bijou::path_of`. This
+    /// costs one more record per entry.
+    ///
+    /// Only entries added while this is enabled are indexed, so this
+    /// should be set when creating the Bijou.
+    pub reverse_index: bool,
+
+    /// Block IO of opened files taking at least this many
+    /// milliseconds is logged as a warning, along with the file and
+    /// the storage. Disabled if `None`.
+    pub slow_op_threshold: Option<u64>,
+
+    /// Whether and how to verify files in the background while the
+    /// Bijou is idle, so that corruption is detected before the
+    /// files are needed. Disabled if `None`.
+    ///
+    /// The scrubber is run by `Bijou::spawn_background_scrub`.
+    pub background_scrub: Option<ScrubSchedule>,
+
+    /// Whether to detect the types of files from their first bytes
+    /// when they are written, and keep them in the
+    /// `MIME_TYPE_XATTR` xattr, so that they can be filtered by type
+    /// without being read (see `Bijou::mime_type`).
+    ///
+    /// Over FUSE, the xattr can only be read if
+    /// [`disable_xattr_gets`] is `false`.
+    ///
+    /// [`disable_xattr_gets`]: Config::disable_xattr_gets
+    pub sniff_mime_types: bool,
+
+    /// Whether to check that the storage is reachable when opening,
+    /// and fall back to a degraded mode if it is not, e.g. when a
+    /// remote backend is down while the database is local.
+    ///
+    /// In degraded mode, metadata can still be read, but changes fail
+    /// with `ErrorKind::ReadOnly` and opening files fails with
+    /// `ErrorKind::StorageUnavailable`. Meanwhile, the storage is
+    /// probed again when needed, at most every few seconds, and the
+    /// Bijou leaves degraded mode once it is reachable.
+    pub offline_fallback: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: 0,
+
+            uuid: None,
+            label: None,
+
+            file_encryption: FileEncryption::Aes256Gcm,
+            block_size: 4096,
+
+            encrypt_db: true,
+            encrypt_file_name: false,
+
+            unix_perms: true,
+
+            storage: FileStorage::Local,
+
+            disable_xattr_gets: true,
+
+            time_source: TimeSource::Storage,
+            clock_skew_tolerance: 300,
+            dir_time_policy: DirTimePolicy::Strict,
+
+            max_file_size: u64::MAX,
+
+            db_statistics: false,
+
+            normalization_duplicates: NormalizationPolicy::Allow,
+
+            embedded: false,
+
+            reverse_index: false,
+
+            slow_op_threshold: None,
+
+            background_scrub: None,
+
+            sniff_mime_types: false,
+
+            offline_fallback: false,
+        }
+    }
+}
+
+/// Options of a [`Config`] that can be changed on an existing Bijou,
+/// since they do not affect how data is stored.
+///
+/// See `Bijou::set_runtime_options`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RuntimeOptions {
+    /// See [`Config::disable_xattr_gets`].
+    pub disable_xattr_gets: bool,
+    /// See [`Config::time_source`].
+    pub time_source: TimeSource,
+    /// See [`Config::clock_skew_tolerance`].
+    pub clock_skew_tolerance: u64,
+    /// See [`Config::dir_time_policy`].
+    pub dir_time_policy: DirTimePolicy,
+    /// See [`Config::max_file_size`].
+    pub max_file_size: u64,
+    /// See [`Config::normalization_duplicates`].
+    pub normalization_duplicates: NormalizationPolicy,
+    /// See [`Config::slow_op_threshold`].
+    pub slow_op_threshold: Option<u64>,
+    /// See [`Config::background_scrub`].
+    pub background_scrub: Option<ScrubSchedule>,
+    /// See [`Config::sniff_mime_types`].
+    pub sniff_mime_types: bool,
+    /// See [`Config::offline_fallback`].
+    pub offline_fallback: bool,
+}
+
+impl Config {
+    pub const CURRENT_VERSION: u32 = 0;
+
+    /// Returns the options that can be changed on an existing Bijou.
+    pub fn runtime_options(&self) -> RuntimeOptions {
+        RuntimeOptions {
+            disable_xattr_gets: self.disable_xattr_gets,
+            time_source: self.time_source,
+            clock_skew_tolerance: self.clock_skew_tolerance,
+            dir_time_policy: self.dir_time_policy,
+            max_file_size: self.max_file_size,
+            normalization_duplicates: self.normalization_duplicates,
+            slow_op_threshold: self.slow_op_threshold,
+            background_scrub: self.background_scrub.clone(),
+            sniff_mime_types: self.sniff_mime_types,
+            offline_fallback: self.offline_fallback,
+        }
+    }
+
+    /// Replaces the options that can be changed on an existing Bijou.
+    pub fn set_runtime_options(&mut self, options: RuntimeOptions) {
+        self.disable_xattr_gets = options.disable_xattr_gets;
+        self.time_source = options.time_source;
+        self.clock_skew_tolerance = options.clock_skew_tolerance;
+        self.dir_time_policy = options.dir_time_policy;
+        self.max_file_size = options.max_file_size;
+        self.normalization_duplicates = options.normalization_duplicates;
+        self.slow_op_threshold = options.slow_op_threshold;
+        self.background_scrub = options.background_scrub;
+        self.sniff_mime_types = options.sniff_mime_types;
+        self.offline_fallback = options.offline_fallback;
+    }
+}
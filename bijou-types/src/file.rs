@@ -0,0 +1,184 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct DirItem {
+    pub id: FileId,
+    pub kind: FileKind,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum FileKind {
+    File,
+    Symlink,
+    Directory,
+}
+
+/// The internal unique identifier of a file.
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
+pub struct FileId(u64);
+impl fmt::Display for FileId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:x}", self.0)
+    }
+}
+impl std::str::FromStr for FileId {
+    type Err = std::num::ParseIntError;
+
+    /// Parses the hexadecimal form produced by [`Display`](fmt::Display).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u64::from_str_radix(s, 16).map(Self)
+    }
+}
+impl AsRef<[u8]> for FileId {
+    fn as_ref(&self) -> &[u8] {
+        unsafe {
+            std::slice::from_raw_parts(
+                &self.0 as *const u64 as *const u8,
+                std::mem::size_of::<u64>(),
+            )
+        }
+    }
+}
+impl FileId {
+    pub const ROOT: FileId = FileId(0);
+
+    pub fn gen() -> Self {
+        loop {
+            // reserved for the root and for `Inode::from_file_id`
+            let id = rand::random();
+            if !matches!(id, 0 | 1 | u64::MAX) {
+                break Self(id);
+            }
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// Returns the ID as an integer.
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    /// The inverse of [`FileId::as_u64`].
+    pub fn from_u64(id: u64) -> Self {
+        Self(id)
+    }
+}
+
+/// Metadata for a file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileMeta {
+    pub id: FileId,
+    pub kind: FileKind,
+
+    /// Size of this file in bytes. We don't actually store this.
+    /// We use the size of the underlying `RawFileSystem`.
+    #[serde(skip)]
+    pub size: u64,
+
+    /// Time of the last access.
+    ///
+    /// For files, this is reconciled with the time reported by the
+    /// underlying filesystem according to [`Config::time_source`].
+    ///
+    /// [`Config::time_source`]: crate::config::Config::time_source
+    #[serde(with = "crate::time::compact_date_time")]
+    pub accessed: DateTime<Utc>,
+
+    /// Time of the last modification.
+    ///
+    /// For files, this is reconciled with the time reported by the
+    /// underlying filesystem according to [`Config::time_source`].
+    ///
+    /// [`Config::time_source`]: crate::config::Config::time_source
+    #[serde(with = "crate::time::compact_date_time")]
+    pub modified: DateTime<Utc>,
+
+    /// Number of links. Should always be 1 for files since we don't
+    /// support hardlinks.
+    pub nlinks: u32,
+
+    /// Optional Unix permissions.
+    pub perms: Option<UnixPerms>,
+
+    /// Attribute flags of this file. See [`FileAttributes`].
+    pub attributes: FileAttributes,
+
+    /// The file whose raw content (and encryption key) is used by
+    /// this file. `None` means the file's own.
+    ///
+    /// This is set for clones created by `Bijou::clone_file`.
+    pub content: Option<FileId>,
+}
+
+impl FileMeta {
+    /// Returns the ID under which the raw content of this file
+    /// is stored.
+    pub fn content_id(&self) -> FileId {
+        self.content.unwrap_or(self.id)
+    }
+}
+
+/// Per-file attribute flags, similar to those managed by `chattr(1)`.
+///
+/// These are enforced by `Bijou` regardless of Unix permissions.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FileAttributes(u8);
+impl FileAttributes {
+    pub const EMPTY: FileAttributes = FileAttributes(0);
+
+    /// The file cannot be modified, deleted, renamed or linked to.
+    /// For directories, entries cannot be added or removed.
+    pub const IMMUTABLE: FileAttributes = FileAttributes(1 << 0);
+
+    /// The file can only be opened in append mode for writing,
+    /// and cannot be deleted or renamed. For directories, entries
+    /// can be added but not removed.
+    pub const APPEND_ONLY: FileAttributes = FileAttributes(1 << 1);
+
+    pub fn has(&self, flag: Self) -> bool {
+        self.0 & flag.0 != 0
+    }
+
+    pub fn remove(&self, flag: Self) -> Self {
+        Self(self.0 & !flag.0)
+    }
+
+    /// Returns `true` if the file can be neither deleted nor renamed.
+    pub fn is_protected(&self) -> bool {
+        self.has(Self::IMMUTABLE) || self.has(Self::APPEND_ONLY)
+    }
+}
+impl std::ops::BitOr for FileAttributes {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct UnixPerms {
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+}
@@ -0,0 +1,33 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Plain data types of [Bijou], for tools that read its metadata,
+//! configs or reports (e.g. the JSON output of the CLI) without
+//! linking RocksDB or libsodium.
+//!
+//! Everything here is re-exported by `bijou`, so the types are the
+//! same on both sides.
+//!
+//! [Bijou]: https://docs.rs/bijou
+
+pub mod config;
+mod file;
+pub mod report;
+#[doc(hidden)]
+pub mod serde_ext;
+pub mod time;
+
+pub use config::Config;
+pub use file::*;
@@ -0,0 +1,110 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Reports and statistics returned by operations on a Bijou.
+
+use crate::{FileId, FileKind, UnixPerms};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+/// Statistics of `Bijou::export`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportStats {
+    pub files: u64,
+    pub directories: u64,
+    pub symlinks: u64,
+    pub hard_links: u64,
+    /// Files skipped since they were already exported.
+    pub skipped: u64,
+    pub bytes: u64,
+}
+
+/// Statistics of `Bijou::import`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportStats {
+    pub files: u64,
+    pub directories: u64,
+    pub symlinks: u64,
+    pub hard_links: u64,
+    pub bytes: u64,
+}
+
+/// An entry of the tree returned by `Bijou::tree`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TreeEntry {
+    pub name: String,
+    pub id: FileId,
+    pub kind: FileKind,
+    /// The size in bytes, if `TreeOptions::metadata` is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// The Unix permissions, if `TreeOptions::metadata` is set and
+    /// the Bijou keeps them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub perms: Option<UnixPerms>,
+    /// Entries in this directory, for directories.
+    pub children: Option<Vec<TreeEntry>>,
+}
+
+/// Usage statistics of a Bijou, computed by `Bijou::usage`.
+///
+/// Every file is counted, including the ones that are not reachable
+/// from the root. Hard-linked files are counted once, and clones
+/// are counted by their own size.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageStats {
+    pub files: u64,
+    pub directories: u64,
+    pub symlinks: u64,
+    /// Total size of regular files, in bytes.
+    pub bytes: u64,
+    /// Number of regular files by size. `sizes[0]` counts empty
+    /// files, and `sizes[i]` files of `2^(i-1)` to `2^i - 1` bytes.
+    /// Trailing empty buckets are omitted.
+    pub sizes: Vec<u64>,
+}
+
+impl UsageStats {
+    /// Returns the index in [`UsageStats::sizes`] of files of `size`
+    /// bytes.
+    pub fn size_bucket(size: u64) -> usize {
+        (u64::BITS - size.leading_zeros()) as usize
+    }
+
+    /// Counts a regular file of `size` bytes.
+    pub fn add_file(&mut self, size: u64) {
+        self.files += 1;
+        self.bytes += size;
+        let bucket = Self::size_bucket(size);
+        if self.sizes.len() <= bucket {
+            self.sizes.resize(bucket + 1, 0);
+        }
+        self.sizes[bucket] += 1;
+    }
+}
+
+/// The usage of a Bijou on a day, recorded by
+/// `Bijou::record_usage`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageSnapshot {
+    /// The day (UTC) of the snapshot.
+    pub date: NaiveDate,
+    pub stats: UsageStats,
+}
@@ -0,0 +1,59 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Compact serialization of times, as `(seconds, nanoseconds)`
+//! tuples since the Unix epoch. Use with `#[serde(with = "...")]`.
+
+pub mod compact_date_time {
+    use chrono::{DateTime, TimeZone, Utc};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S>(val: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let tuple = (val.timestamp(), val.timestamp_subsec_nanos());
+        tuple.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let (secs, nsecs) = <(i64, u32)>::deserialize(deserializer)?;
+        Ok(Utc.timestamp_opt(secs, nsecs).unwrap())
+    }
+}
+
+pub mod opt_compact_date_time {
+    use chrono::{DateTime, TimeZone, Utc};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S>(val: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let tuple = val.map(|val| (val.timestamp(), val.timestamp_subsec_nanos()));
+        tuple.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let tuple = <Option<(i64, u32)>>::deserialize(deserializer)?;
+        Ok(tuple.map(|it| Utc.timestamp_opt(it.0, it.1).unwrap()))
+    }
+}
@@ -0,0 +1,49 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Fuzzes [`bijou::path`], which user-controlled paths flow into.
+//!
+//! Run with `cargo fuzz run path` in `bijou/`.
+
+#![no_main]
+
+use bijou::path::{Component, Path};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &str| {
+    let path = Path::new(data);
+    let _ = path.check();
+
+    // iterating from both ends agrees
+    let forward: Vec<_> = path.components().collect();
+    let mut backward: Vec<_> = path.components().rev().collect();
+    backward.reverse();
+    assert_eq!(forward, backward);
+
+    let _ = path.file_name();
+    if let Ok(relative) = path.to_relative() {
+        assert!(relative
+            .components()
+            .all(|comp| matches!(comp, Component::Normal(_))));
+    }
+
+    // `pop` always terminates
+    let mut buf = path.join("a");
+    let mut pops = 0;
+    while buf.pop() {
+        pops += 1;
+        assert!(pops <= data.len() + 1);
+    }
+});
@@ -16,12 +16,24 @@
 mod ring_aead;
 mod sodium_aead;
 mod sodium_stream;
+mod stats;
+mod units;
 
 pub use ring_aead::*;
 pub use sodium_aead::*;
 pub use sodium_stream::*;
+#[cfg(feature = "rocksdb")]
+pub(crate) use stats::CryptoCounters;
+pub use stats::CryptoStats;
+pub use units::{BlockIndex, CipherSize, PlainSize};
 
-use crate::{Result, SecretBytes};
+use crate::{
+    anyhow,
+    config::{Config, FileEncryption},
+    sodium::{self, utils::rand_bytes},
+    Result, SecretBytes,
+};
+use std::{cell::Cell, sync::Arc};
 
 /// An algorithm that encrypts and decrypts blocks of data.
 ///
@@ -30,10 +42,14 @@ use crate::{Result, SecretBytes};
 /// and then stored in the underlying storage.
 ///
 /// A block has the following structure:
-/// ```
+/// ```text
 /// [header][content][tag]
 /// ```
 ///
+/// Sizes and offsets are [`PlainSize`]s or [`CipherSize`]s depending
+/// on which side of the encryption they are on, and blocks are
+/// identified by [`BlockIndex`]es, so that they are not mixed up.
+///
 /// When implementing this trait, you should make sure that a null buffers (
 /// buffer with all bytes set to 0) always encrypt / decrypt to null buffers.
 /// This corresponds to the file gaps in the underlying storage.
@@ -59,27 +75,73 @@ pub trait Algorithm {
 
     /// Calculates the size of the plaintext from the size of the ciphertext.
     /// This is the inverse function of `Algorithm::ciphertext_size`.
-    fn plaintext_size(&self, ciphertext_size: u64) -> u64 {
+    fn plaintext_size(&self, ciphertext_size: CipherSize) -> PlainSize {
         let metadata_size = self.metadata_size();
         let content_size = self.content_size();
 
-        let blocks = ciphertext_size / (content_size + metadata_size);
-        let rem = ciphertext_size % (content_size + metadata_size);
-        blocks * content_size + rem.saturating_sub(metadata_size)
+        let blocks = ciphertext_size.0 / (content_size + metadata_size);
+        let rem = ciphertext_size.0 % (content_size + metadata_size);
+        // a trailing block too short to hold any content is empty
+        PlainSize(blocks * content_size + rem.saturating_sub(metadata_size))
     }
 
     /// Calculates the size of the ciphertext from the size of the plaintext.
     /// This is the inverse function of `Algorithm::plaintext_size`.
-    fn ciphertext_size(&self, plaintext_size: u64) -> u64 {
+    ///
+    /// Fails with [`ErrorKind::FileTooLarge`] on overflow.
+    ///
+    /// [`ErrorKind::FileTooLarge`]: crate::ErrorKind::FileTooLarge
+    fn ciphertext_size(&self, plaintext_size: PlainSize) -> Result<CipherSize> {
         let metadata_size = self.metadata_size();
         let block_size = self.content_size();
 
-        let blocks = plaintext_size / block_size;
-        let rem = plaintext_size % block_size;
-        blocks * (block_size + metadata_size) + if rem == 0 { 0 } else { metadata_size + rem }
+        let (blocks, rem) = self.locate(plaintext_size);
+        blocks
+            .0
+            .checked_mul(block_size + metadata_size)
+            .and_then(|size| size.checked_add(if rem == 0 { 0 } else { metadata_size + rem }))
+            .map(CipherSize)
+            .ok_or_else(|| anyhow!(@FileTooLarge? "file too large: {plaintext_size}"))
+    }
+
+    /// Returns the block holding the plaintext byte at `offset`, and
+    /// the offset of that byte in the content of the block.
+    #[inline]
+    fn locate(&self, offset: PlainSize) -> (BlockIndex, u64) {
+        (
+            BlockIndex(offset.0 / self.content_size()),
+            offset.0 % self.content_size(),
+        )
+    }
+
+    /// The largest plaintext size whose ciphertext size fits in an
+    /// `i64`, which is the limit of most storages.
+    fn max_plaintext_size(&self) -> PlainSize {
+        self.plaintext_size(CipherSize(i64::MAX as u64))
     }
 }
 
+/// Returns the algorithm encrypting files as configured in `config`.
+pub fn from_config(config: &Config) -> Result<Arc<dyn Algorithm + Send + Sync>> {
+    Ok(match config.file_encryption {
+        FileEncryption::Aes256Gcm => {
+            Arc::new(RingAead::new(&ring::aead::AES_256_GCM, config.block_size)?)
+        }
+        FileEncryption::ChaCha20Poly1305 => Arc::new(RingAead::new(
+            &ring::aead::CHACHA20_POLY1305,
+            config.block_size,
+        )?),
+        FileEncryption::XChaCha20Poly1305IETF => Arc::new(SodiumAead::new(
+            &sodium::aead::XCHACHA20_POLY1305_IETF,
+            config.block_size,
+        )?),
+        FileEncryption::XSalsa20 => Arc::new(SodiumStream::new(
+            &sodium::stream::XSALSA20,
+            config.block_size,
+        )?),
+    })
+}
+
 /// A key for an algorithm. Can be used to encrypt and
 /// decrypt data blocks.
 ///
@@ -89,15 +151,38 @@ pub trait AlgoKey {
     /// Encrypts the buffer inplace.
     ///
     /// The caller should make sure that `buffer.len() >= metadata_size`.
-    fn encrypt(&self, block: u64, buffer: &mut [u8]) -> Result<()>;
+    fn encrypt(&self, block: BlockIndex, buffer: &mut [u8]) -> Result<()>;
 
     /// Decrypts the buffer inplace.
     ///
     /// The caller should make sure that `buffer.len() >= metadata_size`.
-    fn decrypt(&self, block: u64, buffer: &mut [u8]) -> Result<()>;
+    fn decrypt(&self, block: BlockIndex, buffer: &mut [u8]) -> Result<()>;
 }
 
 /// Checks if the given bytes are all 0.
 pub(crate) fn is_nil(bytes: &[u8]) -> bool {
     bytes.iter().all(|&b| b == 0)
 }
+
+thread_local! {
+    /// Nonces regenerated by this thread since the last call to
+    /// [`take_nonce_regenerations`].
+    static NONCE_REGENERATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Fills `nonce` with random bytes. A nil nonce marks a gap (see
+/// [`Algorithm`]), so it is regenerated in the unlikely case that
+/// one is drawn.
+pub(crate) fn random_nonce(nonce: &mut [u8]) {
+    rand_bytes(nonce);
+    while is_nil(nonce) {
+        NONCE_REGENERATIONS.with(|count| count.set(count.get() + 1));
+        rand_bytes(nonce);
+    }
+}
+
+/// Returns and resets the number of nonces regenerated by this thread.
+#[cfg(feature = "rocksdb")]
+pub(crate) fn take_nonce_regenerations() -> u64 {
+    NONCE_REGENERATIONS.with(|count| count.replace(0))
+}
@@ -13,8 +13,8 @@
 // limitations under the License.
 //
 
-use super::{is_nil, AlgoKey, Algorithm};
-use crate::{crypto::crypto_error, move_to_heap, sodium::utils::rand_bytes, Result, SecretBytes};
+use super::{is_nil, random_nonce, AlgoKey, Algorithm, BlockIndex};
+use crate::{crypto::crypto_error, move_to_heap, Result, SecretBytes};
 use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, MAX_TAG_LEN, NONCE_LEN};
 
 /// General wrapper for ring AEAD algorithms.
@@ -62,13 +62,10 @@ fn split(data: &mut [u8]) -> (&mut [u8; NONCE_LEN], &mut [u8]) {
 // be moved to the heap to disallow implicit copy.
 struct Key(Box<LessSafeKey>);
 impl AlgoKey for Key {
-    fn encrypt(&self, block: u64, buffer: &mut [u8]) -> Result<()> {
+    fn encrypt(&self, block: BlockIndex, buffer: &mut [u8]) -> Result<()> {
         let (nonce, data) = split(buffer);
 
-        rand_bytes(nonce);
-        while is_nil(nonce) {
-            rand_bytes(nonce);
-        }
+        random_nonce(nonce);
 
         let (data, tag_bytes) = data.split_at_mut(data.len() - MAX_TAG_LEN);
 
@@ -76,7 +73,7 @@ impl AlgoKey for Key {
             .0
             .seal_in_place_separate_tag(
                 Nonce::assume_unique_for_key(*nonce),
-                Aad::from(block.to_le_bytes()),
+                Aad::from(block.0.to_le_bytes()),
                 data,
             )
             .map_err(crypto_error)?;
@@ -85,7 +82,7 @@ impl AlgoKey for Key {
         Ok(())
     }
 
-    fn decrypt(&self, block: u64, buffer: &mut [u8]) -> Result<()> {
+    fn decrypt(&self, block: BlockIndex, buffer: &mut [u8]) -> Result<()> {
         let (nonce, data) = split(buffer);
         if is_nil(nonce) {
             data.fill(0);
@@ -93,7 +90,7 @@ impl AlgoKey for Key {
             self.0
                 .open_in_place(
                     Nonce::assume_unique_for_key(*nonce),
-                    Aad::from(block.to_le_bytes()),
+                    Aad::from(block.0.to_le_bytes()),
                     data,
                 )
                 .map_err(crypto_error)?;
@@ -13,12 +13,8 @@
 // limitations under the License.
 //
 
-use super::{is_nil, AlgoKey, Algorithm};
-use crate::{
-    crypto::split_nonce_tag,
-    sodium::{aead, utils::rand_bytes},
-    Result, SecretBytes,
-};
+use super::{is_nil, random_nonce, AlgoKey, Algorithm, BlockIndex};
+use crate::{crypto::split_nonce_tag, sodium::aead, Result, SecretBytes};
 
 /// General wrapper for libsodium AEAD algorithms.
 pub struct SodiumAead {
@@ -63,28 +59,25 @@ struct Key {
     key: SecretBytes,
 }
 impl AlgoKey for Key {
-    fn encrypt(&self, block: u64, buffer: &mut [u8]) -> Result<()> {
+    fn encrypt(&self, block: BlockIndex, buffer: &mut [u8]) -> Result<()> {
         let (nonce, data, tag) = split_nonce_tag(buffer, self.algo.nonce_len, self.algo.tag_len);
 
-        rand_bytes(nonce);
-        while is_nil(nonce) {
-            rand_bytes(nonce);
-        }
+        random_nonce(nonce);
 
         self.algo
-            .encrypt_inplace(data, tag, nonce, Some(&block.to_le_bytes()), &self.key)?;
+            .encrypt_inplace(data, tag, nonce, Some(&block.0.to_le_bytes()), &self.key)?;
 
         Ok(())
     }
 
-    fn decrypt(&self, block: u64, buffer: &mut [u8]) -> Result<()> {
+    fn decrypt(&self, block: BlockIndex, buffer: &mut [u8]) -> Result<()> {
         let (nonce, data, tag) = split_nonce_tag(buffer, self.algo.nonce_len, self.algo.tag_len);
 
         if is_nil(nonce) {
             data.fill(0);
         } else {
             self.algo
-                .decrypt_inplace(data, tag, Some(&block.to_le_bytes()), nonce, &self.key)?;
+                .decrypt_inplace(data, tag, Some(&block.0.to_le_bytes()), nonce, &self.key)?;
         }
 
         Ok(())
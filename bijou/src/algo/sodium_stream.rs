@@ -13,12 +13,8 @@
 // limitations under the License.
 //
 
-use super::{is_nil, AlgoKey, Algorithm};
-use crate::{
-    crypto::split_nonce_tag,
-    sodium::{stream, utils::rand_bytes},
-    Result, SecretBytes,
-};
+use super::{is_nil, random_nonce, AlgoKey, Algorithm, BlockIndex};
+use crate::{crypto::split_nonce_tag, sodium::stream, Result, SecretBytes};
 
 /// General wrapper for libsodium stream cipher algorithms.
 pub struct SodiumStream {
@@ -62,26 +58,23 @@ struct Key {
     key: SecretBytes,
 }
 impl AlgoKey for Key {
-    fn encrypt(&self, block: u64, buffer: &mut [u8]) -> Result<()> {
+    fn encrypt(&self, block: BlockIndex, buffer: &mut [u8]) -> Result<()> {
         let (nonce, data, _) = split_nonce_tag(buffer, self.algo.nonce_len, 0);
 
-        rand_bytes(nonce);
-        while is_nil(nonce) {
-            rand_bytes(nonce);
-        }
+        random_nonce(nonce);
 
-        self.algo.xor_inplace_ic(data, nonce, block, &self.key)?;
+        self.algo.xor_inplace_ic(data, nonce, block.0, &self.key)?;
 
         Ok(())
     }
 
-    fn decrypt(&self, block: u64, buffer: &mut [u8]) -> Result<()> {
+    fn decrypt(&self, block: BlockIndex, buffer: &mut [u8]) -> Result<()> {
         let (nonce, data, _) = split_nonce_tag(buffer, self.algo.nonce_len, 0);
 
         if is_nil(nonce) {
             data.fill(0);
         } else {
-            self.algo.xor_inplace_ic(data, nonce, block, &self.key)?;
+            self.algo.xor_inplace_ic(data, nonce, block.0, &self.key)?;
         }
 
         Ok(())
@@ -0,0 +1,113 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+#[cfg(feature = "rocksdb")]
+use super::{take_nonce_regenerations, AlgoKey, BlockIndex};
+#[cfg(feature = "rocksdb")]
+use crate::{ErrorKind, Result};
+use serde::Serialize;
+#[cfg(feature = "rocksdb")]
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// Counters of block-level cryptographic operations, returned by
+/// [`Bijou::crypto_stats`].
+///
+/// Counters start from zero every time the Bijou is opened.
+///
+/// [`Bijou::crypto_stats`]: crate::Bijou::crypto_stats
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CryptoStats {
+    pub blocks_encrypted: u64,
+    /// Including gaps, which are not actually decrypted.
+    pub blocks_decrypted: u64,
+    /// Blocks that failed to be authenticated while decrypting,
+    /// which indicates corruption or tampering.
+    pub auth_failures: u64,
+    /// Random nonces that were drawn as all zeros and had to be
+    /// regenerated. This should practically never happen.
+    pub nonce_regenerations: u64,
+}
+
+/// Atomic counters behind [`CryptoStats`], shared by all keys of a
+/// Bijou.
+#[cfg(feature = "rocksdb")]
+#[derive(Debug, Default)]
+pub struct CryptoCounters {
+    blocks_encrypted: AtomicU64,
+    blocks_decrypted: AtomicU64,
+    auth_failures: AtomicU64,
+    nonce_regenerations: AtomicU64,
+}
+
+#[cfg(feature = "rocksdb")]
+impl CryptoCounters {
+    pub fn snapshot(&self) -> CryptoStats {
+        CryptoStats {
+            blocks_encrypted: self.blocks_encrypted.load(Ordering::Relaxed),
+            blocks_decrypted: self.blocks_decrypted.load(Ordering::Relaxed),
+            auth_failures: self.auth_failures.load(Ordering::Relaxed),
+            nonce_regenerations: self.nonce_regenerations.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Wraps `key` so that operations with it are counted.
+    pub fn wrap(
+        self: &Arc<Self>,
+        key: Box<dyn AlgoKey + Send + Sync>,
+    ) -> Box<dyn AlgoKey + Send + Sync> {
+        Box::new(CountedKey {
+            inner: key,
+            counters: Arc::clone(self),
+        })
+    }
+}
+
+#[cfg(feature = "rocksdb")]
+struct CountedKey {
+    inner: Box<dyn AlgoKey + Send + Sync>,
+    counters: Arc<CryptoCounters>,
+}
+
+#[cfg(feature = "rocksdb")]
+impl AlgoKey for CountedKey {
+    fn encrypt(&self, block: BlockIndex, buffer: &mut [u8]) -> Result<()> {
+        // discard regenerations by other keys on this thread
+        take_nonce_regenerations();
+        let result = self.inner.encrypt(block, buffer);
+        let counters = &self.counters;
+        counters.blocks_encrypted.fetch_add(1, Ordering::Relaxed);
+        counters
+            .nonce_regenerations
+            .fetch_add(take_nonce_regenerations(), Ordering::Relaxed);
+        result
+    }
+
+    fn decrypt(&self, block: BlockIndex, buffer: &mut [u8]) -> Result<()> {
+        let result = self.inner.decrypt(block, buffer);
+        let counters = &self.counters;
+        counters.blocks_decrypted.fetch_add(1, Ordering::Relaxed);
+        if result
+            .as_ref()
+            .is_err_and(|err| err.kind() == ErrorKind::CryptoError)
+        {
+            counters.auth_failures.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+}
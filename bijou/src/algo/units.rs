@@ -0,0 +1,98 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    ops::{Add, AddAssign, Sub},
+};
+
+macro_rules! unit {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[derive(
+            Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+        )]
+        #[serde(transparent)]
+        pub struct $name(pub u64);
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl Add for $name {
+            type Output = Self;
+
+            fn add(self, rhs: Self) -> Self {
+                Self(self.0 + rhs.0)
+            }
+        }
+
+        impl AddAssign for $name {
+            fn add_assign(&mut self, rhs: Self) {
+                self.0 += rhs.0;
+            }
+        }
+
+        impl Sub for $name {
+            type Output = Self;
+
+            fn sub(self, rhs: Self) -> Self {
+                Self(self.0 - rhs.0)
+            }
+        }
+    };
+}
+
+unit! {
+    /// A size or offset in plaintext bytes, as seen by users of a
+    /// file.
+    PlainSize
+}
+
+unit! {
+    /// A size or offset in ciphertext bytes, as stored in a raw file.
+    CipherSize
+}
+
+unit! {
+    /// The index of a block, which is the same in the plaintext and
+    /// in the ciphertext. See [`Algorithm`](super::Algorithm).
+    BlockIndex
+}
+
+impl CipherSize {
+    /// Returns the indices of the blocks of `block_size` bytes (in
+    /// ciphertext) that make up this many bytes, the last one
+    /// possibly partial.
+    pub fn blocks(self, block_size: u64) -> impl Iterator<Item = BlockIndex> {
+        (0..self.0.div_ceil(block_size)).map(BlockIndex)
+    }
+}
+
+impl BlockIndex {
+    /// Returns the block following this one.
+    pub fn next(self) -> Self {
+        Self(self.0 + 1)
+    }
+
+    /// Returns the offset of this block in a raw file of blocks of
+    /// `block_size` bytes.
+    pub fn offset(self, block_size: u64) -> CipherSize {
+        CipherSize(self.0 * block_size)
+    }
+}
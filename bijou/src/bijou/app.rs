@@ -0,0 +1,95 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::Bijou;
+use crate::{bail, db::consts, error::ResultExt, Result};
+use bijou_rocksdb::{Direction, IteratorMode};
+
+/// A key-value store for applications embedding Bijou (e.g. to keep
+/// their settings in the Bijou), isolated from the files and from
+/// other namespaces.
+///
+/// Values are stored in the database, which is encrypted if
+/// [`Config::encrypt_db`] is enabled.
+///
+/// Created by [`Bijou::app_storage`].
+///
+/// [`Config::encrypt_db`]: crate::Config::encrypt_db
+pub struct AppStorage<'a> {
+    bijou: &'a Bijou,
+    prefix: Vec<u8>,
+}
+
+impl AppStorage<'_> {
+    fn key(&self, key: &[u8]) -> Vec<u8> {
+        let mut result = self.prefix.clone();
+        result.extend_from_slice(key);
+        result
+    }
+
+    /// Returns the value of `key`, if any.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.bijou.db.key(self.key(key)).read_owned()
+    }
+
+    /// Sets the value of `key`.
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.bijou.check_writable()?;
+        self.bijou.db.key(self.key(key)).write(value)
+    }
+
+    /// Removes `key`. Does nothing if it does not exist.
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        self.bijou.check_writable()?;
+        self.bijou.db.key(self.key(key)).delete()
+    }
+
+    /// Returns an iterator over the keys and values of the namespace,
+    /// ordered by key.
+    pub fn iter(&self) -> impl Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + '_ {
+        self.bijou
+            .db
+            .0
+            .iterator(IteratorMode::From(&self.prefix, Direction::Forward))
+            .map(|entry| entry.wrap())
+            .take_while(|entry| {
+                entry
+                    .as_ref()
+                    .map_or(true, |(key, _)| key.starts_with(&self.prefix))
+            })
+            .map(|entry| {
+                entry.map(|(key, value)| (key[self.prefix.len()..].to_vec(), value.into_vec()))
+            })
+    }
+}
+
+impl Bijou {
+    /// Returns the key-value store of the application `namespace`
+    /// (e.g. `com.example`), which must be 1 to 255 bytes long. See
+    /// [`AppStorage`].
+    pub fn app_storage(&self, namespace: &str) -> Result<AppStorage<'_>> {
+        if namespace.is_empty() || namespace.len() > u8::MAX as usize {
+            bail!(@InvalidInput "namespace must be 1 to 255 bytes long: {namespace:?}");
+        }
+        // length-prefixed, so that no namespace is a prefix of another
+        let mut prefix = consts::Root::App.as_ref().to_vec();
+        prefix.push(namespace.len() as u8);
+        prefix.extend_from_slice(namespace.as_bytes());
+        Ok(AppStorage {
+            bijou: self,
+            prefix,
+        })
+    }
+}
@@ -0,0 +1,299 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::{scrub::Throttle, TaskFailure, TaskKind, TaskState};
+use crate::{
+    config::ScrubSchedule,
+    db::{consts, DatabaseKey},
+    error::ErrorOrigin,
+    Bijou, ErrorKind, Result,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+use tracing::{info, warn};
+
+/// How often to check whether the Bijou has become idle.
+const IDLE_POLL: Duration = Duration::from_secs(1);
+/// How long to wait before retrying after an error.
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// The progress of the background scrubber, kept in the database.
+/// See [`Bijou::spawn_background_scrub`].
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackgroundScrubState {
+    /// The scrub task of the pass in progress, which is resumed when
+    /// the scrubber restarts.
+    pub task: Option<u64>,
+    /// When the pass in progress, or the last one, started.
+    pub started: Option<DateTime<Utc>>,
+    /// The final state of the last finished pass, including the
+    /// files that failed it.
+    pub last: Option<TaskState>,
+}
+
+/// A running background scrubber, created by
+/// [`Bijou::spawn_background_scrub`]. It is stopped when dropped.
+pub struct BackgroundScrub {
+    stop: Arc<Stop>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for BackgroundScrub {
+    fn drop(&mut self) {
+        self.stop.stop();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[derive(Default)]
+struct Stop {
+    stopped: Arc<AtomicBool>,
+    lock: Mutex<()>,
+    cond: Condvar,
+}
+
+impl Stop {
+    fn stop(&self) {
+        let _guard = self.lock.lock().unwrap();
+        self.stopped.store(true, Ordering::Relaxed);
+        self.cond.notify_all();
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    /// Waits for `timeout`, returning whether the scrubber is stopped.
+    fn wait(&self, timeout: Duration) -> bool {
+        let guard = self.lock.lock().unwrap();
+        let _ = self
+            .cond
+            .wait_timeout_while(guard, timeout, |_| !self.is_stopped())
+            .unwrap();
+        self.is_stopped()
+    }
+}
+
+/// How a pass ended.
+#[allow(clippy::large_enum_variant)]
+enum Pass {
+    Finished(TaskState),
+    /// The task is cancelled, so the pass is skipped.
+    Cancelled,
+    Stopped,
+}
+
+struct Worker {
+    bijou: Arc<Bijou>,
+    schedule: ScrubSchedule,
+    stop: Arc<Stop>,
+}
+
+impl Worker {
+    fn run(&self) {
+        loop {
+            let delay = match self.step() {
+                Ok(Some(delay)) => delay,
+                Ok(None) => return,
+                Err(err) => {
+                    err.context("background scrub failed")
+                        .report(ErrorOrigin::Scrub);
+                    RETRY_DELAY
+                }
+            };
+            if self.stop.wait(delay) {
+                return;
+            }
+        }
+    }
+
+    /// Runs or resumes a pass if one is due. Returns how long to wait
+    /// before the next step, or `None` if stopped.
+    fn step(&self) -> Result<Option<Duration>> {
+        let bijou = &self.bijou;
+        let key = bijou.background_scrub_key();
+        let mut state = key.get()?.unwrap_or_default();
+        let id = match state.task {
+            Some(id) => id,
+            None => {
+                let now = bijou.clock.now();
+                if let Some(started) = state.started {
+                    let due = started + chrono::Duration::seconds(self.schedule.interval as i64);
+                    if due > now {
+                        return Ok(Some((due - now).to_std().unwrap_or_default()));
+                    }
+                }
+                let id = bijou.start_task(TaskKind::Scrub {
+                    quick: self.schedule.quick,
+                })?;
+                state.task = Some(id);
+                state.started = Some(now);
+                key.put(&state)?;
+                id
+            }
+        };
+
+        if !bijou.running_tasks.lock().unwrap().insert(id) {
+            warn!(id, "background scrub task is run elsewhere");
+            return Ok(Some(RETRY_DELAY));
+        }
+        let result = self.run_pass(id);
+        bijou.running_tasks.lock().unwrap().remove(&id);
+        match result? {
+            Pass::Finished(last) => {
+                info!(
+                    id,
+                    files = last.files,
+                    failures = last.failures.len(),
+                    "background scrub finished"
+                );
+                state.last = Some(last);
+            }
+            Pass::Cancelled => info!(id, "background scrub cancelled"),
+            Pass::Stopped => return Ok(None),
+        }
+        state.task = None;
+        key.put(&state)?;
+        Ok(Some(Duration::ZERO))
+    }
+
+    fn run_pass(&self, id: u64) -> Result<Pass> {
+        let bijou = &self.bijou;
+        let (mut state, mut checkpoints) = match bijou.load_task(id) {
+            Ok(loaded) => loaded,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Pass::Cancelled),
+            Err(err) => return Err(err),
+        };
+        let mut throttle =
+            Throttle::new(Some(self.schedule.rate_limit)).stop_on(Arc::clone(&self.stop.stopped));
+
+        let mut files = bijou.iter_files();
+        if let Some(cursor) = state.cursor {
+            files.after(cursor);
+        }
+        for meta in files {
+            let meta = match meta {
+                Ok(meta) => meta,
+                Err(err) => {
+                    warn!("failed to enumerate files: {err}");
+                    continue;
+                }
+            };
+
+            // only scrub while idle
+            let mut paused = false;
+            while !bijou.is_idle() {
+                paused = true;
+                if self.stop.wait(IDLE_POLL) {
+                    break;
+                }
+            }
+            if paused {
+                throttle.restart();
+            }
+
+            let result = if self.stop.is_stopped() {
+                Ok(())
+            } else {
+                bijou.scrub_meta(&meta, self.schedule.quick, &mut throttle)
+            };
+            if self.stop.is_stopped() {
+                return match checkpoints.save(&mut state, &throttle, true) {
+                    Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+                    _ => Ok(Pass::Stopped),
+                };
+            }
+            if let Err(err) = result {
+                let err = err.context(format!("background scrub failed at {}", meta.id));
+                err.report(ErrorOrigin::Scrub);
+                state.failures.push(TaskFailure {
+                    id: meta.id,
+                    error: err.to_string(),
+                });
+            }
+            state.files += 1;
+            state.cursor = Some(meta.id);
+            match checkpoints.save(&mut state, &throttle, false) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Pass::Cancelled),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(Pass::Finished(checkpoints.finish(state, &throttle)?))
+    }
+}
+
+impl Bijou {
+    fn background_scrub_key(&self) -> DatabaseKey<BackgroundScrubState> {
+        self.db.key(consts::Root::Scrub).typed()
+    }
+
+    /// Whether no file is opened.
+    fn is_idle(&self) -> bool {
+        self.file_open_counts
+            .iter()
+            .all(|count| count.load(Ordering::Relaxed) == 0)
+    }
+
+    /// Returns the progress of the background scrubber.
+    pub fn background_scrub_state(&self) -> Result<BackgroundScrubState> {
+        Ok(self.background_scrub_key().get()?.unwrap_or_default())
+    }
+
+    /// Spawns a thread verifying files in the background as
+    /// configured by [`Config::background_scrub`]. Returns `None` if
+    /// that is disabled, or if the Bijou is opened read-only.
+    ///
+    /// Each pass over the Bijou is run as a scrub task (see
+    /// [`TaskKind::Scrub`]), so that it is resumed after restarts.
+    /// Cancelling the task skips the pass. Files are only verified
+    /// while no file is opened, and those failing the pass are
+    /// recorded in [`BackgroundScrubState::last`], as well as
+    /// reported to the error hook.
+    ///
+    /// The scrubber keeps the Bijou alive until it is dropped.
+    ///
+    /// [`Config::background_scrub`]: crate::Config::background_scrub
+    pub fn spawn_background_scrub(self: &Arc<Self>) -> Option<BackgroundScrub> {
+        let schedule = self.config.background_scrub.clone()?;
+        if self.read_only {
+            return None;
+        }
+        info!(?schedule, "starting background scrub");
+        let stop = Arc::<Stop>::default();
+        let worker = Worker {
+            bijou: Arc::clone(self),
+            schedule,
+            stop: Arc::clone(&stop),
+        };
+        let thread = std::thread::spawn(move || worker.run());
+        Some(BackgroundScrub {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
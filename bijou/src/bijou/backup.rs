@@ -0,0 +1,285 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::{embedded_db, prepare_empty_dir, with_workers, KeyStore, TaskFailure};
+use crate::{
+    bail, error::ResultExt, fs::RawFileSystem, Bijou, Context, FileId, FileKind, FileMeta, Result,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{io, path::Path as StdPath, sync::Mutex};
+use tracing::{info, warn};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BackupManifest {
+    version: u32,
+    created_at: DateTime<Utc>,
+    /// Whether only the metadata is backed up, see
+    /// [`Bijou::export_meta`].
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    metadata_only: bool,
+    files: Vec<BackupEntry>,
+}
+
+#[derive(Serialize)]
+struct BackupEntry {
+    path: String,
+    size: u64,
+}
+
+fn collect_files(root: &StdPath, dir: &StdPath, files: &mut Vec<BackupEntry>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let meta = entry.metadata()?;
+        if meta.is_dir() {
+            collect_files(root, &path, files)?;
+        } else {
+            files.push(BackupEntry {
+                path: path
+                    .strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned(),
+                size: meta.len(),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn copy_dir(from: &StdPath, to: &StdPath) -> io::Result<()> {
+    std::fs::create_dir(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let to = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &to)?;
+        } else {
+            std::fs::copy(entry.path(), to)?;
+        }
+    }
+    Ok(())
+}
+
+/// The result of [`Bijou::reconcile`].
+#[derive(Debug, Default, Serialize)]
+pub struct ReconcileReport {
+    /// Number of regular files checked.
+    pub files: u64,
+    /// Files whose content was missing from the storage, which are
+    /// now empty.
+    pub recreated: Vec<FileId>,
+    /// Files whose content failed verification, which are now
+    /// truncated to zero.
+    pub truncated: Vec<FileId>,
+    /// Files that could not be repaired.
+    pub failures: Vec<TaskFailure>,
+}
+
+impl ReconcileReport {
+    /// Whether the storage agreed with the metadata.
+    pub fn is_clean(&self) -> bool {
+        self.recreated.is_empty() && self.truncated.is_empty() && self.failures.is_empty()
+    }
+}
+
+impl Bijou {
+    /// Creates a point-in-time backup of this Bijou at `dest`.
+    ///
+    /// `dest` should either be an empty directory or non-existent.
+    /// The backup is a Bijou itself, and can be opened with the same
+    /// password. A `manifest.json` listing all backed up files is
+    /// written alongside.
+    ///
+    /// Metadata is captured atomically using a database checkpoint,
+    /// and modifications to file contents are paused until raw files
+    /// are copied. This can be called while the Bijou is in use (e.g.
+    /// mounted).
+    pub fn backup(&self, dest: impl AsRef<StdPath>) -> Result<()> {
+        let dest = dest.as_ref();
+        info!("backing up Bijou to {}", dest.display());
+        prepare_empty_dir(dest)?;
+
+        {
+            let _guard = self.raw_lock.write().unwrap();
+            self.raw_fs.flush().context("failed to flush storage")?;
+            self.db.checkpoint(dest.join("db"))?;
+            self.raw_fs
+                .backup(&dest.join("data"))
+                .context("failed to back up storage")?;
+        }
+
+        // embedded ones are in the storage, which is backed up above
+        if self.embedded.is_none() {
+            for name in ["keystore.json", "config.json"] {
+                std::fs::copy(self.path.join(name), dest.join(name))
+                    .with_context(|| format!("failed to copy {name}"))?;
+            }
+        }
+
+        self.save_manifest(dest, false)
+    }
+
+    fn save_manifest(&self, dest: &StdPath, metadata_only: bool) -> Result<()> {
+        let mut files = Vec::new();
+        collect_files(dest, dest, &mut files).context("failed to list backed up files")?;
+        let manifest = BackupManifest {
+            version: 0,
+            created_at: self.clock.now(),
+            metadata_only,
+            files,
+        };
+        (|| {
+            serde_json::to_writer_pretty(
+                std::fs::File::create(dest.join("manifest.json")).wrap()?,
+                &manifest,
+            )
+            .wrap()
+        })()
+        .context("failed to save manifest.json")
+    }
+
+    /// Backs up only the metadata of this Bijou to `dest`, i.e. its
+    /// database, key store and config, but none of the file contents.
+    ///
+    /// This is usually tiny, and can thus be taken more often than
+    /// full backups (see [`Bijou::backup`]). It's consistent the same
+    /// way, and can be restored with [`Bijou::import_meta`]. Embedded
+    /// Bijous keep their metadata with the data, and are not
+    /// supported.
+    pub fn export_meta(&self, dest: impl AsRef<StdPath>) -> Result<()> {
+        if self.embedded.is_some() {
+            bail!(@Unsupported "embedded Bijou keeps its metadata with the data");
+        }
+        let dest = dest.as_ref();
+        info!("exporting metadata to {}", dest.display());
+        prepare_empty_dir(dest)?;
+
+        {
+            // so that the metadata does not refer to unflushed data
+            let _guard = self.raw_lock.write().unwrap();
+            self.raw_fs.flush().context("failed to flush storage")?;
+            self.db.checkpoint(dest.join("db"))?;
+        }
+        for name in ["keystore.json", "config.json"] {
+            std::fs::copy(self.path.join(name), dest.join(name))
+                .with_context(|| format!("failed to copy {name}"))?;
+        }
+
+        self.save_manifest(dest, true)
+    }
+
+    /// Replaces the metadata of the Bijou at `path` with a backup made
+    /// by [`Bijou::export_meta`], keeping its data. The Bijou must
+    /// not be opened.
+    ///
+    /// The replaced database is moved to `db.old`, which must not
+    /// exist. Since the data is most likely older or newer than the
+    /// imported metadata, [`Bijou::reconcile`] should be run next.
+    pub fn import_meta(path: impl AsRef<StdPath>, backup: impl AsRef<StdPath>) -> Result<()> {
+        let (path, backup) = (path.as_ref(), backup.as_ref());
+        info!("importing metadata from {}", backup.display());
+
+        if embedded_db(path, true)?.is_some() {
+            bail!(@Unsupported "embedded Bijou keeps its metadata with the data");
+        }
+        let current = KeyStore::load(path)?;
+        let imported = KeyStore::load(backup)?;
+        if current.uuid != imported.uuid {
+            bail!(@Mismatch "backup belongs to Bijou {:?}, not {:?}", imported.uuid, current.uuid);
+        }
+        let old = path.join("db.old");
+        if old.exists() {
+            bail!(@AlreadyExists "{} already exists", old.display());
+        }
+
+        let staging = path.join("db.import");
+        if staging.exists() {
+            std::fs::remove_dir_all(&staging).context("failed to remove db.import")?;
+        }
+        copy_dir(&backup.join("db"), &staging).context("failed to copy database")?;
+        std::fs::rename(path.join("db"), &old).context("failed to move database")?;
+        std::fs::rename(&staging, path.join("db")).context("failed to move database")?;
+        for name in ["keystore.json", "config.json"] {
+            // replaced atomically, like when saving them
+            let temp = path.join(format!("{name}.tmp"));
+            (|| {
+                std::fs::copy(backup.join(name), &temp).wrap()?;
+                std::fs::rename(&temp, path.join(name)).wrap()
+            })()
+            .with_context(|| format!("failed to copy {name}"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Repairs regular files whose content in the storage disagrees
+    /// with the metadata, e.g. after [`Bijou::import_meta`] with an
+    /// older or newer copy of the data.
+    ///
+    /// Files with missing content are recreated empty, and files
+    /// failing verification are truncated to zero, so that the Bijou
+    /// is consistent again. Their IDs are reported so that they can
+    /// be restored from elsewhere. Content that no file refers to is
+    /// left in the storage.
+    ///
+    /// This verifies every file like [`Bijou::scrub`] does, with one
+    /// thread per CPU core, and should be run while no files are
+    /// opened.
+    pub fn reconcile(&self) -> Result<ReconcileReport> {
+        self.check_writable()?;
+        info!("reconciling storage with metadata");
+
+        let report = Mutex::new(ReconcileReport::default());
+        let reconcile = |meta: FileMeta| {
+            let result = self.reconcile_file(&meta, &report);
+            let mut report = report.lock().unwrap();
+            report.files += 1;
+            if let Err(error) = result {
+                warn!(id = %meta.id, "failed to reconcile file: {error}");
+                report.failures.push(TaskFailure {
+                    id: meta.id,
+                    error: error.to_string(),
+                });
+            }
+        };
+        with_workers(0, reconcile, |queue| -> Result<()> {
+            // the content of files may be missing, which is what this
+            // is about to fix
+            for meta in self.iter_files().kind(FileKind::File).stored() {
+                queue(meta?);
+            }
+            Ok(())
+        })?;
+        Ok(report.into_inner().unwrap())
+    }
+
+    fn reconcile_file(&self, meta: &FileMeta, report: &Mutex<ReconcileReport>) -> Result<()> {
+        let content = meta.content_id();
+        if !self.raw_fs.exists(content)? {
+            let _raw_guard = self.raw_lock.read().unwrap();
+            self.raw_fs.create(content)?;
+            warn!(id = %meta.id, "recreated missing file content");
+            report.lock().unwrap().recreated.push(meta.id);
+        } else if let Err(err) = self.scrub_file_with(meta.id, false, &mut |_| Ok(())) {
+            warn!(id = %meta.id, "truncating file failing verification: {err}");
+            self.set_len(meta.id, 0)?;
+            report.lock().unwrap().truncated.push(meta.id);
+        }
+        Ok(())
+    }
+}
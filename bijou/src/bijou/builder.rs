@@ -0,0 +1,444 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::{
+    bail,
+    config::{
+        ClusterNaming, DirTimePolicy, FileEncryption, FileStorage, NormalizationPolicy,
+        ScrubSchedule, TimeSource,
+    },
+    password::PasswordPolicy,
+    Bijou, Config, Limit, Result, SecretBytes,
+};
+use std::{
+    marker::PhantomData,
+    path::{Path as StdPath, PathBuf as StdPathBuf},
+};
+
+/// Layout of [`BijouBuilder`] keeping the key store and the config in
+/// files next to the data, which allows any storage.
+#[derive(Clone, Copy, Debug)]
+pub struct Separate;
+
+/// Layout of [`BijouBuilder`] keeping everything in RocksDB, see
+/// [`BijouBuilder::compact`].
+#[derive(Clone, Copy, Debug)]
+pub struct Embedded;
+
+/// Builder for creating a new Bijou, as an alternative to
+/// assembling a [`Config`] and calling [`Bijou::create`].
+///
+/// ```no_run
+/// # use bijou::BijouBuilder;
+/// BijouBuilder::new("vault")
+///     .paranoid()
+///     .block_size(8192)
+///     .create(b"password".to_vec())?;
+/// # Ok::<(), bijou::Error>(())
+/// ```
+///
+/// The layout `L` tracks whether the Bijou is embedded, so that
+/// storages it cannot be combined with are rejected at compile time:
+///
+/// ```compile_fail
+/// # use bijou::{config::FileStorage, BijouBuilder};
+/// BijouBuilder::new("vault")
+///     .compact()
+///     .storage(FileStorage::Local);
+/// ```
+///
+/// Values (e.g. a zero block size) and configs passed to
+/// [`BijouBuilder::from_config`] are still validated when creating.
+pub struct BijouBuilder<L = Separate> {
+    path: StdPathBuf,
+    config: Config,
+    ops_limit: Limit,
+    mem_limit: Limit,
+    policy: Option<Box<dyn PasswordPolicy>>,
+    read_only_password: Option<SecretBytes>,
+    throttle_unlock: bool,
+    layout: PhantomData<L>,
+}
+
+impl BijouBuilder {
+    /// Creates a builder with the default configuration.
+    pub fn new(path: impl Into<StdPathBuf>) -> Self {
+        Self::from_config(path, Config::default())
+    }
+
+    /// Creates a builder starting from the given configuration.
+    pub fn from_config(path: impl Into<StdPathBuf>, config: Config) -> Self {
+        Self {
+            path: path.into(),
+            config,
+            ops_limit: Limit::Moderate,
+            mem_limit: Limit::Moderate,
+            policy: None,
+            read_only_password: None,
+            throttle_unlock: false,
+            layout: PhantomData,
+        }
+    }
+
+    /// Preset for data directories synchronized to remote storages.
+    ///
+    /// Files are split into clusters to obfuscate file sizes and to
+    /// reduce the amount of data transferred on small changes, and
+    /// metadata is tracked in the database since remote storages
+    /// often do not preserve it.
+    pub fn remote(&mut self) -> &mut Self {
+        self.config.encrypt_file_name = true;
+        self.config.storage = FileStorage::Tracking {
+            inner: Box::new(FileStorage::Split {
+                inner: Box::new(FileStorage::Local),
+                cluster_size: 256,
+                naming: ClusterNaming::keyed(),
+            }),
+        };
+        self
+    }
+
+    /// Preset for Bijous kept in a single directory of opaque files,
+    /// e.g. to be synchronized as a blob.
+    ///
+    /// File contents are stored in RocksDB, along with the key store
+    /// and the config (see [`Config::embedded`]). The storage cannot
+    /// be changed afterwards.
+    pub fn compact(self) -> BijouBuilder<Embedded> {
+        let mut config = self.config;
+        config.storage = FileStorage::RocksDB;
+        config.embedded = true;
+        BijouBuilder {
+            path: self.path,
+            config,
+            ops_limit: self.ops_limit,
+            mem_limit: self.mem_limit,
+            policy: self.policy,
+            read_only_password: self.read_only_password,
+            throttle_unlock: self.throttle_unlock,
+            layout: PhantomData,
+        }
+    }
+
+    /// Sets the file storage type.
+    pub fn storage(&mut self, storage: FileStorage) -> &mut Self {
+        self.config.storage = storage;
+        self
+    }
+}
+
+impl<L> BijouBuilder<L> {
+    /// Preset favoring security over performance.
+    ///
+    /// Uses XChaCha20-Poly1305, encrypts file names and the
+    /// database, uses the strongest Argon2id limits and throttles
+    /// failed unlock attempts.
+    pub fn paranoid(&mut self) -> &mut Self {
+        self.config.file_encryption = FileEncryption::XChaCha20Poly1305IETF;
+        self.config.encrypt_db = true;
+        self.config.encrypt_file_name = true;
+        self.ops_limit = Limit::Sensitive;
+        self.mem_limit = Limit::Sensitive;
+        self.throttle_unlock = true;
+        self
+    }
+
+    /// Preset favoring performance over security.
+    ///
+    /// Uses AES-256-GCM with larger blocks, does not encrypt file
+    /// names and disables `getxattr`.
+    pub fn fast(&mut self) -> &mut Self {
+        self.config.file_encryption = FileEncryption::Aes256Gcm;
+        self.config.block_size = 16384;
+        self.config.encrypt_file_name = false;
+        self.config.disable_xattr_gets = true;
+        self.ops_limit = Limit::Interactive;
+        self.mem_limit = Limit::Interactive;
+        self
+    }
+
+    /// Sets the label of the Bijou.
+    ///
+    /// See [`Config::label`].
+    pub fn label(&mut self, label: impl Into<String>) -> &mut Self {
+        self.config.label = Some(label.into());
+        self
+    }
+
+    /// Sets the file encryption algorithm.
+    pub fn cipher(&mut self, cipher: FileEncryption) -> &mut Self {
+        self.config.file_encryption = cipher;
+        self
+    }
+
+    /// Sets the file encryption block size.
+    pub fn block_size(&mut self, block_size: u64) -> &mut Self {
+        self.config.block_size = block_size;
+        self
+    }
+
+    /// Sets whether to encrypt the database.
+    pub fn db_encryption(&mut self, enabled: bool) -> &mut Self {
+        self.config.encrypt_db = enabled;
+        self
+    }
+
+    /// Sets whether to encrypt file names.
+    pub fn filename_encryption(&mut self, enabled: bool) -> &mut Self {
+        self.config.encrypt_file_name = enabled;
+        self
+    }
+
+    /// Sets whether to use Unix permissions.
+    pub fn unix_perms(&mut self, enabled: bool) -> &mut Self {
+        self.config.unix_perms = enabled;
+        self
+    }
+
+    /// Sets whether to allow `getxattr` operations.
+    ///
+    /// See [`Config::disable_xattr_gets`].
+    pub fn xattr_gets(&mut self, enabled: bool) -> &mut Self {
+        self.config.disable_xattr_gets = !enabled;
+        self
+    }
+
+    /// Sets where times of files are taken from.
+    ///
+    /// See [`Config::time_source`].
+    pub fn time_source(&mut self, source: TimeSource) -> &mut Self {
+        self.config.time_source = source;
+        self
+    }
+
+    /// Sets how modification times of directories are updated.
+    ///
+    /// See [`Config::dir_time_policy`].
+    pub fn dir_time_policy(&mut self, policy: DirTimePolicy) -> &mut Self {
+        self.config.dir_time_policy = policy;
+        self
+    }
+
+    /// Sets the maximum size of files.
+    ///
+    /// See [`Config::max_file_size`].
+    pub fn max_file_size(&mut self, size: u64) -> &mut Self {
+        self.config.max_file_size = size;
+        self
+    }
+
+    /// Sets whether to collect statistics of the database.
+    ///
+    /// See [`Config::db_statistics`].
+    pub fn db_statistics(&mut self, enabled: bool) -> &mut Self {
+        self.config.db_statistics = enabled;
+        self
+    }
+
+    /// Sets what to do with names only differing in Unicode
+    /// normalization.
+    ///
+    /// See [`Config::normalization_duplicates`].
+    pub fn normalization_duplicates(&mut self, policy: NormalizationPolicy) -> &mut Self {
+        self.config.normalization_duplicates = policy;
+        self
+    }
+
+    /// Sets whether to keep an index from files to their paths.
+    ///
+    /// See [`Config::reverse_index`].
+    pub fn reverse_index(&mut self, enabled: bool) -> &mut Self {
+        self.config.reverse_index = enabled;
+        self
+    }
+
+    /// Sets the threshold in milliseconds beyond which block IO is
+    /// logged as slow.
+    ///
+    /// See [`Config::slow_op_threshold`].
+    pub fn slow_op_threshold(&mut self, threshold: Option<u64>) -> &mut Self {
+        self.config.slow_op_threshold = threshold;
+        self
+    }
+
+    /// Sets when and how fast files are verified in the background.
+    ///
+    /// See [`Config::background_scrub`].
+    pub fn background_scrub(&mut self, schedule: Option<ScrubSchedule>) -> &mut Self {
+        self.config.background_scrub = schedule;
+        self
+    }
+
+    /// Sets whether to keep the detected MIME types of files in an
+    /// xattr.
+    ///
+    /// See [`Config::sniff_mime_types`].
+    pub fn sniff_mime_types(&mut self, enabled: bool) -> &mut Self {
+        self.config.sniff_mime_types = enabled;
+        self
+    }
+
+    /// Sets whether to fall back to a degraded mode when the storage
+    /// is unreachable.
+    ///
+    /// See [`Config::offline_fallback`].
+    pub fn offline_fallback(&mut self, enabled: bool) -> &mut Self {
+        self.config.offline_fallback = enabled;
+        self
+    }
+
+    /// Sets the operation limit of Argon2id.
+    pub fn ops_limit(&mut self, limit: Limit) -> &mut Self {
+        self.ops_limit = limit;
+        self
+    }
+
+    /// Sets the memory limit of Argon2id.
+    pub fn mem_limit(&mut self, limit: Limit) -> &mut Self {
+        self.mem_limit = limit;
+        self
+    }
+
+    /// Sets the policy that the password must satisfy.
+    ///
+    /// See [`password`] for builtin policies.
+    ///
+    /// [`password`]: crate::password
+    pub fn password_policy(&mut self, policy: impl PasswordPolicy + 'static) -> &mut Self {
+        self.policy = Some(Box::new(policy));
+        self
+    }
+
+    /// Sets the read-only password, which is also checked against the
+    /// password policy.
+    ///
+    /// See [`Bijou::set_read_only_password`].
+    pub fn read_only_password(&mut self, password: impl Into<SecretBytes>) -> &mut Self {
+        self.read_only_password = Some(password.into());
+        self
+    }
+
+    /// Sets whether to throttle failed unlock attempts. Defaults to
+    /// `false`.
+    ///
+    /// Failed attempts are then recorded in `unlock.json` next to
+    /// the key store, and each attempt after several failures is
+    /// delayed exponentially, up to five minutes. This only slows
+    /// down brute-forcing through Bijou itself, since anyone with
+    /// write access to the storage can remove the record (or the
+    /// switch, which is kept unauthenticated in the key store).
+    pub fn throttle_unlock(&mut self, enabled: bool) -> &mut Self {
+        self.throttle_unlock = enabled;
+        self
+    }
+
+    /// Returns the configuration assembled so far.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Returns the path of the Bijou to create.
+    pub fn path(&self) -> &StdPath {
+        &self.path
+    }
+
+    /// Validates the configuration.
+    pub fn validate(&self) -> Result<()> {
+        if self.config.block_size == 0 {
+            bail!(@InvalidInput "block size must not be zero");
+        }
+        if self.config.embedded && self.config.storage != FileStorage::RocksDB {
+            bail!(@InvalidInput "embedded Bijou requires RocksDB storage");
+        }
+        validate_storage(&self.config.storage)
+    }
+
+    /// Validates the configuration and creates the Bijou.
+    ///
+    /// See [`Bijou::create`] for more details about `password`.
+    pub fn create(&self, password: impl Into<SecretBytes>) -> Result<()> {
+        self.validate()?;
+        let password = password.into();
+        if let Some(policy) = &self.policy {
+            policy.check(&password)?;
+            if let Some(read_only_password) = &self.read_only_password {
+                policy.check(read_only_password)?;
+            }
+        }
+        Bijou::create_inner(
+            &self.path,
+            password,
+            self.read_only_password.clone(),
+            self.config.clone(),
+            self.ops_limit,
+            self.mem_limit,
+            self.throttle_unlock,
+        )
+    }
+}
+
+fn validate_opendal() -> Result<()> {
+    if cfg!(feature = "opendal") {
+        Ok(())
+    } else {
+        bail!(@Unsupported "OpenDAL storage requires the `opendal` feature");
+    }
+}
+
+pub(super) fn validate_storage(storage: &FileStorage) -> Result<()> {
+    match storage {
+        FileStorage::Local | FileStorage::RocksDB => Ok(()),
+        FileStorage::Split {
+            inner,
+            cluster_size,
+            ..
+        } => {
+            if *cluster_size == 0 {
+                bail!(@InvalidInput "cluster size must not be zero");
+            }
+            let inner = match &**inner {
+                FileStorage::Tracking { inner } => &**inner,
+                inner => inner,
+            };
+            if let (1, FileStorage::OpenDAL { .. }) = (*cluster_size, inner) {
+                return validate_opendal();
+            }
+            validate_storage(inner)
+        }
+        FileStorage::Tracking { inner } => validate_storage(inner),
+        FileStorage::OpenDAL { strict, .. } => {
+            if *strict {
+                bail!(@InvalidInput "strict OpenDAL storage must be wrapped in split storage with cluster size 1");
+            }
+            validate_opendal()
+        }
+        FileStorage::Flaky {
+            inner,
+            failure_rate,
+            ..
+        } => {
+            if !cfg!(feature = "flaky") {
+                bail!(@Unsupported "flaky storage requires the `flaky` feature");
+            }
+            if !(0.0..=1.0).contains(failure_rate) {
+                bail!(@InvalidInput "failure rate must be between 0 and 1");
+            }
+            validate_storage(inner)
+        }
+        FileStorage::Migrating { .. } => {
+            bail!(@InvalidInput "migrating storage is set by migrations only")
+        }
+    }
+}
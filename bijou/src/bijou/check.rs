@@ -0,0 +1,500 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::{
+    scrub::{child_path, Throttle},
+    with_workers, ScrubProgress,
+};
+use crate::{
+    anyhow, error::ErrorKind, fs::DirItem, serde_ext, Bijou, Error, FileId, FileKind, Result,
+};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+/// Options for [`Bijou::check`].
+#[derive(Clone, Debug, Default)]
+pub struct CheckOptions {
+    /// Repairs the issues found where possible. See [`CheckIssue`]
+    /// for what is done to each.
+    pub repair: bool,
+    /// Only checks the metadata, without decrypting the content of
+    /// files.
+    pub skip_content: bool,
+    /// The number of threads verifying files, or `0` for one per CPU
+    /// core.
+    pub threads: usize,
+}
+
+/// An inconsistency found by [`Bijou::check`].
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum CheckIssue {
+    /// A directory entry pointing to a file that does not exist, e.g.
+    /// left by a crash. Repaired by removing the entry.
+    DanglingEntry { path: String, id: FileId },
+    /// A directory entry whose kind differs from that of the file.
+    /// Repaired by correcting the entry.
+    KindMismatch {
+        path: String,
+        id: FileId,
+        entry: FileKind,
+        actual: FileKind,
+    },
+    /// A link count differing from the number of entries pointing to
+    /// the file, counting `.` and the `..` of subdirectories for
+    /// directories. Repaired by correcting the count.
+    WrongLinkCount {
+        id: FileId,
+        recorded: u32,
+        actual: u32,
+    },
+    /// A directory that can't be read or that contains itself, a
+    /// regular file whose content fails verification or a symlink
+    /// without target. Not repaired, see [`Bijou::reconcile`] for
+    /// regular files.
+    Damaged {
+        path: String,
+        id: FileId,
+        #[serde(with = "serde_ext::display")]
+        error: Error,
+    },
+    /// Content in the storage that no file refers to. Repaired by
+    /// deleting it.
+    OrphanedContent { id: FileId },
+}
+
+/// Something found by [`Bijou::check_with`], reported as soon as it
+/// is found.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum CheckEvent {
+    /// An inconsistency, reported after it has been repaired if
+    /// [`CheckOptions::repair`] is set.
+    Issue(CheckIssue),
+    /// The progress so far, reported every few seconds.
+    Progress(ScrubProgress),
+}
+
+/// The result of [`Bijou::check`].
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckReport {
+    pub files: u64,
+    pub directories: u64,
+    pub symlinks: u64,
+    /// The issues found, which are only listed by [`Bijou::check`].
+    pub issues: Vec<CheckIssue>,
+    /// The number of issues found.
+    pub issue_count: u64,
+    /// The number of issues repaired, see [`CheckOptions::repair`].
+    pub repaired: u64,
+    /// Whether the storage was looked for orphaned content, which
+    /// requires it to be able to list its files. Only local storage
+    /// (optionally tracked) can.
+    pub orphans_checked: bool,
+    /// The number of files not reachable from the root, see
+    /// [`ScrubReport::unreachable_count`].
+    ///
+    /// [`ScrubReport::unreachable_count`]: crate::ScrubReport::unreachable_count
+    pub unreachable_count: u64,
+}
+
+impl CheckReport {
+    /// Whether the Bijou is consistent, i.e. no issues were found or
+    /// all of them were repaired.
+    pub fn is_ok(&self) -> bool {
+        self.issue_count == self.repaired
+    }
+}
+
+/// State shared by the threads of a check.
+struct Checker<'a> {
+    bijou: &'a Bijou,
+    options: &'a CheckOptions,
+    throttle: Mutex<Throttle>,
+    /// Files and symlinks with several links, with their recorded
+    /// link count and the number of entries found so far, so that
+    /// each is checked once. Others can only be reached once.
+    linked: Mutex<HashMap<FileId, (u32, u32)>>,
+    files: AtomicU64,
+    directories: AtomicU64,
+    symlinks: AtomicU64,
+    issues: AtomicU64,
+    repaired: AtomicU64,
+    last_progress: Mutex<Instant>,
+    on_event: &'a (dyn Fn(CheckEvent) + Sync),
+}
+
+impl Checker<'_> {
+    /// Interval between [`CheckEvent::Progress`] events.
+    const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+    /// Reports an issue, repairing it with `repair` if enabled.
+    fn issue(&self, issue: CheckIssue, repair: impl FnOnce(&Bijou) -> Result<()>) {
+        warn!("check found {issue:?}");
+        if self.options.repair {
+            match repair(self.bijou) {
+                Ok(()) => {
+                    self.repaired.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(err) => warn!("failed to repair {issue:?}: {err}"),
+            }
+        }
+        self.issues.fetch_add(1, Ordering::Relaxed);
+        (self.on_event)(CheckEvent::Issue(issue));
+    }
+
+    /// Reports a file that can't be repaired.
+    fn damaged(&self, id: FileId, path: String, error: Error) {
+        warn!(%id, "check failed at {path}: {error}");
+        self.issues.fetch_add(1, Ordering::Relaxed);
+        (self.on_event)(CheckEvent::Issue(CheckIssue::Damaged { path, id, error }));
+    }
+
+    fn progress(&self) -> ScrubProgress {
+        ScrubProgress {
+            files: self.files.load(Ordering::Relaxed),
+            directories: self.directories.load(Ordering::Relaxed),
+            symlinks: self.symlinks.load(Ordering::Relaxed),
+            bytes: self.throttle.lock().unwrap().bytes,
+        }
+    }
+
+    /// Reports the progress if the last report is old enough.
+    fn tick(&self) {
+        let Ok(mut last) = self.last_progress.try_lock() else {
+            return;
+        };
+        if last.elapsed() >= Self::PROGRESS_INTERVAL {
+            *last = Instant::now();
+            drop(last);
+            (self.on_event)(CheckEvent::Progress(self.progress()));
+        }
+    }
+
+    /// Walks through the tree from the root, passing files and
+    /// symlinks to `queue`.
+    fn walk(&self, queue: &dyn Fn((FileId, FileKind, String))) {
+        let mut dirs = vec![(FileId::ROOT, "/".to_owned(), 0)];
+        // the directories leading to the current one, so that loops
+        // are not followed
+        let mut ancestors = Vec::new();
+        while let Some((id, path, depth)) = dirs.pop() {
+            ancestors.truncate(depth);
+            if ancestors.contains(&id) {
+                self.damaged(id, path, anyhow!(@InvalidInput "directory contains itself"));
+                continue;
+            }
+            ancestors.push(id);
+            self.directories.fetch_add(1, Ordering::Relaxed);
+            if let Err(err) = self.check_dir(id, &path, depth, &mut dirs, queue) {
+                self.damaged(id, path, err);
+            }
+            self.tick();
+        }
+    }
+
+    /// Checks the entries of a directory at `depth`, pushing its
+    /// subdirectories to `dirs` and passing its other entries to
+    /// `queue`.
+    fn check_dir(
+        &self,
+        id: FileId,
+        path: &str,
+        depth: usize,
+        dirs: &mut Vec<(FileId, String, usize)>,
+        queue: &dyn Fn((FileId, FileKind, String)),
+    ) -> Result<()> {
+        let bijou = self.bijou;
+        let meta = bijou.get_meta(id)?;
+        let mut subdirs = 0;
+        for entry in bijou.read_dir(id)?.dots(false) {
+            let (name, item) = entry?;
+            let child = child_path(path, &name);
+            let child_meta = match bijou.get_meta(item.id) {
+                Ok(meta) => meta,
+                Err(err) if err.kind() == ErrorKind::NotFound => {
+                    self.issue(
+                        CheckIssue::DanglingEntry {
+                            path: child,
+                            id: item.id,
+                        },
+                        |bijou| bijou.remove_entry(id, &name, item.id),
+                    );
+                    continue;
+                }
+                Err(err) => {
+                    self.damaged(item.id, child, err);
+                    continue;
+                }
+            };
+            if child_meta.kind != item.kind {
+                let fixed = DirItem {
+                    id: item.id,
+                    kind: child_meta.kind,
+                };
+                self.issue(
+                    CheckIssue::KindMismatch {
+                        path: child.clone(),
+                        id: item.id,
+                        entry: item.kind,
+                        actual: child_meta.kind,
+                    },
+                    |bijou| bijou.replace_entry(id, &name, &fixed),
+                );
+            }
+
+            if child_meta.kind == FileKind::Directory {
+                subdirs += 1;
+                dirs.push((item.id, child, depth + 1));
+                continue;
+            }
+            if child_meta.nlinks > 1 {
+                let mut linked = self.linked.lock().unwrap();
+                let (_, links) = linked.entry(item.id).or_insert((child_meta.nlinks, 0));
+                *links += 1;
+                if *links > 1 {
+                    // hard link
+                    continue;
+                }
+            } else {
+                self.check_links(item.id, child_meta.nlinks, 1);
+            }
+            queue((item.id, child_meta.kind, child));
+        }
+
+        self.check_links(id, meta.nlinks, 2 + subdirs);
+        Ok(())
+    }
+
+    /// Checks a file or a symlink, on a worker thread.
+    fn check_leaf(&self, id: FileId, kind: FileKind, path: String) {
+        let result = match kind {
+            FileKind::File => {
+                self.files.fetch_add(1, Ordering::Relaxed);
+                if self.options.skip_content {
+                    Ok(())
+                } else {
+                    self.bijou
+                        .scrub_file_with(id, false, &mut |len| {
+                            self.throttle.lock().unwrap().consume(len)
+                        })
+                        .map(drop)
+                }
+            }
+            FileKind::Symlink => {
+                self.symlinks.fetch_add(1, Ordering::Relaxed);
+                self.bijou.read_link(id).map(drop)
+            }
+            FileKind::Directory => unreachable!(),
+        };
+        if let Err(err) = result {
+            self.damaged(id, path, err);
+        }
+        self.tick();
+    }
+
+    fn check_links(&self, id: FileId, recorded: u32, actual: u32) {
+        if recorded != actual {
+            self.issue(
+                CheckIssue::WrongLinkCount {
+                    id,
+                    recorded,
+                    actual,
+                },
+                |bijou| bijou.set_nlinks(id, actual),
+            );
+        }
+    }
+
+    /// Looks for content in the storage that no file refers to,
+    /// returning whether the storage can list its files.
+    ///
+    /// Content is stored under the ID of the file it belongs to, so
+    /// only the content shared by clones is passed in `cloned`.
+    fn check_orphans(&self, cloned: &HashSet<FileId>) -> Result<bool> {
+        let bijou = self.bijou;
+        // nothing can be created in the meantime
+        let _raw_guard = bijou.raw_lock.write().unwrap();
+        let result = bijou.raw_fs.list(&mut |id| {
+            if cloned.contains(&id) {
+                return Ok(());
+            }
+            let referenced = match bijou.get_key(id).get() {
+                Ok(meta) => {
+                    meta.is_some_and(|meta| meta.kind == FileKind::File && meta.content.is_none())
+                }
+                Err(err) => {
+                    warn!(%id, "failed to look up the file of stored content: {err}");
+                    true
+                }
+            };
+            if !referenced {
+                self.issue(CheckIssue::OrphanedContent { id }, |bijou| {
+                    bijou.raw_fs.unlink(id)
+                });
+            }
+            Ok(())
+        });
+        match result {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == ErrorKind::Unsupported => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl Bijou {
+    /// Removes the entry `name` of `parent` pointing to `file`.
+    fn remove_entry(&self, parent: FileId, name: &str, file: FileId) -> Result<()> {
+        let locks = self.file_lock.get_all([parent]);
+        let _guards = locks.write();
+        let mut batch = self.db.batch();
+        let key = self.child_key(self.get_key(parent), name)?;
+        self.delete_entry(&mut batch, parent, &key, file);
+        batch.commit()
+    }
+
+    /// Replaces the entry `name` of `parent` with `item`.
+    fn replace_entry(&self, parent: FileId, name: &str, item: &DirItem) -> Result<()> {
+        let locks = self.file_lock.get_all([parent]);
+        let _guards = locks.write();
+        let mut batch = self.db.batch();
+        let key = self.child_key(self.get_key(parent), name)?;
+        self.put_entry(&mut batch, parent, &key, item)?;
+        batch.commit()
+    }
+
+    fn set_nlinks(&self, id: FileId, nlinks: u32) -> Result<()> {
+        let key = self.get_key(id);
+        let mut meta = self.get_raw_meta(&key)?;
+        meta.nlinks = nlinks;
+        key.put(&meta)?;
+        self.dir_times.forget(id);
+        Ok(())
+    }
+
+    /// Walks through the whole Bijou and checks that its records
+    /// agree with each other, like `fsck`.
+    ///
+    /// This checks that every directory entry points to an existing
+    /// file of the same kind, that link counts match the entries,
+    /// that the content of every regular file passes verification
+    /// (unless [`CheckOptions::skip_content`] is set) and that the
+    /// storage holds no content that no file refers to. The issues
+    /// found are repaired if [`CheckOptions::repair`] is set.
+    ///
+    /// Issues are collected in the returned report. See
+    /// [`Bijou::check_with`] to receive them as they are found
+    /// instead.
+    ///
+    /// This should be run while the Bijou is not otherwise in use.
+    pub fn check(&self, options: &CheckOptions) -> Result<CheckReport> {
+        let issues = Mutex::new(Vec::new());
+        let mut report = self.check_with(options, &|event| {
+            if let CheckEvent::Issue(issue) = event {
+                issues.lock().unwrap().push(issue);
+            }
+        })?;
+        report.issues = issues.into_inner().unwrap();
+        Ok(report)
+    }
+
+    /// Same as [`Bijou::check`], but passes issues to `on_event` as
+    /// they are found, along with the progress every few seconds.
+    /// The list of issues in the returned report is left empty.
+    ///
+    /// Like [`Bijou::scrub_with`], directories are walked on the
+    /// current thread, while files are verified by
+    /// [`CheckOptions::threads`] workers. Memory usage does not grow
+    /// with the number of files, except for files with several hard
+    /// links and content shared by clones, which are remembered.
+    /// `on_event` is called from any of these threads.
+    pub fn check_with(
+        &self,
+        options: &CheckOptions,
+        on_event: &(dyn Fn(CheckEvent) + Sync),
+    ) -> Result<CheckReport> {
+        if options.repair {
+            self.check_writable()?;
+        }
+        info!(
+            repair = options.repair,
+            threads = options.threads,
+            "checking Bijou"
+        );
+
+        let checker = Checker {
+            bijou: self,
+            options,
+            throttle: Mutex::new(Throttle::new(None)),
+            linked: Mutex::default(),
+            files: AtomicU64::new(0),
+            directories: AtomicU64::new(0),
+            symlinks: AtomicU64::new(0),
+            issues: AtomicU64::new(0),
+            repaired: AtomicU64::new(0),
+            last_progress: Mutex::new(Instant::now()),
+            on_event,
+        };
+        with_workers(
+            options.threads,
+            |(id, kind, path): (FileId, FileKind, String)| checker.check_leaf(id, kind, path),
+            |queue| checker.walk(queue),
+        );
+        let linked = std::mem::take(&mut *checker.linked.lock().unwrap());
+        for (id, (recorded, actual)) in linked {
+            checker.check_links(id, recorded, actual);
+        }
+
+        let mut total = 0u64;
+        let mut cloned = HashSet::new();
+        let mut files = self.iter_files();
+        files.stored();
+        for meta in files {
+            match meta {
+                Ok(meta) => {
+                    total += 1;
+                    if let (FileKind::File, Some(content)) = (meta.kind, meta.content) {
+                        cloned.insert(content);
+                    }
+                }
+                Err(err) => warn!("failed to enumerate files: {err}"),
+            }
+        }
+        let orphans_checked = checker.check_orphans(&cloned)?;
+
+        let progress = checker.progress();
+        let reachable = progress.files + progress.directories + progress.symlinks;
+        Ok(CheckReport {
+            files: progress.files,
+            directories: progress.directories,
+            symlinks: progress.symlinks,
+            issues: Vec::new(),
+            issue_count: checker.issues.into_inner(),
+            repaired: checker.repaired.into_inner(),
+            orphans_checked,
+            unreachable_count: total.saturating_sub(reachable),
+        })
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::Bijou;
+use crate::{bail, Result};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+/// Minimum interval between probes of an unreachable storage.
+const PROBE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Whether the storage of a Bijou is unreachable. See
+/// [`Config::offline_fallback`].
+///
+/// [`Config::offline_fallback`]: crate::Config::offline_fallback
+#[derive(Default)]
+pub(super) struct Degraded {
+    active: AtomicBool,
+    /// When the storage was last probed.
+    probed: Mutex<Option<Instant>>,
+}
+
+impl Bijou {
+    /// Probes the storage, entering degraded mode if it is
+    /// unreachable.
+    pub(super) fn probe_storage(&self) {
+        *self.degraded.probed.lock().unwrap() = Some(self.clock.instant());
+        if let Err(err) = self.raw_fs.probe() {
+            warn!("storage is unreachable, entering degraded mode: {err}");
+            self.degraded.active.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Fails with [`ErrorKind::StorageUnavailable`] if this Bijou is
+    /// in degraded mode, probing the storage again if it has not been
+    /// probed for a while.
+    ///
+    /// [`ErrorKind::StorageUnavailable`]: crate::ErrorKind::StorageUnavailable
+    pub(super) fn check_storage(&self) -> Result<()> {
+        if !self.degraded.active.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        {
+            let mut probed = self.degraded.probed.lock().unwrap();
+            let now = self.clock.instant();
+            if probed.is_some_and(|probed| now.duration_since(probed) < PROBE_INTERVAL) {
+                bail!(@StorageUnavailable? "storage is unreachable");
+            }
+            *probed = Some(now);
+            if let Err(err) = self.raw_fs.probe() {
+                bail!(@StorageUnavailable? "storage is unreachable: {err}");
+            }
+        }
+        if self.degraded.active.swap(false, Ordering::Relaxed) {
+            info!("storage is reachable again, leaving degraded mode");
+        }
+        Ok(())
+    }
+
+    /// Returns whether this Bijou is in degraded mode, i.e. its
+    /// storage was unreachable when last probed, in which case the
+    /// storage is probed again if due. See
+    /// [`Config::offline_fallback`].
+    ///
+    /// [`Config::offline_fallback`]: crate::Config::offline_fallback
+    pub fn is_degraded(&self) -> bool {
+        self.check_storage().is_err()
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::{
+    db::consts,
+    format::{ContentPin, FileClusters, FileRecords, TrackingMeta},
+    Bijou, FileId, FileKind, Result,
+};
+use std::collections::HashMap;
+
+impl Bijou {
+    /// Returns all (decrypted) database records of a file, for
+    /// debugging purposes.
+    ///
+    /// See [`format`] for details about these records.
+    ///
+    /// [`format`]: crate::format
+    pub fn dump_records(&self, id: FileId) -> Result<FileRecords> {
+        // make sure cached records are persisted
+        self.raw_fs.flush()?;
+
+        let key = self.get_key(id);
+        let meta = self.get_raw_meta(&key)?;
+        let content = meta.content_id();
+
+        let entries = if meta.kind == FileKind::Directory {
+            Some(self.read_dir(id)?.collect::<Result<_>>()?)
+        } else {
+            None
+        };
+        let symlink = if meta.kind == FileKind::Symlink {
+            Some(self.read_link(id)?)
+        } else {
+            None
+        };
+
+        let mut xattrs = HashMap::new();
+        for name in self.xattrs(id)? {
+            let value = self.xattr_key(id, &name).read_owned()?.unwrap_or_default();
+            xattrs.insert(name, value);
+        }
+
+        let content_key = self.db.key(consts::Root::File).derive(content);
+        let tracking = content_key
+            .clone()
+            .derive(consts::Derive::Tracking)
+            .typed::<TrackingMeta>()
+            .get()?;
+        let clusters = content_key
+            .derive(consts::Derive::Blocks)
+            .typed::<FileClusters>()
+            .get()?;
+        let refs = self
+            .db
+            .key(consts::Root::Refs)
+            .derive(content)
+            .typed::<u32>()
+            .get()?;
+        let pin = key
+            .derive(consts::Derive::Pin)
+            .typed::<ContentPin>()
+            .get()?;
+
+        Ok(FileRecords {
+            meta,
+            entries,
+            symlink,
+            xattrs,
+            tracking,
+            clusters,
+            refs,
+            pin,
+        })
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::{
+    bail, error::ResultExt, fs::time, Bijou, ExportStats, FileId, FileKind, FileMeta, OpenOptions,
+    Result,
+};
+use std::{
+    collections::HashMap,
+    fs::FileTimes,
+    io::Write,
+    path::{Path as StdPath, PathBuf as StdPathBuf},
+};
+use tracing::info;
+
+fn file_times(meta: &FileMeta) -> FileTimes {
+    FileTimes::new()
+        .set_accessed(time::date_time_to_system_time(&meta.accessed))
+        .set_modified(time::date_time_to_system_time(&meta.modified))
+}
+
+struct Exporter<'a> {
+    bijou: &'a Bijou,
+    links: HashMap<FileId, StdPathBuf>,
+    buffer: Vec<u8>,
+    stats: ExportStats,
+}
+
+impl Exporter<'_> {
+    const BUFFER_SIZE: usize = 1 << 20;
+
+    fn export_dir(&mut self, id: FileId, dest: &StdPath) -> Result<()> {
+        let entries = self
+            .bijou
+            .read_dir(id)?
+            .dots(false)
+            .collect::<Result<Vec<_>>>()?;
+        for (name, item) in entries {
+            let path = dest.join(&name);
+            self.export(item.id, &path)
+                .map_err(|err| err.context(format!("failed to export {}", path.display())))?;
+        }
+        Ok(())
+    }
+
+    fn export(&mut self, id: FileId, dest: &StdPath) -> Result<()> {
+        let meta = self.bijou.get_meta(id)?;
+
+        if meta.kind != FileKind::Directory && meta.nlinks > 1 {
+            if let Some(original) = self.links.get(&id) {
+                if dest.symlink_metadata().is_err() {
+                    std::fs::hard_link(original, dest).wrap()?;
+                }
+                self.stats.hard_links += 1;
+                return Ok(());
+            }
+            self.links.insert(id, dest.to_owned());
+        }
+
+        match meta.kind {
+            FileKind::Directory => {
+                if !dest.is_dir() {
+                    std::fs::create_dir(dest).wrap()?;
+                }
+                self.export_dir(id, dest)?;
+                self.stats.directories += 1;
+            }
+            FileKind::Symlink => {
+                if dest.symlink_metadata().is_err() {
+                    let target = self.bijou.read_link(id)?;
+                    #[cfg(unix)]
+                    std::os::unix::fs::symlink(target, dest).wrap()?;
+                    #[cfg(not(unix))]
+                    bail!(@Unsupported "exporting symlink to {target}");
+                }
+                self.stats.symlinks += 1;
+                // times and permissions of symlinks are not preserved
+                return self.export_xattrs(id, dest);
+            }
+            FileKind::File => {
+                if self.is_exported(&meta, dest) {
+                    self.stats.skipped += 1;
+                } else {
+                    self.export_file(&meta, dest)?;
+                    self.stats.files += 1;
+                }
+            }
+        }
+
+        self.export_xattrs(id, dest)?;
+        std::fs::File::open(dest)
+            .and_then(|file| file.set_times(file_times(&meta)))
+            .wrap()?;
+        #[cfg(unix)]
+        if let Some(perms) = &meta.perms {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(dest, std::fs::Permissions::from_mode(perms.mode as u32))
+                .wrap()?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks if the file has been exported by an interrupted run.
+    fn is_exported(&self, meta: &FileMeta, dest: &StdPath) -> bool {
+        let Ok(std) = dest.symlink_metadata() else {
+            return false;
+        };
+        std.is_file()
+            && std.len() == meta.size
+            && std.modified().ok() == Some(time::date_time_to_system_time(&meta.modified))
+    }
+
+    fn export_file(&mut self, meta: &FileMeta, dest: &StdPath) -> Result<()> {
+        let input = self
+            .bijou
+            .open_file_direct(meta.id, OpenOptions::read_only())?;
+        if dest.symlink_metadata().is_ok() {
+            // partially exported, possibly read-only
+            std::fs::remove_file(dest).wrap()?;
+        }
+        let mut output = std::fs::File::create(dest).wrap()?;
+        self.buffer.resize(Self::BUFFER_SIZE, 0);
+        let mut offset = 0;
+        loop {
+            // every block is authenticated while being decrypted
+            let len = input.read(&mut self.buffer, offset)?;
+            if len == 0 {
+                break;
+            }
+            output.write_all(&self.buffer[..len as usize]).wrap()?;
+            offset += len;
+        }
+        if offset != meta.size {
+            bail!(@CryptoError "file size mismatch: expected {}, read {offset}", meta.size);
+        }
+        output.sync_all().wrap()?;
+        self.stats.bytes += offset;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn export_xattrs(&self, id: FileId, dest: &StdPath) -> Result<()> {
+        for name in self.bijou.xattrs(id)? {
+            if let Some(value) = self.bijou.xattr_key(id, &name).read_owned()? {
+                xattr::set(dest, &name, &value).wrap()?;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn export_xattrs(&self, _id: FileId, _dest: &StdPath) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Bijou {
+    /// Decrypts the contents of the directory `src` into `dest` in
+    /// the local filesystem, creating `dest` if needed.
+    ///
+    /// Every block is authenticated while being decrypted, and
+    /// permissions, times, symlinks, hard links and xattrs are
+    /// preserved where supported.
+    ///
+    /// Files that are already exported (with the same size and
+    /// modification time) are skipped, so an interrupted export
+    /// can be resumed by calling this again.
+    pub fn export(&self, src: FileId, dest: impl AsRef<StdPath>) -> Result<ExportStats> {
+        let dest = dest.as_ref();
+        info!("exporting {src} to {}", dest.display());
+
+        if !dest.is_dir() {
+            std::fs::create_dir_all(dest).wrap()?;
+        }
+        let mut exporter = Exporter {
+            bijou: self,
+            links: HashMap::new(),
+            buffer: Vec::new(),
+            stats: ExportStats::default(),
+        };
+        exporter.export_dir(src, dest)?;
+
+        Ok(exporter.stats)
+    }
+}
@@ -0,0 +1,64 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::{Bijou, FileId, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+
+/// Times of a file that Bijou does not track itself, kept in xattrs
+/// so that they survive copies on platforms that have them.
+///
+/// The values are RFC 3339 timestamps, so they can also be read and
+/// set through the xattrs directly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ExtraTime {
+    /// The creation (birth) time, `crtime` on macOS.
+    Creation,
+    /// The time of the last backup, `bkuptime` on macOS.
+    Backup,
+}
+
+impl ExtraTime {
+    /// Returns the xattr in which this time is kept.
+    pub fn xattr(self) -> &'static str {
+        match self {
+            Self::Creation => "user.bijou.crtime",
+            Self::Backup => "user.bijou.bkuptime",
+        }
+    }
+}
+
+impl Bijou {
+    /// Returns the given extra time of a file, or `None` if it was
+    /// never set or its xattr doesn't hold a valid timestamp.
+    ///
+    /// Like [`Bijou::mime_type`], this works even if xattr gets are
+    /// disabled.
+    pub fn extra_time(&self, id: FileId, kind: ExtraTime) -> Result<Option<DateTime<Utc>>> {
+        let key = self.xattr_key(id, kind.xattr());
+        let Some(value) = key.read()? else {
+            return Ok(None);
+        };
+        Ok(std::str::from_utf8(&value)
+            .ok()
+            .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+            .map(|time| time.with_timezone(&Utc)))
+    }
+
+    /// Sets the given extra time of a file.
+    pub fn set_extra_time(&self, id: FileId, kind: ExtraTime, time: DateTime<Utc>) -> Result<()> {
+        let value = time.to_rfc3339_opts(SecondsFormat::AutoSi, true);
+        self.set_xattr(id, kind.xattr(), value.as_bytes())
+    }
+}
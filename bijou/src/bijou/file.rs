@@ -13,7 +13,7 @@
 // limitations under the License.
 //
 
-use crate::{path::Path, BijouFs, FileMeta, LowLevelFile, OpenOptions, Result};
+use crate::{low_level::LowLevelFile, path::Path, BijouFs, FileMeta, OpenOptions, Result};
 use std::io::{self, Read, Seek, Write};
 
 fn wrap<T>(f: impl FnOnce() -> Result<T>) -> io::Result<T> {
@@ -37,15 +37,14 @@ impl File {
     ///
     /// This corresponds to [`std::fs::File::open`].
     pub fn open(fs: &BijouFs, path: impl AsRef<Path>) -> Result<Self> {
-        OpenOptions::new().read(true).open(fs, path)
+        OpenOptions::read_only().open(fs, path)
     }
 
     /// Opens a file in write-only mode.
     ///
     /// This corresponds to [`std::fs::File::create`].
     pub fn create(fs: &BijouFs, path: impl AsRef<Path>) -> Result<Self> {
-        OpenOptions::new()
-            .write(true)
+        OpenOptions::writable()
             .create(true)
             .truncate(true)
             .open(fs, path)
@@ -55,9 +54,8 @@ impl File {
     ///
     /// This corresponds to [`std::fs::File::create_new`].
     pub fn create_new(fs: &BijouFs, path: impl AsRef<Path>) -> Result<Self> {
-        OpenOptions::new()
+        OpenOptions::writable()
             .read(true)
-            .write(true)
             .create_new(true)
             .open(fs, path)
     }
@@ -71,6 +69,13 @@ impl File {
     pub fn set_len(&mut self, size: u64) -> Result<()> {
         self.inner.set_len(size)
     }
+
+    /// Attempts to sync all written data to disk.
+    ///
+    /// This corresponds to [`std::fs::File::sync_data`].
+    pub fn sync_data(&self) -> Result<()> {
+        self.inner.sync()
+    }
 }
 
 impl Read for File {
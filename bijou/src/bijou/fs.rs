@@ -13,12 +13,13 @@
 // limitations under the License.
 //
 
+use super::Resolver;
 use crate::{
     bail,
     error::Context,
-    fs::{DirItem, FileKind},
+    fs::{DirItem, FileAttributes, FileKind, RenameFlags},
     path::{Component, Path, PathBuf},
-    Bijou, ErrorKind, File, FileId, FileMeta, Result,
+    Bijou, ErrorKind, File, FileId, FileMeta, HashAlgorithm, OpenOptions, Result,
 };
 use std::{
     io::{Read, Write},
@@ -26,26 +27,73 @@ use std::{
 };
 
 /// High level wrapper for [`Bijou`].
+///
+/// A `BijouFs` may be a view of a subtree of the Bijou, see
+/// [`BijouFs::subtree`].
 pub struct BijouFs {
     pub(crate) bijou: Arc<Bijou>,
+    /// The directory treated as `/` by this view.
+    pub(crate) root: FileId,
 }
 
 impl BijouFs {
     /// Create a new `BijouFs` for the given Bijou.
     pub fn new(bijou: Arc<Bijou>) -> Self {
-        Self { bijou }
+        Self {
+            bijou,
+            root: FileId::ROOT,
+        }
     }
 
     /// Returns the underlying [`Bijou`] instance.
+    ///
+    /// Note that this gives access to the whole Bijou, even if this
+    /// is a view of a subtree.
     pub fn inner(&self) -> &Bijou {
         &self.bijou
     }
 
+    /// Returns a view rooted at the directory at `path`.
+    ///
+    /// Paths given to the view are resolved within that directory
+    /// like `chroot(2)`: absolute paths (including symlink targets)
+    /// start from it, and `..` at it stays at it. This allows handing
+    /// a restricted view to untrusted code without checking every
+    /// path, as long as [`BijouFs::inner`] is not exposed as well.
+    pub fn subtree(&self, path: impl AsRef<Path>) -> Result<BijouFs> {
+        let root = self.resolve(path)?;
+        if self.bijou.get_meta(root)?.kind != FileKind::Directory {
+            bail!(@NotADirectory? "not a directory: {root}");
+        }
+        Ok(Self {
+            bijou: Arc::clone(&self.bijou),
+            root,
+        })
+    }
+
+    fn resolve(&self, path: impl AsRef<Path>) -> Result<FileId> {
+        self.bijou.resolve_in(self.root, path.as_ref())
+    }
+
+    fn resolve_parent_nonroot<'a>(&self, path: &'a Path) -> Result<(FileId, &'a str)> {
+        self.bijou.resolve_parent_nonroot_in(self.root, path)
+    }
+
+    /// Creates a copy-on-write clone of the file at `from` at `to`.
+    ///
+    /// See [`Bijou::clone_file`] for more details.
+    pub fn clone_file(&self, from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<()> {
+        let (parent, name) = self.resolve_parent_nonroot(to.as_ref())?;
+        self.bijou
+            .clone_file(self.resolve(from)?, parent, name, None)?;
+        Ok(())
+    }
+
     /// Creates a new, empty directory at the provided path.
     ///
     /// This corresponds to [`std::fs::create_dir`].
     pub fn create_dir(&self, path: impl AsRef<Path>) -> Result<()> {
-        let (parent, name) = self.bijou.resolve_parent_nonroot(path.as_ref())?;
+        let (parent, name) = self.resolve_parent_nonroot(path.as_ref())?;
         self.bijou
             .make_node(parent, name, FileKind::Directory, None, None)?;
         Ok(())
@@ -56,56 +104,58 @@ impl BijouFs {
     /// This corresponds to [`std::fs::create_dir_all`].
     pub fn create_dir_all(&self, path: impl AsRef<Path>) -> Result<()> {
         let path = path.as_ref();
-        let mut stack = vec![FileId::ROOT];
-        let mut comps = path.components();
-        let mut symlink_depth = 0;
-        while let Some(comp) = comps.next() {
-            match comp {
-                Component::RootDir => {
-                    stack.truncate(1);
-                }
-                Component::CurDir => {}
-                Component::ParentDir => {
-                    if stack.len() > 1 {
-                        stack.pop();
-                    }
-                }
-                Component::Normal(name) => {
-                    let parent = *stack.last().unwrap();
-                    let id = match self.bijou.resolve_inner(
-                        stack.clone(),
-                        Path::new(name),
-                        &mut symlink_depth,
-                    ) {
-                        Ok(id) => {
-                            let meta = self.bijou.get_meta(id)?;
-                            if meta.kind == FileKind::Directory {
-                                id
-                            } else {
-                                bail!(@NotADirectory "`{}` is not a directory", &path.as_str()[..path.as_str().len() - comps.as_path().as_str().len()]);
-                            }
-                        }
-                        Err(err) if err.kind() == ErrorKind::NotFound => {
-                            self.bijou
-                                .make_node(parent, name, FileKind::Directory, None, None)?
-                                .id
-                        }
-                        Err(err) => return Err(err),
-                    };
-                    stack.push(id);
+        path.check()?;
+        let mut resolver = Resolver::with_root(&self.bijou, self.root);
+        for comp in path.components() {
+            let Component::Normal(name) = comp else {
+                resolver.walk(Path::new(comp.as_str()), true)?;
+                continue;
+            };
+            match resolver.walk(Path::new(name), true) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::NotFound => {
+                    let id = self
+                        .bijou
+                        .make_node(resolver.current(), name, FileKind::Directory, None, None)?
+                        .id;
+                    resolver.push(id);
                 }
+                Err(err) => return Err(err),
             }
         }
         Ok(())
     }
 
+    /// Atomically swaps the files (or directories) at the two paths,
+    /// which must both exist.
+    ///
+    /// This is useful for safe saves: write the new content to a
+    /// temporary file, exchange it with the original, then remove
+    /// the temporary file (now holding the old content).
+    ///
+    /// See [`RenameFlags::EXCHANGE`].
+    pub fn exchange(&self, a: impl AsRef<Path>, b: impl AsRef<Path>) -> Result<()> {
+        let (parent, name) = self.resolve_parent_nonroot(a.as_ref())?;
+        let (new_parent, new_name) = self.resolve_parent_nonroot(b.as_ref())?;
+        self.bijou
+            .rename_with_flags(parent, name, new_parent, new_name, RenameFlags::EXCHANGE)?;
+        Ok(())
+    }
+
+    /// Hashes the plaintext contents of a file without reading it
+    /// into memory as a whole.
+    ///
+    /// See [`Bijou::hash_file`].
+    pub fn hash_file(&self, path: impl AsRef<Path>, algo: HashAlgorithm) -> Result<Vec<u8>> {
+        self.bijou.hash_file(self.resolve(path)?, algo)
+    }
+
     /// Creates a new hard link on the filesystem.
     ///
     /// This corresponds to [`std::fs::hard_link`].
     pub fn hard_link(&self, original: impl AsRef<Path>, link: impl AsRef<Path>) -> Result<()> {
-        let (parent, name) = self.bijou.resolve_parent_nonroot(link.as_ref())?;
-        self.bijou
-            .link(self.bijou.resolve(original)?, parent, name)?;
+        let (parent, name) = self.resolve_parent_nonroot(link.as_ref())?;
+        self.bijou.link(self.resolve(original)?, parent, name)?;
         Ok(())
     }
 
@@ -113,7 +163,7 @@ impl BijouFs {
     ///
     /// This corresponds to [`std::fs::metadata`].
     pub fn metadata(&self, path: impl AsRef<Path>) -> Result<FileMeta> {
-        self.bijou.get_meta(self.bijou.resolve(path)?)
+        self.bijou.get_meta(self.resolve(path)?)
     }
 
     /// Reads the entire contents of a file into a bytes vector.
@@ -136,13 +186,9 @@ impl BijouFs {
         &self,
         path: impl AsRef<Path>,
     ) -> Result<impl Iterator<Item = Result<(String, DirItem)>> + '_> {
-        let mut iter = self.bijou.read_dir(self.bijou.resolve(path.as_ref())?)?;
-        iter.reset();
-
-        Ok(iter.filter(|item| {
-            item.as_ref()
-                .map_or(true, |item| item.0 != "." && item.0 != "..")
-        }))
+        let mut iter = self.bijou.read_dir(self.resolve(path.as_ref())?)?;
+        iter.dots(false);
+        Ok(iter)
     }
 
     /// Reads a symbolic link, returning the file that the link points to.
@@ -150,7 +196,7 @@ impl BijouFs {
     /// This corresponds to [`std::fs::read_link`].
     pub fn read_link(&self, path: impl AsRef<Path>) -> Result<PathBuf> {
         let path = path.as_ref();
-        let target = self.bijou.read_link(self.bijou.resolve(path)?)?;
+        let target = self.bijou.read_link(self.resolve(path)?)?;
         Ok(path.join(Path::new(&target)))
     }
 
@@ -166,22 +212,35 @@ impl BijouFs {
     ///
     /// This corresponds to [`std::fs::remove_file`] and [`std::fs::remove_dir`].
     pub fn remove(&self, path: impl AsRef<Path>) -> Result<()> {
-        let (parent, name) = self.bijou.resolve_parent_nonroot(path.as_ref())?;
+        let (parent, name) = self.resolve_parent_nonroot(path.as_ref())?;
         self.bijou.unlink(parent, name)?;
         Ok(())
     }
 
+    /// Removes a file, making its content unrecoverable as far as
+    /// the storage allows.
+    ///
+    /// See [`Bijou::shred`] for details and guarantees.
+    pub fn shred(&self, path: impl AsRef<Path>) -> Result<()> {
+        let (parent, name) = self.resolve_parent_nonroot(path.as_ref())?;
+        self.bijou.shred(parent, name)
+    }
+
     fn remove_all_inner(&self, parent: FileId, name: &str) -> Result<()> {
         match self.bijou.unlink(parent, name) {
             Ok(_) => Ok(()),
             Err(err) if err.kind() == ErrorKind::NotEmpty => {
                 let current = self.bijou.lookup(parent, name)?;
-                for item in self.bijou.read_dir(current)? {
-                    let item = item?;
-                    if item.0 != "." && item.0 != ".." {
-                        self.remove_all_inner(current, &item.0)?;
-                    }
+                let names = self
+                    .bijou
+                    .read_dir(current)?
+                    .dots(false)
+                    .map(|item| item.map(|item| item.0))
+                    .collect::<Result<Vec<_>>>()?;
+                for child in names {
+                    self.remove_all_inner(current, &child)?;
                 }
+                self.bijou.unlink(parent, name)?;
                 Ok(())
             }
             Err(err) => Err(err),
@@ -192,7 +251,7 @@ impl BijouFs {
     ///
     /// This corresponds to [`std::fs::remove_dir_all`].
     pub fn remove_all(&self, path: impl AsRef<Path>) -> Result<()> {
-        let (parent, name) = self.bijou.resolve_parent_nonroot(path.as_ref())?;
+        let (parent, name) = self.resolve_parent_nonroot(path.as_ref())?;
         self.remove_all_inner(parent, name)
     }
 
@@ -200,17 +259,24 @@ impl BijouFs {
     ///
     /// This corresponds to [`std::fs::rename`].
     pub fn rename(&self, from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<()> {
-        let (parent, name) = self.bijou.resolve_parent_nonroot(from.as_ref())?;
-        let (new_parent, new_name) = self.bijou.resolve_parent_nonroot(to.as_ref())?;
+        let (parent, name) = self.resolve_parent_nonroot(from.as_ref())?;
+        let (new_parent, new_name) = self.resolve_parent_nonroot(to.as_ref())?;
         self.bijou.rename(parent, name, new_parent, new_name)?;
         Ok(())
     }
 
+    /// Sets the attribute flags (immutable, append-only, etc.) of a file.
+    ///
+    /// See [`FileAttributes`] for more details.
+    pub fn set_attributes(&self, path: impl AsRef<Path>, attributes: FileAttributes) -> Result<()> {
+        self.bijou.set_attributes(self.resolve(path)?, attributes)
+    }
+
     /// Creates a new symbolic link on the filesystem.
     ///
     /// This corresponds to [`std::fs::hard_link`].
     pub fn soft_link(&self, original: impl AsRef<Path>, link: impl AsRef<Path>) -> Result<()> {
-        let (parent, name) = self.bijou.resolve_parent_nonroot(link.as_ref())?;
+        let (parent, name) = self.resolve_parent_nonroot(link.as_ref())?;
         self.bijou.make_node(
             parent,
             name,
@@ -225,7 +291,7 @@ impl BijouFs {
     ///
     /// This corresponds to [`std::fs::symlink_metadata`].
     pub fn symlink_metadata(&self, path: impl AsRef<Path>) -> Result<FileMeta> {
-        let (parent, name) = self.bijou.resolve_parent_nonroot(path.as_ref())?;
+        let (parent, name) = self.resolve_parent_nonroot(path.as_ref())?;
         self.bijou.get_meta(self.bijou.lookup(parent, name)?)
     }
 
@@ -238,4 +304,45 @@ impl BijouFs {
             .context("failed to write buffer")
             .kind(ErrorKind::IOError)
     }
+
+    /// Atomically replaces the entire contents of a file, creating it
+    /// if it does not exist.
+    ///
+    /// The contents are written to a temporary file next to `path`
+    /// and synced before the temporary file is renamed over `path`,
+    /// so that readers, even after a crash, see either the old or the
+    /// new contents and never a mix of them. Permissions of an
+    /// existing file are kept.
+    pub fn write_atomic(&self, path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<()> {
+        let (parent, name) = self.resolve_parent_nonroot(path.as_ref())?;
+        let perms = match self.bijou.lookup(parent, name) {
+            Ok(id) => self.bijou.get_meta(id)?.perms,
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+
+        let temp = format!(".{name}.{:016x}.tmp", rand::random::<u64>());
+        let mut file = File::new(self.bijou.open_file(
+            parent,
+            &temp,
+            OpenOptions::writable().create_new(true),
+            perms,
+        )?);
+        let result = (|| -> Result<()> {
+            file.write_all(contents.as_ref())
+                .context("failed to write buffer")
+                .kind(ErrorKind::IOError)?;
+            file.sync_data()?;
+            drop(file);
+            // metadata cached by the raw filesystem (e.g. sizes) must
+            // be persisted before the rename
+            self.bijou.raw_fs.flush()?;
+            self.bijou.rename(parent, &temp, parent, name)?;
+            Ok(())
+        })();
+        if result.is_err() {
+            let _ = self.bijou.unlink(parent, &temp);
+        }
+        result
+    }
 }
@@ -0,0 +1,86 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Virtual xattrs on the mount root, which let scripts query the
+//! state of a mounted Bijou, e.g.
+//!
+//! ```bash
+//! getfattr -n user.bijou.stats <mountpoint>
+//! ```
+
+use super::Shared;
+use crate::{algo::CryptoStats, Bijou, DbStats};
+use serde::Serialize;
+use std::sync::atomic::Ordering;
+
+/// Prefix of the virtual xattrs. xattrs of the root with this
+/// prefix are reserved and can't be set or removed.
+pub const PREFIX: &str = "user.bijou.";
+
+/// Names of all the virtual xattrs.
+pub const NAMES: &[&str] = &[
+    "user.bijou.version",
+    "user.bijou.config",
+    "user.bijou.stats",
+];
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Stats {
+    /// Number of files currently opened.
+    open_files: usize,
+    /// Number of inodes known to the kernel. Not tracked with
+    /// stable inodes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inodes: Option<usize>,
+    stable_inodes: bool,
+    verify_on_open: bool,
+    /// Whether the storage is unreachable. See
+    /// [`Bijou::is_degraded`].
+    degraded: bool,
+    crypto: CryptoStats,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    db: Option<DbStats>,
+}
+
+pub fn is_reserved(name: &str) -> bool {
+    name.starts_with(PREFIX)
+}
+
+impl Shared {
+    /// Returns the value of the virtual xattr `name`, or `None` if
+    /// there is no such xattr.
+    pub(super) fn control_xattr(&self, bijou: &Bijou, name: &str) -> Option<Vec<u8>> {
+        Some(match name.strip_prefix(PREFIX)? {
+            "version" => env!("CARGO_PKG_VERSION").as_bytes().to_vec(),
+            "config" => serde_json::to_vec(&bijou.config).unwrap(),
+            "stats" => serde_json::to_vec(&Stats {
+                open_files: bijou
+                    .file_open_counts
+                    .iter()
+                    .filter(|count| count.load(Ordering::Relaxed) > 0)
+                    .count(),
+                inodes: self.table.count(),
+                stable_inodes: self.table.is_stable(),
+                verify_on_open: bijou.verify_on_open,
+                degraded: bijou.is_degraded(),
+                crypto: bijou.crypto_stats(),
+                db: bijou.db_stats().ok(),
+            })
+            .unwrap(),
+            _ => return None,
+        })
+    }
+}
@@ -14,7 +14,15 @@
 //
 
 use super::{FileId, Inode};
-use std::collections::{hash_map::Entry, HashMap, VecDeque};
+use dashmap::{mapref::entry::Entry, DashMap};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard, PoisonError,
+    },
+};
+use tracing::warn;
 
 #[derive(Debug)]
 struct InodeItem {
@@ -25,13 +33,23 @@ struct InodeItem {
 
 /// A data structure maintaining a mapping between inodes and [`FileId`]s.
 ///
+/// Both directions are sharded maps, so operations on different
+/// files rarely contend. To avoid deadlocks, a shard of `inode_table`
+/// is always locked before a shard of `items`, never the other way.
+///
 /// Inspired by <https://github.com/wfraser/fuse-mt/blob/master/src/inode_table.rs>.
-// TODO optimize (lock-free concurrency)
 pub struct InodeTable {
-    items: Vec<InodeItem>,
-    inode_table: HashMap<FileId, Inode>,
+    items: DashMap<Inode, InodeItem>,
+    inode_table: DashMap<FileId, Inode>,
+
+    next_inode: AtomicU64,
+    bin: Mutex<VecDeque<Inode>>,
 
-    bin: VecDeque<Inode>,
+    /// Whether inodes are derived from [`FileId`]s. See [`InodeTable::stable`].
+    stable: bool,
+    /// Inodes assigned to [`FileId`]s colliding with reserved inodes
+    /// in stable mode.
+    collisions: DashMap<Inode, FileId>,
 }
 
 impl Default for InodeTable {
@@ -42,96 +60,181 @@ impl Default for InodeTable {
 
 impl InodeTable {
     pub fn new() -> Self {
-        let mut items = Vec::new();
-        let mut path_table = HashMap::new();
+        let items = DashMap::new();
+        let path_table = DashMap::new();
 
         let root_id = FileId::ROOT;
-        items.push(InodeItem {
-            id: root_id,
-            ref_count: 1,
-            generation: 0,
-        });
-        path_table.insert(root_id, Inode(1));
+        items.insert(
+            Inode::ROOT,
+            InodeItem {
+                id: root_id,
+                ref_count: 1,
+                generation: 0,
+            },
+        );
+        path_table.insert(root_id, Inode::ROOT);
 
         Self {
             items,
             inode_table: path_table,
 
-            bin: VecDeque::new(),
+            next_inode: AtomicU64::new(Inode::ROOT.0 + 1),
+            bin: Mutex::new(VecDeque::new()),
+
+            stable: false,
+            collisions: DashMap::new(),
+        }
+    }
+
+    /// Creates an inode table whose inodes are derived from
+    /// [`FileId`]s (see [`Inode::from_file_id`]), so that they stay
+    /// the same across mounts.
+    ///
+    /// In this mode inodes are never reused, so generations are
+    /// always zero. The rare IDs colliding with reserved inodes are
+    /// assigned random ones instead, which are not stable.
+    pub fn stable() -> Self {
+        Self {
+            stable: true,
+            ..Self::new()
+        }
+    }
+
+    fn stable_inode(&self, id: FileId) -> Inode {
+        if let Some(inode) = Inode::from_file_id(id) {
+            return inode;
         }
+        *self.inode_table.entry(id).or_insert_with(|| loop {
+            // a fresh ID is virtually never used by any file
+            let inode = Inode::from_file_id(FileId::gen()).unwrap();
+            if let Entry::Vacant(entry) = self.collisions.entry(inode) {
+                entry.insert(id);
+                break inode;
+            }
+        })
     }
 
-    fn allocate_inode(items: &mut Vec<InodeItem>, bin: &mut VecDeque<Inode>, id: FileId) -> Inode {
-        match bin.pop_front() {
+    /// Allocates an inode for `id`, with the entry of `id` in
+    /// `inode_table` locked by the caller.
+    fn allocate_inode(&self, id: FileId) -> Inode {
+        let recycled = self.bin().pop_front();
+        match recycled {
             Some(inode) => {
-                let item = &mut items[inode.as_index()];
+                let mut item = self.items.get_mut(&inode).unwrap();
                 item.id = id;
                 item.generation += 1;
                 inode
             }
             None => {
-                items.push(InodeItem {
-                    id,
-                    ref_count: 0,
-                    generation: 0,
-                });
-                Inode(items.len() as u64)
+                let inode = Inode(self.next_inode.fetch_add(1, Ordering::Relaxed));
+                self.items.insert(
+                    inode,
+                    InodeItem {
+                        id,
+                        ref_count: 0,
+                        generation: 0,
+                    },
+                );
+                inode
             }
         }
     }
 
-    pub fn get_id(&self, inode: Inode) -> FileId {
-        self.items[inode.as_index()].id
+    /// The bin stays consistent even if a holder panicked, so
+    /// poisoning is ignored.
+    fn bin(&self) -> MutexGuard<'_, VecDeque<Inode>> {
+        self.bin.lock().unwrap_or_else(PoisonError::into_inner)
     }
 
-    pub fn add(&mut self, id: FileId) -> (Inode, u64) {
-        let (inode, generation) = {
-            let inode = Self::allocate_inode(&mut self.items, &mut self.bin, id);
-            let item = &mut self.items[inode.as_index()];
-            item.ref_count = 1;
-            (inode, item.generation)
-        };
-
-        if self.inode_table.insert(id, inode).is_some() {
-            panic!("inserting duplicate ID into inode table: {id}")
+    /// Returns the [`FileId`] of `inode`, or `None` if the inode is
+    /// unknown (e.g. already forgotten).
+    pub fn get_id(&self, inode: Inode) -> Option<FileId> {
+        if self.stable {
+            return Some(match self.collisions.get(&inode) {
+                Some(id) => *id,
+                None => inode.to_file_id(),
+            });
         }
+        self.items.get(&inode).map(|item| item.id)
+    }
 
-        (inode, generation)
+    /// Adds a newly created file, counting one lookup.
+    ///
+    /// The file may already be known if it is listed by a concurrent
+    /// `readdirplus`, in which case the existing inode is used.
+    pub fn add(&self, id: FileId) -> (Inode, u64) {
+        self.get_or_insert(id, true)
     }
 
-    pub fn get_or_insert(&mut self, id: FileId, lookup: bool) -> (Inode, u64) {
-        let inode = match self.inode_table.entry(id) {
-            Entry::Occupied(entry) => *entry.get(),
-            Entry::Vacant(entry) => {
-                let inode = Self::allocate_inode(&mut self.items, &mut self.bin, id);
-                entry.insert(inode);
-                inode
-            }
+    pub fn get_or_insert(&self, id: FileId, lookup: bool) -> (Inode, u64) {
+        if self.stable {
+            return (self.stable_inode(id), 0);
+        }
+
+        // keep the entry locked, so that the inode can't be
+        // forgotten before its reference count is increased
+        let entry = match self.inode_table.entry(id) {
+            Entry::Occupied(entry) => entry.into_ref(),
+            Entry::Vacant(entry) => entry.insert(self.allocate_inode(id)),
         };
+        let inode = *entry;
 
-        let item = &mut self.items[inode.as_index()];
+        let mut item = self.items.get_mut(&inode).unwrap();
         if lookup && inode != Inode::ROOT {
             item.ref_count += 1;
         }
         (inode, item.generation)
     }
 
-    pub fn forget(&mut self, inode: Inode, count: u64) {
-        if inode == Inode::ROOT {
+    pub fn forget(&self, inode: Inode, count: u64) {
+        if self.stable || inode == Inode::ROOT {
             return;
         }
 
-        let item = &mut self.items[inode.as_index()];
-        assert!(item.ref_count >= count);
-        item.ref_count -= count;
+        // the inode is not reused while we still hold references
+        let Some(id) = self.get_id(inode) else {
+            warn!("forgetting unknown inode {}", inode.0);
+            return;
+        };
+        let entry = self.inode_table.entry(id);
+
+        let Some(mut item) = self.items.get_mut(&inode) else {
+            return;
+        };
+        if item.ref_count < count {
+            warn!(
+                "forgetting {count} references of inode {}, which only has {}",
+                inode.0, item.ref_count
+            );
+        }
+        item.ref_count = item.ref_count.saturating_sub(count);
 
         if item.ref_count == 0 {
-            self.bin.push_back(inode);
-            self.inode_table.remove(&item.id);
+            drop(item);
+            if let Entry::Occupied(entry) = entry {
+                // the ID may have been unlinked and looked up again
+                if *entry.get() == inode {
+                    entry.remove();
+                }
+            }
+            self.bin().push_back(inode);
         }
     }
 
-    pub fn unlink(&mut self, id: FileId) {
+    /// Returns the number of inodes known to the kernel, or `None`
+    /// in stable mode, where they are not tracked.
+    pub fn count(&self) -> Option<usize> {
+        (!self.stable).then(|| self.inode_table.len())
+    }
+
+    pub fn is_stable(&self) -> bool {
+        self.stable
+    }
+
+    pub fn unlink(&self, id: FileId) {
+        if self.stable {
+            return;
+        }
         self.inode_table.remove(&id);
     }
 }
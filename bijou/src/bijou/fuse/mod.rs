@@ -13,13 +13,15 @@
 // limitations under the License.
 //
 
+mod control;
 mod inode_table;
 
 use crate::{
-    begin_span,
-    bijou::DirIterator,
-    error::Context,
-    fs::{time, DirItem, FileId, FileKind, FileMeta, Inode, LowLevelFile, UnixPerms},
+    anyhow, bail, begin_span,
+    bijou::{DirIterator, ExtraTime},
+    buffer::BufferPool,
+    error::{Context, ErrorOrigin},
+    fs::{time, DirItem, FileId, FileKind, FileMeta, Inode, LowLevelFile, RenameFlags, UnixPerms},
     Bijou, OpenOptions, Result,
 };
 use chrono::{DateTime, Utc};
@@ -29,17 +31,122 @@ use fuser::{
 };
 use inode_table::InodeTable;
 use std::{
-    cell::RefCell,
-    ffi::{CString, OsStr},
+    ffi::{CStr, CString, OsStr},
     os::unix::prelude::OsStrExt,
+    panic::AssertUnwindSafe,
     sync::{Arc, RwLock},
     time::{Duration, SystemTime},
 };
 use threadpool::ThreadPool;
 use tracing::{error, info};
 
+#[cfg(target_os = "linux")]
+use crate::fs::FileAttributes;
+
 const TTL: Duration = Duration::from_secs(1);
 
+/// The error for missing xattrs, which is `ENODATA` on Linux and
+/// `ENOATTR` on macOS and the BSDs.
+#[cfg(target_os = "linux")]
+const ENOATTR: libc::c_int = libc::ENODATA;
+#[cfg(not(target_os = "linux"))]
+const ENOATTR: libc::c_int = libc::ENOATTR;
+
+/// The xattr holding resource forks on macOS, which is the only one
+/// written in chunks (at increasing positions).
+const RESOURCE_FORK_XATTR: &str = "com.apple.ResourceFork";
+/// The maximum size of resource forks. They are rewritten whole for
+/// every chunk, so larger ones would be too slow to write.
+const MAX_RESOURCE_FORK_SIZE: usize = 16 << 20;
+
+/// Size of pooled I/O buffers. Larger requests get dedicated buffers.
+const IO_BUFFER_SIZE: usize = 128 << 10;
+/// The maximum number of idle I/O buffers.
+const MAX_IDLE_IO_BUFFERS: usize = 16;
+
+/// Unwraps a [`Result`], replying with the error and returning
+/// from the current callback on failure.
+macro_rules! try_reply {
+    ($reply:expr, $result:expr) => {
+        match $result {
+            Ok(value) => value,
+            Err(err) => {
+                $reply.error(err.to_libc());
+                return;
+            }
+        }
+    };
+}
+
+// Flags of chattr(1), fallocate(2) and renameat2(2) below are specific
+// to Linux, and not supported elsewhere.
+
+#[cfg(target_os = "linux")]
+const fn ioc(dir: u32, nr: u32, size: usize) -> u32 {
+    (dir << 30) | ((size as u32) << 16) | ((b'f' as u32) << 8) | nr
+}
+
+// See linux/fs.h
+#[cfg(target_os = "linux")]
+const FS_IOC_GETFLAGS: u32 = ioc(2, 1, std::mem::size_of::<libc::c_long>());
+#[cfg(target_os = "linux")]
+const FS_IOC_SETFLAGS: u32 = ioc(1, 2, std::mem::size_of::<libc::c_long>());
+#[cfg(target_os = "linux")]
+const FS_IMMUTABLE_FL: u32 = 0x00000010;
+#[cfg(target_os = "linux")]
+const FS_APPEND_FL: u32 = 0x00000020;
+
+// See linux/falloc.h
+#[cfg(target_os = "linux")]
+const FALLOC_FL_KEEP_SIZE: i32 = 0x01;
+
+// See linux/fs.h
+#[cfg(target_os = "linux")]
+const RENAME_NOREPLACE: u32 = 1 << 0;
+#[cfg(target_os = "linux")]
+const RENAME_EXCHANGE: u32 = 1 << 1;
+
+#[cfg(target_os = "linux")]
+fn attributes_to_chattr(attributes: FileAttributes) -> u32 {
+    let mut flags = 0;
+    if attributes.has(FileAttributes::IMMUTABLE) {
+        flags |= FS_IMMUTABLE_FL;
+    }
+    if attributes.has(FileAttributes::APPEND_ONLY) {
+        flags |= FS_APPEND_FL;
+    }
+    flags
+}
+
+#[cfg(target_os = "linux")]
+fn chattr_to_attributes(flags: u32) -> FileAttributes {
+    let mut attributes = FileAttributes::EMPTY;
+    if flags & FS_IMMUTABLE_FL != 0 {
+        attributes = attributes | FileAttributes::IMMUTABLE;
+    }
+    if flags & FS_APPEND_FL != 0 {
+        attributes = attributes | FileAttributes::APPEND_ONLY;
+    }
+    attributes
+}
+
+/// Returns whether the thread `pid` has `CAP_LINUX_IMMUTABLE` in its
+/// effective capabilities.
+#[cfg(target_os = "linux")]
+fn has_linux_immutable_cap(pid: u32) -> bool {
+    // See linux/capability.h
+    const CAP_LINUX_IMMUTABLE: u32 = 9;
+
+    let Ok(status) = std::fs::read_to_string(format!("/proc/{pid}/status")) else {
+        return false;
+    };
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))
+        .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+        .is_some_and(|caps| caps & (1 << CAP_LINUX_IMMUTABLE) != 0)
+}
+
 fn kind_to_fuse(kind: FileKind) -> fuser::FileType {
     match kind {
         FileKind::File => fuser::FileType::RegularFile,
@@ -73,6 +180,139 @@ fn parse_open_options(flags: i32) -> Option<OpenOptions> {
     Some(opts)
 }
 
+#[cfg(target_os = "linux")]
+fn parse_rename_flags(flags: u32) -> Option<RenameFlags> {
+    let mut result = RenameFlags::EMPTY;
+    if flags & RENAME_NOREPLACE != 0 {
+        result = result | RenameFlags::NOREPLACE;
+    }
+    if flags & RENAME_EXCHANGE != 0 {
+        result = result | RenameFlags::EXCHANGE;
+    }
+    // e.g. RENAME_WHITEOUT
+    if flags & !(RENAME_NOREPLACE | RENAME_EXCHANGE) != 0 {
+        return None;
+    }
+    Some(result)
+}
+
+// e.g. RENAME_SWAP and RENAME_EXCL of macOS
+#[cfg(not(target_os = "linux"))]
+fn parse_rename_flags(flags: u32) -> Option<RenameFlags> {
+    (flags == 0).then_some(RenameFlags::EMPTY)
+}
+
+/// Usage of the filesystem the Bijou is stored in.
+struct HostStats {
+    blocks: u64,
+    bfree: u64,
+    bavail: u64,
+    files: u64,
+    ffree: u64,
+    bsize: u32,
+    frsize: u32,
+}
+
+impl HostStats {
+    /// Uses `statvfs`, which is in POSIX and thus available on every
+    /// platform with FUSE, unlike the fields of `statfs`.
+    fn statvfs(path: &CStr) -> std::io::Result<Self> {
+        let stats = unsafe {
+            let mut buf = std::mem::MaybeUninit::uninit();
+            if libc::statvfs(path.as_ptr(), buf.as_mut_ptr()) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            buf.assume_init()
+        };
+        Ok(Self {
+            blocks: stats.f_blocks as _,
+            bfree: stats.f_bfree as _,
+            bavail: stats.f_bavail as _,
+            files: stats.f_files as _,
+            ffree: stats.f_ffree as _,
+            bsize: stats.f_bsize as _,
+            frsize: stats.f_frsize as _,
+        })
+    }
+
+    /// Block counts from `statvfs` are 32-bit on macOS and overflow
+    /// on large disks, so `statfs` is preferred there.
+    #[cfg(target_os = "macos")]
+    fn statfs(path: &CStr) -> std::io::Result<Self> {
+        let stats = unsafe {
+            let mut buf = std::mem::MaybeUninit::uninit();
+            if libc::statfs(path.as_ptr(), buf.as_mut_ptr()) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            buf.assume_init()
+        };
+        Ok(Self {
+            blocks: stats.f_blocks,
+            bfree: stats.f_bfree,
+            bavail: stats.f_bavail,
+            files: stats.f_files,
+            ffree: stats.f_ffree,
+            bsize: stats.f_bsize,
+            frsize: stats.f_bsize,
+        })
+    }
+
+    fn get(path: &CStr) -> std::io::Result<Self> {
+        #[cfg(target_os = "macos")]
+        if let Ok(stats) = Self::statfs(path) {
+            return Ok(stats);
+        }
+        Self::statvfs(path)
+    }
+}
+
+fn reply_xattr(reply: fuser::ReplyXattr, size: u32, bytes: &[u8]) {
+    if size == 0 {
+        reply.size(bytes.len() as _);
+        return;
+    }
+    if bytes.len() > size as usize {
+        reply.error(libc::ERANGE);
+        return;
+    }
+    reply.data(bytes);
+}
+
+/// Runs `f`, logging a panic instead of propagating it.
+///
+/// Replies owned by `f` are dropped while unwinding, and fuser
+/// answers dropped replies with `EIO`, so the kernel is never left
+/// waiting for a reply.
+fn contain_panic<R>(what: &str, f: impl FnOnce() -> R) -> Option<R> {
+    match std::panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => Some(result),
+        Err(payload) => {
+            let msg = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown panic");
+            error!("panicked in {what}: {msg}");
+            None
+        }
+    }
+}
+
+/// Serves requests of `session` until it is unmounted.
+fn serve(session: &mut Session<BijouFuse>) {
+    loop {
+        // keep serving requests if a callback panics
+        match contain_panic("FUSE session", || session.run()) {
+            Some(Ok(())) => break,
+            Some(Err(err)) => {
+                error!("failed to mount FUSE filesystem: {err:?}");
+                break;
+            }
+            None => {}
+        }
+    }
+}
+
 fn ptr_to_file(ptr: u64) -> &'static RwLock<LowLevelFile> {
     unsafe { &*(ptr as *const RwLock<LowLevelFile>) }
 }
@@ -83,18 +323,153 @@ fn drop_as<T>(ptr: u64) {
     }
 }
 
+/// Classes of FUSE operations. See [`BijouFuse::set_offload`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpClass(u8);
+impl OpClass {
+    pub const EMPTY: OpClass = OpClass(0);
+
+    /// `lookup`, `getattr`, `setattr`, `access`, `readlink`, `open`
+    /// and `ioctl`.
+    pub const METADATA: OpClass = OpClass(1 << 0);
+
+    /// `read`.
+    pub const READ: OpClass = OpClass(1 << 1);
+
+    /// `write`, `flush` and `fsync`.
+    pub const WRITE: OpClass = OpClass(1 << 2);
+
+    /// `mknod`, `mkdir`, `symlink`, `create`, `link`, `unlink`,
+    /// `rmdir` and `rename`.
+    pub const NAMESPACE: OpClass = OpClass(1 << 3);
+
+    /// `getxattr`, `setxattr`, `listxattr` and `removexattr`.
+    pub const XATTR: OpClass = OpClass(1 << 4);
+
+    pub const ALL: OpClass = OpClass((1 << 5) - 1);
+
+    pub fn has(&self, class: Self) -> bool {
+        self.0 & class.0 != 0
+    }
+}
+impl Default for OpClass {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+impl std::ops::BitOr for OpClass {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Rules for ownership of files accessed through FUSE, similar to
+/// those of NFS exports. See [`BijouFuse::set_owner_policy`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OwnerPolicy {
+    /// Only root may change the owner of a file, and the owner may
+    /// only change its group to the requester's own group.
+    pub restrict_chown: bool,
+    /// Treat requests from root as coming from the user who mounted
+    /// the Bijou, both when creating files and when changing their
+    /// owner.
+    ///
+    /// Note that the kernel still lets root access every file, since
+    /// permissions are checked by the kernel.
+    pub root_squash: bool,
+}
+
+fn open_file<T>(
+    bijou: &Bijou,
+    id: FileId,
+    flags: i32,
+    reply: T,
+    cb: impl FnOnce(T, u64, u32),
+    error: impl FnOnce(T, libc::c_int),
+) {
+    let Some(opts) = parse_open_options(flags) else {
+        error(reply, libc::EINVAL);
+        return;
+    };
+    match bijou.open_file_direct(id, &opts) {
+        Ok(file) => cb(
+            reply,
+            Box::into_raw(Box::new(RwLock::new(file))) as u64,
+            if opts.write {
+                FOPEN_DIRECT_IO
+            } else {
+                FOPEN_KEEP_CACHE
+            },
+        ),
+        Err(err) => error(reply, err.to_libc()),
+    }
+}
+
+fn write_file(file: &RwLock<LowLevelFile>, offset: i64, data: &[u8], reply: fuser::ReplyWrite) {
+    let Ok(offset) = u64::try_from(offset) else {
+        reply.error(libc::EINVAL);
+        return;
+    };
+    let Ok(mut file) = file.write() else {
+        // poisoned by a panic while writing
+        reply.error(libc::EIO);
+        return;
+    };
+    match file.write(data, offset) {
+        Ok(written) => reply.written(written as _),
+        Err(err) => reply.error(err.to_libc()),
+    }
+}
+
+/// Uploads or flushes written data, so that failing to do so is
+/// reported to the caller instead of being lost on release.
+fn sync_file(file: &RwLock<LowLevelFile>, reply: fuser::ReplyEmpty) {
+    let Ok(file) = file.read() else {
+        // poisoned by a panic while writing
+        reply.error(libc::EIO);
+        return;
+    };
+    try_reply!(reply, file.sync());
+    reply.ok();
+}
+
 struct Shared {
-    table: RwLock<InodeTable>,
+    table: InodeTable,
     uid: u32,
     gid: u32,
+    owners: OwnerPolicy,
+    io_buffers: Arc<BufferPool>,
 }
 
 impl Shared {
-    fn get_id(&self, inode: u64) -> FileId {
-        self.table.read().unwrap().get_id(Inode(inode))
+    fn get_id(&self, inode: u64) -> Result<FileId> {
+        self.table
+            .get_id(Inode(inode))
+            .ok_or_else(|| anyhow!(@NotFound? "unknown inode {inode}"))
     }
 
-    fn meta_to_fuse(&self, bijou: &Bijou, meta: FileMeta) -> (FileAttr, u64) {
+    /// Returns the uid and gid of the requester, after root squashing.
+    fn requester(&self, req: &Request) -> (u32, u32) {
+        if self.owners.root_squash && req.uid() == 0 {
+            (self.uid, self.gid)
+        } else {
+            (req.uid(), req.gid())
+        }
+    }
+
+    fn to_perms(&self, req: &Request, mode: u32) -> UnixPerms {
+        let (uid, gid) = self.requester(req);
+        UnixPerms {
+            mode: mode as _,
+            uid,
+            gid,
+        }
+    }
+
+    /// Returns the permissions of the file as presented to the kernel.
+    fn perms(&self, bijou: &Bijou, meta: &FileMeta) -> UnixPerms {
         let perms = meta
             .perms
             .filter(|_| bijou.config.unix_perms)
@@ -103,7 +478,75 @@ impl Shared {
                 uid: self.uid,
                 gid: self.gid,
             });
-        let (inode, gen) = self.table.write().unwrap().get_or_insert(meta.id, false);
+        if meta.id == FileId::ROOT {
+            UnixPerms {
+                uid: self.uid,
+                gid: self.gid,
+                ..perms
+            }
+        } else {
+            perms
+        }
+    }
+
+    /// Checks whether `requester` may change the owner of a file
+    /// with `perms` to `uid` and `gid`.
+    fn check_chown(
+        &self,
+        (req_uid, req_gid): (u32, u32),
+        perms: &UnixPerms,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> Result<()> {
+        if !self.owners.restrict_chown || req_uid == 0 {
+            return Ok(());
+        }
+        if uid.is_some_and(|uid| uid != perms.uid) {
+            bail!(@PermissionDenied? "only root may change the owner");
+        }
+        if gid.is_some_and(|gid| gid != perms.gid) && (req_uid != perms.uid || gid != Some(req_gid))
+        {
+            bail!(@PermissionDenied? "only the owner may change the group to its own group");
+        }
+        Ok(())
+    }
+
+    /// Checks whether `requester` (from the thread `pid`) may change
+    /// the attributes of a file with `perms` from `old` to `new`.
+    ///
+    /// As with `chattr(1)` on Linux, only the owner may change them,
+    /// and changing the immutable or append-only flags requires
+    /// `CAP_LINUX_IMMUTABLE` as well.
+    #[cfg(target_os = "linux")]
+    fn check_set_attributes(
+        &self,
+        (req_uid, _): (u32, u32),
+        pid: u32,
+        perms: &UnixPerms,
+        old: FileAttributes,
+        new: FileAttributes,
+    ) -> Result<()> {
+        if req_uid != 0 && req_uid != perms.uid {
+            bail!(@PermissionDenied? "only the owner may change attributes");
+        }
+        if old != new && req_uid != 0 && !has_linux_immutable_cap(pid) {
+            bail!(@PermissionDenied? "changing attributes requires CAP_LINUX_IMMUTABLE");
+        }
+        Ok(())
+    }
+
+    fn meta_to_fuse(&self, bijou: &Bijou, meta: FileMeta) -> (FileAttr, u64) {
+        let perms = self.perms(bijou, &meta);
+        let (inode, gen) = self.table.get_or_insert(meta.id, false);
+        // only macOS has creation times, so don't look them up elsewhere
+        let crtime = if cfg!(target_os = "macos") {
+            bijou
+                .extra_time(meta.id, ExtraTime::Creation)
+                .ok()
+                .flatten()
+        } else {
+            None
+        };
         (
             FileAttr {
                 ino: inode.0,
@@ -113,20 +556,14 @@ impl Shared {
                 atime: time::date_time_to_system_time(&meta.accessed),
                 mtime: time::date_time_to_system_time(&meta.modified),
                 ctime: SystemTime::UNIX_EPOCH,
-                crtime: SystemTime::UNIX_EPOCH,
+                crtime: crtime.map_or(SystemTime::UNIX_EPOCH, |crtime| {
+                    time::date_time_to_system_time(&crtime)
+                }),
                 kind: kind_to_fuse(meta.kind),
                 perm: perms.mode,
                 nlink: meta.nlinks as _,
-                uid: if meta.id == FileId::ROOT {
-                    self.uid
-                } else {
-                    perms.uid
-                },
-                gid: if meta.id == FileId::ROOT {
-                    self.gid
-                } else {
-                    perms.gid
-                },
+                uid: perms.uid,
+                gid: perms.gid,
                 rdev: 0,
                 flags: 0,
             },
@@ -135,24 +572,18 @@ impl Shared {
     }
 }
 
-fn to_perms(req: &Request, mode: u32) -> UnixPerms {
-    UnixPerms {
-        mode: mode as _,
-        uid: req.uid(),
-        gid: req.gid(),
-    }
-}
-
 /// A FUSE wrapper for Bijou.
+///
+/// Operations that may be slow are run in a thread pool so that
+/// they don't block the FUSE session loop. See
+/// [`BijouFuse::set_offload`] and [`BijouFuse::set_threads`].
 pub struct BijouFuse {
     bijou: Arc<Bijou>,
     shared: Arc<Shared>,
 
     thread_pool: ThreadPool,
-}
-
-thread_local! {
-    static READ_BUFFER: RefCell<Vec<u8>> = RefCell::default();
+    offload: OpClass,
+    sorted_dirs: bool,
 }
 
 impl BijouFuse {
@@ -161,17 +592,85 @@ impl BijouFuse {
         Self {
             bijou,
             shared: Arc::new(Shared {
-                table: RwLock::new(InodeTable::new()),
+                table: InodeTable::new(),
                 uid: unsafe { libc::getuid() },
                 gid: unsafe { libc::getgid() },
+                owners: OwnerPolicy::default(),
+                io_buffers: Arc::new(BufferPool::new(IO_BUFFER_SIZE, MAX_IDLE_IO_BUFFERS)),
             }),
 
             thread_pool: ThreadPool::default(),
+            offload: OpClass::default(),
+            sorted_dirs: false,
         }
     }
 
-    fn clone_bijou(&self) -> Arc<Bijou> {
-        Arc::clone(&self.bijou)
+    /// Sets the number of threads in the thread pool. Defaults to
+    /// the number of CPUs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threads` is 0.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.thread_pool.set_num_threads(threads);
+    }
+
+    /// Sets which classes of operations are run in the thread pool.
+    /// The others are run in the FUSE session loop, which handles
+    /// requests one at a time. Defaults to [`OpClass::ALL`].
+    ///
+    /// Directory listing is always run in the session loop.
+    pub fn set_offload(&mut self, classes: OpClass) {
+        self.offload = classes;
+    }
+
+    /// Sets whether directory entries are listed sorted by name.
+    /// Defaults to `false`. See [`DirIterator::sorted`].
+    pub fn set_sorted_dirs(&mut self, sorted: bool) {
+        self.sorted_dirs = sorted;
+    }
+
+    /// Sets whether inode numbers should be derived from file IDs,
+    /// making them stable across mounts. Defaults to `false`.
+    ///
+    /// This is needed by NFS re-exports and backup tools relying
+    /// on inode numbers. Must be called before mounting.
+    pub fn set_stable_inodes(&mut self, stable: bool) {
+        let table = if stable {
+            InodeTable::stable()
+        } else {
+            InodeTable::new()
+        };
+        Arc::get_mut(&mut self.shared)
+            .expect("set_stable_inodes called after mounting")
+            .table = table;
+    }
+
+    /// Sets the rules for ownership of files. Defaults to no
+    /// restrictions. Must be called before mounting.
+    pub fn set_owner_policy(&mut self, policy: OwnerPolicy) {
+        Arc::get_mut(&mut self.shared)
+            .expect("set_owner_policy called after mounting")
+            .owners = policy;
+    }
+
+    /// Runs `job` in the thread pool, containing its panics.
+    fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        self.thread_pool.execute(move || {
+            contain_panic("FUSE worker", job);
+        });
+    }
+
+    /// Runs `job` in the thread pool if operations of `class` are
+    /// offloaded, or in the session loop otherwise.
+    fn dispatch(&self, class: OpClass, job: impl FnOnce(&Bijou, &Shared) + Send + 'static) {
+        if self.offload.has(class) {
+            let bijou = Arc::clone(&self.bijou);
+            let shared = Arc::clone(&self.shared);
+            self.execute(move || job(&bijou, &shared));
+        } else {
+            job(&self.bijou, &self.shared);
+        }
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -185,23 +684,18 @@ impl BijouFuse {
         symlink: Option<String>,
         reply: fuser::ReplyEntry,
     ) {
-        let bijou = self.clone_bijou();
-        let shared = Arc::clone(&self.shared);
-        let perms = to_perms(req, mode);
+        let perms = self.shared.to_perms(req, mode);
         let name = name.to_string_lossy().into_owned();
-        self.thread_pool.execute(move || {
-            let result = {
-                let id = shared.get_id(parent);
-                bijou
-                    .make_node(id, &name, kind, symlink, Some(perms))
-                    .map(|meta| {
-                        shared.table.write().unwrap().add(meta.id);
-                        meta
-                    })
-            };
+        self.dispatch(OpClass::NAMESPACE, move |bijou, shared| {
+            let id = try_reply!(reply, shared.get_id(parent));
+            let result = bijou
+                .make_node(id, &name, kind, symlink, Some(perms))
+                .inspect(|meta| {
+                    shared.table.add(meta.id);
+                });
             match result {
                 Ok(meta) => {
-                    let (attr, gen) = shared.meta_to_fuse(&bijou, meta);
+                    let (attr, gen) = shared.meta_to_fuse(bijou, meta);
                     reply.entry(&TTL, &attr, gen)
                 }
                 Err(err) => reply.error(err.to_libc()),
@@ -209,33 +703,6 @@ impl BijouFuse {
         });
     }
 
-    fn open_inner<T>(
-        &mut self,
-        id: FileId,
-        flags: i32,
-        reply: T,
-        cb: impl FnOnce(T, u64, u32),
-        error: impl FnOnce(T, libc::c_int),
-    ) {
-        let Some(opts) = parse_open_options(flags) else {
-            error(reply, libc::EINVAL);
-            return;
-        };
-        let bijou = &self.bijou;
-        match bijou.open_file_direct(id, &opts) {
-            Ok(file) => cb(
-                reply,
-                Box::into_raw(Box::new(RwLock::new(file))) as u64,
-                if opts.write {
-                    FOPEN_DIRECT_IO
-                } else {
-                    FOPEN_KEEP_CACHE
-                },
-            ),
-            Err(err) => error(reply, err.to_libc()),
-        }
-    }
-
     /// Mounts the Bijou at the given mountpoint. Returns a `SessionUnmounter`
     /// that can be used to unmount the filesystem.
     ///
@@ -245,23 +712,60 @@ impl BijouFuse {
         mount_point: impl AsRef<std::path::Path>,
         options: &[MountOption],
     ) -> Result<SessionUnmounter> {
-        let mountpoint = mount_point.as_ref();
+        let mut session = self.session(mount_point.as_ref(), options)?;
+        let unmounter = session.unmount_callable();
+        std::thread::spawn(move || serve(&mut session));
+
+        Ok(unmounter)
+    }
+
+    /// Mounts the Bijou at the given mountpoint, and serves requests
+    /// on the current thread until it is unmounted (e.g. by `umount`).
+    ///
+    /// `mounted` is called once the filesystem is mounted, e.g. to
+    /// tell the process that started this one.
+    pub fn run(
+        self,
+        mount_point: impl AsRef<std::path::Path>,
+        options: &[MountOption],
+        mounted: impl FnOnce(),
+    ) -> Result<()> {
+        let mut session = self.session(mount_point.as_ref(), options)?;
+        mounted();
+        serve(&mut session);
+        info!("Bijou unmounted");
+
+        Ok(())
+    }
+
+    fn session(
+        self,
+        mountpoint: &std::path::Path,
+        options: &[MountOption],
+    ) -> Result<Session<Self>> {
         info!("mounting Bijou at {}", mountpoint.display());
         let mut options = options.to_vec();
         options.extend_from_slice(&[
             MountOption::FSName("bijou".to_owned()),
             MountOption::DefaultPermissions,
         ]);
-        let mut session =
-            Session::new(self, mountpoint, &options).context("failed to create FUSE session")?;
-        let unmounter = session.unmount_callable();
-        std::thread::spawn(move || {
-            if let Err(err) = session.run() {
-                error!("failed to mount FUSE filesystem: {err:?}");
-            }
-        });
-
-        Ok(unmounter)
+        // shown by Finder instead of "macFUSE Volume 0"
+        #[cfg(target_os = "macos")]
+        if !options
+            .iter()
+            .any(|it| matches!(it, MountOption::CUSTOM(option) if option.starts_with("volname=")))
+        {
+            let name = self.bijou.config().label.as_deref().unwrap_or("Bijou");
+            // options are separated by commas
+            options.push(MountOption::CUSTOM(format!(
+                "volname={}",
+                name.replace(',', " ")
+            )));
+        }
+        if self.bijou.is_read_only() {
+            options.push(MountOption::RO);
+        }
+        Session::new(self, mountpoint, &options).context("failed to create FUSE session")
     }
 }
 
@@ -280,50 +784,46 @@ impl Filesystem for BijouFuse {
     }
 
     fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: fuser::ReplyEntry) {
-        let _span = begin_span("lookup");
-        let bijou = &self.bijou;
-        let id = self.shared.get_id(parent);
-        let result = match bijou.lookup(id, &name.to_string_lossy()) {
-            Ok(file) => bijou.get_meta(file).map(|meta| {
-                self.shared
-                    .table
-                    .write()
-                    .unwrap()
-                    .get_or_insert(meta.id, true);
-                meta
-            }),
-            Err(err) => Err(err.take_it_easy()),
-        };
-        match result {
-            Ok(meta) => {
-                let (attr, gen) = self.shared.meta_to_fuse(bijou, meta);
-                reply.entry(&TTL, &attr, gen);
+        let name = name.to_string_lossy().into_owned();
+        self.dispatch(OpClass::METADATA, move |bijou, shared| {
+            let _span = begin_span("lookup");
+            let id = try_reply!(reply, shared.get_id(parent));
+            let result = match bijou.lookup(id, &name) {
+                Ok(file) => bijou.get_meta(file).map(|meta| {
+                    shared.table.get_or_insert(meta.id, true);
+                    meta
+                }),
+                Err(err) => Err(err.take_it_easy()),
+            };
+            match result {
+                Ok(meta) => {
+                    let (attr, gen) = shared.meta_to_fuse(bijou, meta);
+                    reply.entry(&TTL, &attr, gen);
+                }
+                Err(err) => reply.error(err.to_libc()),
             }
-            Err(err) => reply.error(err.to_libc()),
-        }
+        });
     }
 
     fn forget(&mut self, _req: &Request, inode: u64, nlookup: u64) {
-        self.shared
-            .table
-            .write()
-            .unwrap()
-            .forget(Inode(inode), nlookup);
+        self.shared.table.forget(Inode(inode), nlookup);
     }
 
     fn getattr(&mut self, _req: &Request, inode: u64, reply: fuser::ReplyAttr) {
-        let bijou = &self.bijou;
-        match bijou.get_meta(self.shared.get_id(inode)) {
-            Ok(meta) => {
-                reply.attr(&TTL, &self.shared.meta_to_fuse(bijou, meta).0);
+        self.dispatch(OpClass::METADATA, move |bijou, shared| {
+            let id = try_reply!(reply, shared.get_id(inode));
+            match bijou.get_meta(id) {
+                Ok(meta) => {
+                    reply.attr(&TTL, &shared.meta_to_fuse(bijou, meta).0);
+                }
+                Err(err) => reply.error(err.to_libc()),
             }
-            Err(err) => reply.error(err.to_libc()),
-        }
+        });
     }
 
     fn setattr(
         &mut self,
-        _req: &Request,
+        req: &Request,
         inode: u64,
         mode: Option<u32>,
         uid: Option<u32>,
@@ -333,48 +833,63 @@ impl Filesystem for BijouFuse {
         mtime: Option<fuser::TimeOrNow>,
         _ctime: Option<SystemTime>,
         _fh: Option<u64>,
-        _crtime: Option<SystemTime>,
+        crtime: Option<SystemTime>,
         _chgtime: Option<SystemTime>,
-        _bkuptime: Option<SystemTime>,
+        bkuptime: Option<SystemTime>,
         _flags: Option<u32>,
         reply: fuser::ReplyAttr,
     ) {
-        let bijou = &self.bijou;
-        let id = self.shared.get_id(inode);
-        if let Some(size) = size {
-            if let Err(err) = bijou.set_len(id, size) {
-                reply.error(err.to_libc());
-                return;
-            }
+        fn convert(time: Option<TimeOrNow>) -> DateTime<Utc> {
+            let time = time.map_or(SystemTime::UNIX_EPOCH, |time| match time {
+                TimeOrNow::SpecificTime(time) => time,
+                TimeOrNow::Now => SystemTime::now(),
+            });
+            time::system_time_to_date_time(&time)
         }
 
-        if atime.is_some() || mtime.is_some() {
-            fn convert(time: Option<TimeOrNow>) -> DateTime<Utc> {
-                let time = time.map_or(SystemTime::UNIX_EPOCH, |time| match time {
-                    TimeOrNow::SpecificTime(time) => time,
-                    TimeOrNow::Now => SystemTime::now(),
-                });
-                time::system_time_to_date_time(&time)
+        let requester = self.shared.requester(req);
+        self.dispatch(OpClass::METADATA, move |bijou, shared| {
+            let id = try_reply!(reply, shared.get_id(inode));
+            if uid.is_some() || gid.is_some() {
+                let meta = try_reply!(reply, bijou.get_meta(id));
+                try_reply!(
+                    reply,
+                    shared.check_chown(requester, &shared.perms(bijou, &meta), uid, gid)
+                );
             }
-            if let Err(err) = bijou.set_times(id, convert(atime), convert(mtime)) {
-                reply.error(err.to_libc());
-                return;
+
+            if let Some(size) = size {
+                try_reply!(reply, bijou.set_len(id, size));
             }
-        }
 
-        if mode.is_some() || uid.is_some() || gid.is_some() {
-            if let Err(err) = bijou.set_perms(id, mode.map(|it| it as u16), uid, gid) {
-                reply.error(err.to_libc());
-                return;
+            if atime.is_some() || mtime.is_some() {
+                try_reply!(reply, bijou.set_times(id, convert(atime), convert(mtime)));
             }
-        }
 
-        match bijou.get_meta(self.shared.get_id(inode)) {
-            Ok(meta) => {
-                reply.attr(&TTL, &self.shared.meta_to_fuse(bijou, meta).0);
+            // set by macOS, e.g. when Finder copies files
+            for (kind, value) in [(ExtraTime::Creation, crtime), (ExtraTime::Backup, bkuptime)] {
+                if let Some(value) = value {
+                    try_reply!(
+                        reply,
+                        bijou.set_extra_time(id, kind, time::system_time_to_date_time(&value))
+                    );
+                }
             }
-            Err(err) => reply.error(err.to_libc()),
-        }
+
+            if mode.is_some() || uid.is_some() || gid.is_some() {
+                try_reply!(
+                    reply,
+                    bijou.set_perms(id, mode.map(|it| it as u16), uid, gid)
+                );
+            }
+
+            match bijou.get_meta(id) {
+                Ok(meta) => {
+                    reply.attr(&TTL, &shared.meta_to_fuse(bijou, meta).0);
+                }
+                Err(err) => reply.error(err.to_libc()),
+            }
+        });
     }
 
     fn mknod(
@@ -388,7 +903,8 @@ impl Filesystem for BijouFuse {
         reply: fuser::ReplyEntry,
     ) {
         let _span = begin_span("mknod");
-        let kind = match mode & libc::S_IFMT {
+        // `mode_t` is narrower than `u32` on macOS and the BSDs
+        let kind = match mode as libc::mode_t & libc::S_IFMT {
             libc::S_IFREG => FileKind::File,
             libc::S_IFDIR => FileKind::Directory,
             libc::S_IFLNK => FileKind::Symlink,
@@ -413,18 +929,20 @@ impl Filesystem for BijouFuse {
     }
 
     fn unlink(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: fuser::ReplyEmpty) {
-        let _span = begin_span("unlink");
-        let bijou = &self.bijou;
-        let name = name.to_string_lossy();
-        match bijou.unlink(self.shared.get_id(parent), &name) {
-            Ok(removed) => {
-                if let Some(removed) = removed {
-                    self.shared.table.write().unwrap().unlink(removed);
+        let name = name.to_string_lossy().into_owned();
+        self.dispatch(OpClass::NAMESPACE, move |bijou, shared| {
+            let _span = begin_span("unlink");
+            let parent = try_reply!(reply, shared.get_id(parent));
+            match bijou.unlink(parent, &name) {
+                Ok(removed) => {
+                    if let Some(removed) = removed {
+                        shared.table.unlink(removed);
+                    }
+                    reply.ok()
                 }
-                reply.ok()
+                Err(err) => reply.error(err.to_libc()),
             }
-            Err(err) => reply.error(err.to_libc()),
-        }
+        });
     }
 
     fn rmdir(&mut self, req: &Request, parent: u64, name: &OsStr, reply: fuser::ReplyEmpty) {
@@ -432,13 +950,17 @@ impl Filesystem for BijouFuse {
     }
 
     fn open(&mut self, _req: &Request, inode: u64, flags: i32, reply: fuser::ReplyOpen) {
-        self.open_inner(
-            self.shared.get_id(inode),
-            flags,
-            reply,
-            |reply, fh, flags| reply.opened(fh, flags),
-            |reply, err| reply.error(err),
-        );
+        self.dispatch(OpClass::METADATA, move |bijou, shared| {
+            let id = try_reply!(reply, shared.get_id(inode));
+            open_file(
+                bijou,
+                id,
+                flags,
+                reply,
+                |reply, fh, flags| reply.opened(fh, flags),
+                |reply, err| reply.error(err),
+            );
+        });
     }
 
     fn read(
@@ -452,18 +974,24 @@ impl Filesystem for BijouFuse {
         _lock_owner: Option<u64>,
         reply: fuser::ReplyData,
     ) {
+        let Ok(offset) = u64::try_from(offset) else {
+            reply.error(libc::EINVAL);
+            return;
+        };
         let file = ptr_to_file(fh);
-        self.thread_pool.execute(move || {
-            READ_BUFFER.with(|it| {
-                let mut buffer = it.borrow_mut();
-                buffer.resize(size as usize, 0);
-                match file.read().unwrap().read(&mut buffer, offset as _) {
-                    Ok(read) => {
-                        reply.data(&buffer[..read as usize]);
-                    }
-                    Err(err) => reply.error(err.to_libc()),
+        self.dispatch(OpClass::READ, move |_, shared| {
+            let mut buffer = shared.io_buffers.get(size as usize);
+            let Ok(file) = file.read() else {
+                // poisoned by a panic while writing
+                reply.error(libc::EIO);
+                return;
+            };
+            match file.read(&mut buffer, offset) {
+                Ok(read) => {
+                    reply.data(&buffer[..read as usize]);
                 }
-            });
+                Err(err) => reply.error(err.to_libc()),
+            }
         });
     }
 
@@ -480,13 +1008,81 @@ impl Filesystem for BijouFuse {
         reply: fuser::ReplyWrite,
     ) {
         let file = ptr_to_file(fh);
-        // TODO parallelize
-        match file.write().unwrap().write(data, offset as _) {
-            Ok(written) => reply.written(written as _),
-            Err(err) => reply.error(err.to_libc()),
+        if self.offload.has(OpClass::WRITE) {
+            // keep the plaintext out of ordinary heap memory
+            let mut buffer = self.shared.io_buffers.get(data.len());
+            buffer.copy_from_slice(data);
+            self.execute(move || write_file(file, offset, &buffer, reply));
+        } else {
+            write_file(file, offset, data, reply);
         }
     }
 
+    fn fallocate(
+        &mut self,
+        _req: &Request,
+        _inode: u64,
+        fh: u64,
+        offset: i64,
+        length: i64,
+        mode: i32,
+        reply: fuser::ReplyEmpty,
+    ) {
+        #[cfg(target_os = "linux")]
+        let (supported, keep_size) = (
+            mode & !FALLOC_FL_KEEP_SIZE == 0,
+            mode & FALLOC_FL_KEEP_SIZE != 0,
+        );
+        #[cfg(not(target_os = "linux"))]
+        let (supported, keep_size) = (mode == 0, false);
+        if !supported {
+            // punching holes, etc.
+            reply.error(libc::EOPNOTSUPP);
+            return;
+        }
+        let (Ok(offset), Ok(length)) = (u64::try_from(offset), u64::try_from(length)) else {
+            reply.error(libc::EINVAL);
+            return;
+        };
+        let Some(end) = offset.checked_add(length) else {
+            reply.error(libc::EFBIG);
+            return;
+        };
+        let Ok(mut file) = ptr_to_file(fh).write() else {
+            reply.error(libc::EIO);
+            return;
+        };
+        try_reply!(reply, file.reserve(end));
+        if !keep_size {
+            try_reply!(reply, file.extend(end));
+        }
+        reply.ok();
+    }
+
+    fn flush(
+        &mut self,
+        _req: &Request,
+        _inode: u64,
+        fh: u64,
+        _lock_owner: u64,
+        reply: fuser::ReplyEmpty,
+    ) {
+        let file = ptr_to_file(fh);
+        self.dispatch(OpClass::WRITE, move |_, _| sync_file(file, reply));
+    }
+
+    fn fsync(
+        &mut self,
+        _req: &Request,
+        _inode: u64,
+        fh: u64,
+        _datasync: bool,
+        reply: fuser::ReplyEmpty,
+    ) {
+        let file = ptr_to_file(fh);
+        self.dispatch(OpClass::WRITE, move |_, _| sync_file(file, reply));
+    }
+
     fn release(
         &mut self,
         _req: &Request,
@@ -503,15 +1099,15 @@ impl Filesystem for BijouFuse {
 
     fn opendir(&mut self, _req: &Request, inode: u64, _flags: i32, reply: fuser::ReplyOpen) {
         let bijou = &self.bijou;
-        match bijou.read_dir(self.shared.get_id(inode)) {
-            Ok(iter) => reply.opened(
-                Box::into_raw(Box::new(DirHandle {
-                    iter,
-                    buf: Vec::new(),
-                    filled: false,
-                })) as u64,
-                FOPEN_KEEP_CACHE | (1 << 3),
-            ),
+        let id = try_reply!(reply, self.shared.get_id(inode));
+        match bijou.read_dir(id) {
+            Ok(mut iter) => {
+                iter.sorted(self.sorted_dirs);
+                reply.opened(
+                    Box::into_raw(Box::new(DirHandle::new(iter))) as u64,
+                    FOPEN_KEEP_CACHE | (1 << 3),
+                );
+            }
             Err(err) => reply.error(err.to_libc()),
         }
     }
@@ -571,34 +1167,37 @@ impl Filesystem for BijouFuse {
     }
 
     fn statfs(&mut self, _req: &Request, _ino: u64, reply: fuser::ReplyStatfs) {
-        let mut stats = unsafe {
-            let mut buf = std::mem::MaybeUninit::uninit();
-            let path = CString::new(self.bijou.path().as_os_str().as_bytes()).unwrap();
-            if libc::statvfs(path.as_ptr() as _, buf.as_mut_ptr()) < 0 {
-                reply.error(*libc::__errno_location());
+        let Ok(path) = CString::new(self.bijou.path().as_os_str().as_bytes()) else {
+            reply.error(libc::EINVAL);
+            return;
+        };
+        let stats = match HostStats::get(&path) {
+            Ok(stats) => stats,
+            Err(err) => {
+                reply.error(err.raw_os_error().unwrap_or(libc::EIO));
                 return;
             }
-            buf.assume_init()
         };
-        stats.f_namemax = 1 << 24; // arbitrary value
         reply.statfs(
-            stats.f_blocks,
-            stats.f_bfree,
-            stats.f_bavail,
-            stats.f_files,
-            stats.f_ffree,
-            stats.f_bsize as _,
-            stats.f_namemax as _,
-            stats.f_frsize as _,
+            stats.blocks,
+            stats.bfree,
+            stats.bavail,
+            stats.files,
+            stats.ffree,
+            stats.bsize,
+            1 << 24, // arbitrary value
+            stats.frsize,
         );
     }
 
     fn access(&mut self, _req: &Request, inode: u64, _mask: i32, reply: fuser::ReplyEmpty) {
-        let bijou = &self.bijou;
-        match bijou.get_meta(self.shared.get_id(inode)) {
-            Ok(_) => reply.ok(),
-            Err(err) => reply.error(err.to_libc()),
-        }
+        self.dispatch(OpClass::METADATA, move |bijou, shared| {
+            let id = try_reply!(reply, shared.get_id(inode));
+            match bijou.get_meta(id) {
+                Ok(_) => reply.ok(),
+                Err(err) => reply.error(err.to_libc()),
+            }
+        });
     }
 
     fn rename(
@@ -608,23 +1207,22 @@ impl Filesystem for BijouFuse {
         name: &OsStr,
         new_parent: u64,
         new_name: &OsStr,
-        _flags: u32,
+        flags: u32,
         reply: fuser::ReplyEmpty,
     ) {
+        let Some(flags) = parse_rename_flags(flags) else {
+            reply.error(libc::EINVAL);
+            return;
+        };
         let name = name.to_string_lossy().into_owned();
         let new_name = new_name.to_string_lossy().into_owned();
-        let bijou = self.clone_bijou();
-        let shared = Arc::clone(&self.shared);
-        self.thread_pool.execute(move || {
-            match bijou.rename(
-                shared.get_id(parent),
-                &name,
-                shared.get_id(new_parent),
-                &new_name,
-            ) {
+        self.dispatch(OpClass::NAMESPACE, move |bijou, shared| {
+            let parent = try_reply!(reply, shared.get_id(parent));
+            let new_parent = try_reply!(reply, shared.get_id(new_parent));
+            match bijou.rename_with_flags(parent, &name, new_parent, &new_name, flags) {
                 Ok(removed) => {
                     if let Some(removed) = removed {
-                        shared.table.write().unwrap().unlink(removed);
+                        shared.table.unlink(removed);
                     }
                     reply.ok()
                 }
@@ -662,32 +1260,28 @@ impl Filesystem for BijouFuse {
         flags: i32,
         reply: fuser::ReplyCreate,
     ) {
-        let bijou = &self.bijou;
-        let result = {
-            let id = self.shared.get_id(parent);
-            bijou.make_node(
-                id,
-                &name.to_string_lossy(),
-                FileKind::File,
-                None,
-                Some(to_perms(req, mode)),
-            )
-        };
-        match result {
-            Ok(meta) => {
-                let id = meta.id;
-                self.shared.table.write().unwrap().add(id);
-                let (attr, gen) = self.shared.meta_to_fuse(bijou, meta);
-                self.open_inner(
-                    id,
-                    flags,
-                    reply,
-                    |reply, fh, flags| reply.created(&TTL, &attr, gen, fh, flags),
-                    |reply, err| reply.error(err),
-                );
+        let perms = self.shared.to_perms(req, mode);
+        let name = name.to_string_lossy().into_owned();
+        self.dispatch(OpClass::NAMESPACE, move |bijou, shared| {
+            let parent = try_reply!(reply, shared.get_id(parent));
+            let result = bijou.make_node(parent, &name, FileKind::File, None, Some(perms));
+            match result {
+                Ok(meta) => {
+                    let id = meta.id;
+                    shared.table.add(id);
+                    let (attr, gen) = shared.meta_to_fuse(bijou, meta);
+                    open_file(
+                        bijou,
+                        id,
+                        flags,
+                        reply,
+                        |reply, fh, flags| reply.created(&TTL, &attr, gen, fh, flags),
+                        |reply, err| reply.error(err),
+                    );
+                }
+                Err(err) => reply.error(err.to_libc()),
             }
-            Err(err) => reply.error(err.to_libc()),
-        }
+        });
     }
 
     fn setxattr(
@@ -700,17 +1294,45 @@ impl Filesystem for BijouFuse {
         position: u32,
         reply: fuser::ReplyEmpty,
     ) {
-        let _span = begin_span("setxattr");
-        if position != 0 {
+        let name = name.to_string_lossy().into_owned();
+        // positions are only used by macOS for resource forks
+        if position != 0 && !(cfg!(target_os = "macos") && name == RESOURCE_FORK_XATTR) {
             reply.error(libc::EINVAL);
             return;
         }
-
-        let bijou = &self.bijou;
-        match bijou.set_xattr(self.shared.get_id(inode), &name.to_string_lossy(), value) {
-            Ok(_) => reply.ok(),
-            Err(err) => reply.error(err.to_libc()),
+        if name == RESOURCE_FORK_XATTR && position as usize + value.len() > MAX_RESOURCE_FORK_SIZE {
+            reply.error(libc::E2BIG);
+            return;
         }
+
+        let mut value = value.to_vec();
+        self.dispatch(OpClass::XATTR, move |bijou, shared| {
+            let _span = begin_span("setxattr");
+            let id = try_reply!(reply, shared.get_id(inode));
+            if id == FileId::ROOT && control::is_reserved(&name) {
+                reply.error(libc::EPERM);
+                return;
+            }
+            if position != 0 {
+                let old = try_reply!(
+                    reply,
+                    bijou.get_xattr(id, &name, |bytes| bytes.map(|it| it.map(|it| it.to_vec())))
+                );
+                let mut old = old.unwrap_or_default();
+                if position as usize > old.len() {
+                    reply.error(libc::EINVAL);
+                    return;
+                }
+                old.truncate(position as usize);
+                old.append(&mut value);
+                value = old;
+            }
+
+            match bijou.set_xattr(id, &name, &value) {
+                Ok(_) => reply.ok(),
+                Err(err) => reply.error(err.to_libc()),
+            }
+        });
     }
 
     fn getxattr(
@@ -721,72 +1343,74 @@ impl Filesystem for BijouFuse {
         size: u32,
         reply: fuser::ReplyXattr,
     ) {
-        let _span = begin_span("getxattr");
-        let bijou = &self.bijou;
-        bijou.get_xattr(
-            self.shared.get_id(inode),
-            &name.to_string_lossy(),
-            |bytes| match bytes {
-                Ok(bytes) => {
-                    if let Some(bytes) = bytes {
-                        if size == 0 {
-                            reply.size(bytes.len() as _);
-                            return;
-                        }
-                        if bytes.len() > size as usize {
-                            reply.error(libc::ERANGE);
-                            return;
-                        }
-                        reply.data(&bytes);
-                    } else {
-                        reply.error(libc::ENODATA);
-                    }
+        let name = name.to_string_lossy().into_owned();
+        self.dispatch(OpClass::XATTR, move |bijou, shared| {
+            let _span = begin_span("getxattr");
+            let id = try_reply!(reply, shared.get_id(inode));
+            if id == FileId::ROOT && control::is_reserved(&name) {
+                // available even if xattr gets are disabled
+                match shared.control_xattr(bijou, &name) {
+                    Some(bytes) => reply_xattr(reply, size, &bytes),
+                    None => reply.error(ENOATTR),
                 }
+                return;
+            }
+
+            bijou.get_xattr(id, &name, |bytes| match bytes {
+                Ok(Some(bytes)) => reply_xattr(reply, size, &bytes),
+                Ok(None) => reply.error(ENOATTR),
                 Err(err) => reply.error(err.to_libc()),
-            },
-        );
+            });
+        });
     }
 
     fn removexattr(&mut self, _req: &Request, inode: u64, name: &OsStr, reply: fuser::ReplyEmpty) {
-        let _span = begin_span("removexattr");
-        let bijou = &self.bijou;
-        match bijou.remove_xattr(self.shared.get_id(inode), &name.to_string_lossy()) {
-            Ok(_) => reply.ok(),
-            Err(err) => reply.error(err.to_libc()),
-        }
+        let name = name.to_string_lossy().into_owned();
+        self.dispatch(OpClass::XATTR, move |bijou, shared| {
+            let _span = begin_span("removexattr");
+            let id = try_reply!(reply, shared.get_id(inode));
+            if id == FileId::ROOT && control::is_reserved(&name) {
+                reply.error(libc::EPERM);
+                return;
+            }
+
+            match bijou.remove_xattr(id, &name) {
+                Ok(_) => reply.ok(),
+                Err(err) => reply.error(err.to_libc()),
+            }
+        });
     }
 
     fn listxattr(&mut self, _req: &Request, inode: u64, size: u32, reply: fuser::ReplyXattr) {
-        let _span = begin_span("listxattr");
-        let bijou = &self.bijou;
-        match bijou.xattrs(self.shared.get_id(inode)) {
-            Ok(attrs) => {
-                let len = attrs.len() as u32;
-                if size == 0 {
-                    reply.size(len);
-                    return;
-                }
-                if len > size {
-                    reply.error(libc::ERANGE);
-                    return;
-                }
-                let mut buf = Vec::with_capacity(attrs.iter().map(|attr| attr.len() + 1).sum());
-                for attr in attrs {
-                    buf.extend_from_slice(attr.as_bytes());
-                    buf.push(0);
+        self.dispatch(OpClass::XATTR, move |bijou, shared| {
+            let _span = begin_span("listxattr");
+            let id = try_reply!(reply, shared.get_id(inode));
+            match bijou.xattrs(id) {
+                Ok(mut attrs) => {
+                    if id == FileId::ROOT {
+                        attrs.retain(|attr| !control::is_reserved(attr));
+                        attrs.extend(control::NAMES.iter().map(|name| name.to_string()));
+                    }
+                    let mut buf = Vec::with_capacity(attrs.iter().map(|attr| attr.len() + 1).sum());
+                    for attr in attrs {
+                        buf.extend_from_slice(attr.as_bytes());
+                        buf.push(0);
+                    }
+                    reply_xattr(reply, size, &buf);
                 }
-                reply.data(&buf);
+                Err(err) => reply.error(err.to_libc()),
             }
-            Err(err) => reply.error(err.to_libc()),
-        }
+        });
     }
 
     fn readlink(&mut self, _req: &Request, inode: u64, reply: fuser::ReplyData) {
-        let bijou = &self.bijou;
-        match bijou.read_link(self.shared.get_id(inode)) {
-            Ok(target) => reply.data(target.as_str().as_bytes()),
-            Err(err) => reply.error(err.to_libc()),
-        }
+        self.dispatch(OpClass::METADATA, move |bijou, shared| {
+            let id = try_reply!(reply, shared.get_id(inode));
+            match bijou.read_link(id) {
+                Ok(target) => reply.data(target.as_str().as_bytes()),
+                Err(err) => reply.error(err.to_libc()),
+            }
+        });
     }
 
     fn link(
@@ -797,27 +1421,79 @@ impl Filesystem for BijouFuse {
         newname: &OsStr,
         reply: fuser::ReplyEntry,
     ) {
-        let bijou = &self.bijou;
-        match bijou.link(
-            self.shared.get_id(ino),
-            self.shared.get_id(newparent),
-            &newname.to_string_lossy(),
-        ) {
-            Ok(meta) => {
-                self.shared
-                    .table
-                    .write()
-                    .unwrap()
-                    .get_or_insert(meta.id, true);
-                let (attr, gen) = self.shared.meta_to_fuse(bijou, meta);
-                reply.entry(&TTL, &attr, gen);
+        let newname = newname.to_string_lossy().into_owned();
+        self.dispatch(OpClass::NAMESPACE, move |bijou, shared| {
+            let id = try_reply!(reply, shared.get_id(ino));
+            let newparent = try_reply!(reply, shared.get_id(newparent));
+            match bijou.link(id, newparent, &newname) {
+                Ok(meta) => {
+                    shared.table.get_or_insert(meta.id, true);
+                    let (attr, gen) = shared.meta_to_fuse(bijou, meta);
+                    reply.entry(&TTL, &attr, gen);
+                }
+                Err(err) => reply.error(err.to_libc()),
             }
-            Err(err) => reply.error(err.to_libc()),
-        }
+        });
+    }
+
+    // elsewhere, the default replies with ENOSYS
+    #[cfg(target_os = "linux")]
+    fn ioctl(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        _fh: u64,
+        _flags: u32,
+        cmd: u32,
+        in_data: &[u8],
+        out_size: u32,
+        reply: fuser::ReplyIoctl,
+    ) {
+        let in_flags: Option<[u8; 4]> = in_data.get(..4).and_then(|it| it.try_into().ok());
+        let (requester, pid) = (self.shared.requester(req), req.pid());
+        self.dispatch(OpClass::METADATA, move |bijou, shared| {
+            let id = try_reply!(reply, shared.get_id(ino));
+            match cmd {
+                FS_IOC_GETFLAGS => match bijou.get_meta(id) {
+                    Ok(meta) => {
+                        let flags = attributes_to_chattr(meta.attributes).to_ne_bytes();
+                        reply.ioctl(0, &flags[..flags.len().min(out_size as usize)]);
+                    }
+                    Err(err) => reply.error(err.to_libc()),
+                },
+                FS_IOC_SETFLAGS => {
+                    let Some(flags) = in_flags else {
+                        reply.error(libc::EINVAL);
+                        return;
+                    };
+                    let attributes = chattr_to_attributes(u32::from_ne_bytes(flags));
+                    let meta = try_reply!(reply, bijou.get_meta(id));
+                    try_reply!(
+                        reply,
+                        shared.check_set_attributes(
+                            requester,
+                            pid,
+                            &shared.perms(bijou, &meta),
+                            meta.attributes,
+                            attributes,
+                        )
+                    );
+                    match bijou.set_attributes(id, attributes) {
+                        Ok(_) => reply.ioctl(0, &[]),
+                        Err(err) => reply.error(err.to_libc()),
+                    }
+                }
+                _ => reply.error(libc::ENOTTY),
+            }
+        });
     }
 
     fn destroy(&mut self) {
         info!("destroy() called");
+        if let Err(err) = self.bijou.flush_dir_times() {
+            err.context("failed to flush directory times")
+                .report(ErrorOrigin::DirTimes);
+        }
     }
 }
 
@@ -826,12 +1502,80 @@ struct DirBufItem {
     item: DirItem,
     attr_and_gen: Option<(FileAttr, u64)>,
 }
+/// State of an opened directory.
+///
+/// Entries are buffered in the order they are read, and the offset
+/// of an entry is its index in the buffer plus one. Since the buffer
+/// is only reset when the kernel starts over from offset 0, retried
+/// or partial reads always see the same entries at the same offsets.
 struct DirHandle<'db> {
     iter: DirIterator<'db>,
     buf: Vec<DirBufItem>,
+    /// Entries read from `iter` whose attributes are not fetched yet.
+    pending: Vec<(String, DirItem)>,
     filled: bool,
 }
-impl DirHandle<'_> {
+impl<'db> DirHandle<'db> {
+    const BATCH_SIZE: usize = 128;
+
+    fn new(iter: DirIterator<'db>) -> Self {
+        Self {
+            iter,
+            buf: Vec::new(),
+            pending: Vec::new(),
+            filled: false,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.iter.reset();
+        self.buf.clear();
+        self.pending.clear();
+        self.filled = false;
+    }
+
+    /// Reads the next batch of entries into the buffer, along with
+    /// their attributes if `fuse` is given.
+    ///
+    /// On failure, entries already taken from the iterator are kept
+    /// in `pending` so that a retry does not skip them.
+    fn fetch(&mut self, fuse: Option<&BijouFuse>) -> Result<()> {
+        if self.pending.is_empty() {
+            for item in self.iter.by_ref().take(Self::BATCH_SIZE) {
+                self.pending.push(item?);
+            }
+            if self.pending.is_empty() {
+                self.filled = true;
+                return Ok(());
+            }
+        }
+
+        let items = &self.pending;
+        let attrs = match fuse {
+            Some(fuse) => {
+                let ids = items.iter().map(|(_, item)| item.id).collect::<Vec<_>>();
+                fuse.bijou
+                    .get_meta_many(&ids)?
+                    .into_iter()
+                    .map(|meta| Some(fuse.shared.meta_to_fuse(&fuse.bijou, meta)))
+                    .collect()
+            }
+            None => vec![None; items.len()],
+        };
+        self.buf.extend(
+            self.pending
+                .drain(..)
+                .zip(attrs)
+                .map(|((name, item), attr_and_gen)| DirBufItem {
+                    name,
+                    item,
+                    attr_and_gen,
+                }),
+        );
+
+        Ok(())
+    }
+
     pub fn fill<T>(
         &mut self,
         fuse: Option<&BijouFuse>,
@@ -841,12 +1585,12 @@ impl DirHandle<'_> {
         ok: impl FnOnce(T),
         error: impl FnOnce(T, libc::c_int),
     ) {
-        assert!(offset >= 0);
-        let mut offset = offset as usize;
+        let Ok(mut offset) = usize::try_from(offset) else {
+            error(reply, libc::EINVAL);
+            return;
+        };
         if offset == 0 {
-            self.iter.reset();
-            self.buf.clear();
-            self.filled = false;
+            self.reset();
         }
         loop {
             let DirBufItem {
@@ -859,54 +1603,15 @@ impl DirHandle<'_> {
                     if self.filled {
                         break;
                     }
-                    let Some(item) = self.iter.next() else {
-                        self.filled = true;
-                        break;
-                    };
-                    let (name, item) = match item {
-                        Ok(item) => item,
-                        Err(err) => {
-                            error(reply, err.to_libc());
-                            return;
-                        }
-                    };
-
-                    let attr_and_gen = match fuse
-                        .as_ref()
-                        .map(|fuse| {
-                            fuse.bijou
-                                .get_meta(item.id)
-                                .map(|meta| fuse.shared.meta_to_fuse(&fuse.bijou, meta))
-                        })
-                        .transpose()
-                    {
-                        Ok(val) => val,
-                        Err(err) => {
-                            error(reply, err.to_libc());
-                            return;
-                        }
-                    };
-                    self.buf.push(DirBufItem {
-                        name,
-                        item,
-                        attr_and_gen,
-                    });
-
-                    self.buf.last().unwrap()
+                    if let Err(err) = self.fetch(fuse) {
+                        error(reply, err.to_libc());
+                        return;
+                    }
+                    continue;
                 }
             };
 
             offset += 1;
-            if name != "." && name != ".." {
-                if let Some(fuse) = fuse.as_ref() {
-                    // Increase lookup
-                    fuse.shared
-                        .table
-                        .write()
-                        .unwrap()
-                        .get_or_insert(item.id, true);
-                }
-            }
             if cb(
                 &mut reply,
                 offset as _,
@@ -914,8 +1619,15 @@ impl DirHandle<'_> {
                 name,
                 *attr_and_gen,
             ) {
-                ok(reply);
-                return;
+                // The buffer is full and this entry is not sent, it
+                // will be returned again by the next call.
+                break;
+            }
+            if name != "." && name != ".." {
+                if let Some(fuse) = fuse.as_ref() {
+                    // Increase lookup
+                    fuse.shared.table.get_or_insert(item.id, true);
+                }
             }
         }
 
@@ -0,0 +1,101 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::{sodium::generic_hash, Bijou, FileId, OpenOptions, Result};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+
+/// Hash algorithms supported by [`Bijou::hash_file`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    /// BLAKE2b with a 256-bit output.
+    Blake2b,
+    /// SHA-256.
+    Sha256,
+}
+
+impl HashAlgorithm {
+    /// Length of the output in bytes.
+    pub fn output_len(&self) -> usize {
+        match self {
+            Self::Blake2b => 32,
+            Self::Sha256 => digest::SHA256_OUTPUT_LEN,
+        }
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+enum Hasher {
+    Blake2b(generic_hash::State),
+    Sha256(digest::Context),
+}
+
+impl Hasher {
+    fn new(algo: HashAlgorithm) -> Result<Self> {
+        Ok(match algo {
+            HashAlgorithm::Blake2b => {
+                Self::Blake2b(generic_hash::State::new(algo.output_len(), None)?)
+            }
+            HashAlgorithm::Sha256 => Self::Sha256(digest::Context::new(&digest::SHA256)),
+        })
+    }
+
+    fn update(&mut self, data: &[u8]) -> Result<()> {
+        match self {
+            Self::Blake2b(state) => state.update(data)?,
+            Self::Sha256(context) => context.update(data),
+        }
+        Ok(())
+    }
+
+    fn finalize(self, algo: HashAlgorithm) -> Result<Vec<u8>> {
+        Ok(match self {
+            Self::Blake2b(state) => {
+                let mut output = vec![0; algo.output_len()];
+                state.finalize(&mut output)?;
+                output
+            }
+            Self::Sha256(context) => context.finish().as_ref().to_vec(),
+        })
+    }
+}
+
+impl Bijou {
+    /// Number of blocks decrypted at a time by [`Bijou::hash_file`].
+    const HASH_CHUNK_BLOCKS: u64 = 64;
+
+    /// Hashes the plaintext of a file.
+    ///
+    /// The file is decrypted and hashed a chunk at a time, so it is
+    /// never held in memory as a whole.
+    pub fn hash_file(&self, id: FileId, algo: HashAlgorithm) -> Result<Vec<u8>> {
+        let file = self.open_file_direct(id, OpenOptions::read_only())?;
+        let chunk_size = self.algo.block_size() * Self::HASH_CHUNK_BLOCKS;
+        let mut buffer = self.block_buffers.get(chunk_size as usize);
+
+        let mut hasher = Hasher::new(algo)?;
+        let mut offset = 0;
+        loop {
+            let read = file.read(&mut buffer, offset)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read as usize])?;
+            offset += read;
+        }
+        hasher.finalize(algo)
+    }
+}
@@ -0,0 +1,274 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::{
+    anyhow,
+    error::ResultExt,
+    fs::{time, UnixPerms},
+    Bijou, Context, FileId, FileKind, ImportStats, NewNode, OpenOptions, Result, SecretBytes,
+};
+use std::{
+    collections::HashMap,
+    fs::Metadata,
+    io::Read,
+    path::{Path as StdPath, PathBuf as StdPathBuf},
+};
+use tracing::{info, warn};
+
+#[cfg(unix)]
+fn perms_of(meta: &Metadata) -> Option<UnixPerms> {
+    use std::os::unix::fs::MetadataExt;
+    Some(UnixPerms {
+        mode: (meta.mode() & 0o7777) as u16,
+        uid: meta.uid(),
+        gid: meta.gid(),
+    })
+}
+
+#[cfg(not(unix))]
+fn perms_of(_meta: &Metadata) -> Option<UnixPerms> {
+    None
+}
+
+/// Returns an identifier for files with multiple hard links.
+#[cfg(unix)]
+fn link_id_of(meta: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (meta.nlink() > 1).then_some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn link_id_of(_meta: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+struct Importer<'a> {
+    bijou: &'a Bijou,
+    links: HashMap<(u64, u64), FileId>,
+    /// Holds plaintext, and is thus zeroed out when dropped.
+    buffer: SecretBytes,
+    stats: ImportStats,
+}
+
+/// A file waiting to be created by [`Bijou::make_nodes`].
+struct Pending {
+    src: StdPathBuf,
+    meta: Metadata,
+    node: NewNode,
+}
+
+impl Importer<'_> {
+    const BUFFER_SIZE: usize = 1 << 20;
+    /// The maximum number of files created in a single batch.
+    const BATCH_SIZE: usize = 1024;
+
+    fn import_dir(&mut self, src: &StdPath, parent: FileId) -> Result<()> {
+        let entries = std::fs::read_dir(src)
+            .with_context(|| format!("failed to read directory {}", src.display()))?;
+        let mut pending = Vec::new();
+        for entry in entries {
+            let path = entry.wrap()?.path();
+            self.import(path.clone(), parent, &mut pending)
+                .map_err(|err| err.context(format!("failed to import {}", path.display())))?;
+            if pending.len() >= Self::BATCH_SIZE {
+                self.import_batch(src, parent, &mut pending)?;
+            }
+        }
+        self.import_batch(src, parent, &mut pending)
+    }
+
+    /// Imports `src`, or queues it in `pending` to be created along
+    /// with its siblings.
+    ///
+    /// Hard linked files and symlinks are imported right away, so
+    /// that later links to them can be found in `links`.
+    fn import(
+        &mut self,
+        src: StdPathBuf,
+        parent: FileId,
+        pending: &mut Vec<Pending>,
+    ) -> Result<()> {
+        let meta = std::fs::symlink_metadata(&src).wrap()?;
+        let name = src
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| anyhow!(@InvalidInput "file name is not valid UTF-8"))?
+            .to_owned();
+
+        let link_id = link_id_of(&meta).filter(|_| meta.is_file() || meta.is_symlink());
+        if let Some(id) = link_id.and_then(|it| self.links.get(&it)) {
+            self.bijou.link(*id, parent, &name)?;
+            self.stats.hard_links += 1;
+            return Ok(());
+        }
+
+        let perms = perms_of(&meta);
+        let (kind, symlink) = if meta.is_dir() {
+            (FileKind::Directory, None)
+        } else if meta.is_symlink() {
+            let target = std::fs::read_link(&src).wrap()?;
+            let target = target
+                .into_os_string()
+                .into_string()
+                .map_err(|_| anyhow!(@InvalidInput "symlink target is not valid UTF-8"))?;
+            (FileKind::Symlink, Some(target))
+        } else if meta.is_file() {
+            (FileKind::File, None)
+        } else {
+            warn!("skipping special file {}", src.display());
+            return Ok(());
+        };
+        let node = NewNode {
+            name,
+            kind,
+            symlink,
+            perms,
+        };
+
+        if let Some(link_id) = link_id {
+            let id = self
+                .bijou
+                .make_node(parent, &node.name, kind, node.symlink, perms)?
+                .id;
+            self.links.insert(link_id, id);
+            self.populate(&src, &meta, id, kind)
+        } else {
+            pending.push(Pending { src, meta, node });
+            Ok(())
+        }
+    }
+
+    fn import_batch(
+        &mut self,
+        dir: &StdPath,
+        parent: FileId,
+        pending: &mut Vec<Pending>,
+    ) -> Result<()> {
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let nodes = pending
+            .iter()
+            .map(|pending| pending.node.clone())
+            .collect::<Vec<_>>();
+        let metas = self
+            .bijou
+            .make_nodes(parent, &nodes)
+            .map_err(|err| err.context(format!("failed to import files in {}", dir.display())))?;
+        for (pending, meta) in pending.drain(..).zip(metas) {
+            self.populate(&pending.src, &pending.meta, meta.id, meta.kind)
+                .map_err(|err| {
+                    err.context(format!("failed to import {}", pending.src.display()))
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Copies the content and attributes of `src` into the newly
+    /// created file `id`.
+    fn populate(
+        &mut self,
+        src: &StdPath,
+        meta: &Metadata,
+        id: FileId,
+        kind: FileKind,
+    ) -> Result<()> {
+        match kind {
+            FileKind::Directory => {
+                self.import_dir(src, id)?;
+                self.stats.directories += 1;
+            }
+            FileKind::Symlink => {
+                self.stats.symlinks += 1;
+            }
+            FileKind::File => {
+                self.copy_file(src, id)?;
+                self.stats.files += 1;
+            }
+        }
+
+        self.copy_xattrs(src, id)?;
+        if let (Ok(accessed), Ok(modified)) = (meta.accessed(), meta.modified()) {
+            self.bijou.set_times(
+                id,
+                time::system_time_to_date_time(&accessed),
+                time::system_time_to_date_time(&modified),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn copy_file(&mut self, src: &StdPath, id: FileId) -> Result<()> {
+        let mut input = std::fs::File::open(src).wrap()?;
+        let mut output = self.bijou.open_file_direct(id, OpenOptions::writable())?;
+        let mut offset = 0;
+        loop {
+            let len = input.read(&mut self.buffer).wrap()?;
+            if len == 0 {
+                break;
+            }
+            output.write(&self.buffer[..len], offset)?;
+            offset += len as u64;
+        }
+        self.stats.bytes += offset;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn copy_xattrs(&self, src: &StdPath, id: FileId) -> Result<()> {
+        for name in xattr::list(src).wrap()? {
+            let Some(name) = name.to_str() else {
+                warn!("skipping xattr with non UTF-8 name on {}", src.display());
+                continue;
+            };
+            if let Some(value) = xattr::get(src, name).wrap()? {
+                self.bijou.set_xattr(id, name, &value)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn copy_xattrs(&self, _src: &StdPath, _id: FileId) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Bijou {
+    /// Imports the contents of a directory in the local filesystem
+    /// into the directory `parent`.
+    ///
+    /// Permissions, times, symlinks, hard links and xattrs are
+    /// preserved where supported. Special files (e.g. sockets) are
+    /// skipped. This can be used to migrate from other encrypted
+    /// filesystems (e.g. gocryptfs) by importing from their mount
+    /// points.
+    pub fn import(&self, src: impl AsRef<StdPath>, parent: FileId) -> Result<ImportStats> {
+        self.check_writable()?;
+        let src = src.as_ref();
+        info!("importing {} into {parent}", src.display());
+
+        let mut importer = Importer {
+            bijou: self,
+            links: HashMap::new(),
+            buffer: SecretBytes::allocate_lenient(Importer::BUFFER_SIZE),
+            stats: ImportStats::default(),
+        };
+        importer.import_dir(src, parent)?;
+
+        Ok(importer.stats)
+    }
+}
@@ -0,0 +1,140 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::{
+    db::{self, consts},
+    error::ResultExt,
+    fs::{complete_metadata, TimePolicy},
+    Bijou, FileId, FileKind, FileMeta, Result,
+};
+use bijou_rocksdb::{
+    DBIteratorWithThreadMode, DBWithThreadMode, Direction, IteratorMode, SingleThreaded,
+};
+
+/// Length of the key of a [`FileMeta`] record. Other records of a
+/// file share the prefix, but have a derivation appended.
+const META_KEY_LEN: usize = std::mem::size_of::<consts::Root>() + std::mem::size_of::<FileId>();
+
+/// An iterator over every file in a [`Bijou`], reachable from the
+/// root or not, in no particular order.
+///
+/// Files created or removed during the iteration may or may not be
+/// returned. Metadata is completed the same way as in
+/// [`Bijou::get_meta`].
+///
+/// Created by [`Bijou::iter_files`].
+pub struct FileIterator<'db> {
+    bijou: &'db Bijou,
+    inner: DBIteratorWithThreadMode<'db, DBWithThreadMode<SingleThreaded>>,
+    kind: Option<FileKind>,
+    stored: bool,
+}
+impl FileIterator<'_> {
+    /// Only returns files of the given kind. Other records are
+    /// skipped without touching the underlying file system.
+    ///
+    /// This should be called before iterating.
+    pub fn kind(&mut self, kind: FileKind) -> &mut Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Returns the metadata as stored in the database, without
+    /// filling in the fields that come from the raw file (e.g. the
+    /// size), so that files whose content is missing are returned as
+    /// well.
+    pub fn stored(&mut self) -> &mut Self {
+        self.stored = true;
+        self
+    }
+
+    /// Continues the iteration after the file `id`, e.g. to resume an
+    /// interrupted one. Files are returned in the order of their
+    /// keys, so this skips every file returned before `id`.
+    pub fn after(&mut self, id: FileId) -> &mut Self {
+        let mut key = consts::Root::File.as_ref().to_vec();
+        key.extend_from_slice(id.as_ref());
+        // skips the derived records of `id` as well, none of which
+        // starts with 0xff
+        key.push(u8::MAX);
+        self.inner
+            .set_mode(IteratorMode::From(&key, Direction::Forward));
+        self
+    }
+
+    fn decode(&self, value: &[u8]) -> Option<Result<FileMeta>> {
+        let mut meta: FileMeta = match db::decode(value) {
+            Ok(meta) => meta,
+            Err(err) => return Some(Err(err)),
+        };
+        if self.kind.is_some_and(|kind| kind != meta.kind) {
+            return None;
+        }
+        if self.stored {
+            return Some(Ok(meta));
+        }
+        let bijou = self.bijou;
+        let times = TimePolicy::new(&bijou.config, &bijou.clock);
+        Some(
+            complete_metadata(&mut meta, bijou.algo.as_ref(), &times, |meta| {
+                bijou.raw_fs.stat(meta.content_id())
+            })
+            .map(|()| {
+                bijou.apply_dir_times(&mut meta);
+                meta
+            }),
+        )
+    }
+}
+impl Iterator for FileIterator<'_> {
+    type Item = Result<FileMeta>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, value) = match self.inner.next()?.wrap() {
+                Ok(entry) => entry,
+                Err(err) => return Some(Err(err)),
+            };
+            if !key.starts_with(consts::Root::File.as_ref()) {
+                return None;
+            }
+            if key.len() != META_KEY_LEN {
+                continue;
+            }
+            if let Some(result) = self.decode(&value) {
+                return Some(result);
+            }
+        }
+    }
+}
+
+impl Bijou {
+    /// Returns an iterator over the metadata of every file, including
+    /// the ones that are not reachable from the root (e.g. unlinked
+    /// files that are still open).
+    ///
+    /// This is meant for maintenance tooling. See [`FileIterator`].
+    pub fn iter_files(&self) -> FileIterator<'_> {
+        FileIterator {
+            bijou: self,
+            inner: self.db.0.iterator(IteratorMode::From(
+                consts::Root::File.as_ref(),
+                Direction::Forward,
+            )),
+            kind: None,
+            stored: false,
+        }
+    }
+}
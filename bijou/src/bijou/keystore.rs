@@ -0,0 +1,460 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::{embedded_db, unlock::UnlockThrottle, Bijou};
+use crate::{
+    bail,
+    db::Database,
+    error::ResultExt,
+    low_level::raw_fs::RocksDBFileSystem,
+    serde_ext,
+    sodium::{
+        aead::XCHACHA20_POLY1305_IETF as AEAD,
+        kdf::BLAKE2B as KDF,
+        pwhash::{Limit, ARGON2_ID13 as PWHASH},
+        utils,
+    },
+    Context, ErrorKind, Result, SecretBytes,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    path::{Path as StdPath, PathBuf as StdPathBuf},
+};
+
+/// The decrypted master key of a Bijou, obtained from
+/// [`KeyStore::unlock`].
+///
+/// This can be used to open a Bijou (or its replicas) without
+/// deriving the key from the password again. See
+/// [`Bijou::open_with_key`].
+///
+/// [`Bijou::open_with_key`]: crate::Bijou::open_with_key
+#[derive(Clone)]
+pub struct MasterKey {
+    pub(super) bytes: SecretBytes,
+    pub(super) read_only: bool,
+}
+
+impl MasterKey {
+    /// Whether this key was unlocked with the read-only password (see
+    /// [`Bijou::set_read_only_password`]), in which case the Bijou is
+    /// always opened read-only.
+    ///
+    /// [`Bijou::set_read_only_password`]: crate::Bijou::set_read_only_password
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
+
+/// The master key encrypted with a key derived from a password.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct KeySlot {
+    #[serde(with = "serde_ext::base64")]
+    pub(super) salt: [u8; PWHASH.salt_len],
+    #[serde(with = "serde_ext::base64")]
+    pub(super) nonce: [u8; AEAD.nonce_len],
+    #[serde(with = "serde_ext::base64")]
+    pub(super) tag: [u8; AEAD.tag_len],
+    #[serde(with = "serde_ext::base64")]
+    pub(super) master_key: [u8; KDF.key_len],
+}
+
+impl KeySlot {
+    /// Encrypts `master_key` with a key derived from `password`.
+    pub(super) fn seal(
+        master_key: &[u8],
+        password: SecretBytes,
+        ops_limit: usize,
+        mem_limit: usize,
+    ) -> Result<Self> {
+        let salt = utils::gen_rand_bytes::<{ PWHASH.salt_len }>();
+        let mut key = [0; AEAD.key_len];
+        PWHASH.derive_key(
+            &mut key,
+            &password,
+            &salt,
+            Limit::Custom(ops_limit),
+            Limit::Custom(mem_limit),
+        )?;
+        drop(password);
+        let nonce = utils::gen_rand_bytes::<{ AEAD.nonce_len }>();
+        let mut tag = [0; AEAD.tag_len];
+        let mut encrypted_master_key = [0; KDF.key_len];
+        AEAD.encrypt(
+            &mut encrypted_master_key,
+            &mut tag,
+            master_key,
+            Some(b"bijou"),
+            &nonce,
+            &key,
+        )?;
+        Ok(Self {
+            salt,
+            nonce,
+            tag,
+            master_key: encrypted_master_key,
+        })
+    }
+
+    /// Decrypts the master key, failing if `password` is incorrect.
+    fn open(&self, password: &[u8], ops_limit: usize, mem_limit: usize) -> Result<SecretBytes> {
+        let mut key = [0; AEAD.key_len];
+        PWHASH.derive_key(
+            &mut key,
+            password,
+            &self.salt,
+            Limit::Custom(ops_limit),
+            Limit::Custom(mem_limit),
+        )?;
+        let mut master_key = SecretBytes::allocate(KDF.key_len);
+        master_key.copy_from_slice(&self.master_key);
+        AEAD.decrypt_inplace(
+            &mut master_key,
+            &self.tag,
+            Some(b"bijou"),
+            &self.nonce,
+            &key,
+        )?;
+        Ok(master_key)
+    }
+}
+
+/// The key store of a Bijou (`keystore.json`, or a record in the
+/// storage for [embedded] Bijous), which holds the master key
+/// encrypted with a key derived from the password.
+///
+/// [embedded]: crate::config::Config::embedded
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyStore {
+    #[serde(skip)]
+    pub(super) path: StdPathBuf,
+
+    pub(super) version: u32,
+
+    #[serde(with = "serde_ext::base64")]
+    pub(super) salt: [u8; PWHASH.salt_len],
+    #[serde(with = "serde_ext::base64")]
+    pub(super) nonce: [u8; AEAD.nonce_len],
+    #[serde(with = "serde_ext::base64")]
+    pub(super) tag: [u8; AEAD.tag_len],
+
+    pub(super) ops_limit: usize,
+    pub(super) mem_limit: usize,
+
+    #[serde(with = "serde_ext::base64")]
+    pub(super) master_key: [u8; KDF.key_len],
+
+    /// Copies of [`Config::uuid`] and [`Config::label`], readable
+    /// without the password.
+    ///
+    /// [`Config::uuid`]: crate::Config::uuid
+    /// [`Config::label`]: crate::Config::label
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) uuid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) label: Option<String>,
+
+    /// The master key encrypted with the read-only password, see
+    /// [`Bijou::set_read_only_password`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) read_only: Option<KeySlot>,
+
+    /// Whether failed unlock attempts are throttled, see
+    /// [`BijouBuilder::throttle_unlock`]. Off for Bijous created by
+    /// older versions.
+    ///
+    /// [`BijouBuilder::throttle_unlock`]: crate::BijouBuilder::throttle_unlock
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(super) throttle_unlock: bool,
+}
+
+/// What can be learned about a Bijou without its password, see
+/// [`Bijou::probe`].
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProbeInfo {
+    /// The version of the key store.
+    pub version: u32,
+    /// Whether the key store and the config are kept in the database,
+    /// see [`Config::embedded`].
+    ///
+    /// [`Config::embedded`]: crate::Config::embedded
+    pub embedded: bool,
+    /// The operation limit of Argon2id used to derive the key from
+    /// the password.
+    pub ops_limit: usize,
+    /// The memory limit of Argon2id, in bytes.
+    pub mem_limit: usize,
+    /// See [`KeyStore::uuid`].
+    pub uuid: Option<String>,
+    /// See [`KeyStore::label`].
+    pub label: Option<String>,
+    /// Whether a read-only password is set, see
+    /// [`Bijou::set_read_only_password`].
+    pub has_read_only_password: bool,
+    /// Whether failed unlock attempts are throttled, see
+    /// [`BijouBuilder::throttle_unlock`].
+    ///
+    /// [`BijouBuilder::throttle_unlock`]: crate::BijouBuilder::throttle_unlock
+    pub throttle_unlock: bool,
+}
+
+impl Bijou {
+    /// Inspects the Bijou at `path` without unlocking it, e.g. to
+    /// tell users how long unlocking will take before asking for the
+    /// password.
+    ///
+    /// Returns `None` if `path` does not contain a Bijou, and fails
+    /// with [`ErrorKind::IncompatibleVersion`] if the Bijou is too new
+    /// to be opened. No secret material is read, and nothing is
+    /// authenticated.
+    pub fn probe(path: impl AsRef<StdPath>) -> Result<Option<ProbeInfo>> {
+        let path = path.as_ref();
+        let embedded = embedded_db(path, true)?;
+        let bytes = match &embedded {
+            Some(db) => db.key(RocksDBFileSystem::KEYSTORE_KEY).read_owned()?,
+            None => match std::fs::read(path.join("keystore.json")) {
+                Ok(bytes) => Some(bytes),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+                Err(err) => return Err(err).context("failed to read keystore.json"),
+            },
+        };
+        let Some(bytes) = bytes else {
+            return Ok(None);
+        };
+
+        #[derive(Deserialize)]
+        struct Version {
+            version: u32,
+        }
+        let version = serde_json::from_slice::<Version>(&bytes)
+            .context("failed to parse keystore")?
+            .version;
+        if version > KeyStore::VERSION {
+            bail!(@IncompatibleVersion "keystore version {version} is not supported");
+        }
+        let keystore: KeyStore =
+            serde_json::from_slice(&bytes).context("failed to parse keystore")?;
+
+        Ok(Some(ProbeInfo {
+            version,
+            embedded: embedded.is_some(),
+            ops_limit: keystore.ops_limit,
+            mem_limit: keystore.mem_limit,
+            uuid: keystore.uuid,
+            label: keystore.label,
+            has_read_only_password: keystore.read_only.is_some(),
+            throttle_unlock: keystore.throttle_unlock,
+        }))
+    }
+}
+
+impl KeyStore {
+    /// The latest supported version of key stores.
+    pub(super) const VERSION: u32 = 0;
+
+    /// Loads the key store of the Bijou at `path`.
+    pub fn load(path: impl Into<StdPathBuf>) -> Result<Self> {
+        let path = path.into();
+        // opened read-only, since it may be on read-only media
+        let embedded = embedded_db(&path, true)?;
+        Self::load_in(path, embedded.as_ref())
+    }
+
+    /// Loads the key store of the Bijou at `path`, from `embedded` if
+    /// given.
+    pub(super) fn load_in(path: StdPathBuf, embedded: Option<&Database>) -> Result<Self> {
+        let mut keystore: KeyStore = match embedded {
+            Some(db) => (|| {
+                let bytes = db
+                    .key(RocksDBFileSystem::KEYSTORE_KEY)
+                    .read_owned()?
+                    .kind(ErrorKind::NotFound)?;
+                serde_json::from_slice(&bytes).wrap()
+            })()
+            .context("failed to read embedded keystore")?,
+            None => (|| {
+                serde_json::from_reader(std::fs::File::open(path.join("keystore.json")).wrap()?)
+                    .wrap()
+            })()
+            .context("failed to read keystore.json")?,
+        };
+        if keystore.version > Self::VERSION {
+            bail!(@IncompatibleVersion "keystore version {} is not supported", keystore.version);
+        }
+        keystore.path = path;
+
+        Ok(keystore)
+    }
+
+    /// Saves the key store, in `embedded` if given.
+    pub(super) fn save(&self, embedded: Option<&Database>) -> Result<()> {
+        if let Some(db) = embedded {
+            return serde_json::to_vec(self)
+                .wrap()
+                .and_then(|bytes| db.key(RocksDBFileSystem::KEYSTORE_KEY).write(bytes))
+                .context("failed to save embedded keystore");
+        }
+        // replaced atomically, since a torn key store loses the Bijou
+        let temp = self.path.join("keystore.json.tmp");
+        (|| {
+            serde_json::to_writer_pretty(std::fs::File::create(&temp).wrap()?, self).wrap()?;
+            std::fs::rename(&temp, self.path.join("keystore.json")).wrap()
+        })()
+        .context("failed to save keystore.json")
+    }
+
+    /// The path of the Bijou this key store belongs to.
+    pub fn path(&self) -> &StdPath {
+        &self.path
+    }
+
+    /// The unique ID of the Bijou, see [`Config::uuid`].
+    ///
+    /// Like everything in the key store, this is not authenticated
+    /// before unlocking, and should only be used for display.
+    ///
+    /// [`Config::uuid`]: crate::Config::uuid
+    pub fn uuid(&self) -> Option<&str> {
+        self.uuid.as_deref()
+    }
+
+    /// The label of the Bijou, see [`Config::label`].
+    ///
+    /// Not authenticated, see [`KeyStore::uuid`].
+    ///
+    /// [`Config::label`]: crate::Config::label
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Decrypts the master key with the password, or with the
+    /// read-only password if one is set.
+    ///
+    /// This is slow by design since the key is derived using
+    /// Argon2id (twice for the read-only password, which is tried
+    /// second). If enabled (see [`BijouBuilder::throttle_unlock`]),
+    /// failed attempts are recorded, and further attempts are delayed
+    /// exponentially after several failures.
+    ///
+    /// See [`Bijou::open`] for more details about `password`.
+    ///
+    /// [`Bijou::open`]: crate::Bijou::open
+    /// [`BijouBuilder::throttle_unlock`]: crate::BijouBuilder::throttle_unlock
+    pub fn unlock(&self, password: impl Into<SecretBytes>) -> Result<MasterKey> {
+        let password = password.into();
+
+        let throttle = self
+            .throttle_unlock
+            .then(|| UnlockThrottle::load(self.path.join("unlock.json")));
+        if let Some(throttle) = &throttle {
+            throttle.wait();
+        }
+
+        let (master_key, read_only) = match self.unlock_slot(&password) {
+            Ok(master_key) => (master_key, false),
+            Err(err) => match self
+                .read_only
+                .as_ref()
+                .map(|slot| slot.open(&password, self.ops_limit, self.mem_limit))
+            {
+                Some(Ok(master_key)) => (master_key, true),
+                _ => {
+                    if let Some(throttle) = throttle {
+                        throttle.fail();
+                    }
+                    return Err(err).context("incorrect password");
+                }
+            },
+        };
+        drop(password);
+        if let Some(throttle) = throttle {
+            throttle.succeed();
+        }
+
+        Ok(MasterKey {
+            bytes: master_key,
+            read_only,
+        })
+    }
+
+    /// Decrypts the master key with the (full-access) password.
+    fn unlock_slot(&self, password: &[u8]) -> Result<SecretBytes> {
+        KeySlot {
+            salt: self.salt,
+            nonce: self.nonce,
+            tag: self.tag,
+            master_key: self.master_key,
+        }
+        .open(password, self.ops_limit, self.mem_limit)
+    }
+
+    /// Unlocks the master key with `password`, failing with
+    /// [`ErrorKind::PermissionDenied`] if it's the read-only one.
+    fn unlock_full(&self, password: impl Into<SecretBytes>) -> Result<MasterKey> {
+        let master_key = self.unlock(password)?;
+        if master_key.read_only {
+            bail!(@PermissionDenied? "the read-only password cannot be used here");
+        }
+        Ok(master_key)
+    }
+
+    /// Encrypts the master key with a key derived from `new` with the
+    /// given cost instead, after decrypting it with `old`, which must
+    /// not be the read-only password. The key store is not saved.
+    ///
+    /// The read-only password is derived with the same cost, so the
+    /// cost can't be changed while it is set.
+    pub(super) fn change_password(
+        &mut self,
+        old: impl Into<SecretBytes>,
+        new: impl Into<SecretBytes>,
+        ops_limit: usize,
+        mem_limit: usize,
+    ) -> Result<()> {
+        let master_key = self.unlock_full(old)?;
+        if self.read_only.is_some() && (ops_limit, mem_limit) != (self.ops_limit, self.mem_limit) {
+            bail!(@InvalidInput "cannot change the key derivation cost while a read-only password is set");
+        }
+        let slot = KeySlot::seal(&master_key.bytes, new.into(), ops_limit, mem_limit)?;
+
+        self.salt = slot.salt;
+        self.nonce = slot.nonce;
+        self.tag = slot.tag;
+        self.master_key = slot.master_key;
+        self.ops_limit = ops_limit;
+        self.mem_limit = mem_limit;
+        Ok(())
+    }
+
+    /// Sets or removes the read-only password, after decrypting the
+    /// master key with `password`. The key store is not saved.
+    pub(super) fn set_read_only_password(
+        &mut self,
+        password: impl Into<SecretBytes>,
+        read_only_password: Option<SecretBytes>,
+    ) -> Result<()> {
+        let master_key = self.unlock_full(password)?;
+        self.read_only = read_only_password
+            .map(|password| {
+                KeySlot::seal(&master_key.bytes, password, self.ops_limit, self.mem_limit)
+            })
+            .transpose()?;
+        Ok(())
+    }
+}
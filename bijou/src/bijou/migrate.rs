@@ -0,0 +1,190 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::{builder::validate_storage, save_config, scrub::Throttle, TaskKind, TaskState};
+use crate::{
+    bail,
+    config::{BuildStorage, FileStorage},
+    fs::{MigratingFileSystem, RawFileSystem},
+    Bijou, FileKind, Result,
+};
+use std::{collections::HashSet, sync::Arc};
+use tracing::info;
+
+type ArcRawFileSystem = Arc<dyn RawFileSystem + Send + Sync>;
+
+/// Stages of [`TaskKind::MigrateStorage`].
+const MOVE: u32 = 0;
+const CLEAN_UP: u32 = 1;
+
+/// Adds resources of the Bijou used by `storage` to `result`.
+fn resources(storage: &FileStorage, result: &mut Vec<&'static str>) {
+    match storage {
+        FileStorage::Local | FileStorage::RocksDB => result.push("data directory"),
+        FileStorage::Split { inner, .. } => {
+            result.push("cluster maps");
+            resources(inner, result);
+        }
+        FileStorage::Tracking { inner } => {
+            result.push("tracked metadata");
+            resources(inner, result);
+        }
+        FileStorage::OpenDAL { .. } => {}
+        FileStorage::Flaky { inner, .. } => resources(inner, result),
+        FileStorage::Migrating { from, to } => {
+            resources(from, result);
+            resources(to, result);
+        }
+    }
+}
+
+impl Bijou {
+    /// Creates a task that moves raw files of this Bijou to `storage`,
+    /// which is run by [`Bijou::migrate_storage`]. Records in the
+    /// database are left untouched, except for the ones kept by the
+    /// storages themselves.
+    ///
+    /// Fails if both storages use the same resources, e.g. the data
+    /// directory for [`FileStorage::Local`], or records in the
+    /// database for [`FileStorage::Tracking`].
+    pub fn start_storage_migration(&self, storage: FileStorage) -> Result<u64> {
+        self.check_writable()?;
+        if matches!(self.config.storage, FileStorage::Migrating { .. }) {
+            bail!(@InvalidInput "storage is already being migrated");
+        }
+        if self.embedded.is_some() {
+            bail!(@Unsupported "storage of embedded Bijous cannot be migrated");
+        }
+        validate_storage(&storage)?;
+
+        let (mut used, mut wanted) = (Vec::new(), Vec::new());
+        resources(&self.config.storage, &mut used);
+        resources(&storage, &mut wanted);
+        if let Some(resource) = wanted.iter().find(|it| used.contains(it)) {
+            bail!(@InvalidInput "both storages use the {resource}");
+        }
+
+        self.start_task(TaskKind::MigrateStorage {
+            from: self.config.storage.clone(),
+            to: storage,
+        })
+    }
+
+    /// Saves `storage` in the config and switches to it.
+    fn switch_storage(&mut self, storage: FileStorage, raw_fs: ArcRawFileSystem) -> Result<()> {
+        self.raw_fs.flush()?;
+        let mut config = self.config.clone();
+        config.storage = storage;
+        save_config(
+            &self.path,
+            self.embedded.as_deref(),
+            &config,
+            &self.config_key,
+        )?;
+        info!(storage = ?config.storage, "switched storage");
+        self.config = config;
+        self.raw_fs = raw_fs;
+        Ok(())
+    }
+
+    /// Runs a storage migration created by
+    /// [`Bijou::start_storage_migration`] from where it was last
+    /// checkpointed, until it finishes.
+    ///
+    /// The Bijou is first switched to [`FileStorage::Migrating`], and
+    /// raw files are moved one by one (see [`MigratingFileSystem`]),
+    /// so that it stays usable if the migration is interrupted. It is
+    /// then switched to the new storage, and raw files in the old
+    /// storage are removed.
+    ///
+    /// `rate_limit` is the maximum number of raw bytes to copy per
+    /// second.
+    pub fn migrate_storage(&mut self, id: u64, rate_limit: Option<u64>) -> Result<TaskState> {
+        self.check_writable()?;
+        let (mut state, mut checkpoints) = self.load_task(id)?;
+        let TaskKind::MigrateStorage { from, to } = state.kind.clone() else {
+            bail!(@InvalidInput "task {id} is not a storage migration");
+        };
+
+        let data_dir = self.path.join("data");
+        let migrating = FileStorage::Migrating {
+            from: Box::new(from.clone()),
+            to: Box::new(to.clone()),
+        };
+        if self.config.storage == from {
+            let raw_fs = Arc::new(MigratingFileSystem::new(
+                Arc::clone(&self.raw_fs),
+                to.build(&self.db, &data_dir, self.algo.block_size())?,
+                Arc::clone(&self.db),
+            ));
+            self.switch_storage(migrating.clone(), raw_fs)?;
+        } else if self.config.storage != migrating && self.config.storage != to {
+            bail!(@InvalidInput "storage of the Bijou has changed since task {id} was created");
+        }
+        if self.config.storage == to && state.stage == MOVE {
+            // interrupted right after switching to the new storage
+            state.stage = CLEAN_UP;
+            state.cursor = None;
+        }
+        let old = match self.raw_fs.migrating() {
+            Some(fs) => Arc::clone(fs.from()),
+            None => from.build(&self.db, &data_dir, self.algo.block_size())?,
+        };
+
+        let mut throttle = Throttle::new(rate_limit);
+        let block_size = self.algo.block_size() as usize;
+        let (mut buffer, mut other) = (vec![0; block_size], vec![0; block_size]);
+        while state.stage <= CLEAN_UP {
+            let mut visited = HashSet::new();
+            let mut files = self.iter_files();
+            files.kind(FileKind::File);
+            if let Some(cursor) = state.cursor {
+                files.after(cursor);
+            }
+            for meta in files {
+                let meta = meta?;
+                // clones share their content
+                let content = meta.content_id();
+                if visited.insert(content) {
+                    if state.stage == MOVE {
+                        let fs = self.raw_fs.migrating().unwrap();
+                        if let Some(size) = fs.move_file(content, (&mut buffer, &mut other))? {
+                            throttle.consume(size)?;
+                        }
+                    } else {
+                        if old.exists(content)? {
+                            old.unlink(content)?;
+                        }
+                        MigratingFileSystem::moved_key(&self.db, content).delete()?;
+                    }
+                }
+                state.files += 1;
+                state.cursor = Some(meta.id);
+                checkpoints.save(&mut state, &throttle, false)?;
+            }
+
+            if state.stage == MOVE {
+                let new = Arc::clone(self.raw_fs.migrating().unwrap().to());
+                self.switch_storage(to.clone(), new)?;
+            }
+            state.stage += 1;
+            state.cursor = None;
+            checkpoints.save(&mut state, &throttle, true)?;
+        }
+        old.flush()?;
+
+        checkpoints.finish(state, &throttle)
+    }
+}
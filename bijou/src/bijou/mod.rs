@@ -13,30 +13,84 @@
 // limitations under the License.
 //
 
+mod app;
+mod background;
+mod backup;
+mod builder;
+mod check;
+mod degraded;
+mod dump;
+mod export;
+mod extra_time;
 mod file;
 mod fs;
-
+mod hash;
+mod import;
+mod iter;
+mod keystore;
+mod migrate;
+mod pin;
+mod quota;
+mod resolve;
+mod reverse;
+mod scrub;
+mod shred;
+mod task;
+mod tree;
+mod unlock;
+mod usage;
+
+pub use app::AppStorage;
+pub use background::{BackgroundScrub, BackgroundScrubState};
+pub use backup::ReconcileReport;
+pub use builder::{BijouBuilder, Embedded, Separate};
+pub use check::{CheckEvent, CheckIssue, CheckOptions, CheckReport};
+pub use extra_time::ExtraTime;
 pub use file::File;
 pub use fs::BijouFs;
+pub use hash::HashAlgorithm;
+pub use iter::FileIterator;
+pub use keystore::{KeyStore, MasterKey, ProbeInfo};
+pub use scrub::{ScrubEvent, ScrubFailure, ScrubOptions, ScrubProgress, ScrubReport};
+pub use task::{TaskFailure, TaskKind, TaskState};
+pub use tree::TreeOptions;
+
+pub(crate) use resolve::Resolver;
 
 #[cfg(feature = "fuse")]
 mod fuse;
 #[cfg(feature = "fuse")]
-pub use fuse::BijouFuse;
+pub use fuse::{BijouFuse, OpClass, OwnerPolicy};
 
 use crate::{
-    algo::Algorithm,
+    algo::{
+        self, AlgoKey, Algorithm, BlockIndex, CipherSize, CryptoCounters, CryptoStats, PlainSize,
+    },
     anyhow, bail,
+    buffer::BufferPool,
+    clock::{Clock, SystemClock},
     crypto::{cast_key, crypto_error, split_nonce_tag, xchacha20_siv},
-    db::{consts, Database, DatabaseKey, RawKeyType},
-    error::ResultExt,
+    db::{self, columns, consts, Database, DatabaseKey, DbStats},
+    dir_times::DeferredTimes,
+    error::{ErrorOrigin, ResultExt},
     fs::{
-        config::Config, obtain_metadata, path::Component, DirItem, FileKind, Inode, LowLevelFile,
-        RawFileMeta, RawFileSystem, UnixPerms,
+        complete_metadata,
+        config::{
+            BuildStorage, Config, DirTimePolicy, FileStorage, NormalizationPolicy, RuntimeOptions,
+        },
+        obtain_metadata,
+        path::Component,
+        DirItem, FileAttributes, FileFlags, FileKind, Inode, LowLevelFile, RawFileMeta,
+        RawFileSystem, RenameFlags, SlowOpLog, TimePolicy, UnixPerms,
     },
+    id_alloc::{IdAllocator, IdGenerator, RandomIds, Reservation},
     id_lock::IdLock,
+    low_level::raw_fs::RocksDBFileSystem,
+    mime,
+    password::PasswordPolicy,
     path::Path,
-    serde_ext,
+    quota::{Charged, Quotas},
+    refcount::RefCounter,
     sodium::{
         aead::XCHACHA20_POLY1305_IETF as AEAD,
         kdf::BLAKE2B as KDF,
@@ -55,32 +109,267 @@ use ring::{
     error::Unspecified,
     hkdf::{self, KeyType, Prk},
 };
-use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashSet,
     path::{Path as StdPath, PathBuf as StdPathBuf},
-    sync::{atomic::AtomicU32, Arc},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex, RwLock, RwLockWriteGuard,
+    },
+    time::Duration,
 };
-use tracing::{info, trace};
+use tracing::{info, trace, warn};
+use unicode_normalization::UnicodeNormalization;
 
 pub const SYMBOLIC_MAX_DEPTH: u32 = 40;
 
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct KeyStore {
-    version: u32,
+/// Makes sure that `path` is an empty directory, creating it
+/// if it does not exist.
+fn prepare_empty_dir(path: &StdPath) -> Result<()> {
+    if path.exists() {
+        if !path.is_dir() || path.read_dir().wrap()?.next().is_some() {
+            bail!(@AlreadyExists "not an empty directory: {}", path.display());
+        }
+    } else {
+        std::fs::create_dir(path)
+            .context("failed to create directory")
+            .kind(ErrorKind::AlreadyExists)?;
+    }
+    Ok(())
+}
+
+/// Opens the database of the storage of the Bijou at `path` if the
+/// Bijou is [embedded], i.e. it has no `config.json` but a RocksDB
+/// storage.
+///
+/// [embedded]: Config::embedded
+fn embedded_db(path: &StdPath, read_only: bool) -> Result<Option<Database>> {
+    if path.join("config.json").exists() || !path.join("data").join("CURRENT").is_file() {
+        return Ok(None);
+    }
+    Database::open(path.join("data"), None, false, read_only).map(Some)
+}
+
+/// Prefix of saved configs, see [`config_header`]. Configs saved by
+/// older versions have no header.
+const CONFIG_MAGIC: [u8; 4] = *b"BJC\x01";
+
+/// Returns the header saved in clear before the encrypted config,
+/// which is also its associated data. This binds the config to the
+/// version and the UUID of the key store, so that mixing up files of
+/// different Bijous is reported as such.
+fn config_header(version: u32, uuid: Option<&str>) -> Vec<u8> {
+    let uuid = uuid.unwrap_or_default().as_bytes();
+    let mut header = CONFIG_MAGIC.to_vec();
+    header.extend_from_slice(&version.to_le_bytes());
+    header.extend_from_slice(&(uuid.len() as u32).to_le_bytes());
+    header.extend_from_slice(uuid);
+    header
+}
+
+/// Checks the header of a saved config against `keystore`, failing
+/// with [`ErrorKind::Mismatch`] if it belongs to another key store.
+/// Returns the length of the header.
+fn check_config_header(bytes: &[u8], keystore: &KeyStore) -> Result<usize> {
+    let read_u32 = |pos: usize| {
+        bytes
+            .get(pos..pos + 4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    };
+    let (Some(version), Some(uuid_len)) = (read_u32(4), read_u32(8)) else {
+        bail!(@CryptoError "config is truncated");
+    };
+    let len = 12 + uuid_len as usize;
+    let Some(uuid) = bytes.get(12..len) else {
+        bail!(@CryptoError "config is truncated");
+    };
+
+    if version != keystore.version {
+        bail!(@Mismatch "config is for key store version {version}, but the key store is version {}", keystore.version);
+    }
+    let expected = keystore.uuid.as_deref().unwrap_or_default();
+    if uuid != expected.as_bytes() {
+        bail!(@Mismatch "config belongs to Bijou {:?}, but the key store to {expected:?}", String::from_utf8_lossy(uuid));
+    }
+    Ok(len)
+}
+
+/// Reads and decrypts the config of the Bijou at `path`, or in
+/// `embedded` if given, checking that it belongs to `keystore`.
+///
+/// Also returns whether the config has no header, i.e. it's saved by
+/// an older version and not bound to the key store yet.
+fn load_config(
+    path: &StdPath,
+    embedded: Option<&Database>,
+    keystore: &KeyStore,
+    config_key: &SecretBytes,
+) -> Result<(Config, bool)> {
+    let mut bytes = match embedded {
+        Some(db) => db
+            .key(RocksDBFileSystem::CONFIG_KEY)
+            .read_owned()?
+            .context("embedded config not found")?,
+        None => std::fs::read(path.join("config.json")).context("failed to read config.json")?,
+    };
+    let legacy = !bytes.starts_with(&CONFIG_MAGIC);
+    let header_len = if legacy {
+        0
+    } else {
+        check_config_header(&bytes, keystore)?
+    };
+    if bytes.len() < header_len + AEAD.nonce_len + AEAD.tag_len {
+        bail!(@CryptoError "config is truncated");
+    }
+
+    let (header, rest) = bytes.split_at_mut(header_len);
+    // Safety
+    //
+    // libsodium uses char* under the hood, which
+    // does not require any alignment guarantees.
+    let (nonce, config, tag) = split_nonce_tag(rest, AEAD.nonce_len, AEAD.tag_len);
+    let ad = (!legacy).then_some(&*header);
+    AEAD.decrypt_inplace(config, tag, ad, nonce, config_key)
+        .map_err(|err| err.context("config is corrupted, or not encrypted with this master key"))?;
+    let config: Config = serde_json::from_slice(config).context("failed to parse config")?;
+
+    // configs without a header are not bound to the key store
+    if let (Some(uuid), Some(expected)) = (&config.uuid, &keystore.uuid) {
+        if uuid != expected {
+            bail!(@Mismatch "config belongs to Bijou {uuid:?}, but the key store to {expected:?}");
+        }
+    }
+    Ok((config, legacy))
+}
 
-    #[serde(with = "serde_ext::base64")]
-    salt: [u8; PWHASH.salt_len],
-    #[serde(with = "serde_ext::base64")]
-    nonce: [u8; AEAD.nonce_len],
-    #[serde(with = "serde_ext::base64")]
-    tag: [u8; AEAD.tag_len],
+/// Encrypts `config` with `config_key` and saves it in `path`, or in
+/// `embedded` if given, replacing the existing one atomically.
+fn save_config(
+    path: &StdPath,
+    embedded: Option<&Database>,
+    config: &Config,
+    config_key: &SecretBytes,
+) -> Result<()> {
+    let header = config_header(KeyStore::VERSION, config.uuid.as_deref());
+    let mut bytes = serde_json::to_vec(config).wrap()?;
+    let nonce = utils::gen_rand_bytes::<{ AEAD.nonce_len }>();
+    let mut tag = [0; AEAD.tag_len];
+    AEAD.encrypt_inplace(&mut bytes, &mut tag, &nonce, Some(&header), config_key)?;
+    bytes = header
+        .into_iter()
+        .chain(nonce)
+        .chain(bytes)
+        .chain(tag)
+        .collect::<Vec<_>>();
+
+    if let Some(db) = embedded {
+        return db
+            .key(RocksDBFileSystem::CONFIG_KEY)
+            .write(bytes)
+            .context("failed to save embedded config");
+    }
+    let temp = path.join("config.json.tmp");
+    std::fs::write(&temp, bytes).context("failed to save config.json")?;
+    std::fs::rename(temp, path.join("config.json")).context("failed to save config.json")?;
+    Ok(())
+}
 
-    ops_limit: usize,
-    mem_limit: usize,
+/// Generates a random (version 4) UUID.
+fn gen_uuid() -> String {
+    let mut bytes = utils::gen_rand_bytes::<16>();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
 
-    #[serde(with = "serde_ext::base64")]
-    master_key: [u8; KDF.key_len],
+/// Calls `f` on every item, spreading them across threads if there
+/// are enough of them.
+fn par_try_for_each<T: Send>(
+    items: &mut [T],
+    f: impl Fn(&mut T) -> Result<()> + Sync,
+) -> Result<()> {
+    const MIN_CHUNK_SIZE: usize = 16;
+
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = items.len().div_ceil(threads).max(MIN_CHUNK_SIZE);
+    if items.len() <= chunk_size {
+        items.iter_mut().try_for_each(f)
+    } else {
+        let f = &f;
+        std::thread::scope(|scope| {
+            items
+                .chunks_mut(chunk_size)
+                .map(|chunk| scope.spawn(move || chunk.iter_mut().try_for_each(f)))
+                .collect::<Vec<_>>()
+                .into_iter()
+                .try_for_each(|handle| handle.join().unwrap())
+        })
+    }
+}
+
+/// Runs `produce` on the current thread, and `f` on `threads` worker
+/// threads (or one per CPU core if `0`) for every item passed to the
+/// queue by `produce`.
+///
+/// Only a few items per worker are queued, and `produce` blocks
+/// until they are taken, so that memory usage stays bounded however
+/// many items there are.
+fn with_workers<T: Send, R>(
+    threads: usize,
+    f: impl Fn(T) + Sync,
+    produce: impl FnOnce(&dyn Fn(T)) -> R,
+) -> R {
+    let threads = match threads {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        threads => threads,
+    };
+    let (sender, receiver) = std::sync::mpsc::sync_channel(threads * 2);
+    let receiver = Mutex::new(receiver);
+    // the queue is closed once `produce` returns, stopping the workers
+    let produce = move || produce(&|item| sender.send(item).unwrap());
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                // not held while `f` runs
+                let item = receiver.lock().unwrap().recv();
+                let Ok(item) = item else {
+                    break;
+                };
+                f(item);
+            });
+        }
+        produce()
+    })
+}
+
+/// A file (or directory, symlink, etc.) to be created by
+/// [`Bijou::make_nodes`]. See [`Bijou::make_node`] for the fields.
+#[derive(Clone, Debug)]
+pub struct NewNode {
+    pub name: String,
+    pub kind: FileKind,
+    pub symlink: Option<String>,
+    pub perms: Option<UnixPerms>,
+}
+
+/// An entry removed from a batch that is not committed yet, see
+/// [`Bijou::unlink_inner`].
+#[derive(Default)]
+struct Unlinked<'a> {
+    /// The removed file, see [`Bijou::unlink`].
+    removed: Option<FileId>,
+    /// Raw content that lost its last reference, to be removed once
+    /// the batch is committed.
+    freed: Option<FileId>,
+    refs_guard: Option<RwLockWriteGuard<'a, ()>>,
 }
 
 /// The main Bijou interface providing low level APIs.
@@ -95,9 +384,26 @@ pub struct Bijou {
 
     config: Config,
 
+    /// Key of `config.json`, kept for saving changes of the config.
+    config_key: SecretBytes,
+    /// Database of the storage, which holds the key store and the
+    /// config if the Bijou is [embedded](Config::embedded).
+    embedded: Option<Arc<Database>>,
     content_key: hkdf::Prk,
     file_name_key: Option<SecretBytes>,
-
+    /// Key of [`ContentPin`](crate::format::ContentPin)s.
+    pin_hash_key: SecretBytes,
+
+    // Locks are always acquired in the following order to avoid
+    // deadlocks:
+    //
+    // 1. `rename_lock`, for renames across directories;
+    // 2. `raw_lock`;
+    // 3. `file_lock` of directories. If multiple directories are
+    //    involved, they are locked together with `IdLock::get_all`;
+    // 4. locks of reference counts in `refs`;
+    // 5. `file_lock` of other files;
+    // 6. the state of `quotas`.
     /// For files, this is acquired whenever the file is being
     /// read/written. Note that this is not necessarily acquired
     /// when the file is being opened. This conforms to the typical
@@ -107,16 +413,64 @@ pub struct Bijou {
     /// being modified (add, unlink, etc.).
     file_lock: Arc<IdLock<RawFileMeta>>,
 
+    /// Acquired (shared) while the raw storage is being modified,
+    /// and exclusively while a backup is being taken.
+    raw_lock: Arc<RwLock<()>>,
+
+    /// Acquired while renaming across directories, so that the
+    /// ancestry check in [`Bijou::rename_with_flags`] cannot be raced
+    /// by other renames.
+    rename_lock: Mutex<()>,
+
+    /// Reference counts of raw files, which can be shared
+    /// between files (see [`Bijou::clone_file`]).
+    refs: RefCounter,
+
+    /// Usage of directory quotas (see [`Bijou::set_quota`]).
+    quotas: Arc<Quotas>,
+
+    /// Counters of block encryption and decryption, shared by all
+    /// file keys.
+    crypto: Arc<CryptoCounters>,
+
+    /// Buffers of the block size, shared by all file handles.
+    block_buffers: Arc<BufferPool>,
+
+    /// IDs of new files and raw files.
+    ids: IdAllocator,
+
+    /// Directory times deferred by [`DirTimePolicy::Relaxed`].
+    dir_times: DeferredTimes,
+
+    /// Source of times of files and of delays.
+    clock: Arc<dyn Clock>,
+
+    /// IDs of maintenance tasks running in this process (see
+    /// [`Bijou::run_task`]).
+    running_tasks: Mutex<HashSet<u64>>,
+
+    /// Held while rewriting the key store.
+    keystore_lock: Mutex<()>,
+
     /// The currently opened file handles count for each file.
     ///
     /// The GC thread will periodically check files in the GC pool.
     /// If the file doesn't have opened handles anymore, the GC thread
     /// will remove it.
     file_open_counts: Arc<DashMap<FileId, Arc<AtomicU32>>>,
+
+    /// Whether to verify the integrity of files when opening them.
+    verify_on_open: bool,
+    /// Whether this Bijou is opened read-only. See [`Bijou::open_read_only`].
+    read_only: bool,
+    /// Whether the storage is unreachable. See [`Bijou::is_degraded`].
+    degraded: degraded::Degraded,
 }
 
 impl Bijou {
     const KDF_CTX: [u8; 8] = *b"@bijoufs";
+    /// The maximum number of idle buffers kept in `block_buffers`.
+    const MAX_IDLE_BUFFERS: usize = 64;
 
     /// Create a new Bijou.
     ///
@@ -127,88 +481,113 @@ impl Bijou {
     /// to create a [`SecretBytes`] from a mutable byte slice. This
     /// is to prevent the password from being copied around in memory.
     /// For more details, see [`SecretBytes`].
+    ///
+    /// A UUID is generated for the Bijou unless [`Config::uuid`] is
+    /// set.
     pub fn create(
         path: impl AsRef<StdPath>,
         password: impl Into<SecretBytes>,
         config: Config,
         ops_limit: Limit,
         mem_limit: Limit,
+    ) -> Result<()> {
+        Self::create_inner(
+            path.as_ref(),
+            password.into(),
+            None,
+            config,
+            ops_limit,
+            mem_limit,
+            false,
+        )
+    }
+
+    /// Same as [`Bijou::create`], but also sets the read-only
+    /// password if given (see [`Bijou::set_read_only_password`]) and
+    /// whether to throttle unlocking (see
+    /// [`BijouBuilder::throttle_unlock`]).
+    fn create_inner(
+        path: &StdPath,
+        password: SecretBytes,
+        read_only_password: Option<SecretBytes>,
+        mut config: Config,
+        ops_limit: Limit,
+        mem_limit: Limit,
+        throttle_unlock: bool,
     ) -> Result<()> {
         info!("creating Bijou");
 
-        let password = password.into();
+        prepare_empty_dir(path)?;
 
-        let path = path.as_ref();
-        if path.exists() {
-            if !path.is_dir() || path.read_dir().wrap()?.next().is_some() {
-                bail!(@AlreadyExists "not an empty directory: {}", path.display());
-            }
-        } else {
-            std::fs::create_dir(path)
-                .context("failed to create directory")
-                .kind(ErrorKind::AlreadyExists)?;
+        if config.uuid.is_none() {
+            config.uuid = Some(gen_uuid());
         }
 
-        // This is not made into SecretBytes because we'll encrypt it inplace later.
         let master_key = KDF.gen_key();
         let prk = KDF.prk(master_key.clone(), Self::KDF_CTX.as_slice());
         let config_key = prk.derive(0, AEAD.key_len)?;
 
-        let salt = utils::gen_rand_bytes::<{ PWHASH.salt_len }>();
-
-        let mut key = [0; AEAD.key_len];
-        PWHASH.derive_key(&mut key, &password, &salt, ops_limit, mem_limit)?;
-        drop(password);
-        let nonce = utils::gen_rand_bytes::<{ AEAD.nonce_len }>();
-        let mut tag = [0; AEAD.tag_len];
-
-        let mut encrypted_master_key = [0; KDF.key_len];
-        AEAD.encrypt(
-            &mut encrypted_master_key,
-            &mut tag,
-            &master_key,
-            Some(b"bijou"),
-            &nonce,
-            &key,
-        )?;
+        let ops_limit = ops_limit.eval(PWHASH.ops_limits);
+        let mem_limit = mem_limit.eval(PWHASH.mem_limits);
+        let slot = keystore::KeySlot::seal(&master_key, password, ops_limit, mem_limit)?;
+        let read_only = read_only_password
+            .map(|password| keystore::KeySlot::seal(&master_key, password, ops_limit, mem_limit))
+            .transpose()?;
         drop(master_key);
 
         let keystore = KeyStore {
-            version: 0,
+            path: path.to_owned(),
 
-            salt,
-            nonce,
-            tag,
+            version: KeyStore::VERSION,
 
-            ops_limit: ops_limit.eval(PWHASH.ops_limits),
-            mem_limit: mem_limit.eval(PWHASH.mem_limits),
+            salt: slot.salt,
+            nonce: slot.nonce,
+            tag: slot.tag,
 
-            master_key: encrypted_master_key,
+            ops_limit,
+            mem_limit,
+
+            master_key: slot.master_key,
+
+            uuid: config.uuid.clone(),
+            label: config.label.clone(),
+
+            read_only,
+
+            throttle_unlock,
         };
-        (|| {
-            serde_json::to_writer_pretty(
-                std::fs::File::create(path.join("keystore.json")).wrap()?,
-                &keystore,
-            )
-            .wrap()
-        })()
-        .context("failed to save keystore.json")?;
-
-        let mut bytes = serde_json::to_vec(&config).wrap()?;
-        let nonce = utils::gen_rand_bytes::<{ AEAD.nonce_len }>();
-        let mut tag = [0; AEAD.tag_len];
-        AEAD.encrypt_inplace(&mut bytes, &mut tag, &nonce, None, &config_key)?;
-        drop(config_key);
-        bytes = nonce
-            .into_iter()
-            .chain(bytes.into_iter())
-            .chain(tag.into_iter())
-            .collect::<Vec<_>>();
-        std::fs::write(path.join("config.json"), bytes).context("failed to save config.json")?;
+        let embedded = if config.embedded {
+            Some(Database::open(path.join("data"), None, false, false)?)
+        } else {
+            None
+        };
+        keystore.save(embedded.as_ref())?;
+
+        save_config(path, embedded.as_ref(), &config, &config_key)?;
 
         Ok(())
     }
 
+    /// Same as [`Bijou::create`], but rejects the password if it does
+    /// not satisfy the given policy.
+    ///
+    /// The policy is checked before the (expensive) key derivation.
+    /// See [`password`] for builtin policies.
+    ///
+    /// [`password`]: crate::password
+    pub fn create_with_policy(
+        path: impl AsRef<StdPath>,
+        password: impl Into<SecretBytes>,
+        config: Config,
+        ops_limit: Limit,
+        mem_limit: Limit,
+        policy: &dyn PasswordPolicy,
+    ) -> Result<()> {
+        let password = password.into();
+        policy.check(&password)?;
+        Self::create(path, password, config, ops_limit, mem_limit)
+    }
+
     /// Open an existing Bijou.
     ///
     /// `password` should be convertible to [`SecretBytes`] (e.g.
@@ -216,43 +595,63 @@ impl Bijou {
     /// to create a [`SecretBytes`] from a mutable byte slice. This
     /// is to prevent the password from being copied around in memory.
     /// For more details, see [`SecretBytes`].
+    ///
+    /// This is a shortcut for [`KeyStore::unlock`] followed by
+    /// [`Bijou::open_with_key`]. With the read-only password (see
+    /// [`Bijou::set_read_only_password`]), the Bijou is opened like
+    /// [`Bijou::open_read_only`] does.
     pub fn open(path: impl Into<StdPathBuf>, password: impl Into<SecretBytes>) -> Result<Self> {
-        let password = password.into();
+        let path = path.into();
+        if !path.is_dir() {
+            bail!(@NotFound "directory not found: {}", path.display());
+        }
+
+        let key = KeyStore::load(&path)?.unlock(password)?;
+        Self::open_with_key(path, &key)
+    }
+
+    /// Open an existing Bijou with an unlocked master key.
+    ///
+    /// The Bijou is opened read-only if the key was unlocked with the
+    /// read-only password. See [`KeyStore::unlock`].
+    pub fn open_with_key(path: impl Into<StdPathBuf>, key: &MasterKey) -> Result<Self> {
+        Self::open_with_mode(path.into(), key, false)
+    }
 
+    /// Open an existing Bijou read-only, e.g. from read-only media.
+    ///
+    /// Nothing is written to the Bijou directory, so the databases
+    /// must have been closed cleanly or their logs are replayed in
+    /// memory only. All mutating APIs, including opening files for
+    /// writing, fail with [`ErrorKind::ReadOnly`].
+    pub fn open_read_only(
+        path: impl Into<StdPathBuf>,
+        password: impl Into<SecretBytes>,
+    ) -> Result<Self> {
         let path = path.into();
         if !path.is_dir() {
             bail!(@NotFound "directory not found: {}", path.display());
         }
 
-        let file_lock = Arc::default();
+        let key = KeyStore::load(&path)?.unlock(password)?;
+        Self::open_with_key_read_only(path, &key)
+    }
 
-        let mut keystore: KeyStore = (|| {
-            serde_json::from_reader(std::fs::File::open(path.join("keystore.json")).wrap()?).wrap()
-        })()
-        .context("failed to read keystore.json")?;
-        if keystore.version > 0 {
-            bail!(@IncompatibleVersion "keystore version {} is not supported", keystore.version);
-        }
-
-        let mut key = [0; AEAD.key_len];
-        PWHASH.derive_key(
-            &mut key,
-            &password,
-            &keystore.salt,
-            Limit::Custom(keystore.ops_limit),
-            Limit::Custom(keystore.mem_limit),
-        )?;
+    /// Same as [`Bijou::open_read_only`], but with an unlocked master
+    /// key.
+    pub fn open_with_key_read_only(path: impl Into<StdPathBuf>, key: &MasterKey) -> Result<Self> {
+        Self::open_with_mode(path.into(), key, true)
+    }
 
-        let mut master_key: SecretBytes = SecretBytes::move_from(&mut keystore.master_key);
-        AEAD.decrypt_inplace(
-            &mut master_key,
-            &keystore.tag,
-            Some(b"bijou"),
-            &keystore.nonce,
-            &key,
-        )
-        .context("incorrect password")?;
-        let mk = KDF.prk(master_key, Self::KDF_CTX.as_slice());
+    fn open_with_mode(path: StdPathBuf, key: &MasterKey, read_only: bool) -> Result<Self> {
+        if !path.is_dir() {
+            bail!(@NotFound "directory not found: {}", path.display());
+        }
+
+        let file_lock = Arc::default();
+
+        let read_only = read_only || key.read_only;
+        let mk = KDF.prk(key.bytes.clone(), Self::KDF_CTX.as_slice());
 
         let config_key = mk.derive(0, AEAD.key_len)?;
         let content_key_bytes = mk.derive(1, hkdf::KeyType::len(&hkdf::HKDF_SHA256))?;
@@ -260,16 +659,13 @@ impl Bijou {
         let content_key = Prk::new_less_safe(hkdf::HKDF_SHA256, &content_key_bytes);
         drop(content_key_bytes);
 
-        let mut config =
-            std::fs::read(path.join("config.json")).context("failed to read config.json")?;
-        // Safety
-        //
-        // libsodium uses char* under the hood, which
-        // does not require any alignment guarantees.
-        let (nonce, config, tag) = split_nonce_tag(&mut config, AEAD.nonce_len, AEAD.tag_len);
-        AEAD.decrypt_inplace(config, tag, None, nonce, &config_key)?;
-        drop(config_key);
-        let config: Config = serde_json::from_slice(config).context("failed to parse config")?;
+        let embedded = embedded_db(&path, read_only)?.map(Arc::new);
+        let keystore = KeyStore::load_in(path.clone(), embedded.as_deref())?;
+        let (config, legacy) = load_config(&path, embedded.as_deref(), &keystore, &config_key)?;
+        if legacy && !read_only {
+            save_config(&path, embedded.as_deref(), &config, &config_key)?;
+            info!("bound config to the key store");
+        }
 
         info!("config: {config:?}");
 
@@ -285,37 +681,81 @@ impl Bijou {
             None
         };
 
+        let pin_hash_key = mk.derive(4, Self::PIN_KEY_LEN)?;
+
         let data_dir = path.join("data");
-        if !data_dir.is_dir() {
+        if !read_only && !data_dir.is_dir() {
             std::fs::create_dir_all(&data_dir).context("failed to create data directory")?;
         }
 
-        let db = Arc::new(Database::open(path.join("db"), db_key)?);
-        let raw_fs = config
-            .storage
-            .build(&db, &data_dir)
-            .context("failed to build storage")?;
+        let db = Arc::new(Database::open(
+            path.join("db"),
+            db_key,
+            config.db_statistics,
+            read_only,
+        )?);
+        let algo = algo::from_config(&config)?;
+        let raw_fs: Arc<dyn RawFileSystem + Send + Sync> = match &embedded {
+            // the database is already opened for the config
+            Some(db) => {
+                if config.storage != FileStorage::RocksDB {
+                    bail!(@InvalidInput "embedded Bijou must use RocksDB storage");
+                }
+                Arc::new(RocksDBFileSystem::new(Arc::clone(db)))
+            }
+            None => config
+                .storage
+                .build(&db, &data_dir, algo.block_size())
+                .context("failed to build storage")?,
+        };
 
         info!("launching Bijou");
 
         let file_open_counts = Arc::new(DashMap::<FileId, Arc<AtomicU32>>::new());
+        let refs = RefCounter::new(Arc::clone(&db));
+        let block_buffers = Arc::new(BufferPool::new(
+            algo.block_size() as usize,
+            Self::MAX_IDLE_BUFFERS,
+        ));
 
         let mut result = Self {
             path,
 
             db,
             raw_fs,
-            algo: config.to_algorithm()?,
+            algo,
 
             config,
 
+            config_key,
+            embedded,
             content_key,
             file_name_key,
+            pin_hash_key,
 
             file_lock,
+            raw_lock: Arc::default(),
+            rename_lock: Mutex::default(),
+            refs,
+            quotas: Arc::default(),
+            crypto: Arc::default(),
+            block_buffers,
+            ids: IdAllocator::new(Box::new(RandomIds)),
+            dir_times: DeferredTimes::default(),
+            clock: Arc::new(SystemClock),
+            running_tasks: Mutex::default(),
+            keystore_lock: Mutex::default(),
             file_open_counts,
+
+            verify_on_open: false,
+            read_only,
+            degraded: Default::default(),
         };
+        if result.config.offline_fallback {
+            result.probe_storage();
+        }
         result.init()?;
+        result.init_quotas()?;
         Ok(result)
     }
 
@@ -324,6 +764,150 @@ impl Bijou {
         &self.path
     }
 
+    /// Returns the config of this Bijou.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Changes the options that do not affect how data is stored,
+    /// and saves them in the config.
+    ///
+    /// Directory times deferred by [`DirTimePolicy::Relaxed`] are
+    /// flushed when switching to another policy.
+    pub fn set_runtime_options(&mut self, options: RuntimeOptions) -> Result<()> {
+        self.check_writable()?;
+        if self.config.dir_time_policy == DirTimePolicy::Relaxed
+            && options.dir_time_policy != DirTimePolicy::Relaxed
+        {
+            self.flush_dir_times()?;
+        }
+        let mut config = self.config.clone();
+        config.set_runtime_options(options);
+        save_config(
+            &self.path,
+            self.embedded.as_deref(),
+            &config,
+            &self.config_key,
+        )?;
+        info!(options = ?config.runtime_options(), "changed runtime options");
+        self.config = config;
+        Ok(())
+    }
+
+    /// Sets the label of this Bijou, see [`Config::label`].
+    pub fn set_label(&mut self, label: Option<String>) -> Result<()> {
+        self.check_writable()?;
+        let mut config = self.config.clone();
+        config.label = label;
+        save_config(
+            &self.path,
+            self.embedded.as_deref(),
+            &config,
+            &self.config_key,
+        )?;
+
+        let _guard = self.keystore_lock.lock().unwrap();
+        let mut keystore = KeyStore::load_in(self.path.clone(), self.embedded.as_deref())?;
+        keystore.uuid = config.uuid.clone();
+        keystore.label = config.label.clone();
+        keystore.save(self.embedded.as_deref())?;
+
+        info!(label = ?config.label, "changed label");
+        self.config = config;
+        Ok(())
+    }
+
+    /// Changes the password of this Bijou, failing if `old` is
+    /// incorrect.
+    ///
+    /// Only the master key in the key store is encrypted again, so
+    /// this is safe while the Bijou is in use, and other instances
+    /// opened earlier keep working. Both passwords are handled like
+    /// the one of [`Bijou::open`].
+    ///
+    /// The cost of deriving the key from the password (see
+    /// [`BijouBuilder::ops_limit`] and [`BijouBuilder::mem_limit`])
+    /// can be changed at the same time, e.g. to raise it as hardware
+    /// gets faster. `None` keeps the current limit, and limits below
+    /// [`Limit::Interactive`] are rejected with
+    /// [`ErrorKind::InvalidInput`]. The read-only password is derived
+    /// with the same cost, so this fails if one is set and the cost
+    /// changes. It can be removed first with
+    /// [`Bijou::set_read_only_password`].
+    pub fn change_password(
+        &self,
+        old: impl Into<SecretBytes>,
+        new: impl Into<SecretBytes>,
+        ops_limit: Option<Limit>,
+        mem_limit: Option<Limit>,
+    ) -> Result<()> {
+        let ops_limit = ops_limit.map(|limit| limit.eval(PWHASH.ops_limits));
+        let mem_limit = mem_limit.map(|limit| limit.eval(PWHASH.mem_limits));
+        if ops_limit.is_some_and(|limit| limit < PWHASH.ops_limits[0]) {
+            bail!(@InvalidInput "operation limit must be at least {}", PWHASH.ops_limits[0]);
+        }
+        if mem_limit.is_some_and(|limit| limit < PWHASH.mem_limits[0]) {
+            bail!(@InvalidInput "memory limit must be at least {}", PWHASH.mem_limits[0]);
+        }
+
+        self.check_writable()?;
+        let _guard = self.keystore_lock.lock().unwrap();
+        let mut keystore = KeyStore::load_in(self.path.clone(), self.embedded.as_deref())?;
+        let ops_limit = ops_limit.unwrap_or(keystore.ops_limit);
+        let mem_limit = mem_limit.unwrap_or(keystore.mem_limit);
+        keystore.change_password(old, new, ops_limit, mem_limit)?;
+        keystore.save(self.embedded.as_deref())?;
+        info!(ops_limit, mem_limit, "changed password");
+        Ok(())
+    }
+
+    /// Sets or removes the read-only password of this Bijou, failing
+    /// if `password` is not the (full-access) password.
+    ///
+    /// The read-only password unlocks the same master key, but the
+    /// Bijou is then always opened read-only, e.g. for backup jobs.
+    /// Note that this is enforced by Bijou rather than by
+    /// cryptography: anyone holding the read-only password and
+    /// write access to the storage could still modify files with a
+    /// modified Bijou.
+    pub fn set_read_only_password(
+        &self,
+        password: impl Into<SecretBytes>,
+        read_only_password: Option<SecretBytes>,
+    ) -> Result<()> {
+        self.check_writable()?;
+        let _guard = self.keystore_lock.lock().unwrap();
+        let mut keystore = KeyStore::load_in(self.path.clone(), self.embedded.as_deref())?;
+        keystore.set_read_only_password(password, read_only_password)?;
+        keystore.save(self.embedded.as_deref())?;
+        info!(
+            set = keystore.read_only.is_some(),
+            "changed read-only password"
+        );
+        Ok(())
+    }
+
+    /// Sets whether to verify the integrity of files every time
+    /// they are opened. Defaults to `false`.
+    ///
+    /// This detects tampering at access time, at the cost of
+    /// reading the whole file on open. See [`LowLevelFile::verify`].
+    pub fn set_verify_on_open(&mut self, verify: bool) {
+        self.verify_on_open = verify;
+    }
+
+    /// Sets how IDs of new files are generated. Defaults to
+    /// [`RandomIds`].
+    pub fn set_id_generator(&mut self, generator: impl IdGenerator + 'static) {
+        self.ids.set_generator(Box::new(generator));
+    }
+
+    /// Sets the source of time, used for times of files and for
+    /// delays. Defaults to [`SystemClock`].
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Arc::new(clock);
+    }
+
     fn child_key<T>(&self, key: DatabaseKey<T>, name: &str) -> Result<DatabaseKey<DirItem>> {
         if let Some(file_name_key) = &self.file_name_key {
             if name != "." && name != ".." {
@@ -336,21 +920,105 @@ impl Bijou {
                 )
                 .map_err(crypto_error)?;
                 name.extend(tag.0);
-                return Ok(key.derive(consts::DIR_DERIVE).derive(&name).typed());
+                return Ok(self.dir_key(key).derive(&name).typed());
             }
         }
 
-        Ok(key
-            .derive(consts::DIR_DERIVE)
-            .derive(name.as_bytes())
-            .typed())
+        Ok(self.dir_key(key).derive(name.as_bytes()).typed())
+    }
+
+    /// Returns the prefix of the directory entries of `key`.
+    fn dir_key<T>(&self, key: DatabaseKey<T>) -> DatabaseKey {
+        key.derive(consts::Derive::Dir).column(columns::DIRS)
+    }
+
+    /// Returns the prefix of the xattrs of `file`.
+    fn xattrs_key(&self, file: FileId) -> DatabaseKey {
+        self.get_key(file)
+            .derive(consts::Derive::Xattr)
+            .column(columns::XATTRS)
+    }
+
+    fn xattr_key(&self, file: FileId, name: &str) -> DatabaseKey {
+        self.xattrs_key(file).derive(name)
+    }
+
+    /// Decodes `name` of an entry of `parent` as stored in the
+    /// database, decrypting it in place if needed.
+    fn decode_name(&self, parent: FileId, name: &mut [u8]) -> Result<String> {
+        if let Some(key) = &self.file_name_key {
+            if name != b"." && name != b".." {
+                assert!(name.len() > xchacha20_siv::ABYTES);
+                let (name, tag) = name.split_at_mut(name.len() - xchacha20_siv::ABYTES);
+                // the same associated data as in `child_key`
+                xchacha20_siv::decrypt_inplace(
+                    name,
+                    cast_key(tag),
+                    &self.get_key(parent).key,
+                    cast_key(key),
+                )
+                .map_err(|_| anyhow!(@CryptoError "failed to decrypt filename"))?;
+                return Ok(String::from_utf8(name.to_vec()).unwrap());
+            }
+        }
+        Ok(String::from_utf8(name.to_vec()).unwrap())
+    }
+
+    /// Returns the key of the entry of `file` in the reverse index,
+    /// for its entry `entry_key` in `parent`.
+    fn reverse_key(
+        &self,
+        file: FileId,
+        parent: FileId,
+        entry_key: &DatabaseKey<DirItem>,
+    ) -> DatabaseKey {
+        self.get_key(file)
+            .derive(consts::Derive::Parent)
+            .derive(parent)
+            .derive(&entry_key.key[columns::PREFIX_LEN..])
+    }
+
+    /// Adds the entry `entry_key` of `parent`, and its counterpart in
+    /// the reverse index if enabled (see [`Config::reverse_index`]).
+    fn put_entry(
+        &self,
+        batch: &mut WriteBatch,
+        parent: FileId,
+        entry_key: &DatabaseKey<DirItem>,
+        item: &DirItem,
+    ) -> Result<()> {
+        entry_key.put_batch(batch, item)?;
+        if self.config.reverse_index {
+            self.reverse_key(item.id, parent, entry_key)
+                .write_batch(batch, b"");
+        }
+        Ok(())
+    }
+
+    /// Removes the entry `entry_key` of `parent` pointing to `file`,
+    /// and its counterpart in the reverse index.
+    fn delete_entry(
+        &self,
+        batch: &mut WriteBatch,
+        parent: FileId,
+        entry_key: &DatabaseKey<DirItem>,
+        file: FileId,
+    ) {
+        entry_key.delete_batch(batch);
+        if self.config.reverse_index {
+            self.reverse_key(file, parent, entry_key)
+                .delete_batch(batch);
+        }
     }
 
     fn init(&mut self) -> Result<()> {
         let root_id = FileId::ROOT;
         let root_key = self.get_key(root_id);
         if !root_key.exists()? {
-            let now = Utc::now();
+            if self.read_only {
+                bail!(@NotFound "root directory not found");
+            }
+            let now = self.clock.now();
             let attrs = FileMeta {
                 id: root_id,
                 kind: FileKind::Directory,
@@ -371,17 +1039,14 @@ impl Bijou {
                 } else {
                     None
                 },
+
+                attributes: FileAttributes::EMPTY,
+
+                content: None,
             };
 
             let mut batch = self.db.batch();
             root_key.put_batch(&mut batch, &attrs)?;
-            self.child_key(root_key.clone(), ".")?.put_batch(
-                &mut batch,
-                &DirItem {
-                    id: root_id,
-                    kind: FileKind::Directory,
-                },
-            )?;
             self.child_key(root_key, "..")?.put_batch(
                 &mut batch,
                 &DirItem {
@@ -396,25 +1061,43 @@ impl Bijou {
         Ok(())
     }
 
+    /// Returns whether this Bijou is opened read-only.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Fails with [`ErrorKind::ReadOnly`] if this Bijou is opened
+    /// read-only.
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            bail!(@ReadOnly? "Bijou is opened read-only");
+        }
+        if self.check_storage().is_err() {
+            bail!(@ReadOnly? "Bijou is in degraded mode since its storage is unreachable");
+        }
+        Ok(())
+    }
+
     /// Returns the root inode.
     pub fn root(&self) -> Inode {
         Inode::ROOT
     }
 
-    fn allocate_id(&self) -> Result<FileId> {
-        let mut id = FileId::gen();
-
-        while self.get_key(id).exists()? {
-            // Unlikely
-            id = FileId::gen();
-        }
-        Ok(id)
+    /// Reserves an ID that is used by neither a file nor a raw file
+    /// (raw files of unshared clones do not have metadata). The
+    /// reservation should be held until the new file is persisted.
+    fn allocate_id(&self) -> Result<Reservation<'_>> {
+        self.ids
+            .reserve(|id| Ok(self.get_key(id).exists()? || self.raw_fs.exists(id)?))
     }
 
     /// Looks up a file by name.
     ///
     /// Returns the inode and its generation.
     pub fn lookup(&self, parent: FileId, name: &str) -> Result<FileId> {
+        if name == "." {
+            return Ok(parent);
+        }
         Ok(self
             .child_key(self.get_key(parent), name)?
             .get()?
@@ -423,18 +1106,109 @@ impl Bijou {
     }
 
     fn get_key(&self, file: FileId) -> DatabaseKey<FileMeta> {
-        self.db.key(consts::FILE_ROOT).derive(file).typed()
+        self.db.key(consts::Root::File).derive(file).typed()
     }
 
     fn get_raw_meta(&self, key: &DatabaseKey<FileMeta>) -> Result<FileMeta> {
-        key.get()?.kind(ErrorKind::NotFound)
+        let mut meta = key.get()?.kind(ErrorKind::NotFound)?;
+        self.apply_dir_times(&mut meta);
+        Ok(meta)
+    }
+
+    /// Applies the deferred modification time of `meta`, if any.
+    fn apply_dir_times(&self, meta: &mut FileMeta) {
+        if self.config.dir_time_policy == DirTimePolicy::Relaxed {
+            if let Some(modified) = self.dir_times.get(meta.id) {
+                meta.modified = meta.modified.max(modified);
+            }
+        }
+    }
+
+    /// Writes the metadata of the directory `meta` after its entries
+    /// changed. With [`DirTimePolicy::Relaxed`], this is deferred if
+    /// the number of links did not change, i.e. only the modification
+    /// time is updated.
+    fn put_dir_meta(
+        &self,
+        batch: &mut WriteBatch,
+        key: &DatabaseKey<FileMeta>,
+        meta: &FileMeta,
+        links_changed: bool,
+    ) -> Result<()> {
+        if !links_changed
+            && self.config.dir_time_policy == DirTimePolicy::Relaxed
+            && self
+                .dir_times
+                .defer(meta.id, meta.modified, self.clock.instant())
+        {
+            return Ok(());
+        }
+        self.dir_times.forget(meta.id);
+        key.put_batch(batch, meta)
+    }
+
+    /// Writes modification times of directories deferred by
+    /// [`DirTimePolicy::Relaxed`] to the database.
+    ///
+    /// This is also done when the Bijou is dropped.
+    pub fn flush_dir_times(&self) -> Result<()> {
+        let _raw_guard = self.raw_lock.read().unwrap();
+        for (dir, modified) in self.dir_times.take() {
+            let lock = self.file_lock.get(dir);
+            let _guard = lock.write().unwrap();
+
+            let key = self.get_key(dir);
+            let Some(mut meta) = key.get()? else {
+                // removed in the meantime
+                continue;
+            };
+            if modified > meta.modified {
+                meta.modified = modified;
+                key.put(&meta)?;
+            }
+        }
+        Ok(())
     }
 
     /// Returns the metadata of the given file.
     pub fn get_meta(&self, file: FileId) -> Result<FileMeta> {
-        obtain_metadata(&self.get_key(file), self.algo.as_ref(), || {
-            self.raw_fs.stat(file)
-        })
+        let times = TimePolicy::new(&self.config, &self.clock);
+        let mut meta = obtain_metadata(&self.get_key(file), self.algo.as_ref(), &times, |meta| {
+            self.raw_fs.stat(meta.content_id())
+        })?;
+        self.apply_dir_times(&mut meta);
+        Ok(meta)
+    }
+
+    /// Returns the metadata of the given files, in the same order.
+    ///
+    /// This is faster than calling [`Bijou::get_meta`] for each file:
+    /// records are fetched from the database in a single batch, and
+    /// raw files are stat'ed in parallel.
+    pub fn get_meta_many(&self, files: &[FileId]) -> Result<Vec<FileMeta>> {
+        let keys = files.iter().map(|&file| self.get_key(file).key);
+        let mut metas = self
+            .db
+            .0
+            .multi_get(keys)
+            .into_iter()
+            .map(|value| {
+                let value = value.kind(ErrorKind::DBError)?.kind(ErrorKind::NotFound)?;
+                db::decode::<FileMeta>(&value)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let raw_fs = &*self.raw_fs;
+        let algo = self.algo.as_ref();
+        let times = TimePolicy::new(&self.config, &self.clock);
+        par_try_for_each(&mut metas, |meta| {
+            complete_metadata(meta, algo, &times, |meta| raw_fs.stat(meta.content_id()))
+        })?;
+        for meta in &mut metas {
+            self.apply_dir_times(meta);
+        }
+
+        Ok(metas)
     }
 
     /// Creates a new file (or directory, symlink, etc.).
@@ -449,6 +1223,169 @@ impl Bijou {
         perms: Option<UnixPerms>,
     ) -> Result<FileMeta> {
         trace!(%parent, name, ?kind, "make node");
+        self.make_node_inner(parent, name, kind, symlink, perms, None)
+    }
+
+    /// Creates several files (or directories, symlinks, etc.) in
+    /// `parent`, returning their metadata in the same order.
+    ///
+    /// This is faster than calling [`Bijou::make_node`] for each of
+    /// them: database records are written in a single batch, and raw
+    /// files are created in parallel. Nothing is created if any name
+    /// already exists or is given twice.
+    pub fn make_nodes(&self, parent: FileId, nodes: &[NewNode]) -> Result<Vec<FileMeta>> {
+        self.check_writable()?;
+        trace!(%parent, count = nodes.len(), "make nodes");
+
+        let _raw_guard = self.raw_lock.read().unwrap();
+        let lock = self.file_lock.get(parent);
+        let _guard = lock.write().unwrap();
+
+        let mut batch = self.db.batch();
+
+        let parent_key = self.get_key(parent);
+        let mut names = HashSet::new();
+        let mut child_keys = Vec::with_capacity(nodes.len());
+        for node in nodes {
+            let child_key = self.child_key(parent_key.clone(), &node.name)?;
+            if node.name == "." || !names.insert(&node.name) || child_key.exists()? {
+                bail!(@AlreadyExists? "file already exists: {}", node.name);
+            }
+            self.check_normalization(&parent_key, &node.name)?;
+            child_keys.push(child_key);
+        }
+
+        let now = self.clock.now();
+
+        let mut parent_meta = self.get_raw_meta(&parent_key)?;
+        if parent_meta.attributes.has(FileAttributes::IMMUTABLE) {
+            bail!(@PermissionDenied? "parent directory is immutable");
+        }
+        let dirs = nodes
+            .iter()
+            .filter(|node| node.kind == FileKind::Directory)
+            .count() as u32;
+        parent_meta.modified = now;
+        parent_meta.nlinks += dirs;
+        self.put_dir_meta(&mut batch, &parent_key, &parent_meta, dirs > 0)?;
+
+        let reservations = nodes
+            .iter()
+            .map(|_| self.allocate_id())
+            .collect::<Result<Vec<_>>>()?;
+        let mut metas = Vec::with_capacity(nodes.len());
+        for ((node, child_key), reservation) in nodes.iter().zip(child_keys).zip(&reservations) {
+            let (id, kind) = (reservation.id(), node.kind);
+            let key = self.get_key(id);
+            let meta = FileMeta {
+                id,
+                kind,
+
+                size: 0,
+
+                accessed: now,
+                modified: now,
+
+                nlinks: if kind == FileKind::Directory { 2 } else { 1 },
+
+                perms: node.perms.filter(|_| self.config.unix_perms),
+
+                attributes: FileAttributes::EMPTY,
+
+                content: None,
+            };
+            key.put_batch(&mut batch, &meta)?;
+
+            match kind {
+                FileKind::Directory => {
+                    self.child_key(key, "..")?.put_batch(
+                        &mut batch,
+                        &DirItem {
+                            id: parent,
+                            kind: FileKind::Directory,
+                        },
+                    )?;
+                }
+                FileKind::Symlink => {
+                    let Some(target) = &node.symlink else {
+                        bail!(@InvalidInput "symlink target must not be None");
+                    };
+                    key.derive(consts::Derive::Symlink)
+                        .typed::<String>()
+                        .put_batch(&mut batch, target)?;
+                }
+                _ => {}
+            }
+
+            self.put_entry(&mut batch, parent, &child_key, &DirItem { id, kind })?;
+            metas.push(meta);
+        }
+
+        let roots = self.quota_roots(parent)?;
+        if !roots.is_empty() {
+            let files = metas
+                .iter()
+                .map(|meta| Charged {
+                    id: meta.id,
+                    bytes: 0,
+                    exclusive: true,
+                })
+                .collect::<Vec<_>>();
+            self.quotas.transfer(&files, &[], &roots)?;
+        }
+
+        let commit = || -> Result<()> {
+            batch.commit()?;
+
+            let mut files = metas
+                .iter()
+                .filter(|meta| meta.kind == FileKind::File)
+                .map(|meta| meta.id)
+                .collect::<Vec<_>>();
+            par_try_for_each(&mut files, |id| self.raw_fs.create(*id))
+        };
+        if let Err(err) = commit() {
+            for meta in &metas {
+                self.quotas.uncharge(meta.id);
+            }
+            return Err(err);
+        }
+
+        Ok(metas)
+    }
+
+    /// Checks `name`, about to be added to the directory `parent_key`,
+    /// against its other normalization forms already there. See
+    /// [`Config::normalization_duplicates`].
+    fn check_normalization(&self, parent_key: &DatabaseKey<FileMeta>, name: &str) -> Result<()> {
+        let policy = self.config.normalization_duplicates;
+        if policy == NormalizationPolicy::Allow || name.is_ascii() {
+            return Ok(());
+        }
+        for form in [name.nfc().collect::<String>(), name.nfd().collect()] {
+            if form == name || !self.child_key(parent_key.clone(), &form)?.exists()? {
+                continue;
+            }
+            if policy == NormalizationPolicy::Reject {
+                bail!(@AlreadyExists? "file already exists in another normalization form: {name}");
+            }
+            warn!(name, existing = %form, "file name only differs in normalization");
+            break;
+        }
+        Ok(())
+    }
+
+    fn make_node_inner(
+        &self,
+        parent: FileId,
+        name: &str,
+        kind: FileKind,
+        symlink: Option<String>,
+        perms: Option<UnixPerms>,
+        content: Option<FileId>,
+    ) -> Result<FileMeta> {
+        self.check_writable()?;
+        let _raw_guard = self.raw_lock.read().unwrap();
         let lock = self.file_lock.get(parent);
         let _guard = lock.write().unwrap();
 
@@ -456,18 +1393,28 @@ impl Bijou {
 
         let parent_key = self.get_key(parent);
         let child_key = self.child_key(parent_key.clone(), name)?;
-        if child_key.exists()? {
+        if name == "." || child_key.exists()? {
             bail!(@AlreadyExists? "file already exists: {name}");
         }
+        self.check_normalization(&parent_key, name)?;
 
-        let now = Utc::now();
+        let now = self.clock.now();
 
         let mut parent_meta = self.get_raw_meta(&parent_key)?;
+        if parent_meta.attributes.has(FileAttributes::IMMUTABLE) {
+            bail!(@PermissionDenied? "parent directory is immutable");
+        }
         parent_meta.modified = now;
         parent_meta.nlinks += (kind == FileKind::Directory) as u32;
-        parent_key.put_batch(&mut batch, &parent_meta)?;
+        self.put_dir_meta(
+            &mut batch,
+            &parent_key,
+            &parent_meta,
+            kind == FileKind::Directory,
+        )?;
 
-        let id = self.allocate_id()?;
+        let reservation = self.allocate_id()?;
+        let id = reservation.id();
         let key = self.get_key(id);
         let meta = FileMeta {
             id,
@@ -481,18 +1428,16 @@ impl Bijou {
             nlinks: if kind == FileKind::Directory { 2 } else { 1 },
 
             perms: perms.filter(|_| self.config.unix_perms),
+
+            attributes: FileAttributes::EMPTY,
+
+            content,
         };
         key.put_batch(&mut batch, &meta)?;
 
         match kind {
             FileKind::Directory => {
-                self.child_key(key.clone(), ".")?.put_batch(
-                    &mut batch,
-                    &DirItem {
-                        id,
-                        kind: FileKind::Directory,
-                    },
-                )?;
+                // `.` is not stored, see `DirIterator`
                 self.child_key(key, "..")?.put_batch(
                     &mut batch,
                     &DirItem {
@@ -505,32 +1450,181 @@ impl Bijou {
                 let Some(target) = symlink else {
                     bail!(@InvalidInput "symlink target must not be None");
                 };
-                key.derive(consts::SYMLINK_DERIVE)
+                key.derive(consts::Derive::Symlink)
                     .typed::<String>()
                     .put_batch(&mut batch, &target)?;
             }
-            _ => {}
+            _ => {}
+        }
+
+        self.put_entry(
+            &mut batch,
+            parent,
+            &child_key,
+            &DirItem {
+                id,
+                kind: meta.kind,
+            },
+        )?;
+
+        let roots = self.quota_roots(parent)?;
+        if !roots.is_empty() {
+            let bytes = match content {
+                Some(content) => self.algo.plaintext_size(self.raw_fs.stat(content)?.size).0,
+                None => 0,
+            };
+            let file = Charged {
+                id,
+                bytes,
+                exclusive: true,
+            };
+            self.quotas.transfer(&[file], &[], &roots)?;
+        }
+
+        let commit = || -> Result<()> {
+            if let Some(content) = content {
+                let refs_lock = self.refs.lock(content);
+                let _guard = refs_lock.write().unwrap();
+                self.refs.acquire_batch(&mut batch, content)?;
+
+                batch.commit()?;
+            } else {
+                batch.commit()?;
+
+                if kind == FileKind::File {
+                    self.raw_fs.create(id)?;
+                }
+            }
+            Ok(())
+        };
+        if let Err(err) = commit() {
+            self.quotas.uncharge(id);
+            return Err(err);
+        }
+
+        Ok(meta)
+    }
+
+    /// Creates a copy-on-write clone of `file` named `name` under `parent`.
+    ///
+    /// The clone shares raw content with `file` until either of them
+    /// is opened for writing, at which point the content is copied.
+    /// Since handles opened before would keep writing into the shared
+    /// content, this fails if `file` is opened.
+    ///
+    /// Note that this is not reachable through FUSE, since the kernel
+    /// does not forward `FICLONE` to FUSE filesystems.
+    pub fn clone_file(
+        &self,
+        file: FileId,
+        parent: FileId,
+        name: &str,
+        perms: Option<UnixPerms>,
+    ) -> Result<FileMeta> {
+        self.check_writable()?;
+        trace!(%file, %parent, name, "clone file");
+        let meta = self.get_raw_meta(&self.get_key(file))?;
+        if meta.kind != FileKind::File {
+            bail!(@InvalidInput? "can only clone regular files");
+        }
+        if self
+            .file_open_counts
+            .get(&file)
+            .is_some_and(|count| count.load(Ordering::SeqCst) > 0)
+        {
+            bail!(@InvalidInput? "cloning opened file");
+        }
+        self.make_node_inner(
+            parent,
+            name,
+            FileKind::File,
+            None,
+            perms.or(meta.perms),
+            Some(meta.content_id()),
+        )
+    }
+
+    /// Copies the content of `from` into the empty raw file `to`,
+    /// re-encrypting it with the key of `to`.
+    fn copy_content(&self, from: FileId, to: FileId) -> Result<()> {
+        let src = self.raw_fs.open(from, FileFlags::READ)?;
+        let mut dst = self.raw_fs.open(to, FileFlags::WRITE)?;
+        let src_key = self.file_key(from)?;
+        let dst_key = self.file_key(to)?;
+
+        let mut buffer = self.block_buffers.get(self.algo.block_size() as _);
+        let mut block = BlockIndex(0);
+        let mut size = CipherSize(0);
+        loop {
+            let block_end = src.read_block(&mut buffer, block)? as usize;
+            if block_end == 0 {
+                break;
+            }
+            src_key.decrypt(block, &mut buffer[..block_end])?;
+            dst_key.encrypt(block, &mut buffer[..block_end])?;
+            dst.write_block(&buffer, block_end, block)?;
+
+            size += CipherSize(block_end as u64);
+            block = block.next();
         }
 
-        child_key.put_batch(
-            &mut batch,
-            &DirItem {
-                id,
-                kind: meta.kind,
-            },
-        )?;
+        dst.set_metadata(RawFileMeta {
+            size,
+            ..RawFileMeta::create()
+        })
+    }
 
-        batch.commit()?;
+    /// Makes sure that the content of the given file is not shared
+    /// with any other files, copying it if necessary.
+    ///
+    /// The caller should hold `raw_lock`.
+    fn unshare_content(&self, meta: FileMeta, truncate: bool) -> Result<FileMeta> {
+        let content = meta.content_id();
+        let refs_lock = self.refs.lock(content);
+        let _guard = refs_lock.write().unwrap();
+        if self.refs.count(content)? <= 1 {
+            return Ok(meta);
+        }
+
+        let lock = self
+            .file_lock
+            .get_or_try_insert(meta.id, || self.raw_fs.stat(content))?;
+        let mut raw_meta = lock.write().unwrap();
+
+        let key = self.get_key(meta.id);
+        let mut meta = self.get_raw_meta(&key)?;
+        if meta.content_id() != content {
+            // Unshared by others in the meantime
+            return Ok(meta);
+        }
 
-        if kind == FileKind::File {
-            self.raw_fs.create(id)?;
+        let reservation;
+        let new_content = if content != meta.id {
+            meta.id
+        } else {
+            reservation = self.allocate_id()?;
+            reservation.id()
+        };
+        self.raw_fs.create(new_content)?;
+        if !truncate {
+            self.copy_content(content, new_content)?;
         }
+        *raw_meta = self.raw_fs.stat(new_content)?;
+
+        meta.content = Some(new_content).filter(|it| *it != meta.id);
+        let mut batch = self.db.batch();
+        key.put_batch(&mut batch, &meta)?;
+        self.refs.release_batch(&mut batch, content)?;
+        batch.commit()?;
 
         Ok(meta)
     }
 
-    /// Creates a hard link for the given file.
+    /// Creates a hard link for the given file or symlink.
+    ///
+    /// Directories cannot be hard linked.
     pub fn link(&self, file: FileId, parent: FileId, name: &str) -> Result<FileMeta> {
+        self.check_writable()?;
         trace!(%parent, name, "link");
 
         let lock = self.file_lock.get(parent);
@@ -543,22 +1637,41 @@ impl Bijou {
         if meta.kind == FileKind::Directory {
             bail!(@InvalidInput? "creating hard link to directory");
         }
+        if meta.attributes.is_protected() {
+            bail!(@PermissionDenied? "creating hard link to protected file");
+        }
         meta.nlinks += 1;
         key.put_batch(&mut batch, &meta)?;
 
         let parent_key = self.get_key(parent);
-        let child_key = self.child_key(parent_key, name)?;
-        if child_key.exists()? {
+        if self
+            .get_raw_meta(&parent_key)?
+            .attributes
+            .has(FileAttributes::IMMUTABLE)
+        {
+            bail!(@PermissionDenied? "parent directory is immutable");
+        }
+        let child_key = self.child_key(parent_key.clone(), name)?;
+        if name == "." || child_key.exists()? {
             bail!(@AlreadyExists? "file already exists: {name}");
         }
-        child_key.put_batch(
+        self.check_normalization(&parent_key, name)?;
+        self.put_entry(
             &mut batch,
+            parent,
+            &child_key,
             &DirItem {
                 id: file,
                 kind: meta.kind,
             },
         )?;
 
+        let roots = self.quota_roots(parent)?;
+        if !roots.is_empty() {
+            let file = Self::charged(&self.get_meta(file)?);
+            self.quotas.transfer(&[file], &[], &roots)?;
+        }
+
         batch.commit()?;
 
         Ok(meta)
@@ -582,23 +1695,108 @@ impl Bijou {
         Ok(bytes)
     }
 
+    /// Returns the key of the raw file `content`.
+    fn file_key(&self, content: FileId) -> Result<Box<dyn AlgoKey + Send + Sync>> {
+        Ok(self.crypto.wrap(self.algo.key(self.derive_key(content)?)?))
+    }
+
+    /// Returns counters of block encryption and decryption since
+    /// this Bijou was opened.
+    ///
+    /// A growing number of authentication failures indicates
+    /// corruption or tampering, e.g. found by [`Bijou::scrub`] or
+    /// when reading files.
+    pub fn crypto_stats(&self) -> CryptoStats {
+        self.crypto.snapshot()
+    }
+
+    /// Makes all following database commits fail if `fail` is set, for
+    /// testing how failures are handled.
+    ///
+    /// This requires the `flaky` feature.
+    #[cfg(feature = "flaky")]
+    pub fn fail_commits(&self, fail: bool) {
+        self.db.fail_commits(fail);
+    }
+
+    /// Returns statistics of the database, e.g. for capacity planning
+    /// and performance debugging.
+    ///
+    /// Counters like block cache hits are only collected if
+    /// [`Config::db_statistics`] is enabled.
+    pub fn db_stats(&self) -> Result<DbStats> {
+        self.db.stats()
+    }
+
     fn open_inner(&self, meta: FileMeta, options: &OpenOptions) -> Result<LowLevelFile> {
+        options.check()?;
+        self.check_storage()?;
+        if options.write || options.truncate {
+            self.check_writable()?;
+        }
+        if meta.attributes.has(FileAttributes::IMMUTABLE) && (options.write || options.truncate) {
+            bail!(@PermissionDenied? "opening immutable file for writing");
+        }
+        if meta.attributes.has(FileAttributes::APPEND_ONLY)
+            && ((options.write && !options.append) || options.truncate)
+        {
+            bail!(@PermissionDenied? "opening append-only file without append mode");
+        }
+
+        let _raw_guard = self.raw_lock.read().unwrap();
+
+        let meta = if options.write || options.truncate {
+            self.unpin(meta.id)?;
+            self.unshare_content(meta, options.truncate)?
+        } else {
+            meta
+        };
+        let content = meta.content_id();
+
         let flags = options.to_flags();
-        let raw_file = self
-            .raw_fs
-            .open(meta.id, options.clone().read(true).to_flags())?;
+        // Truncation is done below under the file lock, not by the
+        // backend when opening.
+        let mut raw_file = self.raw_fs.open(
+            content,
+            options.clone().read(true).truncate(false).to_flags(),
+        )?;
+        if let Some(threshold) = self.config.slow_op_threshold {
+            raw_file = Box::new(SlowOpLog::new(
+                raw_file,
+                meta.id,
+                self.config.storage.to_string().into(),
+                Duration::from_millis(threshold),
+            ));
+        }
         let key = self.get_key(meta.id);
 
-        Ok(LowLevelFile::new(
+        let mut file = LowLevelFile::new(
             raw_file,
             Arc::clone(&self.algo),
-            self.algo.key(self.derive_key(meta.id)?)?,
+            self.file_key(content)?,
+            Arc::clone(&self.block_buffers),
+            meta.id,
             key,
+            TimePolicy::new(&self.config, &self.clock),
+            PlainSize(self.config.max_file_size).min(self.algo.max_plaintext_size()),
+            Arc::clone(&self.quotas),
             flags,
+            self.config.sniff_mime_types,
             self.file_lock
-                .get_or_try_insert(meta.id, || self.raw_fs.stat(meta.id))?,
+                .get_or_try_insert(meta.id, || self.raw_fs.stat(content))?,
+            Arc::clone(&self.raw_lock),
             Arc::clone(&self.file_open_counts.entry(meta.id).or_default()),
-        ))
+        );
+        drop(_raw_guard);
+        if options.truncate {
+            file.set_len(0)?;
+        }
+        if self.verify_on_open && !options.truncate {
+            file.verify()
+                .map_err(|err| err.context(format!("failed to verify file {}", meta.id)))?;
+        }
+
+        Ok(file)
     }
 
     /// Opens a file directly.
@@ -609,9 +1807,13 @@ impl Bijou {
     /// See also [`open_file`].
     ///
     /// [`open_file`]: Bijou::open_file
-    pub fn open_file_direct(&self, file: FileId, options: &OpenOptions) -> Result<LowLevelFile> {
+    pub fn open_file_direct(
+        &self,
+        file: FileId,
+        options: impl AsRef<OpenOptions>,
+    ) -> Result<LowLevelFile> {
         let meta = self.get_raw_meta(&self.get_key(file))?;
-        self.open_inner(meta, options)
+        self.open_inner(meta, options.as_ref())
     }
 
     /// Opens a file, and creates it if necessary.
@@ -623,12 +1825,11 @@ impl Bijou {
         &self,
         parent: FileId,
         name: &str,
-        options: &OpenOptions,
+        options: impl AsRef<OpenOptions>,
         perms: Option<UnixPerms>,
     ) -> Result<LowLevelFile> {
-        if options.truncate && !options.write {
-            bail!(@InvalidInput? "cannot specify truncate without write")
-        }
+        let options = options.as_ref();
+        options.check()?;
         match self.child_key(self.get_key(parent), name)?.get()? {
             Some(item) => {
                 if options.create_new {
@@ -647,51 +1848,18 @@ impl Bijou {
         }
     }
 
-    pub(crate) fn resolve_inner(
-        &self,
-        mut stack: Vec<FileId>,
-        path: &Path,
-        depth: &mut u32,
-    ) -> Result<FileId> {
-        for comp in path.components() {
-            match comp {
-                Component::RootDir => {
-                    stack.truncate(1);
-                }
-                Component::CurDir => {}
-                Component::ParentDir => {
-                    if stack.len() > 1 {
-                        stack.pop();
-                    }
-                }
-                other => {
-                    let id = self.lookup(*stack.last().unwrap(), other.as_str())?;
-                    let id = match self.read_link(id) {
-                        Ok(path) => {
-                            *depth += 1;
-                            if *depth > SYMBOLIC_MAX_DEPTH {
-                                bail!(@FilesystemLoop? "too many levels of symbolic links");
-                            }
-                            // symlink
-                            self.resolve_inner(stack.clone(), Path::new(&path), depth)?
-                        }
-                        Err(err) if err.kind() == ErrorKind::InvalidInput => {
-                            // not a symlink
-                            id
-                        }
-                        Err(err) => return Err(err),
-                    };
-                    stack.push(id);
-                }
-            }
-        }
-
-        Ok(stack.into_iter().rev().next().unwrap())
-    }
-
     /// Resolves a path to a file.
     pub fn resolve(&self, path: impl AsRef<Path>) -> Result<FileId> {
-        self.resolve_inner(vec![FileId::ROOT], path.as_ref(), &mut 0)
+        self.resolve_in(FileId::ROOT, path.as_ref())
+    }
+
+    /// Resolves a path to a file, treating the directory `root` as
+    /// the root. See [`Resolver`].
+    pub(crate) fn resolve_in(&self, root: FileId, path: &Path) -> Result<FileId> {
+        path.check()?;
+        let mut resolver = Resolver::with_root(self, root);
+        resolver.walk(path, false)?;
+        Ok(resolver.current())
     }
 
     /// Resolves a path, returning its parent and its name.
@@ -699,41 +1867,33 @@ impl Bijou {
     /// If the path is `/`, returns `(FileId::ROOT, None)`.
     ///
     /// Different from [`resolve`], this method does not require
-    /// the path to exist.
+    /// the path to exist. Paths ending with `.` or `..` (other than
+    /// the root) are rejected.
     ///
     /// [`resolve`]: Bijou::resolve
     pub fn resolve_parent<'a>(&self, path: &'a Path) -> Result<(FileId, Option<&'a str>)> {
-        let mut stack = vec![(FileId::ROOT, "")];
-        let mut current_name = None;
-        let mut symlink_depth = 0;
-        for comp in path.components() {
-            match comp {
-                Component::RootDir => {
-                    stack.truncate(1);
-                    current_name = None;
-                }
-                Component::CurDir => {}
-                Component::ParentDir => {
-                    if stack.len() == 1 {
-                        current_name = None;
-                    } else {
-                        current_name = Some(stack.pop().unwrap().1);
-                    }
-                }
-                Component::Normal(name) => {
-                    if let Some(parent_name) = current_name {
-                        let parent = self.resolve_inner(
-                            stack.iter().map(|it| it.0).collect(),
-                            Path::new(parent_name),
-                            &mut symlink_depth,
-                        )?;
-                        stack.push((parent, parent_name));
-                    }
-                    current_name = Some(name);
-                }
-            }
+        self.resolve_parent_in(FileId::ROOT, path)
+    }
+
+    /// Same as [`Bijou::resolve_parent`], but treating the directory
+    /// `root` as the root.
+    pub(crate) fn resolve_parent_in<'a>(
+        &self,
+        root: FileId,
+        path: &'a Path,
+    ) -> Result<(FileId, Option<&'a str>)> {
+        path.check()?;
+        let mut resolver = Resolver::with_root(self, root);
+        let mut comps = path.components();
+        if let Some(Component::Normal(name)) = comps.next_back() {
+            resolver.walk(comps.as_path(), true)?;
+            return Ok((resolver.current(), Some(name)));
+        }
+        resolver.walk(path, true)?;
+        if resolver.current() != root {
+            bail!(@InvalidInput? "path must not end with `.` or `..`: `{path}`");
         }
-        Ok((stack.into_iter().rev().next().unwrap().0, current_name))
+        Ok((root, None))
     }
 
     /// Resolves a path, returning its parent and its name.
@@ -743,7 +1903,17 @@ impl Bijou {
     ///
     /// [`resolve_parent`]: Bijou::resolve_parent
     pub fn resolve_parent_nonroot<'a>(&self, path: &'a Path) -> Result<(FileId, &'a str)> {
-        let (parent, name) = self.resolve_parent(path)?;
+        self.resolve_parent_nonroot_in(FileId::ROOT, path)
+    }
+
+    /// Same as [`Bijou::resolve_parent_nonroot`], but treating the
+    /// directory `root` as the root.
+    pub(crate) fn resolve_parent_nonroot_in<'a>(
+        &self,
+        root: FileId,
+        path: &'a Path,
+    ) -> Result<(FileId, &'a str)> {
+        let (parent, name) = self.resolve_parent_in(root, path)?;
         let Some(name) = name else {
             bail!(@InvalidInput "expected non-root path, got `{path}`");
         };
@@ -752,35 +1922,30 @@ impl Bijou {
 
     /// Returns an iterator of the entries of the given directory.
     ///
-    /// Note that [`DirIterator::reset`] must be called before
-    /// the iterator is used.
-    ///
-    /// The content will only be updated when the iterator is reset.
-    /// Before that, the content is a snapshot of the directory
-    /// at the time of the last call to [`DirIterator::reset`].
-    ///
-    /// The results include `.` and `..`.
+    /// The results include `.` and `..` unless disabled with
+    /// [`DirIterator::dots`]. See [`DirIterator`] for details about
+    /// its snapshot behavior.
     pub fn read_dir(&self, id: FileId) -> Result<DirIterator> {
         let key = self.get_key(id);
         if key.get()?.kind(ErrorKind::NotFound)?.kind != FileKind::Directory {
             bail!(@NotADirectory "not a directory");
         }
-        let mut opts = ReadOptions::default();
-        opts.set_iterate_upper_bound(key.clone().derive(consts::DIR_DERIVE_UPPER).key.to_vec());
-        Ok(DirIterator {
-            key: key.derive(consts::DIR_DERIVE).key,
-            inner: self.db.0.iterator_opt(IteratorMode::Start, opts),
-            // inner: self.db.0.prefix_iterator(&key.derive(consts::DIR_DERIVE).key),
-            decrypt: self.file_name_key.as_ref().map(|key| (id, cast_key(key))),
-        })
+        Ok(DirIterator::new(self, id))
     }
 
-    fn unlink_inner(
+    /// Removes the entry `name` of `parent` in `batch`.
+    ///
+    /// The lock of the reference count of the released content is put
+    /// into `refs_lock` and held by the returned [`Unlinked`], which
+    /// should be kept until `batch` is committed (see
+    /// [`RefCounter::lock`]) and then passed to [`Bijou::finish_unlink`].
+    fn unlink_inner<'a>(
         &self,
         batch: &mut WriteBatch,
         parent: FileId,
         name: &str,
-    ) -> Result<Option<FileId>> {
+        refs_lock: &'a mut Option<Arc<RwLock<()>>>,
+    ) -> Result<Unlinked<'a>> {
         trace!(%parent, name, "unlink");
 
         let child = self.lookup(parent, name)?;
@@ -789,22 +1954,32 @@ impl Bijou {
         let mut meta = self.get_raw_meta(&key)?;
         let is_dir = meta.kind == FileKind::Directory;
 
-        if is_dir && self.read_dir(child)?.reset().nth(2).is_some() {
+        if meta.attributes.is_protected() {
+            bail!(@PermissionDenied? "trying to unlink protected file: {name}");
+        }
+
+        if is_dir && self.read_dir(child)?.dots(false).next().is_some() {
             bail!(@NotEmpty? "trying to unlink non-empty directory: {name}");
         }
 
         let parent_key = self.get_key(parent);
         let mut parent_meta = self.get_raw_meta(&parent_key)?;
+        if parent_meta.attributes.is_protected() {
+            bail!(@PermissionDenied? "trying to unlink from protected directory");
+        }
 
-        parent_meta.modified = Utc::now();
+        parent_meta.modified = self.clock.now();
         parent_meta.nlinks -= is_dir as u32;
-        parent_key.put_batch(batch, &parent_meta)?;
+        self.put_dir_meta(batch, &parent_key, &parent_meta, is_dir)?;
 
-        self.child_key(parent_key, name)?.delete_batch(batch);
+        let child_key = self.child_key(parent_key, name)?;
+        self.delete_entry(batch, parent, &child_key, child);
 
+        let mut unlinked = Unlinked::default();
         if meta.kind == FileKind::Directory {
             meta.nlinks = 0;
 
+            // `.` is only stored by older versions
             self.child_key(key.clone(), ".")?.delete_batch(batch);
             self.child_key(key.clone(), "..")?.delete_batch(batch);
 
@@ -812,55 +1987,122 @@ impl Bijou {
             // since they don't have hardlinks.
             key.delete_batch(batch);
         } else {
-            // TODO can symlinks have hardlink?
-
-            // For files, we reduce its nlinks by 1.
-            // If it reaches zero, we put it into the GC pool.
+            // Both files and symlinks can have hard links, as in POSIX.
+            // We reduce its nlinks by 1, and remove its records (and
+            // the raw file, or the symlink target) when it reaches zero.
+            // The raw file is only removed once the batch is committed,
+            // and raw files still opened are kept by the GC pool.
             assert!(meta.nlinks > 0);
             meta.nlinks -= 1;
 
             if meta.nlinks == 0 {
                 key.delete_batch(batch);
-                // batch.delete_range(
-                // key.clone().derive(consts::XATTR_DERIVE).key,
-                // key.clone().derive(consts::XATTR_DERIVE_UPPER).key,
-                // );
-                for item in key.range_iter(consts::XATTR_DERIVE, consts::XATTR_DERIVE_UPPER) {
-                    let item = item.wrap()?;
-                    batch.delete(&item.0);
-                }
+                self.xattrs_key(meta.id).delete_prefix_batch(batch)?;
                 if meta.kind == FileKind::Symlink {
-                    key.derive(consts::SYMLINK_DERIVE).delete_batch(batch);
+                    key.derive(consts::Derive::Symlink).delete_batch(batch);
                 } else {
-                    self.raw_fs.unlink(child)?;
+                    key.derive(consts::Derive::Pin).delete_batch(batch);
+                    let content = meta.content_id();
+                    let guard = refs_lock.insert(self.refs.lock(content)).write().unwrap();
+                    if self.refs.release_batch(batch, content)? {
+                        unlinked.freed = Some(content);
+                    }
+                    unlinked.refs_guard = Some(guard);
                 }
             } else {
                 key.put_batch(batch, &meta)?;
             }
         }
 
-        Ok(if meta.nlinks == 0 { Some(child) } else { None })
+        unlinked.removed = (meta.nlinks == 0).then_some(child);
+        Ok(unlinked)
+    }
+
+    /// Finishes an unlink after its batch is committed, removing the
+    /// raw content if it has no more references.
+    ///
+    /// Returns the removed file, see [`Bijou::unlink`].
+    fn finish_unlink(&self, unlinked: Unlinked) -> Result<Option<FileId>> {
+        if let Some(content) = unlinked.freed {
+            // Nothing refers to the content anymore, so it is only
+            // left behind as an orphan if this fails.
+            if let Err(err) = self.raw_fs.unlink(content) {
+                warn!(%content, "failed to remove unlinked content: {err}");
+            }
+        }
+        drop(unlinked.refs_guard);
+
+        if let Some(removed) = unlinked.removed {
+            self.quota_removed(removed)?;
+        }
+
+        Ok(unlinked.removed)
     }
 
     /// Unlinks a file.
     ///
-    /// Returns the removed file if it is a file and has no more
-    /// hardlinks. Otherwise, returns `None`.
+    /// Returns the removed file if it is a file or a symlink and has
+    /// no more hard links. Otherwise, returns `None`.
     pub fn unlink(&self, parent: FileId, name: &str) -> Result<Option<FileId>> {
-        let parent_lock = self.file_lock.get(parent);
-        let _guard = parent_lock.write().unwrap();
+        self.check_writable()?;
+        let _raw_guard = self.raw_lock.read().unwrap();
+        let child_dir_key = self.child_key(self.get_key(parent), name)?;
+        // The directory being removed is locked as well, so that
+        // nothing can be added to it in the meantime.
+        let mut locks;
+        let _guards = loop {
+            let child = self.child_dir(&child_dir_key)?;
+            locks = self.file_lock.get_all([parent].into_iter().chain(child));
+            let guards = locks.write();
+            if self.child_dir(&child_dir_key)? == child {
+                break guards;
+            }
+        };
 
         let mut batch = self.db.batch();
-        let removed = self.unlink_inner(&mut batch, parent, name)?;
+        let mut refs_lock = None;
+        let unlinked = self.unlink_inner(&mut batch, parent, name, &mut refs_lock)?;
         batch.commit()?;
 
-        Ok(removed)
+        self.finish_unlink(unlinked)
+    }
+
+    /// Returns the ID of the directory the entry refers to, or `None`
+    /// if it does not exist or is not a directory.
+    fn child_dir(&self, key: &DatabaseKey<DirItem>) -> Result<Option<FileId>> {
+        Ok(key
+            .get()?
+            .filter(|item| item.kind == FileKind::Directory)
+            .map(|item| item.id))
+    }
+
+    /// Returns `true` if the directory `ancestor` is `dir` or one of
+    /// its ancestors.
+    fn is_ancestor(&self, ancestor: FileId, mut dir: FileId) -> Result<bool> {
+        loop {
+            if dir == ancestor {
+                return Ok(true);
+            }
+            if dir == FileId::ROOT {
+                return Ok(false);
+            }
+            dir = self.parent_dir(dir)?;
+        }
+    }
+
+    /// Returns the parent of the directory `dir`.
+    fn parent_dir(&self, dir: FileId) -> Result<FileId> {
+        Ok(self
+            .child_key(self.get_key(dir), "..")?
+            .get()?
+            .kind(ErrorKind::NotFound)?
+            .id)
     }
 
     /// Renames a file.
     ///
-    /// Returns the removed file if it is a file and has no more
-    /// hardlinks. Otherwise, returns `None`.
+    /// Returns the removed file if it is a file or a symlink and has
+    /// no more hard links. Otherwise, returns `None`.
     pub fn rename(
         &self,
         parent: FileId,
@@ -868,43 +2110,154 @@ impl Bijou {
         new_parent: FileId,
         new_name: &str,
     ) -> Result<Option<FileId>> {
-        trace!(%parent, name, %new_parent, new_name, "rename");
+        self.rename_with_flags(parent, name, new_parent, new_name, RenameFlags::EMPTY)
+    }
+
+    /// Renames a file with the given flags. See [`RenameFlags`] for
+    /// details.
+    ///
+    /// Moving a directory into itself or one of its descendants fails
+    /// with [`ErrorKind::InvalidInput`].
+    ///
+    /// Returns the removed file if it is a file or a symlink and has
+    /// no more hard links. Otherwise, returns `None`.
+    pub fn rename_with_flags(
+        &self,
+        parent: FileId,
+        name: &str,
+        new_parent: FileId,
+        new_name: &str,
+        flags: RenameFlags,
+    ) -> Result<Option<FileId>> {
+        self.check_writable()?;
+        trace!(%parent, name, %new_parent, new_name, ?flags, "rename");
+
+        let exchange = flags.has(RenameFlags::EXCHANGE);
+        let no_replace = flags.has(RenameFlags::NOREPLACE);
+        if exchange && no_replace {
+            bail!(@InvalidInput? "EXCHANGE and NOREPLACE cannot be used together");
+        }
+        if matches!(name, "." | "..") || matches!(new_name, "." | "..") {
+            bail!(@InvalidInput? "trying to rename . or ..");
+        }
 
         if parent == new_parent && name == new_name {
+            if no_replace {
+                bail!(@AlreadyExists? "file already exists: {new_name}");
+            }
             return Ok(None);
         }
 
         let parent_key = self.get_key(parent);
         let new_parent_key = self.get_key(new_parent);
 
-        let parent_lock = self.file_lock.get(parent);
-        let new_parent_lock = self.file_lock.get(new_parent);
-        let _guard = parent_lock.write().unwrap();
-        let _guard2 = if parent == new_parent {
-            None
-        } else {
-            Some(new_parent_lock.write().unwrap())
-        };
-
-        let mut batch = self.db.batch();
+        let _rename_guard = (parent != new_parent).then(|| self.rename_lock.lock().unwrap());
+        let _raw_guard = self.raw_lock.read().unwrap();
 
         let old_child_dir_key = self.child_key(parent_key.clone(), name)?;
         let new_child_dir_key = self.child_key(new_parent_key.clone(), new_name)?;
 
+        // The directory being replaced is locked as well, so that
+        // nothing can be added to it in the meantime.
+        let target_dir = || -> Result<Option<FileId>> {
+            if exchange {
+                Ok(None)
+            } else {
+                self.child_dir(&new_child_dir_key)
+            }
+        };
+        let mut locks;
+        let _guards = loop {
+            let target = target_dir()?;
+            locks = self
+                .file_lock
+                .get_all([parent, new_parent].into_iter().chain(target));
+            let guards = locks.write();
+            if target_dir()? == target {
+                break guards;
+            }
+        };
+
+        let mut batch = self.db.batch();
+
         let dir_item = old_child_dir_key.get()?.kind(ErrorKind::NotFound)?;
         let child = self.get_key(dir_item.id);
         let meta = self.get_raw_meta(&child)?;
+        if meta.attributes.is_protected() {
+            bail!(@PermissionDenied? "trying to rename protected file: {name}");
+        }
+        if parent != new_parent
+            && dir_item.kind == FileKind::Directory
+            && self.is_ancestor(dir_item.id, new_parent)?
+        {
+            bail!(@InvalidInput? "trying to move a directory into itself: {name}");
+        }
 
-        let mut removed = None;
-
-        if new_child_dir_key.exists()? {
-            removed = self.unlink_inner(&mut batch, new_parent, new_name)?;
+        let mut parent_meta = self.get_raw_meta(&parent_key)?;
+        let mut new_parent_meta = self.get_raw_meta(&new_parent_key)?;
+        if parent_meta.attributes.is_protected()
+            || new_parent_meta.attributes.has(FileAttributes::IMMUTABLE)
+            || (exchange && new_parent_meta.attributes.is_protected())
+        {
+            bail!(@PermissionDenied? "trying to rename within protected directory");
         }
 
-        old_child_dir_key.delete_batch(&mut batch);
-        new_child_dir_key.put_batch(&mut batch, &dir_item)?;
+        let mut refs_lock = None;
+        let mut unlinked = Unlinked::default();
+        // Changes of `nlinks` of the two parents, caused by moving
+        // directories around.
+        let mut moved_dirs = (meta.kind == FileKind::Directory) as i64;
+        let mut replaced_dir = false;
+        let mut exchanged = None;
+
+        if exchange {
+            let new_item = new_child_dir_key.get()?.kind(ErrorKind::NotFound)?;
+            let new_child = self.get_key(new_item.id);
+            if self.get_raw_meta(&new_child)?.attributes.is_protected() {
+                bail!(@PermissionDenied? "trying to rename protected file: {new_name}");
+            }
+            if parent != new_parent
+                && new_item.kind == FileKind::Directory
+                && self.is_ancestor(new_item.id, parent)?
+            {
+                bail!(@InvalidInput? "trying to move a directory into itself: {new_name}");
+            }
+            self.delete_entry(&mut batch, new_parent, &new_child_dir_key, new_item.id);
+            self.delete_entry(&mut batch, parent, &old_child_dir_key, dir_item.id);
+            self.put_entry(&mut batch, parent, &old_child_dir_key, &new_item)?;
+            exchanged = Some(new_item.id);
+            if new_item.kind == FileKind::Directory {
+                self.child_key(new_child, "..")?.put_batch(
+                    &mut batch,
+                    &DirItem {
+                        id: parent,
+                        kind: FileKind::Directory,
+                    },
+                )?;
+                moved_dirs -= 1;
+            }
+        } else {
+            // renaming to another form of the name fixes the duplicate
+            if parent != new_parent || !name.nfc().eq(new_name.nfc()) {
+                self.check_normalization(&new_parent_key, new_name)?;
+            }
+            if let Some(target) = new_child_dir_key.get()? {
+                if no_replace {
+                    bail!(@AlreadyExists? "file already exists: {new_name}");
+                }
+                // parent metadata written by `unlink_inner` is
+                // overwritten below
+                unlinked = self.unlink_inner(&mut batch, new_parent, new_name, &mut refs_lock)?;
+                replaced_dir = target.kind == FileKind::Directory;
+            }
+            self.delete_entry(&mut batch, parent, &old_child_dir_key, dir_item.id);
+        }
+        if parent != new_parent {
+            self.quota_rename(parent, new_parent, dir_item.id, exchanged)?;
+        }
+        self.put_entry(&mut batch, new_parent, &new_child_dir_key, &dir_item)?;
 
-        let now = Utc::now();
+        let now = self.clock.now();
 
         if meta.kind == FileKind::Directory {
             self.child_key(child, "..")?.put_batch(
@@ -916,28 +2269,43 @@ impl Bijou {
             )?;
         }
 
-        let mut parent_meta = self.get_raw_meta(&parent_key)?;
-        parent_meta.nlinks -= (meta.kind == FileKind::Directory) as u32;
-        parent_meta.modified = now;
-        parent_key.put_batch(&mut batch, &parent_meta)?;
-
-        let mut new_parent_meta = self.get_raw_meta(&new_parent_key)?;
-        new_parent_meta.nlinks += (meta.kind == FileKind::Directory) as u32;
-        new_parent_meta.modified = now;
-        new_parent_key.put_batch(&mut batch, &new_parent_meta)?;
+        if parent == new_parent {
+            parent_meta.nlinks -= replaced_dir as u32;
+            parent_meta.modified = now;
+            self.put_dir_meta(&mut batch, &parent_key, &parent_meta, replaced_dir)?;
+        } else {
+            parent_meta.nlinks = (parent_meta.nlinks as i64 - moved_dirs) as u32;
+            parent_meta.modified = now;
+            self.put_dir_meta(&mut batch, &parent_key, &parent_meta, moved_dirs != 0)?;
+
+            let added_dirs = moved_dirs - replaced_dir as i64;
+            new_parent_meta.nlinks = (new_parent_meta.nlinks as i64 + added_dirs) as u32;
+            new_parent_meta.modified = now;
+            self.put_dir_meta(
+                &mut batch,
+                &new_parent_key,
+                &new_parent_meta,
+                added_dirs != 0,
+            )?;
+        }
 
         batch.commit()?;
 
-        Ok(removed)
+        self.finish_unlink(unlinked)
     }
 
     /// Sets the size of a file.
     ///
     /// If `len` is larger than the current size, the file will be
     /// extended with zeros. Otherwise, the file will be truncated.
+    ///
+    /// Fails with [`ErrorKind::FileTooLarge`] if `len` exceeds
+    /// [`Config::max_file_size`], and with [`ErrorKind::QuotaExceeded`]
+    /// if the file would grow beyond a quota it is under.
     pub fn set_len(&self, file: FileId, len: u64) -> Result<()> {
+        self.check_writable()?;
         trace!(%file, len, "set length");
-        self.open_file_direct(file, OpenOptions::new().write(true))?
+        self.open_file_direct(file, OpenOptions::writable())?
             .set_len(len)
     }
 
@@ -950,7 +2318,7 @@ impl Bijou {
             bail!(@InvalidInput? "not a symlink");
         }
 
-        key.derive(consts::SYMLINK_DERIVE)
+        key.derive(consts::Derive::Symlink)
             .typed::<String>()
             .get()?
             .kind(ErrorKind::NotFound)
@@ -963,11 +2331,29 @@ impl Bijou {
         accessed: DateTime<Utc>,
         modified: DateTime<Utc>,
     ) -> Result<()> {
+        self.check_writable()?;
         let key = self.get_key(file);
         let mut meta = self.get_raw_meta(&key)?;
+        if meta.attributes.has(FileAttributes::IMMUTABLE) {
+            bail!(@PermissionDenied? "changing times of immutable file");
+        }
+        if meta.kind == FileKind::File {
+            // keep the underlying filesystem in sync, see `Config::time_source`
+            let content = meta.content_id();
+            let lock = self
+                .file_lock
+                .get_or_try_insert(meta.id, || self.raw_fs.stat(content))?;
+            let mut raw_meta = lock.write().unwrap();
+            raw_meta.accessed = Some(accessed);
+            raw_meta.modified = Some(modified);
+            self.raw_fs
+                .open(content, FileFlags::READ)?
+                .set_times(&raw_meta)?;
+        }
         meta.accessed = accessed;
         meta.modified = modified;
         key.put(&meta)?;
+        self.dir_times.forget(file);
 
         Ok(())
     }
@@ -980,8 +2366,12 @@ impl Bijou {
         uid: Option<u32>,
         gid: Option<u32>,
     ) -> Result<()> {
+        self.check_writable()?;
         let key = self.get_key(id);
         let mut meta = self.get_raw_meta(&key)?;
+        if meta.attributes.has(FileAttributes::IMMUTABLE) {
+            bail!(@PermissionDenied? "changing permissions of immutable file");
+        }
         meta.perms = Some(UnixPerms {
             mode: mode
                 .or_else(|| meta.perms.as_ref().map(|it| it.mode))
@@ -998,12 +2388,36 @@ impl Bijou {
         Ok(())
     }
 
+    fn check_mutable(&self, id: FileId) -> Result<()> {
+        if self
+            .get_raw_meta(&self.get_key(id))?
+            .attributes
+            .has(FileAttributes::IMMUTABLE)
+        {
+            bail!(@PermissionDenied? "modifying immutable file");
+        }
+        Ok(())
+    }
+
+    /// Sets the attribute flags of a file, replacing the existing ones.
+    ///
+    /// See [`FileAttributes`] for how each flag is enforced.
+    pub fn set_attributes(&self, id: FileId, attributes: FileAttributes) -> Result<()> {
+        self.check_writable()?;
+        trace!(%id, ?attributes, "set attributes");
+        let key = self.get_key(id);
+        let mut meta = self.get_raw_meta(&key)?;
+        meta.attributes = attributes;
+        key.put(&meta)?;
+
+        Ok(())
+    }
+
     /// Sets extended attribute (xattr) of a file.
     pub fn set_xattr(&self, id: FileId, name: &str, value: &[u8]) -> Result<()> {
-        self.get_key(id)
-            .derive(consts::XATTR_DERIVE)
-            .derive(name)
-            .write(value)
+        self.check_writable()?;
+        self.check_mutable(id)?;
+        self.xattr_key(id, name).write(value)
     }
 
     /// Returns extended attribute (xattr) of a file.
@@ -1016,32 +2430,38 @@ impl Bijou {
         if self.config.disable_xattr_gets {
             return cb(Err(anyhow!(@Unsupported "xattr gets are disabled")));
         }
-        cb(self
-            .get_key(id)
-            .derive(consts::XATTR_DERIVE)
-            .derive(name)
-            .read())
+        cb(self.xattr_key(id, name).read())
+    }
+
+    /// Returns the MIME type of a file detected from its content, if
+    /// [`Config::sniff_mime_types`] was enabled when it was last
+    /// written and its type was recognized. See [`mime`].
+    ///
+    /// Unlike [`Bijou::get_xattr`], this works even if xattr gets are
+    /// disabled.
+    ///
+    pub fn mime_type(&self, id: FileId) -> Result<Option<String>> {
+        let key = self.xattr_key(id, mime::MIME_TYPE_XATTR);
+        let Some(value) = key.read()? else {
+            return Ok(None);
+        };
+        Ok(std::str::from_utf8(&value).ok().map(str::to_owned))
     }
 
     /// Removes extended attribute (xattr) of a file.
     pub fn remove_xattr(&self, id: FileId, name: &str) -> Result<()> {
-        self.get_key(id)
-            .derive(consts::XATTR_DERIVE)
-            .derive(name)
-            .delete()
+        self.check_writable()?;
+        self.check_mutable(id)?;
+        self.xattr_key(id, name).delete()
     }
 
     // TODO cache
     /// Returns all extended attributes (xattr) of a file.
     pub fn xattrs(&self, id: FileId) -> Result<Vec<String>> {
         let mut result = Vec::new();
-        let key = self.get_key(id);
-        let iter = key.range_iter(consts::XATTR_DERIVE, consts::XATTR_DERIVE_UPPER);
-        let len =
-            consts::FILE_ROOT.len() + std::mem::size_of::<FileId>() + consts::XATTR_DERIVE.len();
-        for entry in iter {
+        for entry in self.xattrs_key(id).prefix_iter() {
             let (key, _value) = entry.wrap()?;
-            let name = &key[len..];
+            let name = &key[columns::PREFIX_LEN..];
             result.push(String::from_utf8(name.to_vec()).unwrap());
         }
 
@@ -1049,44 +2469,185 @@ impl Bijou {
     }
 }
 
+impl Drop for Bijou {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush_dir_times() {
+            err.context("failed to flush directory times")
+                .report(ErrorOrigin::DirTimes);
+        }
+    }
+}
+
 /// Iterator of directory entries, created by [`Bijou::read_dir`].
+///
+/// The iterator reads from a consistent snapshot of the directory,
+/// taken when it is created or [reset]. Changes made afterwards are
+/// not visible until the next reset.
+///
+/// Entries are ordered by their keys in the database, which is
+/// unrelated to their names if file name encryption is enabled,
+/// except that `.` always comes first. Use [`DirIterator::sorted`]
+/// to order them by name instead. `.` is not stored in the
+/// database, while `..` is stored as the link to the parent.
+///
+/// [reset]: DirIterator::reset
 pub struct DirIterator<'db> {
-    key: RawKeyType,
+    bijou: &'db Bijou,
+    id: FileId,
     inner: DBIteratorWithThreadMode<'db, DBWithThreadMode<SingleThreaded>>,
-    decrypt: Option<(FileId, &'db xchacha20_siv::Key)>,
+    dots: bool,
+    /// Whether `.` is yet to be returned.
+    pending_dot: bool,
+    sort: bool,
+    /// All entries of the snapshot sorted by name, and the index of
+    /// the next one, once read.
+    sorted: Option<(Vec<(String, DirItem)>, usize)>,
 }
-impl DirIterator<'_> {
+impl<'db> DirIterator<'db> {
+    fn new(bijou: &'db Bijou, id: FileId) -> Self {
+        Self {
+            bijou,
+            id,
+            inner: Self::snapshot(bijou, id),
+            dots: true,
+            pending_dot: true,
+            sort: false,
+            sorted: None,
+        }
+    }
+
+    /// Sets whether to return entries sorted by name (`.` and `..`
+    /// still come first). Defaults to `false`.
+    ///
+    /// The whole snapshot is then read and decrypted on the first
+    /// call to [`next`](Iterator::next), and kept in memory until the
+    /// next reset, which costs memory proportional to the size of the
+    /// directory.
+    ///
+    /// This should be called before iterating.
+    pub fn sorted(&mut self, sorted: bool) -> &mut Self {
+        self.sort = sorted;
+        self.sorted = None;
+        self
+    }
+
+    /// Sort key of entries, putting `..` first.
+    fn sort_key(name: &str) -> (bool, &str) {
+        (name != "..", name)
+    }
+
+    /// Reads the rest of the snapshot and sorts it, if not done yet.
+    fn load_sorted(&mut self) -> Result<&mut (Vec<(String, DirItem)>, usize)> {
+        if self.sorted.is_none() {
+            let entries: Result<Vec<_>> = std::iter::from_fn(|| self.next_stored()).collect();
+            let mut entries = match entries {
+                Ok(entries) => entries,
+                Err(err) => {
+                    // so that a retry starts over
+                    self.inner = Self::snapshot(self.bijou, self.id);
+                    return Err(err);
+                }
+            };
+            entries.sort_unstable_by(|(a, _), (b, _)| Self::sort_key(a).cmp(&Self::sort_key(b)));
+            self.sorted = Some((entries, 0));
+        }
+        Ok(self.sorted.as_mut().unwrap())
+    }
+    /// Sets whether to include `.` and `..` in the results. Defaults
+    /// to `true`.
+    ///
+    /// This should be called before iterating.
+    pub fn dots(&mut self, dots: bool) -> &mut Self {
+        self.dots = dots;
+        self.pending_dot = dots;
+        self
+    }
+
+    fn snapshot(
+        bijou: &'db Bijou,
+        id: FileId,
+    ) -> DBIteratorWithThreadMode<'db, DBWithThreadMode<SingleThreaded>> {
+        let db = &bijou.db.0;
+        let mut opts = ReadOptions::default();
+        opts.set_prefix_same_as_start(true);
+        let lower = bijou.dir_key(bijou.get_key(id)).key;
+        db.iterator_cf_opt(
+            db.cf_handle(columns::DIRS).unwrap(),
+            opts,
+            IteratorMode::From(&lower, Direction::Forward),
+        )
+    }
+
+    /// Restarts from the first entry, with a new snapshot of the
+    /// directory.
     pub fn reset(&mut self) -> &mut Self {
-        self.inner
-            .set_mode(IteratorMode::From(&self.key, Direction::Forward));
+        self.inner = Self::snapshot(self.bijou, self.id);
+        self.pending_dot = self.dots;
+        self.sorted = None;
         self
     }
+
+    /// Moves to the entry `name`, or to where it would be if it does
+    /// not exist, within the current snapshot.
+    ///
+    /// Unless [sorted](DirIterator::sorted), entries are not
+    /// necessarily ordered by name, so this is mostly useful for
+    /// resuming from an entry returned earlier. `.` is skipped after
+    /// seeking.
+    pub fn seek(&mut self, name: &str) -> Result<&mut Self> {
+        self.pending_dot = false;
+        if self.sort {
+            let (entries, next) = self.load_sorted()?;
+            *next = entries.partition_point(|(it, _)| Self::sort_key(it) < Self::sort_key(name));
+            return Ok(self);
+        }
+        let key = self.bijou.child_key(self.bijou.get_key(self.id), name)?.key;
+        self.inner
+            .set_mode(IteratorMode::From(&key, Direction::Forward));
+        Ok(self)
+    }
+
+    fn decode(&self, name: &mut [u8], value: &[u8]) -> Result<(String, DirItem)> {
+        Ok((self.bijou.decode_name(self.id, name)?, db::decode(value)?))
+    }
+
+    /// Returns the next entry in the order of the database.
+    fn next_stored(&mut self) -> Option<Result<(String, DirItem)>> {
+        loop {
+            let (mut key, value) = match self.inner.next()?.wrap() {
+                Ok(entry) => entry,
+                Err(err) => return Some(Err(err)),
+            };
+            let name = &mut key[columns::PREFIX_LEN..];
+            // `.` stored by older versions is replaced by the one above
+            if name == b"." || (name == b".." && !self.dots) {
+                continue;
+            }
+            return Some(self.decode(name, &value));
+        }
+    }
 }
 impl Iterator for DirIterator<'_> {
     type Item = Result<(String, DirItem)>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next().map(|result| {
-            let (mut key, value) = result.wrap()?;
-            let name = &mut key[consts::FILE_ROOT.len()
-                + std::mem::size_of::<FileId>()
-                + consts::DIR_DERIVE.len()..];
-            if let Some((id, key)) = &self.decrypt {
-                if name != b"." && name != b".." {
-                    assert!(name.len() > xchacha20_siv::ABYTES);
-                    let (name, tag) = name.split_at_mut(name.len() - xchacha20_siv::ABYTES);
-                    xchacha20_siv::decrypt_inplace(name, cast_key(tag), id.as_ref(), key)
-                        .map_err(|_| anyhow!(@CryptoError "failed to decrypt filename"))?;
-                    return Ok((
-                        String::from_utf8(name.to_vec()).unwrap(),
-                        postcard::from_bytes(&value).wrap()?,
-                    ));
+        if std::mem::take(&mut self.pending_dot) {
+            let item = DirItem {
+                id: self.id,
+                kind: FileKind::Directory,
+            };
+            return Some(Ok((".".to_owned(), item)));
+        }
+        if self.sort {
+            return match self.load_sorted() {
+                Ok((entries, next)) => {
+                    let entry = entries.get(*next)?.clone();
+                    *next += 1;
+                    Some(Ok(entry))
                 }
-            }
-            Ok((
-                String::from_utf8(name.to_vec()).unwrap(),
-                postcard::from_bytes(&value).wrap()?,
-            ))
-        })
+                Err(err) => Some(Err(err)),
+            };
+        }
+        self.next_stored()
     }
 }
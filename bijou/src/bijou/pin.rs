@@ -0,0 +1,155 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::{
+    bail,
+    db::{consts, DatabaseKey},
+    format::ContentPin,
+    fs::LowLevelFile,
+    sodium::generic_hash,
+    Bijou, FileId, OpenOptions, Result,
+};
+
+/// Computes [`ContentPin`]s from the ciphertext of blocks.
+struct PinHasher {
+    state: generic_hash::State,
+    size: u64,
+}
+
+impl PinHasher {
+    fn update(&mut self, block: &[u8]) -> Result<()> {
+        // lengths are hashed as well, so that blocks cannot be
+        // shifted around
+        self.state.update(&(block.len() as u64).to_le_bytes())?;
+        self.state.update(block)?;
+        self.size += block.len() as u64;
+        Ok(())
+    }
+
+    fn finish(self) -> Result<ContentPin> {
+        let mut digest = [0; 32];
+        self.state.finalize(&mut digest)?;
+        Ok(ContentPin {
+            size: self.size,
+            digest,
+        })
+    }
+}
+
+impl Bijou {
+    pub(super) const PIN_KEY_LEN: usize = 32;
+
+    fn pin_key(&self, id: FileId) -> DatabaseKey<ContentPin> {
+        self.get_key(id).derive(consts::Derive::Pin).typed()
+    }
+
+    fn pin_hasher(&self) -> Result<PinHasher> {
+        Ok(PinHasher {
+            state: generic_hash::State::new(32, Some(&self.pin_hash_key))?,
+            size: 0,
+        })
+    }
+
+    /// Removes the pin of a file, if any.
+    pub(super) fn unpin(&self, id: FileId) -> Result<()> {
+        let key = self.pin_key(id);
+        if key.exists()? {
+            key.delete()?;
+        }
+        Ok(())
+    }
+
+    /// Verifies the opened file `id` and pins its raw content.
+    ///
+    /// Returns whether the file is pinned. Files also opened
+    /// elsewhere are verified but not pinned, since their content
+    /// may be changing.
+    pub(super) fn verify_and_pin(
+        &self,
+        id: FileId,
+        file: &LowLevelFile,
+        on_block: &mut dyn FnMut(u64) -> Result<()>,
+    ) -> Result<bool> {
+        let mut hasher = self.pin_hasher()?;
+        file.verify_with(&mut |data| {
+            on_block(data.len() as u64)?;
+            hasher.update(data)
+        })?;
+        if file.handles() > 1 {
+            return Ok(false);
+        }
+
+        self.pin_key(id).put(&hasher.finish()?)?;
+        Ok(true)
+    }
+
+    /// Checks the raw content of the opened file `id` against its
+    /// pin, without decrypting it.
+    ///
+    /// Returns whether the file is checked. Files not pinned, or also
+    /// opened elsewhere, are not.
+    pub(super) fn check_pin_with(
+        &self,
+        id: FileId,
+        file: &LowLevelFile,
+        on_block: &mut dyn FnMut(u64) -> Result<()>,
+    ) -> Result<bool> {
+        let Some(pin) = self.pin_key(id).get()? else {
+            return Ok(false);
+        };
+        if file.handles() > 1 {
+            return Ok(false);
+        }
+
+        let mut hasher = self.pin_hasher()?;
+        file.read_raw_blocks(&mut |_, data| {
+            on_block(data.len() as u64)?;
+            hasher.update(data)
+        })?;
+        if hasher.finish()? != pin {
+            bail!(@CryptoError "raw content does not match its pin");
+        }
+        Ok(true)
+    }
+
+    /// Verifies a file and pins its raw content, so that changes made
+    /// to it outside Bijou (by tampering, or corruption of the
+    /// storage) can later be detected by [`Bijou::check_pin`]
+    /// without decrypting the file.
+    ///
+    /// Pins are removed whenever the file is opened for writing, so
+    /// legitimate changes are never reported.
+    ///
+    /// Returns `false` if the file is opened elsewhere, in which case
+    /// it is verified but not pinned.
+    pub fn pin_file(&self, id: FileId) -> Result<bool> {
+        self.check_writable()?;
+        let file = self.open_file_direct(id, OpenOptions::read_only())?;
+        self.verify_and_pin(id, &file, &mut |_| Ok(()))
+    }
+
+    /// Checks the raw content of a file against the pin recorded by
+    /// [`Bijou::pin_file`] or [`Bijou::scrub`].
+    ///
+    /// Returns `false` if the file is not pinned, or opened
+    /// elsewhere. Fails with [`ErrorKind::CryptoError`] if the raw
+    /// content has changed.
+    ///
+    /// [`ErrorKind::CryptoError`]: crate::ErrorKind::CryptoError
+    pub fn check_pin(&self, id: FileId) -> Result<bool> {
+        let file = self.open_file_direct(id, OpenOptions::read_only())?;
+        self.check_pin_with(id, &file, &mut |_| Ok(()))
+    }
+}
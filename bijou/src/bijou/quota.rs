@@ -0,0 +1,237 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::{
+    bail,
+    db::{consts, DatabaseKey},
+    quota::{Charged, QuotaInfo, QuotaLimits, Transfer},
+    Bijou, ErrorKind, FileId, FileKind, FileMeta, Result,
+};
+use std::collections::{BTreeMap, HashSet};
+use tracing::{info, trace, warn};
+
+impl Bijou {
+    /// Loads quotas from the database and computes their usage.
+    pub(super) fn init_quotas(&self) -> Result<()> {
+        let Some(limits) = self.quotas_key().get()? else {
+            return Ok(());
+        };
+        let mut dropped = false;
+        for (root, limits) in limits {
+            let files = match self.scan_quota_tree(root) {
+                Ok(files) => files,
+                Err(err) if err.kind() == ErrorKind::NotFound => {
+                    warn!(%root, "dropping quota of missing directory");
+                    dropped = true;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            self.quotas.insert(root, limits, &files);
+        }
+        info!("loaded {} quotas", self.quotas.all().len());
+        if dropped && !self.read_only {
+            self.save_quotas()?;
+        }
+        Ok(())
+    }
+
+    fn quotas_key(&self) -> DatabaseKey<BTreeMap<FileId, QuotaLimits>> {
+        self.db.key(consts::Root::Quota).typed()
+    }
+
+    fn save_quotas(&self) -> Result<()> {
+        let key = self.quotas_key();
+        self.quotas.save(|limits| {
+            if limits.is_empty() {
+                key.delete()
+            } else {
+                key.put(limits)
+            }
+        })
+    }
+
+    /// Returns the files under the directory `dir`, each reachable
+    /// file once.
+    fn scan_quota_tree(&self, dir: FileId) -> Result<Vec<Charged>> {
+        let mut files = Vec::new();
+        let mut visited = HashSet::new();
+        let mut stack = vec![dir];
+        while let Some(dir) = stack.pop() {
+            let ids = self
+                .read_dir(dir)?
+                .dots(false)
+                .map(|entry| entry.map(|(_, item)| item.id))
+                .filter(|id| id.as_ref().map_or(true, |id| visited.insert(*id)))
+                .collect::<Result<Vec<_>>>()?;
+            for meta in self.get_meta_many(&ids)? {
+                if meta.kind == FileKind::Directory {
+                    stack.push(meta.id);
+                }
+                files.push(Self::charged(&meta));
+            }
+        }
+        Ok(files)
+    }
+
+    /// Returns `file` and everything under it if it is a directory.
+    fn scan_quota_item(&self, file: FileId) -> Result<Vec<Charged>> {
+        let meta = self.get_meta(file)?;
+        let mut files = vec![Self::charged(&meta)];
+        if meta.kind == FileKind::Directory {
+            files.extend(self.scan_quota_tree(file)?);
+        }
+        Ok(files)
+    }
+
+    pub(super) fn charged(meta: &FileMeta) -> Charged {
+        Charged {
+            id: meta.id,
+            bytes: if meta.kind == FileKind::File {
+                meta.size
+            } else {
+                0
+            },
+            exclusive: meta.kind == FileKind::Directory || meta.nlinks <= 1,
+        }
+    }
+
+    /// Returns the quota roots that files created in `dir` are
+    /// charged to, i.e. `dir` and its ancestors that have quotas.
+    pub(super) fn quota_roots(&self, mut dir: FileId) -> Result<Vec<FileId>> {
+        let mut roots = Vec::new();
+        if !self.quotas.is_enabled() {
+            return Ok(roots);
+        }
+        loop {
+            if self.quotas.is_root(dir) {
+                roots.push(dir);
+            }
+            if dir == FileId::ROOT {
+                return Ok(roots);
+            }
+            dir = self.parent_dir(dir)?;
+        }
+    }
+
+    /// Moves `file` from `parent` to `new_parent` in terms of quotas,
+    /// together with `exchanged` moving the other way if any.
+    pub(super) fn quota_rename(
+        &self,
+        parent: FileId,
+        new_parent: FileId,
+        file: FileId,
+        exchanged: Option<FileId>,
+    ) -> Result<()> {
+        if !self.quotas.is_enabled() {
+            return Ok(());
+        }
+
+        let old_roots = self.quota_roots(parent)?;
+        let new_roots = self.quota_roots(new_parent)?;
+        let difference = |a: &[FileId], b: &[FileId]| -> Vec<FileId> {
+            a.iter().filter(|root| !b.contains(root)).copied().collect()
+        };
+        let from = difference(&old_roots, &new_roots);
+        let to = difference(&new_roots, &old_roots);
+        if from.is_empty() && to.is_empty() {
+            return Ok(());
+        }
+
+        let files = self.scan_quota_item(file)?;
+        let exchanged = match exchanged {
+            Some(exchanged) => self.scan_quota_item(exchanged)?,
+            None => Vec::new(),
+        };
+        self.quotas.transfer_many(&[
+            Transfer {
+                files: &files,
+                from: &from,
+                to: &to,
+            },
+            Transfer {
+                files: &exchanged,
+                from: &to,
+                to: &from,
+            },
+        ])
+    }
+
+    /// Uncharges a file that has been removed, dropping its quota if
+    /// it is a quota root.
+    pub(super) fn quota_removed(&self, file: FileId) -> Result<()> {
+        self.quotas.uncharge(file);
+        if self.quotas.remove(file) {
+            self.save_quotas()?;
+        }
+        Ok(())
+    }
+
+    /// Sets a quota on the directory `dir`, replacing its limits if
+    /// it already has one.
+    ///
+    /// Files under `dir` are counted against the quota, and creating,
+    /// writing or moving files into it fails with
+    /// [`ErrorKind::QuotaExceeded`] once a limit would be exceeded.
+    /// Setting limits below the current usage is allowed, in which
+    /// case only removals succeed until the usage drops.
+    ///
+    /// A file with several hard links is charged to every quota it
+    /// has been linked under, until it is removed.
+    ///
+    /// [`ErrorKind::QuotaExceeded`]: crate::ErrorKind::QuotaExceeded
+    pub fn set_quota(&self, dir: FileId, limits: QuotaLimits) -> Result<()> {
+        self.check_writable()?;
+        trace!(%dir, ?limits, "set quota");
+        if self.get_raw_meta(&self.get_key(dir))?.kind != FileKind::Directory {
+            bail!(@NotADirectory? "quotas can only be set on directories");
+        }
+
+        // Nothing can be created, resized or moved while scanning
+        let _rename_guard = self.rename_lock.lock().unwrap();
+        let _raw_guard = self.raw_lock.write().unwrap();
+
+        let files = if self.quotas.is_root(dir) {
+            Vec::new()
+        } else {
+            self.scan_quota_tree(dir)?
+        };
+        self.quotas.insert(dir, limits, &files);
+        self.save_quotas()
+    }
+
+    /// Removes the quota on the directory `dir`, returning whether it
+    /// had one.
+    pub fn remove_quota(&self, dir: FileId) -> Result<bool> {
+        self.check_writable()?;
+        trace!(%dir, "remove quota");
+        if !self.quotas.remove(dir) {
+            return Ok(false);
+        }
+        self.save_quotas()?;
+        Ok(true)
+    }
+
+    /// Returns the quota on the directory `dir` and its usage, if
+    /// any.
+    pub fn quota(&self, dir: FileId) -> Option<QuotaInfo> {
+        self.quotas.get(dir)
+    }
+
+    /// Returns all quotas and their usage, sorted by directory.
+    pub fn quotas(&self) -> Vec<(FileId, QuotaInfo)> {
+        self.quotas.all()
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::SYMBOLIC_MAX_DEPTH;
+use crate::{
+    bail,
+    path::{Component, Path},
+    Bijou, Context, ErrorKind, FileId, FileKind, Result,
+};
+
+/// A pending step of [`Resolver::walk`].
+enum Step {
+    Root,
+    Parent,
+    Name(String),
+}
+
+impl Step {
+    /// Pushes steps of `path` onto `steps`, which is popped from
+    /// the back.
+    fn push_all(steps: &mut Vec<Step>, path: &Path) {
+        for comp in path.components().rev() {
+            steps.push(match comp {
+                Component::RootDir => Step::Root,
+                Component::CurDir => continue,
+                Component::ParentDir => Step::Parent,
+                Component::Normal(name) => Step::Name(name.to_owned()),
+            });
+        }
+    }
+}
+
+/// An iterative path resolver.
+///
+/// The resolver keeps the chain of directories from the root to the
+/// current file. Symlinks are expanded in place, so that `..` after a
+/// symlink refers to the parent of its target like in POSIX, and `..`
+/// at the root stays at the root. At most [`SYMBOLIC_MAX_DEPTH`]
+/// symlinks are followed in total.
+///
+/// The root can be any directory (see [`Resolver::with_root`]), in
+/// which case neither `..` nor absolute paths (including symlink
+/// targets) can escape it, like `chroot(2)`.
+pub(crate) struct Resolver<'a> {
+    bijou: &'a Bijou,
+    stack: Vec<FileId>,
+    links: u32,
+}
+
+impl<'a> Resolver<'a> {
+    /// Creates a resolver treating the directory `root` as the root.
+    pub fn with_root(bijou: &'a Bijou, root: FileId) -> Self {
+        Self {
+            bijou,
+            stack: vec![root],
+            links: 0,
+        }
+    }
+
+    /// Returns the current file.
+    pub fn current(&self) -> FileId {
+        *self.stack.last().unwrap()
+    }
+
+    /// Enters `id`, which must be a directory in the current one.
+    pub fn push(&mut self, id: FileId) {
+        self.stack.push(id);
+    }
+
+    /// Walks `path` relatively to the current file, following all
+    /// symlinks. If `dir` is true, the result must be a directory.
+    ///
+    /// The resolver is left unchanged on error.
+    pub fn walk(&mut self, path: &Path, dir: bool) -> Result<()> {
+        let mut stack = self.stack.clone();
+        let mut steps = Vec::new();
+        Step::push_all(&mut steps, path);
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Root => stack.truncate(1),
+                Step::Parent => {
+                    if stack.len() > 1 {
+                        stack.pop();
+                    }
+                }
+                Step::Name(name) => {
+                    let parent = *stack.last().unwrap();
+                    let item = self
+                        .bijou
+                        .child_key(self.bijou.get_key(parent), &name)?
+                        .get()?
+                        .kind(ErrorKind::NotFound)?;
+                    if item.kind == FileKind::Symlink {
+                        self.links += 1;
+                        if self.links > SYMBOLIC_MAX_DEPTH {
+                            bail!(@FilesystemLoop? "too many levels of symbolic links");
+                        }
+                        let target = self.bijou.read_link(item.id)?;
+                        if target.is_empty() {
+                            bail!(@NotFound? "empty symbolic link `{name}`");
+                        }
+                        Step::push_all(&mut steps, Path::new(&target));
+                        continue;
+                    }
+                    if item.kind != FileKind::Directory && (dir || !steps.is_empty()) {
+                        bail!(@NotADirectory? "`{name}` is not a directory");
+                    }
+                    stack.push(item.id);
+                }
+            }
+        }
+        self.stack = stack;
+        Ok(())
+    }
+}
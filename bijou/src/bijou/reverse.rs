@@ -0,0 +1,106 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::{bail, db::consts, error::ResultExt, Bijou, FileId, FileKind, FileMeta, Result};
+use bijou_rocksdb::{Direction, IteratorMode};
+use std::collections::HashSet;
+
+impl Bijou {
+    /// Returns the directories containing entries of `file`, along
+    /// with the names of the entries.
+    fn parents_of(&self, file: FileId) -> Result<Vec<(FileId, String)>> {
+        let prefix = self.get_key(file).derive(consts::Derive::Parent).key;
+        let mut result = Vec::new();
+        for entry in self
+            .db
+            .0
+            .iterator(IteratorMode::From(&prefix, Direction::Forward))
+        {
+            let (mut key, _) = entry.wrap()?;
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            let (parent, name) = key[prefix.len()..].split_at_mut(std::mem::size_of::<FileId>());
+            let parent = FileId::from_bytes(parent);
+            result.push((parent, self.decode_name(parent, name)?));
+        }
+        Ok(result)
+    }
+
+    /// Returns the path of the directory `dir`, or `None` if it is
+    /// not reachable from the root.
+    fn dir_path(&self, dir: FileId) -> Result<Option<String>> {
+        let mut names = Vec::new();
+        let mut visited = HashSet::new();
+        let mut current = dir;
+        while current != FileId::ROOT {
+            if !visited.insert(current) {
+                bail!(@FilesystemLoop "directory {dir} is its own ancestor");
+            }
+            // directories have a single entry
+            let Some((parent, name)) = self.parents_of(current)?.pop() else {
+                return Ok(None);
+            };
+            names.push(name);
+            current = parent;
+        }
+        names.reverse();
+        Ok(Some(format!("/{}", names.join("/"))))
+    }
+
+    /// Returns whether a file is reachable from the root, using the
+    /// reverse index.
+    pub(super) fn is_reachable(&self, meta: &FileMeta) -> Result<bool> {
+        if meta.kind == FileKind::Directory {
+            return Ok(self.dir_path(meta.id)?.is_some());
+        }
+        for (parent, _) in self.parents_of(meta.id)? {
+            if self.dir_path(parent)?.is_some() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Returns the paths of `file`, one for each of its hard links,
+    /// using the reverse index (see [`Config::reverse_index`]).
+    ///
+    /// Files that are not reachable from the root (e.g. unlinked
+    /// files that are still open) have no paths. The paths are not
+    /// read atomically, so they may be inconsistent if the file or its
+    /// ancestors are renamed meanwhile.
+    ///
+    /// Fails with [`ErrorKind::Unsupported`] if the index is disabled.
+    ///
+    /// [`Config::reverse_index`]: crate::Config::reverse_index
+    /// [`ErrorKind::Unsupported`]: crate::ErrorKind::Unsupported
+    pub fn path_of(&self, file: FileId) -> Result<Vec<String>> {
+        if !self.config.reverse_index {
+            bail!(@Unsupported "reverse index is disabled");
+        }
+        if file == FileId::ROOT {
+            return Ok(vec!["/".to_owned()]);
+        }
+        let mut paths = Vec::new();
+        for (parent, name) in self.parents_of(file)? {
+            match self.dir_path(parent)?.as_deref() {
+                Some("/") => paths.push(format!("/{name}")),
+                Some(dir) => paths.push(format!("{dir}/{name}")),
+                None => {}
+            }
+        }
+        Ok(paths)
+    }
+}
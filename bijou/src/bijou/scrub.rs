@@ -0,0 +1,478 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::with_workers;
+use crate::{bail, serde_ext, Bijou, Error, FileId, FileKind, FileMeta, OpenOptions, Result};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+use unicode_normalization::UnicodeNormalization;
+
+/// Options for [`Bijou::scrub`].
+#[derive(Clone, Debug, Default)]
+pub struct ScrubOptions {
+    /// The maximum number of raw bytes to read per second.
+    /// `None` means unlimited.
+    pub rate_limit: Option<u64>,
+    /// Only check the raw content of files against the pins recorded
+    /// by earlier scrubs, without decrypting them. This detects
+    /// changes made to the storage outside Bijou at a fraction of
+    /// the cost.
+    ///
+    /// Files not pinned yet are verified in full. See
+    /// [`Bijou::pin_file`].
+    pub quick: bool,
+    /// The number of threads verifying files, or `0` for one per CPU
+    /// core.
+    pub threads: usize,
+}
+
+/// A file that failed to pass [`Bijou::scrub`].
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScrubFailure {
+    pub path: String,
+    pub id: FileId,
+    #[serde(with = "serde_ext::display")]
+    pub error: Error,
+}
+
+/// The result of [`Bijou::scrub`].
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScrubReport {
+    pub files: u64,
+    pub directories: u64,
+    pub symlinks: u64,
+    /// The number of raw bytes read.
+    pub bytes: u64,
+    /// The number of files checked against their pins instead of
+    /// being verified in full.
+    pub pin_checked: u64,
+    /// The number of files pinned. Every file verified in full is
+    /// pinned, unless it is opened elsewhere.
+    pub pinned: u64,
+    pub failures: Vec<ScrubFailure>,
+    /// The number of files that are not reachable from the root,
+    /// e.g. unlinked files that are still open, or ones leaked by a
+    /// crash. These are not verified and do not count as failures.
+    pub unreachable_count: u64,
+    /// The unreachable files, which can only be told apart with the
+    /// reverse index (see [`Config::reverse_index`]). Otherwise,
+    /// they are only counted.
+    ///
+    /// [`Config::reverse_index`]: crate::Config::reverse_index
+    pub unreachable: Vec<FileId>,
+    /// Paths of entries whose names only differ in Unicode
+    /// normalization, grouped by directory and name. See
+    /// [`Config::normalization_duplicates`].
+    ///
+    /// [`Config::normalization_duplicates`]: crate::Config::normalization_duplicates
+    pub normalization_duplicates: Vec<Vec<String>>,
+}
+
+impl ScrubReport {
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Counters of a running scrub or check, see [`ScrubEvent::Progress`]
+/// and [`CheckEvent::Progress`].
+///
+/// [`CheckEvent::Progress`]: super::CheckEvent::Progress
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct ScrubProgress {
+    pub files: u64,
+    pub directories: u64,
+    pub symlinks: u64,
+    /// The number of raw bytes read.
+    pub bytes: u64,
+}
+
+/// Something found by [`Bijou::scrub_with`], reported as soon as it
+/// is found.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ScrubEvent {
+    /// A file failed to pass the scrub.
+    Failure(ScrubFailure),
+    /// A file not reachable from the root, see
+    /// [`ScrubReport::unreachable`].
+    Unreachable(FileId),
+    /// Paths of entries in a directory whose names only differ in
+    /// Unicode normalization, see
+    /// [`ScrubReport::normalization_duplicates`].
+    NormalizationDuplicates(Vec<String>),
+    /// The progress so far, reported every few seconds.
+    Progress(ScrubProgress),
+}
+
+/// Limits the rate of reads, and counts the bytes read.
+pub(super) struct Throttle {
+    rate_limit: Option<u64>,
+    start: Instant,
+    /// Bytes read before `start`.
+    offset: u64,
+    /// Makes reads fail once set.
+    stop: Option<Arc<AtomicBool>>,
+    pub bytes: u64,
+}
+
+impl Throttle {
+    pub fn new(rate_limit: Option<u64>) -> Self {
+        Self {
+            rate_limit,
+            start: Instant::now(),
+            offset: 0,
+            stop: None,
+            bytes: 0,
+        }
+    }
+
+    /// Makes reads fail once `stop` is set, so that long reads can be
+    /// interrupted.
+    pub fn stop_on(mut self, stop: Arc<AtomicBool>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    /// Restarts measuring the rate, so that a pause is not made up
+    /// for by a burst of reads.
+    pub fn restart(&mut self) {
+        self.start = Instant::now();
+        self.offset = self.bytes;
+    }
+
+    pub fn consume(&mut self, bytes: u64) -> Result<()> {
+        if self
+            .stop
+            .as_ref()
+            .is_some_and(|stop| stop.load(Ordering::Relaxed))
+        {
+            bail!(@Unspecified? "stopped");
+        }
+        self.bytes += bytes;
+        if let Some(rate_limit) = self.rate_limit {
+            let expected =
+                Duration::from_secs_f64((self.bytes - self.offset) as f64 / rate_limit as f64);
+            let elapsed = self.start.elapsed();
+            if expected > elapsed {
+                std::thread::sleep(expected - elapsed);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How a file passed [`Bijou::scrub_file`].
+pub(super) enum FileCheck {
+    /// Checked against its pin.
+    Pin,
+    /// Verified in full and pinned.
+    Pinned,
+    /// Verified in full, but not pinned since it is opened elsewhere.
+    Verified,
+}
+
+/// State shared by the threads of a scrub.
+struct Scrubber<'a> {
+    bijou: &'a Bijou,
+    quick: bool,
+    throttle: Mutex<Throttle>,
+    /// Visited files with several links, so that each is checked
+    /// once. Other files can only be reached once.
+    linked: Mutex<HashSet<FileId>>,
+    files: AtomicU64,
+    directories: AtomicU64,
+    symlinks: AtomicU64,
+    pin_checked: AtomicU64,
+    pinned: AtomicU64,
+    last_progress: Mutex<Instant>,
+    on_event: &'a (dyn Fn(ScrubEvent) + Sync),
+}
+
+impl Scrubber<'_> {
+    /// Interval between [`ScrubEvent::Progress`] events.
+    const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+    fn fail(&self, id: FileId, path: String, error: Error) {
+        warn!(%id, "scrub failed at {path}: {error}");
+        (self.on_event)(ScrubEvent::Failure(ScrubFailure { path, id, error }));
+    }
+
+    fn progress(&self) -> ScrubProgress {
+        ScrubProgress {
+            files: self.files.load(Ordering::Relaxed),
+            directories: self.directories.load(Ordering::Relaxed),
+            symlinks: self.symlinks.load(Ordering::Relaxed),
+            bytes: self.throttle.lock().unwrap().bytes,
+        }
+    }
+
+    /// Reports the progress if the last report is old enough.
+    fn tick(&self) {
+        let Ok(mut last) = self.last_progress.try_lock() else {
+            return;
+        };
+        if last.elapsed() >= Self::PROGRESS_INTERVAL {
+            *last = Instant::now();
+            drop(last);
+            (self.on_event)(ScrubEvent::Progress(self.progress()));
+        }
+    }
+
+    /// Checks a directory, pushing its subdirectories to `dirs` and
+    /// passing its other entries to `queue`.
+    fn check_dir(
+        &self,
+        id: FileId,
+        path: &str,
+        dirs: &mut Vec<(FileId, String)>,
+        queue: &dyn Fn((FileId, FileKind, String)),
+    ) -> Result<()> {
+        self.bijou.check_entry(id, FileKind::Directory)?;
+        self.directories.fetch_add(1, Ordering::Relaxed);
+
+        // only names in this directory are kept
+        let mut forms = HashMap::<String, Vec<String>>::new();
+        for entry in self.bijou.read_dir(id)?.dots(false) {
+            let (name, item) = entry?;
+            let child = child_path(path, &name);
+            if !name.is_ascii() {
+                forms
+                    .entry(name.nfc().collect())
+                    .or_default()
+                    .push(child.clone());
+            }
+            match item.kind {
+                FileKind::Directory => dirs.push((item.id, child)),
+                kind => queue((item.id, kind, child)),
+            }
+        }
+        for paths in forms.into_values().filter(|paths| paths.len() > 1) {
+            (self.on_event)(ScrubEvent::NormalizationDuplicates(paths));
+        }
+        Ok(())
+    }
+
+    /// Checks a file or a symlink, on a worker thread.
+    fn check_leaf(&self, id: FileId, kind: FileKind, path: String) {
+        if let Err(error) = self.check_leaf_inner(id, kind) {
+            self.fail(id, path, error);
+        }
+        self.tick();
+    }
+
+    fn check_leaf_inner(&self, id: FileId, kind: FileKind) -> Result<()> {
+        let meta = self.bijou.check_entry(id, kind)?;
+        if meta.nlinks > 1 && !self.linked.lock().unwrap().insert(id) {
+            // hard link
+            return Ok(());
+        }
+
+        match kind {
+            FileKind::File => {
+                self.files.fetch_add(1, Ordering::Relaxed);
+                let check = self.bijou.scrub_file_with(id, self.quick, &mut |len| {
+                    self.throttle.lock().unwrap().consume(len)
+                })?;
+                let counter = match check {
+                    FileCheck::Pin => &self.pin_checked,
+                    FileCheck::Pinned => &self.pinned,
+                    FileCheck::Verified => return Ok(()),
+                };
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+            FileKind::Symlink => {
+                self.symlinks.fetch_add(1, Ordering::Relaxed);
+                self.bijou.read_link(id)?;
+            }
+            FileKind::Directory => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+pub(super) fn child_path(path: &str, name: &str) -> String {
+    if path.ends_with('/') {
+        format!("{path}{name}")
+    } else {
+        format!("{path}/{name}")
+    }
+}
+
+impl Bijou {
+    /// Checks that the metadata of a file agrees with a directory
+    /// entry saying it is of `kind`, returning the metadata.
+    pub(super) fn check_entry(&self, id: FileId, kind: FileKind) -> Result<FileMeta> {
+        let meta = self.get_meta(id)?;
+        if meta.kind != kind {
+            bail!(
+                "directory entry says {kind:?}, but metadata says {:?}",
+                meta.kind
+            );
+        }
+        Ok(meta)
+    }
+
+    /// Verifies the content of a regular file, or only checks it
+    /// against its pin if `quick` is set and it is pinned.
+    pub(super) fn scrub_file(
+        &self,
+        id: FileId,
+        quick: bool,
+        throttle: &mut Throttle,
+    ) -> Result<FileCheck> {
+        self.scrub_file_with(id, quick, &mut |len| throttle.consume(len))
+    }
+
+    /// Same as [`Bijou::scrub_file`], but calls `on_block` with the
+    /// length of every raw block read.
+    pub(super) fn scrub_file_with(
+        &self,
+        id: FileId,
+        quick: bool,
+        on_block: &mut dyn FnMut(u64) -> Result<()>,
+    ) -> Result<FileCheck> {
+        let file = self.open_file_direct(id, OpenOptions::read_only())?;
+        Ok(if quick && self.check_pin_with(id, &file, on_block)? {
+            FileCheck::Pin
+        } else if self.verify_and_pin(id, &file, on_block)? {
+            FileCheck::Pinned
+        } else {
+            FileCheck::Verified
+        })
+    }
+
+    /// Walks through the whole Bijou and verifies every file.
+    ///
+    /// Every block of every file is decrypted (but never written
+    /// anywhere) and authenticated, and metadata of each file is
+    /// checked against its directory entry. See also
+    /// [`LowLevelFile::verify`].
+    ///
+    /// Failures of individual files are collected in the returned
+    /// report instead of aborting the scrub. See [`Bijou::scrub_with`]
+    /// to receive them as they are found instead.
+    ///
+    /// Verified files are pinned, and only checked against their pins
+    /// by quick scrubs (see [`ScrubOptions::quick`]).
+    ///
+    /// [`LowLevelFile::verify`]: crate::low_level::LowLevelFile::verify
+    pub fn scrub(&self, options: &ScrubOptions) -> ScrubReport {
+        let found = Mutex::new(ScrubReport::default());
+        let mut report = self.scrub_with(options, &|event| {
+            let mut found = found.lock().unwrap();
+            match event {
+                ScrubEvent::Failure(failure) => found.failures.push(failure),
+                ScrubEvent::Unreachable(id) => found.unreachable.push(id),
+                ScrubEvent::NormalizationDuplicates(paths) => {
+                    found.normalization_duplicates.push(paths)
+                }
+                ScrubEvent::Progress(_) => {}
+            }
+        });
+        let found = found.into_inner().unwrap();
+        report.failures = found.failures;
+        report.unreachable = found.unreachable;
+        report.normalization_duplicates = found.normalization_duplicates;
+        report
+    }
+
+    /// Same as [`Bijou::scrub`], but passes failures and other
+    /// findings to `on_event` as they are found, along with the
+    /// progress every few seconds. The lists in the returned report
+    /// are left empty.
+    ///
+    /// Directories are walked on the current thread, while files are
+    /// verified by [`ScrubOptions::threads`] workers. Memory usage
+    /// does not grow with the number of files, except for files with
+    /// several hard links, which are remembered so that they are
+    /// only verified once. `on_event` is called from any of these
+    /// threads.
+    pub fn scrub_with(
+        &self,
+        options: &ScrubOptions,
+        on_event: &(dyn Fn(ScrubEvent) + Sync),
+    ) -> ScrubReport {
+        info!(threads = options.threads, "scrubbing Bijou");
+
+        let scrubber = Scrubber {
+            bijou: self,
+            quick: options.quick,
+            throttle: Mutex::new(Throttle::new(options.rate_limit)),
+            linked: Mutex::default(),
+            files: AtomicU64::new(0),
+            directories: AtomicU64::new(0),
+            symlinks: AtomicU64::new(0),
+            pin_checked: AtomicU64::new(0),
+            pinned: AtomicU64::new(0),
+            last_progress: Mutex::new(Instant::now()),
+            on_event,
+        };
+        with_workers(
+            options.threads,
+            |(id, kind, path): (FileId, FileKind, String)| scrubber.check_leaf(id, kind, path),
+            |queue| {
+                let mut dirs = vec![(FileId::ROOT, "/".to_owned())];
+                while let Some((id, path)) = dirs.pop() {
+                    if let Err(error) = scrubber.check_dir(id, &path, &mut dirs, queue) {
+                        scrubber.fail(id, path, error);
+                    }
+                    scrubber.tick();
+                }
+            },
+        );
+
+        let progress = scrubber.progress();
+        let mut report = ScrubReport {
+            files: progress.files,
+            directories: progress.directories,
+            symlinks: progress.symlinks,
+            bytes: progress.bytes,
+            pin_checked: scrubber.pin_checked.into_inner(),
+            pinned: scrubber.pinned.into_inner(),
+            ..Default::default()
+        };
+
+        let mut total = 0u64;
+        for meta in self.iter_files() {
+            match meta {
+                Ok(_) => total += 1,
+                Err(err) => warn!("failed to enumerate files: {err}"),
+            }
+        }
+        let reachable = report.files + report.directories + report.symlinks;
+        report.unreachable_count = total.saturating_sub(reachable);
+        if report.unreachable_count != 0 && self.config.reverse_index {
+            for meta in self.iter_files().flatten() {
+                match self.is_reachable(&meta) {
+                    Ok(true) => {}
+                    Ok(false) => on_event(ScrubEvent::Unreachable(meta.id)),
+                    Err(err) => warn!(id = %meta.id, "failed to check reachability: {err}"),
+                }
+            }
+        }
+        report
+    }
+}
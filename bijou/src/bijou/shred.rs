@@ -0,0 +1,96 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::{bail, fs::FileFlags, sodium::utils, Bijou, FileId, FileKind, Result};
+use std::sync::atomic::Ordering;
+
+impl Bijou {
+    /// Unlinks the regular file `name` from `parent`, making its
+    /// content unrecoverable as far as the storage backend allows.
+    ///
+    /// Before the raw file is removed, every block of it is
+    /// overwritten with random bytes and synced. Keys of files are
+    /// derived from the master key instead of being stored, and
+    /// Bijou does not cache them outside of opened files, so there
+    /// is no per-file key record to destroy; shredding is refused
+    /// for opened files instead.
+    ///
+    /// What is actually guaranteed depends on the storage:
+    ///
+    /// - [`FileStorage::Local`] and [`FileStorage::Split`]: blocks
+    ///   are overwritten in place. Copy-on-write or journaling
+    ///   filesystems, snapshots and SSD wear leveling may still
+    ///   keep the old blocks.
+    /// - [`FileStorage::RocksDB`]: overwriting writes new values,
+    ///   and old ones stay in the database files until they are
+    ///   compacted away.
+    /// - [`FileStorage::OpenDAL`]: new objects are uploaded over
+    ///   the old ones. Versioning or backups of the remote are out
+    ///   of Bijou's reach.
+    ///
+    /// In all cases, leftovers are still encrypted and can only be
+    /// decrypted with the master key.
+    ///
+    /// Fails with [`ErrorKind::InvalidInput`] if the file is not a
+    /// regular file, has other hard links, shares its content with
+    /// clones (see [`Bijou::clone_file`]) or is currently opened.
+    ///
+    /// [`FileStorage::Local`]: crate::config::FileStorage::Local
+    /// [`FileStorage::Split`]: crate::config::FileStorage::Split
+    /// [`FileStorage::RocksDB`]: crate::config::FileStorage::RocksDB
+    /// [`FileStorage::OpenDAL`]: crate::config::FileStorage::OpenDAL
+    /// [`ErrorKind::InvalidInput`]: crate::ErrorKind::InvalidInput
+    pub fn shred(&self, parent: FileId, name: &str) -> Result<()> {
+        self.check_writable()?;
+        let file = self.lookup(parent, name)?;
+        let meta = self.get_raw_meta(&self.get_key(file))?;
+        if meta.kind != FileKind::File {
+            bail!(@InvalidInput? "can only shred regular files: {name}");
+        }
+        if meta.nlinks > 1 {
+            bail!(@InvalidInput? "file has other hard links: {name}");
+        }
+        let content = meta.content_id();
+        if meta.content.is_some() || self.refs.count(content)? > 1 {
+            bail!(@InvalidInput? "file shares its content with clones: {name}");
+        }
+        if self
+            .file_open_counts
+            .get(&file)
+            .is_some_and(|count| count.load(Ordering::SeqCst) > 0)
+        {
+            bail!(@InvalidInput? "file is opened: {name}");
+        }
+
+        self.overwrite_content(content)?;
+        self.unlink(parent, name)?;
+        self.raw_fs.flush()
+    }
+
+    /// Overwrites every block of the raw file `content` with random
+    /// bytes.
+    fn overwrite_content(&self, content: FileId) -> Result<()> {
+        let size = self.raw_fs.stat(content)?.size;
+        let block_size = self.algo.block_size();
+        let mut raw = self.raw_fs.open(content, FileFlags::WRITE)?;
+        let mut buffer = self.block_buffers.get(block_size as _);
+        for block in size.blocks(block_size) {
+            let block_end = (size - block.offset(block_size)).0.min(block_size) as usize;
+            utils::rand_bytes(&mut buffer[..block_end]);
+            raw.write_block(&buffer, block_end, block)?;
+        }
+        raw.sync()
+    }
+}
@@ -0,0 +1,289 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::scrub::Throttle;
+use crate::{
+    bail,
+    clock::Clock,
+    config::FileStorage,
+    db::{self, consts, DatabaseKey},
+    error::ResultExt,
+    serde_ext, Bijou, FileId, FileKind, FileMeta, Result,
+};
+use bijou_rocksdb::{Direction, IteratorMode};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+/// Kinds of maintenance tasks, see [`Bijou::start_task`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TaskKind {
+    /// Verifies every file like [`Bijou::scrub`] does, including
+    /// unreachable ones.
+    ///
+    /// Files are visited in the order they are stored instead of
+    /// walking the tree, so that the scrub can be resumed. Failures
+    /// are thus reported by file ID, and directory entries are
+    /// checked when their directory is visited.
+    Scrub {
+        /// See [`ScrubOptions::quick`](crate::ScrubOptions::quick).
+        quick: bool,
+    },
+    /// Moves raw files from one storage to another. Created by
+    /// [`Bijou::start_storage_migration`] and run by
+    /// [`Bijou::migrate_storage`].
+    MigrateStorage {
+        #[serde(with = "serde_ext::json")]
+        from: FileStorage,
+        #[serde(with = "serde_ext::json")]
+        to: FileStorage,
+    },
+}
+
+/// A file that failed a task.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TaskFailure {
+    pub id: FileId,
+    pub error: String,
+}
+
+/// The progress of a maintenance task, checkpointed in the database
+/// so that the task can be resumed after restarts.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskState {
+    pub id: u64,
+    pub kind: TaskKind,
+    pub started: DateTime<Utc>,
+    /// When the last checkpoint was made.
+    pub updated: DateTime<Utc>,
+    /// The stage of tasks made of several passes over the files,
+    /// starting from 0.
+    pub stage: u32,
+    /// The last file processed. The task resumes after it.
+    pub cursor: Option<FileId>,
+    /// The number of files processed.
+    pub files: u64,
+    /// The number of raw bytes read.
+    pub bytes: u64,
+    pub failures: Vec<TaskFailure>,
+}
+
+/// Saves the progress of a running task.
+pub(super) struct Checkpoints {
+    key: DatabaseKey<TaskState>,
+    /// Bytes read by earlier runs.
+    initial_bytes: u64,
+    last: Instant,
+    clock: Arc<dyn Clock>,
+}
+
+impl Checkpoints {
+    /// Interval between checkpoints.
+    const INTERVAL: Duration = Duration::from_secs(5);
+
+    /// Saves `state` if the last checkpoint is old enough, or if
+    /// `force` is set. Fails if the task has been cancelled.
+    pub fn save(&mut self, state: &mut TaskState, throttle: &Throttle, force: bool) -> Result<()> {
+        if !force && self.clock.instant() - self.last < Self::INTERVAL {
+            return Ok(());
+        }
+        if !self.key.exists()? {
+            bail!(@NotFound? "task {} is cancelled", state.id);
+        }
+        state.bytes = self.initial_bytes + throttle.bytes;
+        state.updated = self.clock.now();
+        self.key.put(state)?;
+        self.last = self.clock.instant();
+        Ok(())
+    }
+
+    /// Removes the finished task, returning its final state.
+    pub fn finish(self, mut state: TaskState, throttle: &Throttle) -> Result<TaskState> {
+        self.key.delete()?;
+        state.bytes = self.initial_bytes + throttle.bytes;
+        state.updated = self.clock.now();
+        info!(id = state.id, "task finished");
+        Ok(state)
+    }
+}
+
+impl Bijou {
+    fn task_key(&self, id: u64) -> DatabaseKey<TaskState> {
+        // big-endian, so that tasks are listed in order
+        self.db
+            .key(consts::Root::Task)
+            .derive(id.to_be_bytes())
+            .typed()
+    }
+
+    /// Returns all unfinished maintenance tasks, whether running,
+    /// interrupted or not started yet.
+    pub fn tasks(&self) -> Result<Vec<TaskState>> {
+        let mut tasks = Vec::new();
+        for entry in self.db.0.iterator(IteratorMode::From(
+            consts::Root::Task.as_ref(),
+            Direction::Forward,
+        )) {
+            let (key, value) = entry.wrap()?;
+            if !key.starts_with(consts::Root::Task.as_ref()) {
+                break;
+            }
+            tasks.push(db::decode(&value)?);
+        }
+        Ok(tasks)
+    }
+
+    /// Creates a maintenance task and returns its ID. The task is run
+    /// by [`Bijou::run_task`].
+    pub fn start_task(&self, kind: TaskKind) -> Result<u64> {
+        self.check_writable()?;
+        let _guard = self.running_tasks.lock().unwrap();
+        let id = self.tasks()?.last().map_or(1, |task| task.id + 1);
+        let now = self.clock.now();
+        info!(id, ?kind, "creating task");
+        self.task_key(id).put(&TaskState {
+            id,
+            kind,
+            started: now,
+            updated: now,
+            stage: 0,
+            cursor: None,
+            files: 0,
+            bytes: 0,
+            failures: Vec::new(),
+        })?;
+        Ok(id)
+    }
+
+    /// Removes a task, returning whether it exists.
+    ///
+    /// If the task is running, it stops at its next checkpoint.
+    pub fn cancel_task(&self, id: u64) -> Result<bool> {
+        self.check_writable()?;
+        let key = self.task_key(id);
+        if !key.exists()? {
+            return Ok(false);
+        }
+        key.delete()?;
+        info!(id, "task cancelled");
+        Ok(true)
+    }
+
+    /// Runs a task from where it was last checkpointed, until it
+    /// finishes. Finished tasks are removed, and their final state
+    /// is returned.
+    ///
+    /// `rate_limit` is the maximum number of raw bytes to read per
+    /// second.
+    pub fn run_task(&self, id: u64, rate_limit: Option<u64>) -> Result<TaskState> {
+        self.check_writable()?;
+        if !self.running_tasks.lock().unwrap().insert(id) {
+            bail!(@AlreadyExists "task {id} is already running");
+        }
+        let result = self.run_task_inner(id, rate_limit);
+        self.running_tasks.lock().unwrap().remove(&id);
+        result
+    }
+
+    /// Loads a task to be run.
+    pub(super) fn load_task(&self, id: u64) -> Result<(TaskState, Checkpoints)> {
+        let key = self.task_key(id);
+        let Some(state) = key.get()? else {
+            bail!(@NotFound "task {id} not found");
+        };
+        info!(
+            id,
+            kind = ?state.kind,
+            stage = state.stage,
+            cursor = ?state.cursor,
+            "running task"
+        );
+        let checkpoints = Checkpoints {
+            key,
+            initial_bytes: state.bytes,
+            last: self.clock.instant(),
+            clock: Arc::clone(&self.clock),
+        };
+        Ok((state, checkpoints))
+    }
+
+    fn run_task_inner(&self, id: u64, rate_limit: Option<u64>) -> Result<TaskState> {
+        let (mut state, mut checkpoints) = self.load_task(id)?;
+        let mut throttle = Throttle::new(rate_limit);
+
+        match state.kind {
+            TaskKind::Scrub { quick } => {
+                let mut files = self.iter_files();
+                if let Some(cursor) = state.cursor {
+                    files.after(cursor);
+                }
+                for meta in files {
+                    let meta = match meta {
+                        Ok(meta) => meta,
+                        Err(err) => {
+                            warn!("failed to enumerate files: {err}");
+                            continue;
+                        }
+                    };
+                    if let Err(err) = self.scrub_meta(&meta, quick, &mut throttle) {
+                        warn!(id = %meta.id, "scrub failed: {err}");
+                        state.failures.push(TaskFailure {
+                            id: meta.id,
+                            error: err.to_string(),
+                        });
+                    }
+                    state.files += 1;
+                    state.cursor = Some(meta.id);
+                    checkpoints.save(&mut state, &throttle, false)?;
+                }
+            }
+            TaskKind::MigrateStorage { .. } => {
+                bail!(@InvalidInput "storage migrations are run by Bijou::migrate_storage");
+            }
+        }
+
+        checkpoints.finish(state, &throttle)
+    }
+
+    pub(super) fn scrub_meta(
+        &self,
+        meta: &FileMeta,
+        quick: bool,
+        throttle: &mut Throttle,
+    ) -> Result<()> {
+        match meta.kind {
+            FileKind::File => {
+                self.scrub_file(meta.id, quick, throttle)?;
+            }
+            FileKind::Symlink => {
+                self.read_link(meta.id)?;
+            }
+            FileKind::Directory => {
+                for entry in self.read_dir(meta.id)?.dots(false) {
+                    let (name, item) = entry?;
+                    self.check_entry(item.id, item.kind)
+                        .map_err(|err| err.context(format!("entry {name}")))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::{Bijou, FileId, FileKind, Result, TreeEntry};
+
+/// Options for [`Bijou::tree_with`].
+#[derive(Clone, Debug, Default)]
+pub struct TreeOptions {
+    /// The number of levels to list, e.g. `Some(1)` only lists the
+    /// entries of the directory itself. `None` means unlimited.
+    ///
+    /// Directories at the last level have no
+    /// [`children`](TreeEntry::children).
+    pub max_depth: Option<usize>,
+    /// Whether to fill in [`TreeEntry::size`] and
+    /// [`TreeEntry::perms`], which costs a metadata lookup per entry.
+    pub metadata: bool,
+}
+
+impl Bijou {
+    /// Returns entries under the directory `dir` recursively,
+    /// sorted by name.
+    pub fn tree(&self, dir: FileId) -> Result<Vec<TreeEntry>> {
+        self.tree_with(dir, &TreeOptions::default())
+    }
+
+    /// Same as [`Bijou::tree`], but with options for the depth and
+    /// for the metadata included.
+    pub fn tree_with(&self, dir: FileId, options: &TreeOptions) -> Result<Vec<TreeEntry>> {
+        let mut entries = Vec::new();
+        if options.max_depth == Some(0) {
+            return Ok(entries);
+        }
+        let options_below = TreeOptions {
+            max_depth: options.max_depth.map(|depth| depth - 1),
+            ..options.clone()
+        };
+        for entry in self.read_dir(dir)?.dots(false) {
+            let (name, item) = entry?;
+            let children = if item.kind == FileKind::Directory && options_below.max_depth != Some(0)
+            {
+                Some(self.tree_with(item.id, &options_below)?)
+            } else {
+                None
+            };
+            let (size, perms) = if options.metadata {
+                let meta = self.get_meta(item.id)?;
+                (Some(meta.size), meta.perms)
+            } else {
+                (None, None)
+            };
+            entries.push(TreeEntry {
+                name,
+                id: item.id,
+                kind: item.kind,
+                size,
+                perms,
+                children,
+            });
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+}
@@ -0,0 +1,118 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::error::ResultExt;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{path::PathBuf as StdPathBuf, time::Duration};
+use tracing::warn;
+
+/// Failed unlock attempts are allowed without delay for this many times.
+const FREE_ATTEMPTS: u32 = 3;
+const MAX_DELAY: Duration = Duration::from_secs(300);
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UnlockRecord {
+    failures: u32,
+    last_failure: Option<DateTime<Utc>>,
+}
+
+/// Throttles unlock attempts with wrong passwords.
+///
+/// Failed attempts are recorded in `unlock.json` next to the keystore,
+/// and each attempt after [`FREE_ATTEMPTS`] failures is delayed
+/// exponentially. This only slows down brute-forcing through Bijou
+/// itself, since the record can be removed by anyone having access
+/// to the storage.
+pub(super) struct UnlockThrottle {
+    path: StdPathBuf,
+    record: UnlockRecord,
+}
+
+impl UnlockThrottle {
+    pub fn load(path: StdPathBuf) -> Self {
+        let record = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|err| {
+                warn!("corrupted unlock record: {err}");
+                UnlockRecord {
+                    failures: FREE_ATTEMPTS,
+                    last_failure: None,
+                }
+            }),
+            Err(_) => UnlockRecord::default(),
+        };
+        Self { path, record }
+    }
+
+    fn delay(&self) -> Duration {
+        let Some(exp) = self.record.failures.checked_sub(FREE_ATTEMPTS) else {
+            return Duration::ZERO;
+        };
+        Duration::from_secs(1)
+            .checked_mul(1 << exp.min(31))
+            .map_or(MAX_DELAY, |delay| delay.min(MAX_DELAY))
+    }
+
+    /// Waits until the next unlock attempt is allowed.
+    pub fn wait(&self) {
+        let delay = self.delay();
+        if delay.is_zero() {
+            return;
+        }
+        let elapsed = self
+            .record
+            .last_failure
+            .and_then(|time| (Utc::now() - time).to_std().ok())
+            .unwrap_or_default();
+        if let Some(remaining) = delay.checked_sub(elapsed) {
+            warn!(
+                "{} failed unlock attempts, waiting for {remaining:?}",
+                self.record.failures
+            );
+            std::thread::sleep(remaining);
+        }
+    }
+
+    /// Records a failed unlock attempt.
+    pub fn fail(mut self) {
+        self.record.failures += 1;
+        self.record.last_failure = Some(Utc::now());
+        warn!("failed unlock attempt #{}", self.record.failures);
+        let result = serde_json::to_vec(&self.record)
+            .wrap()
+            .and_then(|bytes| std::fs::write(&self.path, bytes).wrap());
+        if let Err(err) = result {
+            warn!("failed to save unlock record: {err}");
+        }
+    }
+
+    /// Records a successful unlock, resetting the counter.
+    ///
+    /// Failing to do so (e.g. on read-only media) only leaves the
+    /// counter as is, which must not fail the unlock.
+    pub fn succeed(self) {
+        if self.record.failures == 0 {
+            return;
+        }
+        warn!(
+            "{} failed unlock attempts since last unlock, last one at {:?}",
+            self.record.failures, self.record.last_failure
+        );
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            warn!("failed to reset unlock record: {err}");
+        }
+    }
+}
@@ -0,0 +1,97 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::Bijou;
+use crate::{
+    anyhow,
+    db::{self, consts, DatabaseKey},
+    error::ResultExt,
+    FileKind, Result, UsageSnapshot, UsageStats,
+};
+use bijou_rocksdb::{Direction, IteratorMode};
+use chrono::NaiveDate;
+use tracing::info;
+
+/// The format of dates in the keys of usage snapshots, which sorts
+/// chronologically.
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+impl Bijou {
+    fn usage_key(&self, date: NaiveDate) -> DatabaseKey<UsageStats> {
+        self.db
+            .key(consts::Root::Usage)
+            .derive(date.format(DATE_FORMAT).to_string())
+            .typed()
+    }
+
+    /// Computes the usage statistics of this Bijou.
+    ///
+    /// This reads the metadata of every file and the size of every
+    /// raw file, so it may take a while for large Bijous.
+    pub fn usage(&self) -> Result<UsageStats> {
+        let mut stats = UsageStats::default();
+        for meta in self.iter_files() {
+            let meta = meta?;
+            match meta.kind {
+                FileKind::File => stats.add_file(meta.size),
+                FileKind::Directory => stats.directories += 1,
+                FileKind::Symlink => stats.symlinks += 1,
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Computes the usage statistics of this Bijou and keeps them as
+    /// the snapshot of today, replacing the one taken earlier today,
+    /// if any. See [`Bijou::usage_history`].
+    ///
+    /// Snapshots are not taken automatically. Calling this daily
+    /// (e.g. by running `bijou du` from a scheduler) records how the
+    /// Bijou grows over time.
+    pub fn record_usage(&self) -> Result<UsageSnapshot> {
+        self.check_writable()?;
+        let stats = self.usage()?;
+        let date = self.clock.now().date_naive();
+        self.usage_key(date).put(&stats)?;
+        info!(%date, files = stats.files, bytes = stats.bytes, "recorded usage");
+        Ok(UsageSnapshot { date, stats })
+    }
+
+    /// Returns the usage snapshots recorded by
+    /// [`Bijou::record_usage`], oldest first.
+    pub fn usage_history(&self) -> Result<Vec<UsageSnapshot>> {
+        let root = consts::Root::Usage.as_ref();
+        let mut snapshots = Vec::new();
+        for entry in self
+            .db
+            .0
+            .iterator(IteratorMode::From(root, Direction::Forward))
+        {
+            let (key, value) = entry.wrap()?;
+            if !key.starts_with(root) {
+                break;
+            }
+            let date = std::str::from_utf8(&key[root.len()..])
+                .ok()
+                .and_then(|date| NaiveDate::parse_from_str(date, DATE_FORMAT).ok())
+                .ok_or_else(|| anyhow!(@DBError "invalid usage snapshot key: {key:?}"))?;
+            snapshots.push(UsageSnapshot {
+                date,
+                stats: db::decode(&value)?,
+            });
+        }
+        Ok(snapshots)
+    }
+}
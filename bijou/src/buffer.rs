@@ -0,0 +1,106 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::{sodium::utils, SecretBytes};
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+
+/// A pool of buffers of a fixed size, shared between threads.
+///
+/// Compared to thread-local buffers, the memory held by idle buffers
+/// is bounded by `max_idle` no matter how many threads are used.
+/// Requests larger than the buffer size get a dedicated buffer,
+/// which is freed instead of pooled.
+///
+/// Buffers are allocated as [`SecretBytes`] so that plaintext is not
+/// swapped out. If memory cannot be locked, unlocked buffers are used
+/// instead. Buffers are zeroed as they are returned, so that no
+/// plaintext lingers in idle buffers.
+pub struct BufferPool {
+    size: usize,
+    max_idle: usize,
+    idle: Mutex<Vec<SecretBytes>>,
+}
+
+impl BufferPool {
+    pub fn new(size: usize, max_idle: usize) -> Self {
+        Self {
+            size,
+            max_idle,
+            idle: Mutex::default(),
+        }
+    }
+
+    /// Returns a buffer of `len` bytes. Its content is unspecified.
+    pub fn get(self: &Arc<Self>, len: usize) -> Buffer {
+        let buffer = if len <= self.size {
+            self.idle
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .pop()
+        } else {
+            None
+        };
+        Buffer {
+            pool: Arc::clone(self),
+            buffer: Some(
+                buffer.unwrap_or_else(|| SecretBytes::allocate_lenient(len.max(self.size))),
+            ),
+            len,
+        }
+    }
+
+    fn release(&self, mut buffer: SecretBytes, len: usize) {
+        utils::memzero(&mut buffer[..len]);
+        if buffer.len() != self.size {
+            return;
+        }
+        let mut idle = self.idle.lock().unwrap_or_else(|err| err.into_inner());
+        if idle.len() < self.max_idle {
+            idle.push(buffer);
+        }
+    }
+}
+
+/// A buffer borrowed from a [`BufferPool`], returned on drop.
+pub struct Buffer {
+    pool: Arc<BufferPool>,
+    buffer: Option<SecretBytes>,
+    len: usize,
+}
+
+impl Deref for Buffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer.as_ref().unwrap()[..self.len]
+    }
+}
+
+impl DerefMut for Buffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer.as_mut().unwrap()[..self.len]
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            self.pool.release(buffer, self.len);
+        }
+    }
+}
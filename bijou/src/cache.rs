@@ -14,18 +14,18 @@
 //
 
 use crate::{
-    db::{consts, Database, DatabaseKey},
+    db::{consts, Database, DatabaseKey, Record},
+    error::ErrorOrigin,
     fs::FileId,
     id_lock::IdLock,
     Context, ErrorKind, Result,
 };
-use serde::{de::DeserializeOwned, Serialize};
 use std::{
     collections::HashMap,
     sync::{Arc, Condvar, Mutex, RwLock, RwLockWriteGuard},
+    thread::JoinHandle,
     time::Duration,
 };
-use tracing::error;
 
 #[derive(Default)]
 struct State<T> {
@@ -52,17 +52,20 @@ pub struct CachedStorage<T> {
     db: Arc<Database>,
     lock: IdLock<T>,
     shared: Arc<(Mutex<State<T>>, Condvar)>,
-    derive: &'static [u8],
+    derive: consts::Derive,
+    /// The thread persisting updates, which holds the database open
+    /// until it's stopped.
+    thread: Option<JoinHandle<()>>,
 }
 impl<T> CachedStorage<T>
 where
-    T: Serialize + DeserializeOwned + Clone + Default + Send + std::fmt::Debug + 'static,
+    T: Record + Clone + Default + Send + std::fmt::Debug + 'static,
 {
     const BATCH_DELAY: Duration = Duration::from_millis(100);
 
-    pub fn new(db: Arc<Database>, derive: &'static [u8]) -> Self {
+    pub fn new(db: Arc<Database>, derive: consts::Derive) -> Self {
         let shared = Arc::new((Mutex::default(), Condvar::new()));
-        std::thread::spawn({
+        let thread = std::thread::spawn({
             let db = Arc::clone(&db);
             let shared = Arc::clone(&shared);
             move || loop {
@@ -73,10 +76,8 @@ where
                         !guard.stopped && guard.updated.is_empty()
                     })
                     .unwrap();
-                if guard.stopped {
-                    break;
-                }
-                if !guard.immediate {
+                let stopped = guard.stopped;
+                if !guard.immediate && !stopped {
                     drop(guard);
                     std::thread::sleep(Self::BATCH_DELAY);
                     guard = lock.lock().unwrap();
@@ -85,15 +86,19 @@ where
                 }
                 for (id, value) in guard.updated.drain() {
                     if let Err(err) = db
-                        .key(consts::FILE_ROOT)
+                        .key(consts::Root::File)
                         .derive(id)
                         .derive(derive)
                         .typed()
                         .put(&value)
                     {
-                        error!("failed to persist object: {}", err);
+                        err.context("failed to persist object")
+                            .report(ErrorOrigin::Persist);
                     }
                 }
+                if stopped {
+                    break;
+                }
             }
         });
         Self {
@@ -101,12 +106,13 @@ where
             lock: IdLock::new(),
             shared,
             derive,
+            thread: Some(thread),
         }
     }
 
     fn db_key(&self, id: FileId) -> DatabaseKey<T> {
         self.db
-            .key(consts::FILE_ROOT)
+            .key(consts::Root::File)
             .derive(id)
             .derive(self.derive)
             .typed()
@@ -142,6 +148,16 @@ where
         self.db_key(id).delete()
     }
 
+    /// Persists all pending updates immediately.
+    pub fn flush(&self) -> Result<()> {
+        let mut guard = self.shared.0.lock().unwrap();
+        let mut batch = self.db.batch();
+        for (id, value) in guard.updated.drain() {
+            self.db_key(id).put_batch(&mut batch, &value)?;
+        }
+        batch.commit()
+    }
+
     /// Hello
     pub fn key(&self, id: FileId) -> Result<CachedStorageKey<T>> {
         Ok(CachedStorageKey {
@@ -152,6 +168,16 @@ where
     }
 }
 
+impl<T> Drop for CachedStorage<T> {
+    fn drop(&mut self) {
+        self.shared.0.lock().unwrap().stopped = true;
+        self.shared.1.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
 /// A key for a [`CachedStorage`]. Access and modifications
 /// are controlled internally by a [`RwLock`].
 ///
@@ -0,0 +1,97 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Sources of time, so that behavior depending on it can be tested.
+
+use chrono::{DateTime, Utc};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// A source of wall-clock and monotonic time, see
+/// [`Bijou::set_clock`].
+///
+/// Wall-clock time is used for times of files, while monotonic time
+/// is used for delays, e.g. when batching updates of directory times.
+///
+/// [`Bijou::set_clock`]: crate::Bijou::set_clock
+pub trait Clock: Send + Sync {
+    /// Returns the current wall-clock time.
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Returns the current monotonic time.
+    fn instant(&self) -> Instant;
+}
+
+/// The default [`Clock`], using the system clocks.
+pub struct SystemClock;
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A [`Clock`] that only advances when told to.
+pub struct MockClock {
+    start: (DateTime<Utc>, Instant),
+    elapsed: Mutex<Duration>,
+}
+
+impl MockClock {
+    /// Creates a clock starting at `now`.
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            start: (now, Instant::now()),
+            elapsed: Mutex::default(),
+        }
+    }
+
+    /// Advances both wall-clock and monotonic time by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new(Utc::now())
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        let elapsed = *self.elapsed.lock().unwrap();
+        self.start.0 + chrono::Duration::from_std(elapsed).unwrap()
+    }
+
+    fn instant(&self) -> Instant {
+        self.start.1 + *self.elapsed.lock().unwrap()
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> DateTime<Utc> {
+        (**self).now()
+    }
+
+    fn instant(&self) -> Instant {
+        (**self).instant()
+    }
+}
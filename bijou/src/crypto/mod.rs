@@ -13,6 +13,7 @@
 // limitations under the License.
 //
 
+#[cfg(feature = "rocksdb")]
 pub mod xchacha20_siv;
 
 use crate::{anyhow, sodium::utils, Error};
@@ -43,6 +44,7 @@ macro_rules! move_to_heap {
     }};
 }
 
+#[cfg(feature = "rocksdb")]
 pub(crate) fn cast_key<K>(key: &[u8]) -> &K {
     assert_eq!(key.len(), std::mem::size_of::<K>());
     unsafe { &*(key.as_ptr() as *const K) }
@@ -13,35 +13,131 @@
 // limitations under the License.
 //
 
-use crate::{error::ResultExt, fs::FileId, Context, ErrorKind, Result, SecretBytes};
+use crate::{
+    bail,
+    bijou::{BackgroundScrubState, TaskState},
+    error::ResultExt,
+    format::{ContentPin, FileClusters},
+    fs::{time, DirItem, FileAttributes, FileId, FileKind, FileMeta, RawFileMeta, UnixPerms},
+    quota::QuotaLimits,
+    Context, ErrorKind, Result, SecretBytes, UsageStats,
+};
 use bijou_rocksdb::{
-    BlockBasedOptions, DBPinnableSlice, DBWithThreadMode, Env, IteratorMode, LogLevel, Options,
-    ReadOptions, SingleThreaded, WriteBatchWithTransaction, DB,
+    checkpoint::Checkpoint, BlockBasedOptions, ColumnFamily, ColumnFamilyDescriptor,
+    DBPinnableSlice, DBWithThreadMode, Env, IteratorMode, LogLevel, Options, ReadOptions,
+    SingleThreaded, SliceTransform, WriteBatchWithTransaction, DB,
 };
-use serde::{de::DeserializeOwned, Serialize};
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use smallvec::SmallVec;
 use std::{
+    collections::BTreeMap,
     marker::PhantomData,
     ops::{Deref, DerefMut},
     path::Path,
     sync::Arc,
 };
 
-pub type RawKeyType = SmallVec<[u8; consts::FILE_ROOT.len() + std::mem::size_of::<FileId>()]>;
-
+#[cfg(feature = "flaky")]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub type RawKeyType =
+    SmallVec<[u8; std::mem::size_of::<consts::Root>() + std::mem::size_of::<FileId>()]>;
+
+/// Key prefixes and derivations of database records.
+///
+/// Both are single bytes at a fixed position of the key: the root
+/// comes first, and the derivation right after the [`FileId`]. Keys
+/// of different kinds thus never share a prefix, whatever follows
+/// them (e.g. encrypted names). The bytes are the discriminants of
+/// the enums below, so reusing one does not compile.
+///
+/// See [`format`](crate::format) for the layout.
 pub mod consts {
-    pub const FILE_ROOT: &[u8] = b"f";
+    macro_rules! namespace {
+        ($(#[$meta:meta])* $name:ident { $($(#[$vmeta:meta])* $variant:ident = $byte:literal,)* }) => {
+            $(#[$meta])*
+            #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+            #[repr(u8)]
+            pub enum $name {
+                $($(#[$vmeta])* $variant = $byte,)*
+            }
+
+            // 0xff is used as an upper bound when iterating
+            const _: () = { $(assert!($byte != u8::MAX);)* };
 
-    pub const DIR_DERIVE: &[u8] = b":";
-    pub const DIR_DERIVE_UPPER: &[u8] = b";";
+            impl $name {
+                pub fn from_byte(byte: u8) -> Option<Self> {
+                    match byte {
+                        $($byte => Some(Self::$variant),)*
+                        _ => None,
+                    }
+                }
+            }
+
+            impl AsRef<[u8]> for $name {
+                fn as_ref(&self) -> &[u8] {
+                    match self {
+                        $(Self::$variant => &[$byte],)*
+                    }
+                }
+            }
+        };
+    }
 
-    pub const SYMLINK_DERIVE: &[u8] = b"s";
+    namespace! {
+        /// The first byte of every key.
+        Root {
+            File = b'f',
+            Refs = b'r',
+            Quota = b'q',
+            Task = b'j',
+            App = b'a',
+            Scrub = b's',
+            Usage = b'u',
+        }
+    }
 
-    pub const BLOCKS_DERIVE: &[u8] = b"b";
-    pub const TRACKING_DERIVE: &[u8] = b"t";
+    namespace! {
+        /// The byte following the [`FileId`](crate::FileId) in keys of
+        /// records derived from a file.
+        Derive {
+            Dir = b':',
+            Symlink = b's',
+            Blocks = b'b',
+            Tracking = b't',
+            Pin = b'p',
+            Moved = b'm',
+            Xattr = b'x',
+            Parent = b'^',
+        }
+    }
+}
 
-    pub const XATTR_DERIVE: &[u8] = b"x";
-    pub const XATTR_DERIVE_UPPER: &[u8] = b"y";
+/// Column families besides the default one, which holds the other
+/// records.
+///
+/// Keys in these column families are the same as they would be in
+/// the default one, and all keys sharing the first [`PREFIX_LEN`]
+/// bytes (i.e. belonging to the same file) are grouped together, so
+/// they can be scanned with [`DatabaseKey::prefix_iter`].
+///
+/// [`PREFIX_LEN`]: columns::PREFIX_LEN
+pub mod columns {
+    use super::{consts, FileId};
+
+    /// Directory entries, derived with [`Derive::Dir`](consts::Derive::Dir).
+    pub const DIRS: &str = "dirs";
+    /// xattrs, derived with [`Derive::Xattr`](consts::Derive::Xattr).
+    pub const XATTRS: &str = "xattrs";
+
+    pub const ALL: &[&str] = &[DIRS, XATTRS];
+
+    /// Length of the common prefix: the root, the file ID and the
+    /// derivation.
+    pub const PREFIX_LEN: usize = std::mem::size_of::<consts::Root>()
+        + std::mem::size_of::<FileId>()
+        + std::mem::size_of::<consts::Derive>();
 }
 
 mod cipher {
@@ -76,11 +172,287 @@ mod cipher {
     }
 }
 
-pub struct Database(pub Arc<DBWithThreadMode<SingleThreaded>>, Arc<Options>);
+/// A type stored in the database with [`DatabaseKey::put`].
+///
+/// Records are wrapped in an envelope (see [`encode`]) so that
+/// corruption and records written by newer versions are detected
+/// instead of being misread.
+pub trait Record: Serialize + DeserializeOwned {
+    /// Identifies the kind of record.
+    const TAG: u8;
+    /// The version of the layout of the record. Bump this when the
+    /// layout changes.
+    const VERSION: u8 = 0;
+
+    /// Deserializes a record written before envelopes were introduced,
+    /// returning it and the remaining bytes. Override this if the
+    /// layout has changed since then.
+    fn take_legacy(bytes: &[u8]) -> postcard::Result<(Self, &[u8])> {
+        postcard::take_from_bytes(bytes)
+    }
+}
+
+/// The layout of [`FileMeta`] before envelopes were introduced, which
+/// has neither attributes nor shared content.
+#[derive(Serialize, Deserialize)]
+struct LegacyFileMeta {
+    id: FileId,
+    kind: FileKind,
+    #[serde(with = "time::compact_date_time")]
+    accessed: DateTime<Utc>,
+    #[serde(with = "time::compact_date_time")]
+    modified: DateTime<Utc>,
+    nlinks: u32,
+    perms: Option<UnixPerms>,
+}
+
+impl Record for FileMeta {
+    const TAG: u8 = b'm';
+
+    fn take_legacy(bytes: &[u8]) -> postcard::Result<(Self, &[u8])> {
+        let (legacy, rest) = postcard::take_from_bytes::<LegacyFileMeta>(bytes)?;
+        let meta = FileMeta {
+            id: legacy.id,
+            kind: legacy.kind,
+            size: 0,
+            accessed: legacy.accessed,
+            modified: legacy.modified,
+            nlinks: legacy.nlinks,
+            perms: legacy.perms,
+            attributes: FileAttributes::EMPTY,
+            content: None,
+        };
+        Ok((meta, rest))
+    }
+}
+impl Record for DirItem {
+    const TAG: u8 = b'd';
+}
+/// Symlink targets.
+impl Record for String {
+    const TAG: u8 = b's';
+}
+/// Tracked metadata of raw files.
+impl Record for RawFileMeta {
+    const TAG: u8 = b't';
+}
+impl Record for FileClusters {
+    const TAG: u8 = b'b';
+}
+impl Record for ContentPin {
+    const TAG: u8 = b'p';
+}
+/// Reference counts.
+impl Record for u32 {
+    const TAG: u8 = b'r';
+}
+/// Directory quotas.
+impl Record for BTreeMap<FileId, QuotaLimits> {
+    const TAG: u8 = b'q';
+}
+impl Record for TaskState {
+    const TAG: u8 = b'j';
+}
+impl Record for BackgroundScrubState {
+    const TAG: u8 = b'c';
+}
+impl Record for UsageStats {
+    const TAG: u8 = b'u';
+}
+
+const MAGIC: &[u8] = &[0xb1, 0x70];
+/// Magic, tag, version and checksum.
+const HEADER_LEN: usize = MAGIC.len() + 2 + 4;
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 (IEEE) of `data`.
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Serializes a record with postcard, wrapped in an envelope:
+///
+/// | Magic (`b1 70`) | Tag | Version | CRC-32 (LE) | Payload |
+/// |-----------------|-----|---------|-------------|---------|
+///
+/// The checksum covers the tag, the version and the payload.
+pub fn encode<T: Record>(value: &T) -> Result<Vec<u8>> {
+    let payload = postcard::to_allocvec(value).context("failed to serialize data")?;
+    let mut result = Vec::with_capacity(HEADER_LEN + payload.len());
+    result.extend_from_slice(MAGIC);
+    result.extend_from_slice(&[T::TAG, T::VERSION]);
+    result.extend_from_slice(&[0; 4]);
+    result.extend_from_slice(&payload);
+    let checksum = crc32(&result[MAGIC.len()..MAGIC.len() + 2]) ^ crc32(&payload);
+    result[MAGIC.len() + 2..HEADER_LEN].copy_from_slice(&checksum.to_le_bytes());
+    Ok(result)
+}
+
+/// Deserializes a record written by [`encode`].
+///
+/// Records written before envelopes were introduced are plain
+/// postcard, and are still accepted.
+pub fn decode<T: Record>(bytes: &[u8]) -> Result<T> {
+    if bytes.len() < HEADER_LEN || !bytes.starts_with(MAGIC) {
+        return T::take_legacy(bytes)
+            .map(|(value, _)| value)
+            .context("corrupted record")
+            .kind(ErrorKind::DBError);
+    }
+
+    let (header, payload) = bytes[MAGIC.len()..].split_at(HEADER_LEN - MAGIC.len());
+    let (tag, version) = (header[0], header[1]);
+    let checksum = u32::from_le_bytes(header[2..].try_into().unwrap());
+    if crc32(&header[..2]) ^ crc32(payload) != checksum {
+        // A plain record may happen to start with the magic. It is
+        // only taken as such if it spans exactly all of `bytes`, so
+        // that damaged envelopes are still reported.
+        return match T::take_legacy(bytes) {
+            Ok((value, [])) => Ok(value),
+            _ => bail!(@DBError "corrupted record: checksum mismatch"),
+        };
+    }
+    if tag != T::TAG {
+        let (expected, found) = (T::TAG as char, tag as char);
+        bail!(@DBError "corrupted record: expected type {expected:?}, found {found:?}");
+    }
+    if version > T::VERSION {
+        let supported = T::VERSION;
+        bail!(@IncompatibleVersion "unsupported record version {version} (up to {supported})");
+    }
+    postcard::from_bytes(payload)
+        .context("corrupted record")
+        .kind(ErrorKind::DBError)
+}
+
+/// Statistics of the database, returned by [`Bijou::db_stats`].
+///
+/// [`Bijou::db_stats`]: crate::Bijou::db_stats
+#[derive(Debug, Default, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DbStats {
+    /// Estimated number of keys, across all column families.
+    pub estimated_keys: u64,
+    /// Total size of SST files, in bytes.
+    pub sst_files_size: u64,
+    /// Total size of write-ahead log files, in bytes.
+    pub wal_size: u64,
+    /// Memory used by memtables, in bytes.
+    pub memtable_size: u64,
+    /// Memory used by the block cache, in bytes.
+    pub block_cache_usage: u64,
+    /// Whether a compaction is pending.
+    pub compaction_pending: bool,
+    pub running_compactions: u64,
+    /// Counters since the database was opened, only collected if
+    /// [`Config::db_statistics`] is enabled.
+    ///
+    /// [`Config::db_statistics`]: crate::Config::db_statistics
+    pub counters: Option<DbCounters>,
+}
+
+/// Counters of database operations. See [`DbStats::counters`].
+#[derive(Debug, Default, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DbCounters {
+    pub block_cache_hits: u64,
+    pub block_cache_misses: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub compaction_bytes_read: u64,
+    pub compaction_bytes_written: u64,
+    /// Bytes written to the write-ahead log.
+    pub wal_bytes: u64,
+}
+
+impl DbCounters {
+    /// Parses the counters from the text returned by
+    /// [`Options::get_statistics`], which has lines like
+    /// `rocksdb.block.cache.hit COUNT : 42`.
+    fn parse(statistics: &str) -> Self {
+        let mut result = Self::default();
+        for line in statistics.lines() {
+            let mut parts = line.split_whitespace();
+            let (Some(name), Some("COUNT"), Some(":"), Some(value)) =
+                (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                continue;
+            };
+            let Ok(value) = value.parse() else {
+                continue;
+            };
+            let counter = match name {
+                "rocksdb.block.cache.hit" => &mut result.block_cache_hits,
+                "rocksdb.block.cache.miss" => &mut result.block_cache_misses,
+                "rocksdb.bytes.read" => &mut result.bytes_read,
+                "rocksdb.bytes.written" => &mut result.bytes_written,
+                "rocksdb.compact.read.bytes" => &mut result.compaction_bytes_read,
+                "rocksdb.compact.write.bytes" => &mut result.compaction_bytes_written,
+                "rocksdb.wal.bytes" => &mut result.wal_bytes,
+                _ => continue,
+            };
+            *counter = value;
+        }
+        result
+    }
+
+    /// The ratio of block cache lookups that hit, or `None` if there
+    /// were no lookups.
+    pub fn block_cache_hit_rate(&self) -> Option<f64> {
+        let lookups = self.block_cache_hits + self.block_cache_misses;
+        (lookups != 0).then(|| self.block_cache_hits as f64 / lookups as f64)
+    }
+}
+
+/// The opened database, with its options and whether it is opened
+/// read-only.
+pub struct Database(
+    pub Arc<DBWithThreadMode<SingleThreaded>>,
+    Arc<Options>,
+    bool,
+    #[cfg_attr(not(feature = "flaky"), allow(dead_code))] FailCommits,
+);
+
+/// Whether commits should fail, see [`Database::fail_commits`].
+#[derive(Default)]
+struct FailCommits(#[cfg(feature = "flaky")] AtomicBool);
 impl Database {
     pub const KEYBYTES: usize = cipher::KEYBYTES;
 
-    pub fn open(path: impl AsRef<Path>, key: Option<SecretBytes>) -> Result<Self> {
+    /// Opens the database at `path`, encrypted with `key` if given.
+    ///
+    /// Collecting statistics (see [`DbStats::counters`]) costs a bit
+    /// of performance, and is only done if `statistics` is set.
+    ///
+    /// If `read_only` is set, nothing is ever written to `path` (which
+    /// must contain a database), and writes fail.
+    pub fn open(
+        path: impl AsRef<Path>,
+        key: Option<SecretBytes>,
+        statistics: bool,
+        read_only: bool,
+    ) -> Result<Self> {
         let env = Arc::new(if let Some(key) = key {
             Env::encrypted(
                 Box::new(cipher::MyCipher(key)),
@@ -95,34 +467,170 @@ impl Database {
         // TODO increase parallelism?
         let mut options = Options::default();
         options.increase_parallelism(4);
-        options.create_if_missing(true);
+        options.create_if_missing(!read_only);
+        options.create_missing_column_families(!read_only);
         options.set_log_level(LogLevel::Fatal);
         options.set_use_adaptive_mutex(true);
         options.set_env(&env);
         options.set_compression_type(bijou_rocksdb::DBCompressionType::None);
+        if statistics {
+            options.enable_statistics();
+        }
         let mut block_opts = BlockBasedOptions::default();
         block_opts.set_ribbon_filter(20.0);
         options.set_block_based_table_factory(&block_opts);
-        // options.set_prefix_extractor(SliceTransform::create_fixed_prefix(std::mem::size_of::<FileId>() + 1));
         let options = Arc::new(options);
 
-        Ok(Self(
-            DB::open(&options, path.as_ref())
-                .context("failed to open database")
-                .kind(ErrorKind::DBError)?
-                .into(),
-            options,
-        ))
+        let path = path.as_ref();
+        let existing = DB::list_cf(&options, path).unwrap_or_default();
+        let descriptors = columns::ALL.iter().map(|name| {
+            let mut cf_options = Options::clone(&options);
+            cf_options
+                .set_prefix_extractor(SliceTransform::create_fixed_prefix(columns::PREFIX_LEN));
+            ColumnFamilyDescriptor::new(*name, cf_options)
+        });
+        let needs_migration =
+            !existing.is_empty() && !existing.iter().any(|name| name == columns::DIRS);
+        if read_only && needs_migration {
+            bail!(@ReadOnly "database of an older version must be opened writable once");
+        }
+        let db = if read_only {
+            DB::open_cf_descriptors_read_only(&options, path, descriptors, false)
+        } else {
+            DB::open_cf_descriptors(&options, path, descriptors)
+        }
+        .context("failed to open database")
+        .kind(ErrorKind::DBError)?;
+        if needs_migration {
+            Self::migrate_columns(&db)?;
+        }
+
+        Ok(Self(db.into(), options, read_only, FailCommits::default()))
+    }
+
+    /// Moves directory entries and xattrs written by older versions
+    /// from the default column family into their own ones.
+    ///
+    /// This is done in a single batch, so that it either completes or
+    /// is retried on the next open.
+    fn migrate_columns(db: &DB) -> Result<()> {
+        let mut batch = WriteBatchWithTransaction::<false>::default();
+        for entry in db.iterator(IteratorMode::From(
+            consts::Root::File.as_ref(),
+            bijou_rocksdb::Direction::Forward,
+        )) {
+            let (key, value) = entry.kind(ErrorKind::DBError)?;
+            if !key.starts_with(consts::Root::File.as_ref()) {
+                break;
+            }
+            let Some(&derive) = key.get(columns::PREFIX_LEN - 1) else {
+                continue;
+            };
+            let name = match consts::Derive::from_byte(derive) {
+                Some(consts::Derive::Dir) => columns::DIRS,
+                Some(consts::Derive::Xattr) => columns::XATTRS,
+                _ => continue,
+            };
+            batch.put_cf(db.cf_handle(name).unwrap(), &key, value);
+            batch.delete(&key);
+        }
+        db.write(batch)
+            .context("failed to migrate column families")
+            .kind(ErrorKind::DBError)
     }
 
     pub fn key(&self, key: impl AsRef<[u8]>) -> DatabaseKey {
         DatabaseKey {
             db: Arc::clone(&self.0),
             key: key.as_ref().into(),
+            cf: None,
             marker: PhantomData,
         }
     }
 
+    /// Returns whether the database is opened read-only.
+    pub fn is_read_only(&self) -> bool {
+        self.2
+    }
+
+    /// Makes all following commits fail if `fail` is set, for testing
+    /// how failures are handled.
+    #[cfg(feature = "flaky")]
+    pub fn fail_commits(&self, fail: bool) {
+        self.3 .0.store(fail, Ordering::Relaxed);
+    }
+
+    /// Creates a consistent snapshot of the database at `path`,
+    /// which should not exist.
+    ///
+    /// Files are hard linked where possible.
+    pub fn checkpoint(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        Checkpoint::new(&*self.0)
+            .and_then(|checkpoint| checkpoint.create_checkpoint(path))
+            .context("failed to create checkpoint")
+            .kind(ErrorKind::DBError)?;
+
+        // The encrypted env keeps the metadata of each file in a `.meta`
+        // file next to it, which is left behind when the file is linked.
+        for entry in std::fs::read_dir(path).wrap()? {
+            let mut name = entry.wrap()?.file_name();
+            name.push(".meta");
+            let (src, dest) = (self.0.path().join(&name), path.join(&name));
+            if src.exists() && !dest.exists() {
+                std::fs::copy(src, dest).context("failed to copy file metadata")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns statistics of the database.
+    pub fn stats(&self) -> Result<DbStats> {
+        let db = &self.0;
+        let cfs: Vec<_> = columns::ALL
+            .iter()
+            .map(|name| db.cf_handle(name).unwrap())
+            .collect();
+        // summed across all column families
+        let sum = |name: &str| -> Result<u64> {
+            let mut total = db.property_int_value(name).kind(ErrorKind::DBError)?;
+            for cf in &cfs {
+                let value = db.property_int_value_cf(*cf, name);
+                total = Some(total.unwrap_or(0) + value.kind(ErrorKind::DBError)?.unwrap_or(0));
+            }
+            Ok(total.unwrap_or(0))
+        };
+
+        let mut wal_size = 0;
+        for entry in std::fs::read_dir(db.path()).wrap()? {
+            let entry = entry.wrap()?;
+            if entry.path().extension().is_some_and(|ext| ext == "log") {
+                wal_size += entry.metadata().wrap()?.len();
+            }
+        }
+
+        Ok(DbStats {
+            estimated_keys: sum("rocksdb.estimate-num-keys")?,
+            sst_files_size: sum("rocksdb.total-sst-files-size")?,
+            wal_size,
+            memtable_size: sum("rocksdb.cur-size-all-mem-tables")?,
+            // the block cache is shared by all column families
+            block_cache_usage: db
+                .property_int_value("rocksdb.block-cache-usage")
+                .kind(ErrorKind::DBError)?
+                .unwrap_or(0),
+            compaction_pending: sum("rocksdb.compaction-pending")? != 0,
+            running_compactions: db
+                .property_int_value("rocksdb.num-running-compactions")
+                .kind(ErrorKind::DBError)?
+                .unwrap_or(0),
+            counters: self
+                .1
+                .get_statistics()
+                .map(|stats| DbCounters::parse(&stats)),
+        })
+    }
+
     pub fn batch(&self) -> BatchWrapper {
         BatchWrapper {
             db: self,
@@ -149,6 +657,10 @@ impl DerefMut for BatchWrapper<'_> {
 }
 impl BatchWrapper<'_> {
     pub fn commit(self) -> Result<()> {
+        #[cfg(feature = "flaky")]
+        if self.db.3 .0.load(Ordering::Relaxed) {
+            bail!(@DBError "injected failure of commit");
+        }
         self.db.0.write(self.inner).kind(ErrorKind::DBError)
     }
 }
@@ -158,6 +670,8 @@ pub struct Nothing;
 pub struct DatabaseKey<T = Nothing> {
     pub db: Arc<DBWithThreadMode<SingleThreaded>>,
     pub key: RawKeyType,
+    /// The column family, or `None` for the default one. See [`columns`].
+    cf: Option<&'static str>,
     marker: PhantomData<T>,
 }
 
@@ -166,33 +680,61 @@ impl<T> Clone for DatabaseKey<T> {
         Self {
             db: Arc::clone(&self.db),
             key: self.key.clone(),
+            cf: self.cf,
             marker: PhantomData,
         }
     }
 }
 
 impl<T> DatabaseKey<T> {
+    fn cf(&self) -> Option<&ColumnFamily> {
+        self.cf
+            .map(|name| self.db.cf_handle(name).expect("column family not opened"))
+    }
+
+    /// Moves this key into the column family `name`. See [`columns`].
+    pub fn column(mut self, name: &'static str) -> Self {
+        self.cf = Some(name);
+        self
+    }
+
     pub fn read(&self) -> Result<Option<DBPinnableSlice>> {
-        self.db.get_pinned(&self.key).kind(ErrorKind::DBError)
+        match self.cf() {
+            Some(cf) => self.db.get_pinned_cf(cf, &self.key),
+            None => self.db.get_pinned(&self.key),
+        }
+        .kind(ErrorKind::DBError)
     }
 
     pub fn read_owned(&self) -> Result<Option<Vec<u8>>> {
-        self.db.get(&self.key).kind(ErrorKind::DBError)
+        match self.cf() {
+            Some(cf) => self.db.get_cf(cf, &self.key),
+            None => self.db.get(&self.key),
+        }
+        .kind(ErrorKind::DBError)
     }
 
     pub fn get(&self) -> Result<Option<T>>
     where
-        T: DeserializeOwned,
+        T: Record,
     {
-        self.read()
-            .kind(ErrorKind::DBError)?
-            .map(|bytes| postcard::from_bytes(&bytes))
+        self.read()?
+            .map(|bytes| decode(&bytes))
             .transpose()
-            .wrap()
+            .map_err(|err| err.context(format!("failed to read record {}", self.hex())))
+    }
+
+    /// Formats the key in hex for diagnostics.
+    fn hex(&self) -> String {
+        self.key.iter().map(|byte| format!("{byte:02x}")).collect()
     }
 
     pub fn write(&self, value: impl AsRef<[u8]>) -> Result<()> {
-        self.db.put(&self.key, value).kind(ErrorKind::DBError)
+        match self.cf() {
+            Some(cf) => self.db.put_cf(cf, &self.key, value),
+            None => self.db.put(&self.key, value),
+        }
+        .kind(ErrorKind::DBError)
     }
 
     pub fn write_batch<const B: bool>(
@@ -200,15 +742,18 @@ impl<T> DatabaseKey<T> {
         batch: &mut WriteBatchWithTransaction<B>,
         value: impl AsRef<[u8]>,
     ) {
-        batch.put(&self.key, value);
+        match self.cf() {
+            Some(cf) => batch.put_cf(cf, &self.key, value),
+            None => batch.put(&self.key, value),
+        }
     }
 
     pub fn put(&self, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: Record,
     {
         // TODO cache
-        self.write(postcard::to_allocvec(value).context("failed to serialize data")?)
+        self.write(encode(value)?)
     }
 
     pub fn put_batch<const B: bool>(
@@ -217,29 +762,33 @@ impl<T> DatabaseKey<T> {
         value: &T,
     ) -> Result<()>
     where
-        T: Serialize,
+        T: Record,
     {
-        self.write_batch(
-            batch,
-            postcard::to_allocvec(value).context("failed to deserialize data")?,
-        );
+        self.write_batch(batch, encode(value)?);
         Ok(())
     }
 
     pub fn delete(&self) -> Result<()> {
-        self.db.delete(&self.key).kind(ErrorKind::DBError)
+        match self.cf() {
+            Some(cf) => self.db.delete_cf(cf, &self.key),
+            None => self.db.delete(&self.key),
+        }
+        .kind(ErrorKind::DBError)
     }
 
     pub fn delete_batch<const B: bool>(&self, batch: &mut WriteBatchWithTransaction<B>) {
-        batch.delete(&self.key);
+        match self.cf() {
+            Some(cf) => batch.delete_cf(cf, &self.key),
+            None => batch.delete(&self.key),
+        }
     }
 
     pub fn exists(&self) -> Result<bool> {
-        Ok(if self.db.key_may_exist(&self.key) {
-            self.read().is_ok()
-        } else {
-            false
-        })
+        let may_exist = match self.cf() {
+            Some(cf) => self.db.key_may_exist_cf(cf, &self.key),
+            None => self.db.key_may_exist(&self.key),
+        };
+        Ok(may_exist && self.read()?.is_some())
     }
 
     pub fn derive(self, name: impl AsRef<[u8]>) -> DatabaseKey<Nothing> {
@@ -248,35 +797,130 @@ impl<T> DatabaseKey<T> {
         DatabaseKey {
             db: self.db,
             key,
+            cf: self.cf,
             marker: PhantomData,
         }
     }
 
-    pub fn range_iter(
+    /// Returns an iterator over the entries of the column family
+    /// starting with this key, which must be [`PREFIX_LEN`] long.
+    ///
+    /// [`PREFIX_LEN`]: columns::PREFIX_LEN
+    pub fn prefix_iter(
         &self,
-        lower: &[u8],
-        upper: &[u8],
     ) -> impl Iterator<Item = Result<(Box<[u8]>, Box<[u8]>), bijou_rocksdb::Error>> + '_ {
+        debug_assert_eq!(self.key.len(), columns::PREFIX_LEN);
+        let cf = self
+            .cf()
+            .expect("prefix iteration requires a column family");
         let mut opts = ReadOptions::default();
-
-        let mut upper_key = self.key.to_vec();
-        upper_key.extend_from_slice(upper);
-        opts.set_iterate_upper_bound(upper_key);
-
-        let mut lower_key = self.key.to_vec();
-        lower_key.extend_from_slice(lower);
-        self.db.iterator_opt(
-            IteratorMode::From(&lower_key, bijou_rocksdb::Direction::Forward),
+        opts.set_prefix_same_as_start(true);
+        self.db.iterator_cf_opt(
+            cf,
             opts,
+            IteratorMode::From(&self.key, bijou_rocksdb::Direction::Forward),
         )
     }
 
+    /// Deletes every entry returned by [`DatabaseKey::prefix_iter`].
+    pub fn delete_prefix_batch<const B: bool>(
+        &self,
+        batch: &mut WriteBatchWithTransaction<B>,
+    ) -> Result<()> {
+        let cf = self.cf().expect("prefix deletion requires a column family");
+        for entry in self.prefix_iter() {
+            let (key, _value) = entry.kind(ErrorKind::DBError)?;
+            batch.delete_cf(cf, key);
+        }
+        Ok(())
+    }
+
     #[inline]
     pub fn typed<U>(self) -> DatabaseKey<U> {
         DatabaseKey {
             db: self.db,
             key: self.key,
+            cf: self.cf,
             marker: PhantomData,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_envelope() {
+        let value = "hello world".to_owned();
+        let bytes = encode(&value).unwrap();
+        assert!(bytes.starts_with(MAGIC));
+        assert_eq!(decode::<String>(&bytes).unwrap(), value);
+    }
+
+    #[test]
+    fn test_bit_flip() {
+        let bytes = encode(&"hello world".to_owned()).unwrap();
+        for index in MAGIC.len()..bytes.len() {
+            let mut bytes = bytes.clone();
+            bytes[index] ^= 1;
+            let err = decode::<String>(&bytes).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::DBError);
+        }
+    }
+
+    #[test]
+    fn test_wrong_tag() {
+        let bytes = encode(&42u32).unwrap();
+        let err = decode::<String>(&bytes).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::DBError);
+    }
+
+    #[test]
+    fn test_legacy_file_meta() {
+        let legacy = LegacyFileMeta {
+            id: FileId::from_u64(42),
+            kind: FileKind::File,
+            accessed: Utc::now(),
+            modified: Utc::now(),
+            nlinks: 2,
+            perms: None,
+        };
+        let bytes = postcard::to_allocvec(&legacy).unwrap();
+        let meta = decode::<FileMeta>(&bytes).unwrap();
+        assert_eq!(meta.id, legacy.id);
+        assert_eq!(meta.nlinks, 2);
+        assert_eq!(meta.attributes, FileAttributes::EMPTY);
+        assert_eq!(meta.content_id(), legacy.id);
+    }
+
+    #[test]
+    fn test_newer_version() {
+        #[derive(Serialize, Deserialize)]
+        struct Newer(String);
+        impl Record for Newer {
+            const TAG: u8 = String::TAG;
+            const VERSION: u8 = String::VERSION + 1;
+        }
+
+        let bytes = encode(&Newer("hello".to_owned())).unwrap();
+        let err = decode::<String>(&bytes).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::IncompatibleVersion);
+    }
+
+    #[test]
+    fn test_legacy() {
+        let value = "hello world".to_owned();
+        let bytes = postcard::to_allocvec(&value).unwrap();
+        assert_eq!(decode::<String>(&bytes).unwrap(), value);
+
+        // the length prefix of this one is the magic
+        let value = "a".repeat(0x70 << 7 | 0x31);
+        let bytes = postcard::to_allocvec(&value).unwrap();
+        assert!(bytes.starts_with(MAGIC));
+        assert_eq!(decode::<String>(&bytes).unwrap(), value);
+
+        let err = decode::<String>(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::DBError);
+    }
+}
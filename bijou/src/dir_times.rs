@@ -0,0 +1,87 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::FileId;
+use chrono::{DateTime, Utc};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+struct Deferred {
+    modified: DateTime<Utc>,
+    since: Instant,
+}
+
+/// Modification times of directories that have not been written to
+/// the database yet, see [`DirTimePolicy::Relaxed`].
+///
+/// [`DirTimePolicy::Relaxed`]: crate::config::DirTimePolicy::Relaxed
+#[derive(Default)]
+pub struct DeferredTimes {
+    dirs: Mutex<HashMap<FileId, Deferred>>,
+}
+
+impl DeferredTimes {
+    /// How long an update can be deferred before it is written along
+    /// with the next change to the directory.
+    pub const MAX_DELAY: Duration = Duration::from_secs(5);
+
+    /// Defers updating the modification time of `dir`, `now` being
+    /// the current monotonic time. Returns `false` if an update has
+    /// been deferred for longer than [`MAX_DELAY`], in which case it
+    /// should be written now.
+    ///
+    /// [`MAX_DELAY`]: DeferredTimes::MAX_DELAY
+    pub fn defer(&self, dir: FileId, modified: DateTime<Utc>, now: Instant) -> bool {
+        let mut dirs = self.dirs.lock().unwrap();
+        let deferred = dirs.entry(dir).or_insert_with(|| Deferred {
+            modified,
+            since: now,
+        });
+        if now.saturating_duration_since(deferred.since) >= Self::MAX_DELAY {
+            dirs.remove(&dir);
+            return false;
+        }
+        deferred.modified = deferred.modified.max(modified);
+        true
+    }
+
+    /// Returns the deferred modification time of `dir`, if any.
+    pub fn get(&self, dir: FileId) -> Option<DateTime<Utc>> {
+        self.dirs
+            .lock()
+            .unwrap()
+            .get(&dir)
+            .map(|deferred| deferred.modified)
+    }
+
+    /// Drops the deferred update of `dir`, e.g. because its metadata
+    /// has been written.
+    pub fn forget(&self, dir: FileId) {
+        self.dirs.lock().unwrap().remove(&dir);
+    }
+
+    /// Removes and returns all deferred updates.
+    pub fn take(&self) -> Vec<(FileId, DateTime<Utc>)> {
+        self.dirs
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(dir, deferred)| (dir, deferred.modified))
+            .collect()
+    }
+}
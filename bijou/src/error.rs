@@ -13,7 +13,7 @@
 // limitations under the License.
 //
 
-use std::{fmt, io};
+use std::{fmt, io, sync::RwLock};
 use tracing::error;
 
 macro_rules! anyhow {
@@ -109,6 +109,70 @@ impl Error {
     pub fn kind(&self) -> ErrorKind {
         self.kind
     }
+
+    /// Whether this error indicates something going wrong, rather than
+    /// an expected failure such as a missing file.
+    pub fn is_severe(&self) -> bool {
+        self.severe
+    }
+
+    /// Logs this error if severe, and passes it to the hook set by
+    /// [`set_error_hook`].
+    pub(crate) fn report(&self, origin: ErrorOrigin) {
+        if !self.severe {
+            return;
+        }
+        error!(?origin, "{self:?}");
+        if let Some(hook) = ERROR_HOOK.read().unwrap().as_ref() {
+            hook(&ErrorEvent {
+                origin,
+                error: self,
+            });
+        }
+    }
+}
+
+/// Where a reported error happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorOrigin {
+    /// An operation whose error was converted to an errno by
+    /// [`Error::to_libc`], e.g. a FUSE request.
+    Operation,
+    /// Writing back cached metadata in the background.
+    Persist,
+    /// Flushing deferred directory times.
+    DirTimes,
+    /// Verifying files in the background.
+    Scrub,
+}
+
+/// A severe error that Bijou handled on its own, either by turning it
+/// into an errno or because there was no caller to return it to.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ErrorEvent<'a> {
+    pub origin: ErrorOrigin,
+    pub error: &'a Error,
+}
+
+type ErrorHook = Box<dyn Fn(&ErrorEvent) + Send + Sync>;
+
+static ERROR_HOOK: RwLock<Option<ErrorHook>> = RwLock::new(None);
+
+/// Sets a hook to be called with every severe error Bijou handles on
+/// its own, e.g. to forward them to monitoring. Such errors are logged
+/// through `tracing` either way.
+///
+/// The hook is global and replaces any previous one. It's called on
+/// the thread the error happened on, so it should return quickly.
+pub fn set_error_hook(hook: impl Fn(&ErrorEvent) + Send + Sync + 'static) {
+    *ERROR_HOOK.write().unwrap() = Some(Box::new(hook));
+}
+
+/// Removes the hook set by [`set_error_hook`].
+pub fn clear_error_hook() {
+    *ERROR_HOOK.write().unwrap() = None;
 }
 
 pub trait ErrorExt {
@@ -122,9 +186,11 @@ where
         Error::anyhow(self.into())
     }
 }
+#[cfg(feature = "rocksdb")]
 pub trait ResultExt<T> {
     fn wrap(self) -> Result<T>;
 }
+#[cfg(feature = "rocksdb")]
 impl<T, E> ResultExt<T> for Result<T, E>
 where
     E: std::error::Error + Send + Sync + 'static,
@@ -143,12 +209,11 @@ pub trait Context<T> {
     where
         C: fmt::Display + fmt::Debug + Send + Sync + 'static;
 
+    #[cfg(feature = "rocksdb")]
     fn with_context<C, F>(self, f: F) -> Result<T>
     where
         C: fmt::Display + fmt::Debug + Send + Sync + 'static,
         F: FnOnce() -> C;
-
-    fn take_it_easy(self) -> Result<T>;
 }
 
 impl<T, E> Context<T> for Result<T, E>
@@ -172,6 +237,7 @@ where
         }
     }
 
+    #[cfg(feature = "rocksdb")]
     fn with_context<C, F>(self, context: F) -> Result<T, Error>
     where
         C: fmt::Display + fmt::Debug + Send + Sync + 'static,
@@ -182,13 +248,6 @@ where
             Err(error) => Err(error.wrap().context(context())),
         }
     }
-
-    fn take_it_easy(self) -> Result<T> {
-        match self {
-            Ok(ok) => Ok(ok),
-            Err(error) => Err(error.wrap().take_it_easy()),
-        }
-    }
 }
 
 impl<T> Context<T> for Option<T> {
@@ -203,6 +262,7 @@ impl<T> Context<T> for Option<T> {
         self.ok_or_else(|| Error::msg(context))
     }
 
+    #[cfg(feature = "rocksdb")]
     fn with_context<C, F>(self, context: F) -> Result<T, Error>
     where
         C: fmt::Display + fmt::Debug + Send + Sync + 'static,
@@ -210,17 +270,11 @@ impl<T> Context<T> for Option<T> {
     {
         self.ok_or_else(|| Error::msg(context()))
     }
-
-    fn take_it_easy(self) -> Result<T> {
-        self.ok_or_else(|| Error::new(ErrorKind::Unspecified, None).take_it_easy())
-    }
 }
 
 impl Error {
     pub fn to_libc(&self) -> libc::c_int {
-        if self.severe {
-            error!("{self:?}");
-        }
+        self.report(ErrorOrigin::Operation);
         if !matches!(self.kind, ErrorKind::Unspecified) {
             return self.kind.to_libc();
         }
@@ -228,9 +282,10 @@ impl Error {
             return libc::EIO;
         };
 
+        #[cfg(feature = "rocksdb")]
         if let Some(err) = source.downcast_ref::<bijou_rocksdb::Error>() {
             use bijou_rocksdb::ErrorKind::*;
-            match err.kind() {
+            return match err.kind() {
                 NotFound => libc::ENOENT,
                 NotSupported => libc::ENOTSUP,
                 InvalidArgument => libc::EINVAL,
@@ -239,8 +294,10 @@ impl Error {
                 TimedOut => libc::ETIMEDOUT,
                 TryAgain => libc::EAGAIN,
                 _ => libc::EIO,
-            }
-        } else if let Some(err) = source.downcast_ref::<std::io::Error>() {
+            };
+        }
+
+        if let Some(err) = source.downcast_ref::<std::io::Error>() {
             use std::io::ErrorKind::*;
             match err.kind() {
                 NotFound => libc::ENOENT,
@@ -286,6 +343,18 @@ pub enum ErrorKind {
     NotFound,
     NotADirectory,
     FilesystemLoop,
+    PermissionDenied,
+    FileTooLarge,
+    QuotaExceeded,
+    ReadOnly,
+    /// Components of a Bijou (e.g. the key store and the config) do not
+    /// belong together.
+    Mismatch,
+    /// The storage of file contents is unreachable. See
+    /// [`Config::offline_fallback`].
+    ///
+    /// [`Config::offline_fallback`]: crate::Config::offline_fallback
+    StorageUnavailable,
 }
 
 impl ErrorKind {
@@ -309,6 +378,12 @@ impl ErrorKind {
             NotFound => libc::ENOENT,
             NotADirectory => libc::ENOTDIR,
             FilesystemLoop => libc::ELOOP,
+            PermissionDenied => libc::EPERM,
+            FileTooLarge => libc::EFBIG,
+            QuotaExceeded => libc::EDQUOT,
+            ReadOnly => libc::EROFS,
+            Mismatch => libc::EIO,
+            StorageUnavailable => libc::EHOSTDOWN,
         }
     }
 }
@@ -325,6 +400,8 @@ impl From<ErrorKind> for io::ErrorKind {
             E::AlreadyExists => T::AlreadyExists,
             E::InvalidInput => T::InvalidInput,
             E::NotFound => T::NotFound,
+            E::PermissionDenied => T::PermissionDenied,
+            E::ReadOnly => T::PermissionDenied,
 
             _ => T::Other,
         }
@@ -336,3 +413,18 @@ impl From<Error> for io::Error {
         io::Error::new(value.kind.into(), value)
     }
 }
+
+#[cfg(feature = "opendal")]
+impl From<opendal::Error> for Error {
+    fn from(value: opendal::Error) -> Self {
+        use opendal::ErrorKind as T;
+        let kind = match value.kind() {
+            T::NotFound => ErrorKind::NotFound,
+            T::AlreadyExists => ErrorKind::AlreadyExists,
+            T::PermissionDenied => ErrorKind::PermissionDenied,
+            T::Unsupported => ErrorKind::Unsupported,
+            _ => ErrorKind::IOError,
+        };
+        Error::new(kind, Some(value.into()))
+    }
+}
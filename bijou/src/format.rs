@@ -0,0 +1,139 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! The on-disk format of records stored in the database.
+//!
+//! All values except xattrs are serialized with [postcard], wrapped
+//! in an envelope:
+//!
+//! | Magic (`b1 70`) | Tag | Version | CRC-32 (LE) | Payload |
+//! |-----------------|-----|---------|-------------|---------|
+//!
+//! The tag identifies the kind of record (`m` for [`FileMeta`], `d`
+//! for [`DirItem`], `s` for symlink targets, `t` for
+//! [`TrackingMeta`], `b` for [`FileClusters`], `p` for
+//! [`ContentPin`], `r` for reference counts, `q` for quotas, `j`
+//! for [`TaskState`]s, `c` for [`BackgroundScrubState`] and `u` for
+//! [`UsageStats`]). The checksum covers the tag, the version and the
+//! payload. Records written by older versions have no envelope.
+//!
+//! Keys are built from a root prefix, the little-endian [`FileId`]
+//! and an optional derivation (see [`keys`]):
+//!
+//! | Key                              | Value                        |
+//! |----------------------------------|------------------------------|
+//! | `f` id                           | [`FileMeta`]                 |
+//! | `f` id `:` name                  | [`DirItem`]                  |
+//! | `f` id `s`                       | symlink target ([`String`])  |
+//! | `f` id `x` name                  | xattr value (raw bytes)      |
+//! | `f` id `p`                       | [`ContentPin`]               |
+//! | `f` id `^` parent name           | reverse index entry (empty)  |
+//! | `f` content `t`                  | [`TrackingMeta`]             |
+//! | `f` content `b`                  | [`FileClusters`]             |
+//! | `f` content `m`                  | moved by a migration (empty) |
+//! | `r` content                      | reference count ([`u32`])    |
+//! | `q`                              | quota limits, by directory   |
+//! | `j` task (big-endian)            | [`TaskState`]                |
+//! | `s`                              | [`BackgroundScrubState`]     |
+//! | `u` date (`YYYY-MM-DD`)          | [`UsageStats`]               |
+//! | `a` len namespace key            | app value (raw bytes)        |
+//!
+//! Directory entries and xattrs are stored in their own column
+//! families (see [`columns`]), and the rest in the default one.
+//! Databases created by older versions are migrated when opened.
+//!
+//! App values are set through [`AppStorage`], and their namespaces
+//! are prefixed with their length (one byte).
+//!
+//! `content` is the ID under which the raw content of a file is
+//! stored, which differs from the file's own ID for clones. If file
+//! name encryption is enabled, `name` in directory entries is
+//! encrypted with XChaCha20-SIV and followed by its tag.
+//!
+//! Reverse index entries mirror the directory entries (except `..`)
+//! if [`Config::reverse_index`] is enabled, and `name` in them is
+//! stored the same way.
+//!
+//! Every directory has a `..` entry (never encrypted) pointing to
+//! its parent, or to itself for the root. `.` is not stored, though
+//! directories created by older versions may still have it.
+//!
+//! [`AppStorage`]: crate::AppStorage
+//! [`BackgroundScrubState`]: crate::BackgroundScrubState
+//! [`Config::reverse_index`]: crate::Config::reverse_index
+//! [`UsageStats`]: crate::UsageStats
+//! [postcard]: https://docs.rs/postcard
+
+pub use crate::{
+    bijou::TaskState,
+    db::{columns, consts as keys},
+    fs::{DirItem, FileId, FileKind, FileMeta, RawFileMeta as TrackingMeta},
+};
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// The version of the format described by this module.
+pub const FORMAT_VERSION: u32 = 0;
+
+/// Cluster map of a file stored in [`SplitFileSystem`], mapping block
+/// indices to clusters.
+///
+/// [`SplitFileSystem`]: crate::low_level::raw_fs::SplitFileSystem
+// TODO optimize
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct FileClusters {
+    /// Clusters of contiguous blocks starting from 0.
+    pub ids: Vec<FileId>,
+    /// The remaining clusters.
+    pub sparse: BTreeMap<u64, FileId>,
+}
+
+/// Keyed hash of the raw content of a file, recorded when the file
+/// is verified and checked by quick scrubs (see [`ScrubOptions`]),
+/// which detects changes to the storage without decrypting it.
+///
+/// Pins are removed whenever the file is opened for writing.
+///
+/// [`ScrubOptions`]: crate::ScrubOptions
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContentPin {
+    /// The number of raw bytes hashed.
+    pub size: u64,
+    /// Keyed BLAKE2b of the ciphertext of every block, each preceded
+    /// by its length.
+    pub digest: [u8; 32],
+}
+
+/// All records of a file, returned by [`Bijou::dump_records`].
+///
+/// [`Bijou::dump_records`]: crate::Bijou::dump_records
+#[derive(Debug, Serialize)]
+pub struct FileRecords {
+    pub meta: FileMeta,
+    /// Directory entries, for directories.
+    pub entries: Option<BTreeMap<String, DirItem>>,
+    /// Symlink target, for symlinks.
+    pub symlink: Option<String>,
+    pub xattrs: HashMap<String, Vec<u8>>,
+    /// Tracked metadata of the raw content, if tracked.
+    pub tracking: Option<TrackingMeta>,
+    /// Cluster map of the raw content, if split.
+    pub clusters: Option<FileClusters>,
+    /// Reference count of the raw content, if shared.
+    pub refs: Option<u32>,
+    /// Pin of the raw content, if pinned.
+    pub pin: Option<ContentPin>,
+}
@@ -13,182 +13,78 @@
 // limitations under the License.
 //
 
-use super::{obtain_metadata, FileMeta, RawFile, RawFileMeta};
+use super::{
+    obtain_metadata, FileCounters, FileFlags, FileId, FileMeta, FileStats, RawFile, RawFileMeta,
+    TimePolicy,
+};
 use crate::{
-    algo::{AlgoKey, Algorithm},
+    algo::{AlgoKey, Algorithm, BlockIndex, PlainSize},
     bail,
-    db::DatabaseKey,
-    path::Path,
-    Bijou, BijouFs, File, Result, sodium::utils,
+    buffer::BufferPool,
+    db::{columns, consts, DatabaseKey},
+    mime,
+    quota::Quotas,
+    Result,
 };
 use std::{
-    cell::RefCell,
     sync::{
         atomic::{AtomicU32, Ordering},
         Arc, RwLock,
     },
+    time::Instant,
 };
-
-/// Options and flags which can be used to configure how a file is opened.
-///
-/// This corresponds to [`std::fs::OpenOptions`].
-#[derive(Clone, Debug, Default)]
-pub struct OpenOptions {
-    pub(crate) read: bool,
-    pub(crate) write: bool,
-    pub(crate) append: bool,
-    pub(crate) truncate: bool,
-    pub(crate) create: bool,
-    pub(crate) create_new: bool,
-}
-
-impl OpenOptions {
-    pub fn new() -> Self {
-        Self::default()
-    }
-
-    /// Sets the option for read access.
-    ///
-    /// See also [`std::fs::OpenOptions::read`].
-    pub fn read(&mut self, read: bool) -> &mut Self {
-        self.read = read;
-        self
-    }
-
-    /// Sets the option for write access.
-    ///
-    /// See also [`std::fs::OpenOptions::write`].
-    pub fn write(&mut self, write: bool) -> &mut Self {
-        self.write = write;
-        self
-    }
-
-    /// Sets the option for append mode.
-    ///
-    /// See also [`std::fs::OpenOptions::append`].
-    pub fn append(&mut self, append: bool) -> &mut Self {
-        self.append = append;
-        self
-    }
-
-    /// Sets the option for truncating a previous file.
-    ///
-    /// See also [`std::fs::OpenOptions::truncate`].
-    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
-        self.truncate = truncate;
-        self
-    }
-
-    /// Sets the option to create a new file, or open it if it already exists.
-    ///
-    /// See also [`std::fs::OpenOptions::create`].
-    pub fn create(&mut self, create: bool) -> &mut Self {
-        self.create = create;
-        self
-    }
-
-    /// Sets the option to always create a new file.
-    ///
-    /// See also [`std::fs::OpenOptions::create_new`].
-    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
-        self.create_new = create_new;
-        self
-    }
-
-    #[doc(hidden)]
-    pub fn to_flags(&self) -> FileFlags {
-        let mut flags = FileFlags::EMPTY;
-
-        if self.read {
-            flags = flags | FileFlags::READ;
-        }
-        if self.write {
-            flags = flags | FileFlags::WRITE;
-        }
-        if self.truncate {
-            flags = flags | FileFlags::TRUNCATE;
-        }
-
-        flags
-    }
-
-    /// Opens a low level file at `path` with the options specified by `self`.
-    pub fn open_low_level(&self, bijou: &Bijou, path: impl AsRef<Path>) -> Result<LowLevelFile> {
-        Ok(if !(self.create || self.create_new) {
-            bijou.open_file_direct(bijou.resolve(path.as_ref())?, self)?
-        } else {
-            let (parent, name) = bijou.resolve_parent_nonroot(path.as_ref())?;
-            bijou.open_file(parent, name, self, None)?
-        })
-    }
-
-    /// Opens a file at path `with` the options specified by `self`.
-    ///
-    /// This corresponds to [`std::fs::OpenOptions::open`].
-    pub fn open(&self, fs: &BijouFs, path: impl AsRef<Path>) -> Result<File> {
-        self.open_low_level(&fs.bijou, path).map(File::new)
-    }
-}
-
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub struct FileFlags(u8);
-impl FileFlags {
-    pub const EMPTY: FileFlags = FileFlags(0);
-    pub const READ: FileFlags = FileFlags(1 << 0);
-    pub const WRITE: FileFlags = FileFlags(1 << 1);
-    pub const TRUNCATE: FileFlags = FileFlags(1 << 2);
-
-    pub fn has(&self, flag: Self) -> bool {
-        self.0 & flag.0 != 0
-    }
-
-    pub fn remove(&self, flag: Self) -> Self {
-        Self(self.0 & !flag.0)
-    }
-
-    pub fn to_std(self) -> std::fs::OpenOptions {
-        let mut opts = std::fs::OpenOptions::new();
-        opts.read(self.has(Self::READ))
-            .write(self.has(Self::WRITE))
-            .truncate(self.has(Self::TRUNCATE));
-        opts
-    }
-}
-impl std::ops::BitOr for FileFlags {
-    type Output = Self;
-
-    fn bitor(self, rhs: Self) -> Self::Output {
-        Self(self.0 | rhs.0)
-    }
-}
-
-thread_local! {
-    static BUFFER: RefCell<Vec<u8>> = RefCell::default();
-}
+use tracing::{debug, warn};
 
 /// File handle with low-level APIs, created by [`Bijou::open_file`].
 ///
+/// Reads, writes and resizes are atomic with respect to each other,
+/// even across different handles of the same file: they are
+/// serialized by a readers-writer lock shared by all handles, so a
+/// read racing with a truncation sees the file either before or
+/// after it, and never a partially rewritten block.
+///
 /// [`Bijou::open_file`]: crate::Bijou::open_file
 pub struct LowLevelFile {
     raw_file: Box<dyn RawFile + Send + Sync>,
     algo: Arc<dyn Algorithm + Send + Sync>,
     key: Box<dyn AlgoKey + Send + Sync>,
+    /// Buffers of the block size of `algo`.
+    buffers: Arc<BufferPool>,
 
+    id: FileId,
     db_key: DatabaseKey<FileMeta>,
+    times: TimePolicy,
+    /// Maximum size of the plaintext.
+    max_size: PlainSize,
+    quotas: Arc<Quotas>,
     flags: FileFlags,
+    /// Whether to keep the MIME type xattr up to date. See
+    /// [`mime`].
+    sniff_mime: bool,
 
     lock: Arc<RwLock<RawFileMeta>>,
+    raw_lock: Arc<RwLock<()>>,
     handle_count: Arc<AtomicU32>,
+
+    counters: FileCounters,
 }
 
 impl LowLevelFile {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         raw_file: Box<dyn RawFile + Send + Sync>,
         algo: Arc<dyn Algorithm + Send + Sync>,
         key: Box<dyn AlgoKey + Send + Sync>,
+        buffers: Arc<BufferPool>,
+        id: FileId,
         db_key: DatabaseKey<FileMeta>,
+        times: TimePolicy,
+        max_size: PlainSize,
+        quotas: Arc<Quotas>,
         flags: FileFlags,
+        sniff_mime: bool,
         lock: Arc<RwLock<RawFileMeta>>,
+        raw_lock: Arc<RwLock<()>>,
         handle_count: Arc<AtomicU32>,
     ) -> Self {
         handle_count.fetch_add(1, Ordering::Relaxed);
@@ -196,12 +92,21 @@ impl LowLevelFile {
             raw_file,
             algo,
             key,
+            buffers,
 
+            id,
             db_key,
+            times,
+            max_size,
+            quotas,
             flags,
+            sniff_mime,
 
             lock,
+            raw_lock,
             handle_count,
+
+            counters: FileCounters::default(),
         }
     }
 }
@@ -212,7 +117,7 @@ impl LowLevelFile {
         key: &dyn AlgoKey,
         raw_file: &dyn RawFile,
         buffer: &mut [u8],
-        block: u64,
+        block: BlockIndex,
     ) -> Result<usize> {
         let block_end = raw_file.read_block(buffer, block)? as usize;
 
@@ -230,88 +135,119 @@ impl LowLevelFile {
     /// Reads a number of bytes starting from a given offset.
     ///
     /// Returns the number of bytes read.
-    pub fn read(&self, mut data: &mut [u8], offset: u64) -> Result<u64> {
+    pub fn read(&self, data: &mut [u8], offset: u64) -> Result<u64> {
         if !self.flags.has(FileFlags::READ) {
             bail!(@BadFileDescriptor "reading a file without permission");
         }
+        let start = Instant::now();
+        let read = self.read_inner(data, offset)?;
+        self.counters.record_read(read, start.elapsed());
+        Ok(read)
+    }
 
+    /// Same as [`read`], without checking the permission.
+    ///
+    /// [`read`]: LowLevelFile::read
+    fn read_inner(&self, mut data: &mut [u8], offset: u64) -> Result<u64> {
         if data.is_empty() {
             return Ok(0);
         }
 
-        BUFFER.with(move |buffer| {
-            let mut buffer = buffer.borrow_mut();
-            buffer.resize(self.algo.block_size() as _, 0);
+        let mut buffer = self.buffers.get(self.algo.block_size() as _);
 
-            let _guard = self.lock.read().unwrap();
+        let meta = self.lock.read().unwrap();
 
-            let content_size = self.algo.content_size();
-            let header_size = self.algo.header_size() as usize;
-            let tag_size = self.algo.tag_size() as usize;
+        // never read past the end of file, e.g. from a block
+        // outlived by a truncation in storages where resizing
+        // is not atomic
+        let size = self.algo.plaintext_size(meta.size).0;
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = (size - offset).min(data.len() as u64) as usize;
+        data = &mut data[..len];
 
-            let start_block = offset / content_size;
-            let start_offset = offset % content_size;
+        let content_size = self.algo.content_size();
+        let header_size = self.algo.header_size() as usize;
+        let tag_size = self.algo.tag_size() as usize;
 
-            let mut read = 0;
+        let (start_block, start_offset) = self.algo.locate(PlainSize(offset));
 
-            // First block
+        let mut read = 0;
 
+        let load = |buffer: &mut [u8], block| -> Result<usize> {
             let block_end = Self::load_block(
                 self.algo.as_ref(),
                 self.key.as_ref(),
                 self.raw_file.as_ref(),
-                &mut buffer,
-                start_block,
+                buffer,
+                block,
             )?;
+            if block_end == 0 {
+                // Blocks before the end of file can be missing, e.g. in
+                // clusters skipped by extending the file, and are zeros.
+                buffer.fill(0);
+                return Ok(buffer.len());
+            }
+            Ok(block_end)
+        };
 
-            let block_read = {
-                let offset = header_size + start_offset as usize;
-                let len = block_end.saturating_sub(offset + tag_size).min(data.len());
-                data[..len].copy_from_slice(&buffer[offset..offset + len]);
-                len as u64
-            };
-            read += block_read;
-            data = &mut data[block_read as usize..];
+        // First block
 
-            let mut block = start_block + 1;
-            for chunk in data.chunks_mut(content_size as _) {
-                let block_end = Self::load_block(
-                    self.algo.as_ref(),
-                    self.key.as_ref(),
-                    self.raw_file.as_ref(),
-                    &mut buffer,
-                    block,
-                )?;
+        let block_end = load(&mut buffer, start_block)?;
 
-                if block_end == 0 {
-                    break;
-                }
+        let block_read = {
+            let offset = header_size + start_offset as usize;
+            let len = block_end.saturating_sub(offset + tag_size).min(data.len());
+            data[..len].copy_from_slice(&buffer[offset..offset + len]);
+            len as u64
+        };
+        read += block_read;
+        data = &mut data[block_read as usize..];
 
-                let block_read = {
-                    let len = (block_end - header_size - tag_size).min(chunk.len());
-                    chunk[..len].copy_from_slice(&buffer[header_size..header_size + len]);
-                    len as u64
-                };
-                read += block_read;
-                if block_read < content_size {
-                    break;
-                }
+        let mut block = start_block.next();
+        for chunk in data.chunks_mut(content_size as _) {
+            let block_end = load(&mut buffer, block)?;
 
-                block += 1;
+            let block_read = {
+                let len = (block_end - header_size - tag_size).min(chunk.len());
+                chunk[..len].copy_from_slice(&buffer[header_size..header_size + len]);
+                len as u64
+            };
+            read += block_read;
+            if block_read < content_size {
+                break;
             }
 
-            utils::memzero(&mut buffer);
+            block = block.next();
+        }
 
-            // TODO access time
+        // TODO access time
 
-            Ok(read)
-        })
+        Ok(read)
     }
 
     /// Writes a number of bytes starting from a given offset.
     ///
-    /// Returns the number of bytes written.
-    pub fn write(&mut self, mut data: &[u8], offset: u64) -> Result<u64> {
+    /// Returns the number of bytes written, which is less than
+    /// `data.len()` if the maximum file size is reached. Fails with
+    /// [`ErrorKind::FileTooLarge`] if `offset` is already beyond it,
+    /// and with [`ErrorKind::QuotaExceeded`] if the file would grow
+    /// beyond a quota it is under.
+    ///
+    /// [`ErrorKind::FileTooLarge`]: crate::ErrorKind::FileTooLarge
+    /// [`ErrorKind::QuotaExceeded`]: crate::ErrorKind::QuotaExceeded
+    pub fn write(&mut self, data: &[u8], offset: u64) -> Result<u64> {
+        let start = Instant::now();
+        let written = self.write_inner(data, offset)?;
+        self.counters.record_write(written, start.elapsed());
+        if written != 0 && offset < mime::SNIFF_LEN as u64 {
+            self.update_mime_type();
+        }
+        Ok(written)
+    }
+
+    fn write_inner(&mut self, mut data: &[u8], offset: u64) -> Result<u64> {
         if !self.flags.has(FileFlags::WRITE) {
             bail!(@BadFileDescriptor "writing a file without permission");
         }
@@ -320,28 +256,38 @@ impl LowLevelFile {
             return Ok(0);
         }
 
+        if offset >= self.max_size.0 {
+            bail!(@FileTooLarge? "writing beyond the maximum file size: {offset}");
+        }
+        let len = (self.max_size.0 - offset).min(data.len() as u64) as usize;
+        data = &data[..len];
+
+        let _raw_guard = self.raw_lock.read().unwrap();
         let mut meta = self.lock.write().unwrap();
 
-        if offset > self.algo.plaintext_size(meta.size) {
-            Self::set_len_inner(
-                self.raw_file.as_mut(),
-                self.algo.as_ref(),
-                self.key.as_ref(),
-                &mut meta,
-                offset,
-            )?;
-        }
+        let size = self.algo.plaintext_size(meta.size).0;
+        self.quotas
+            .reserve(self.id, size.max(offset + data.len() as u64))?;
+
+        let result = (|| -> Result<u64> {
+            if offset > size {
+                Self::set_len_inner(
+                    self.raw_file.as_mut(),
+                    self.algo.as_ref(),
+                    self.key.as_ref(),
+                    &self.buffers,
+                    &mut meta,
+                    PlainSize(offset),
+                )?;
+            }
 
-        BUFFER.with(|buffer| {
-            let mut buffer = buffer.borrow_mut();
-            buffer.resize(self.algo.block_size() as _, 0);
+            let mut buffer = self.buffers.get(self.algo.block_size() as _);
 
             let content_size = self.algo.content_size();
             let header_size = self.algo.header_size() as usize;
             let tag_size = self.algo.tag_size() as usize;
 
-            let start_block = offset / content_size;
-            let start_offset = offset % content_size;
+            let (start_block, start_offset) = self.algo.locate(PlainSize(offset));
 
             let mut written = 0;
 
@@ -374,7 +320,7 @@ impl LowLevelFile {
             written += block_written;
             data = &data[block_written as usize..];
 
-            let mut block = start_block + 1;
+            let mut block = start_block.next();
             for chunk in data.chunks(content_size as _) {
                 let block_end = if chunk.len() < content_size as usize {
                     Self::load_block(
@@ -402,79 +348,86 @@ impl LowLevelFile {
                     break;
                 }
 
-                block += 1;
+                block = block.next();
             }
 
-            utils::memzero(&mut buffer);
-
-            meta.size = meta.size.max(self.algo.ciphertext_size(offset + written));
-            meta.modified = Some(chrono::Utc::now());
+            meta.size = meta
+                .size
+                .max(self.algo.ciphertext_size(PlainSize(offset + written))?);
+            meta.modified = Some(self.times.now());
             self.raw_file.set_metadata(meta.clone())?;
 
             Ok(written)
-        })
+        })();
+        self.quotas
+            .settle(self.id, self.algo.plaintext_size(meta.size).0);
+        result
     }
 
     fn edit_block(
         file: &mut dyn RawFile,
         algo: &dyn Algorithm,
         key: &dyn AlgoKey,
-        block: u64,
+        buffers: &Arc<BufferPool>,
+        block: BlockIndex,
         f: impl FnOnce(&dyn Algorithm, &mut [u8], usize) -> usize,
     ) -> Result<()> {
-        BUFFER.with(|buffer| {
-            let mut buffer = buffer.borrow_mut();
-            buffer.resize(algo.block_size() as _, 0);
-            let block_end = file.read_block(&mut buffer, block)? as usize;
+        let mut buffer = buffers.get(algo.block_size() as _);
+        let block_end = file.read_block(&mut buffer, block)? as usize;
 
-            key.decrypt(block, &mut buffer[..block_end])?;
-            let block_end = f(algo, &mut buffer, block_end);
-            key.encrypt(block, &mut buffer[..block_end])?;
+        key.decrypt(block, &mut buffer[..block_end])?;
+        let block_end = f(algo, &mut buffer, block_end);
+        key.encrypt(block, &mut buffer[..block_end])?;
 
-            file.write_block(&buffer, block_end, block)
-        })
+        file.write_block(&buffer, block_end, block)
     }
 
     fn set_len_inner(
         file: &mut dyn RawFile,
         algo: &dyn Algorithm,
         key: &dyn AlgoKey,
+        buffers: &Arc<BufferPool>,
         meta: &mut RawFileMeta,
-        len: u64,
+        len: PlainSize,
     ) -> Result<()> {
         let current_size = algo.plaintext_size(meta.size);
 
         if current_size == len {
             return Ok(());
         }
+        let new_len = algo.ciphertext_size(len)?;
 
         if current_size < len {
-            let block = current_size / algo.content_size();
-            let offset = current_size % algo.content_size();
+            let (block, offset) = algo.locate(current_size);
 
             if offset != 0 {
-                Self::edit_block(file, algo, key, block, |algo, data, block_end| {
-                    let end = if len / algo.content_size() == block {
-                        (algo.metadata_size() + len % algo.content_size()) as usize
+                Self::edit_block(file, algo, key, buffers, block, |algo, data, block_end| {
+                    let (len_block, len_offset) = algo.locate(len);
+                    let end = if len_block == block {
+                        (algo.metadata_size() + len_offset) as usize
                     } else {
                         data.len()
                     };
-                    data[block_end..end].fill(0);
+                    // the old tag becomes content
+                    data[block_end - algo.tag_size() as usize..end].fill(0);
                     end
                 })?;
             }
         } else {
-            let block = len / algo.content_size();
-            let offset = len % algo.content_size();
+            let (block, offset) = algo.locate(len);
 
             if offset != 0 {
-                Self::edit_block(file, algo, key, block, |algo, _data, _block_end| {
-                    (algo.metadata_size() + offset) as usize
-                })?;
+                Self::edit_block(
+                    file,
+                    algo,
+                    key,
+                    buffers,
+                    block,
+                    |algo, _data, _block_end| (algo.metadata_size() + offset) as usize,
+                )?;
             }
         }
 
-        let new_len = algo.ciphertext_size(len);
         file.set_len(new_len, algo.block_size())?;
         meta.size = new_len;
 
@@ -487,32 +440,207 @@ impl LowLevelFile {
     ///
     /// [`Bijou::set_len`]: crate::Bijou::set_len
     pub fn set_len(&mut self, len: u64) -> Result<()> {
+        self.resize(len, true)?;
+        if len < mime::SNIFF_LEN as u64 {
+            self.update_mime_type();
+        }
+        Ok(())
+    }
+
+    /// Extends the file to `len` bytes if it is smaller, like
+    /// `fallocate` without `FALLOC_FL_KEEP_SIZE`.
+    pub fn extend(&mut self, len: u64) -> Result<()> {
+        self.resize(len, false)?;
+        if len < mime::SNIFF_LEN as u64 {
+            self.update_mime_type();
+        }
+        Ok(())
+    }
+
+    /// Detects the type of the file from its first bytes and updates
+    /// its MIME type xattr, if enabled. Failures are only logged, as
+    /// the xattr is merely a cache.
+    fn update_mime_type(&self) {
+        if !self.sniff_mime {
+            return;
+        }
+        let result = (|| -> Result<()> {
+            let mut head = [0; mime::SNIFF_LEN];
+            let len = self.read_inner(&mut head, 0)? as usize;
+            let key = self
+                .db_key
+                .clone()
+                .derive(consts::Derive::Xattr)
+                .column(columns::XATTRS)
+                .derive(mime::MIME_TYPE_XATTR);
+            match mime::sniff(&head[..len]) {
+                Some(mime) => key.write(mime),
+                None => key.delete(),
+            }
+        })();
+        if let Err(err) = result {
+            warn!(id = %self.id, "failed to update MIME type: {err}");
+        }
+    }
+
+    fn resize(&mut self, len: u64, shrink: bool) -> Result<()> {
         if !self.flags.has(FileFlags::WRITE) {
             bail!(@BadFileDescriptor "resizing a file without permission");
         }
+        if len > self.max_size.0 {
+            bail!(@FileTooLarge? "resizing beyond the maximum file size: {len}");
+        }
 
+        let _raw_guard = self.raw_lock.read().unwrap();
         let mut meta = self.lock.write().unwrap();
-        Self::set_len_inner(
+        if !shrink && self.algo.plaintext_size(meta.size).0 >= len {
+            return Ok(());
+        }
+        self.quotas.reserve(self.id, len)?;
+        let result = Self::set_len_inner(
             self.raw_file.as_mut(),
             self.algo.as_ref(),
             self.key.as_ref(),
+            &self.buffers,
             &mut meta,
-            len,
-        )?;
-        self.raw_file.set_metadata(meta.clone())?;
+            PlainSize(len),
+        )
+        .and_then(|()| self.raw_file.set_metadata(meta.clone()));
+        self.quotas
+            .settle(self.id, self.algo.plaintext_size(meta.size).0);
+        result
+    }
+
+    /// Hints that the file is going to grow to `len` bytes, so that
+    /// the storage can allocate space ahead, e.g. to speed up large
+    /// writes. See [`RawFile::reserve`].
+    ///
+    /// Neither the size of the file nor quotas are affected.
+    pub fn reserve(&mut self, len: u64) -> Result<()> {
+        if !self.flags.has(FileFlags::WRITE) {
+            bail!(@BadFileDescriptor "reserving space without permission");
+        }
+        if len > self.max_size.0 {
+            bail!(@FileTooLarge? "reserving beyond the maximum file size: {len}");
+        }
+
+        let _raw_guard = self.raw_lock.read().unwrap();
+        let meta = self.lock.write().unwrap();
+        let len = self.algo.ciphertext_size(PlainSize(len))?;
+        if len <= meta.size {
+            return Ok(());
+        }
+        self.raw_file.reserve(len, self.algo.block_size())
+    }
+
+    /// Flushes written data to durable storage.
+    ///
+    /// Metadata cached by the underlying filesystem is persisted by
+    /// the database, see [`RawFileSystem::flush`].
+    ///
+    /// [`RawFileSystem::flush`]: crate::low_level::raw_fs::RawFileSystem::flush
+    pub fn sync(&self) -> Result<()> {
+        self.raw_file.sync()
+    }
+
+    /// Verifies the integrity of the whole file.
+    ///
+    /// This checks that the ciphertext length is valid, that each
+    /// block is either complete or a gap, and that every block can
+    /// be authenticated. Returns [`ErrorKind::CryptoError`] if the
+    /// file has been tampered with.
+    ///
+    /// [`ErrorKind::CryptoError`]: crate::ErrorKind::CryptoError
+    pub fn verify(&self) -> Result<()> {
+        self.verify_with(&mut |_| Ok(()))
+    }
+
+    /// Same as [`verify`], but calls `on_block` with the ciphertext
+    /// of each block read, before it is decrypted.
+    ///
+    /// [`verify`]: LowLevelFile::verify
+    pub(crate) fn verify_with(&self, on_block: &mut dyn FnMut(&[u8]) -> Result<()>) -> Result<()> {
+        self.read_raw_blocks(&mut |block, data| {
+            on_block(data)?;
+            if !data.is_empty() {
+                self.key.decrypt(block, data)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Reads the ciphertext of every block in order, checking their
+    /// lengths, and calls `f` with the index and the ciphertext of
+    /// each. Gaps are passed as empty blocks.
+    pub(crate) fn read_raw_blocks(
+        &self,
+        f: &mut dyn FnMut(BlockIndex, &mut [u8]) -> Result<()>,
+    ) -> Result<()> {
+        let meta = self.lock.read().unwrap();
+
+        let block_size = self.algo.block_size();
+        let rem = meta.size.0 % block_size;
+        if rem != 0 && rem <= self.algo.metadata_size() {
+            bail!(@CryptoError "invalid ciphertext length: {}", meta.size);
+        }
+        let blocks = meta.size.0.div_ceil(block_size);
+
+        let mut buffer = self.buffers.get(block_size as _);
+        for block in (0..=blocks).map(BlockIndex) {
+            let expected = if block.0 + 1 < blocks {
+                block_size
+            } else if block.0 + 1 == blocks {
+                (meta.size - block.offset(block_size)).0
+            } else {
+                0
+            };
+
+            let block_end = self.raw_file.read_block(&mut buffer, block)?;
+            // a gap has no length
+            if block_end != 0 && block_end != expected {
+                bail!(@CryptoError "block {block} has unexpected length: {block_end}");
+            }
+
+            f(block, &mut buffer[..block_end as usize])?;
+        }
 
         Ok(())
     }
 
+    /// Returns the number of open handles of the file, including
+    /// this one.
+    pub(crate) fn handles(&self) -> u32 {
+        self.handle_count.load(Ordering::Relaxed)
+    }
+
+    /// Returns statistics of reads and writes through this handle.
+    pub fn stats(&self) -> FileStats {
+        self.counters.snapshot()
+    }
+
     /// Returns the metadata of a file.
     pub fn metadata(&self) -> Result<FileMeta> {
         let meta = self.lock.read().unwrap();
-        obtain_metadata(&self.db_key, self.algo.as_ref(), || Ok(meta.clone()))
+        obtain_metadata(&self.db_key, self.algo.as_ref(), &self.times, |_| {
+            Ok(meta.clone())
+        })
     }
 }
 
 impl Drop for LowLevelFile {
     fn drop(&mut self) {
+        if self.flags.has(FileFlags::WRITE) || self.flags.has(FileFlags::TRUNCATE) {
+            // The content may have been changed after it was pinned
+            let pin = self.db_key.clone().derive(consts::Derive::Pin);
+            if let Err(err) = pin.delete() {
+                warn!(id = %self.id, "failed to remove pin: {err}");
+            }
+        }
         self.handle_count.fetch_sub(1, Ordering::Relaxed);
+
+        let stats = self.stats();
+        if stats.reads != 0 || stats.writes != 0 {
+            debug!(id = %self.id, ?stats, "file closed");
+        }
     }
 }
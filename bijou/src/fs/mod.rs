@@ -14,50 +14,130 @@
 //
 
 pub mod config;
+#[cfg(feature = "rocksdb")]
 mod file;
+mod options;
 pub mod path;
 pub mod raw;
+#[cfg(feature = "rocksdb")]
+mod stats;
 pub mod time;
 
+#[cfg(feature = "rocksdb")]
+pub use bijou_types::DirItem;
+pub use bijou_types::{FileAttributes, FileId, FileKind, FileMeta, UnixPerms};
+#[cfg(feature = "rocksdb")]
 pub use file::*;
+pub use options::*;
+#[cfg(feature = "rocksdb")]
 pub use raw::*;
-
-use crate::{algo::Algorithm, db::DatabaseKey, Context, ErrorKind, Result};
+#[cfg(feature = "rocksdb")]
+pub use stats::FileStats;
+#[cfg(feature = "rocksdb")]
+pub(crate) use stats::{FileCounters, SlowOpLog};
+
+#[cfg(feature = "rocksdb")]
+use crate::{algo::Algorithm, db::DatabaseKey, Clock, Context, ErrorKind, Result};
+#[cfg(feature = "rocksdb")]
 use chrono::{DateTime, Utc};
+#[cfg(feature = "rocksdb")]
+use config::TimeSource;
+#[cfg(feature = "rocksdb")]
 use postcard::fixint;
+#[cfg(feature = "rocksdb")]
 use serde::{Deserialize, Serialize};
-use std::fmt;
+#[cfg(feature = "rocksdb")]
+use std::sync::Arc;
+
+/// How times of files are reconciled between the storage and the
+/// database. See [`Config::time_source`].
+///
+/// [`Config::time_source`]: config::Config::time_source
+#[cfg(feature = "rocksdb")]
+#[derive(Clone)]
+pub(crate) struct TimePolicy {
+    source: TimeSource,
+    skew_tolerance: chrono::Duration,
+    clock: Arc<dyn Clock>,
+}
+#[cfg(feature = "rocksdb")]
+impl TimePolicy {
+    pub fn new(config: &config::Config, clock: &Arc<dyn Clock>) -> Self {
+        Self {
+            source: config.time_source,
+            skew_tolerance: chrono::Duration::seconds(
+                config.clock_skew_tolerance.min(i64::MAX as u64) as i64,
+            ),
+            clock: Arc::clone(clock),
+        }
+    }
 
+    /// Returns the current time, for updating times of files.
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    /// Picks between the time `stored` in the database and the one
+    /// reported by the storage.
+    fn resolve(&self, stored: DateTime<Utc>, raw: Option<DateTime<Utc>>) -> DateTime<Utc> {
+        let now = self.now();
+        let raw = raw.map(|time| {
+            if time > now + self.skew_tolerance {
+                now
+            } else {
+                time
+            }
+        });
+        match self.source {
+            TimeSource::Storage => raw.unwrap_or(stored),
+            TimeSource::Database => stored,
+            TimeSource::Latest => raw.map_or(stored, |raw| raw.max(stored)),
+        }
+    }
+}
+
+#[cfg(feature = "rocksdb")]
 pub(crate) fn obtain_metadata(
     key: &DatabaseKey<FileMeta>,
     algo: &dyn Algorithm,
-    f: impl FnOnce() -> Result<RawFileMeta>,
+    times: &TimePolicy,
+    f: impl FnOnce(&FileMeta) -> Result<RawFileMeta>,
 ) -> Result<FileMeta> {
     let mut meta = key.get()?.kind(ErrorKind::NotFound)?;
+    complete_metadata(&mut meta, algo, times, f)?;
+
+    Ok(meta)
+}
+
+/// Fills in the fields of `meta` that are not stored in the
+/// database, using `f` to stat the raw file if needed.
+#[cfg(feature = "rocksdb")]
+pub(crate) fn complete_metadata(
+    meta: &mut FileMeta,
+    algo: &dyn Algorithm,
+    times: &TimePolicy,
+    f: impl FnOnce(&FileMeta) -> Result<RawFileMeta>,
+) -> Result<()> {
     match meta.kind {
         FileKind::Directory => {
             meta.size = 512;
         }
         FileKind::Symlink => {}
         FileKind::File => {
-            let std = f()?;
-            meta.accessed = std.accessed.unwrap_or_else(time::unix_epoch_date_time);
-            meta.modified = std.modified.unwrap_or_else(time::unix_epoch_date_time);
-            meta.size = algo.plaintext_size(std.size);
+            let std = f(meta)?;
+            meta.accessed = times.resolve(meta.accessed, std.accessed);
+            meta.modified = times.resolve(meta.modified, std.modified);
+            meta.size = algo.plaintext_size(std.size).0;
         }
     }
 
-    Ok(meta)
-}
-
-#[derive(Serialize, Deserialize, Clone, Copy)]
-pub struct DirItem {
-    pub id: FileId,
-    pub kind: FileKind,
+    Ok(())
 }
 
+#[cfg(feature = "rocksdb")]
 #[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug, PartialOrd, Ord)]
 pub struct Inode(#[serde(with = "fixint::le")] pub u64);
+#[cfg(feature = "rocksdb")]
 impl Inode {
     pub const ROOT: Inode = Inode(1);
     pub const DUMMY: Inode = Inode(!0);
@@ -66,79 +146,54 @@ impl Inode {
     pub fn as_index(&self) -> usize {
         self.0 as usize - 1
     }
-}
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug)]
-pub enum FileKind {
-    File,
-    Symlink,
-    Directory,
-}
-
-/// The internal unique identifier of a file.
-#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug)]
-pub struct FileId(u64);
-impl fmt::Display for FileId {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:x}", self.0)
-    }
-}
-impl AsRef<[u8]> for FileId {
-    fn as_ref(&self) -> &[u8] {
-        unsafe {
-            std::slice::from_raw_parts(
-                &self.0 as *const u64 as *const u8,
-                std::mem::size_of::<u64>(),
-            )
+    /// Derives the inode of a file from its ID, so that it stays
+    /// the same across mounts.
+    ///
+    /// Returns `None` if the ID collides with a reserved inode,
+    /// which never happens for IDs from [`FileId::gen`].
+    pub fn from_file_id(id: FileId) -> Option<Self> {
+        match id.as_u64() {
+            0 => Some(Self::ROOT),
+            1 | u64::MAX => None,
+            id => Some(Self(id)),
         }
     }
-}
-impl FileId {
-    pub const ROOT: FileId = FileId(0);
 
-    pub fn gen() -> Self {
-        Self(rand::random())
-    }
-
-    pub fn from_bytes(bytes: &[u8]) -> Self {
-        Self(u64::from_le_bytes(bytes.try_into().unwrap()))
+    /// The inverse of [`Inode::from_file_id`].
+    pub fn to_file_id(self) -> FileId {
+        if self == Self::ROOT {
+            FileId::ROOT
+        } else {
+            FileId::from_u64(self.0)
+        }
     }
 }
 
-/// Metadata for a file.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct FileMeta {
-    pub id: FileId,
-    pub kind: FileKind,
-
-    /// Size of this file in bytes. We don't actually store this.
-    /// We use the size of the underlying [`RawFileSystem`].
-    #[serde(skip)]
-    pub size: u64,
-
-    /// Time of the last access. Only for directories.
-    ///
-    /// For files, we use times from the underlying filesystem.
-    #[serde(with = "time::compact_date_time")]
-    pub accessed: DateTime<Utc>,
+/// Flags of [`Bijou::rename_with_flags`], corresponding to those of
+/// `renameat2(2)`.
+///
+/// [`Bijou::rename_with_flags`]: crate::Bijou::rename_with_flags
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RenameFlags(u8);
+impl RenameFlags {
+    pub const EMPTY: RenameFlags = RenameFlags(0);
 
-    /// Time of the last modification. Only for directories.
-    ///
-    /// For files, we use times from the underlying filesystem.
-    #[serde(with = "time::compact_date_time")]
-    pub modified: DateTime<Utc>,
+    /// Fails if the target already exists, instead of replacing it.
+    pub const NOREPLACE: RenameFlags = RenameFlags(1 << 0);
 
-    /// Number of links. Should always be 1 for files since we don't
-    /// support hardlinks.
-    pub nlinks: u32,
+    /// Atomically exchanges the source and the target, which must
+    /// both exist.
+    pub const EXCHANGE: RenameFlags = RenameFlags(1 << 1);
 
-    /// Optional Unix permissions.
-    pub perms: Option<UnixPerms>,
+    pub fn has(&self, flag: Self) -> bool {
+        self.0 & flag.0 != 0
+    }
 }
+impl std::ops::BitOr for RenameFlags {
+    type Output = Self;
 
-#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
-pub struct UnixPerms {
-    pub mode: u16,
-    pub uid: u32,
-    pub gid: u32,
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
 }
@@ -0,0 +1,291 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::{bail, Result};
+#[cfg(feature = "rocksdb")]
+use crate::{low_level::LowLevelFile, path::Path, Bijou, BijouFs, File, FileId};
+use std::marker::PhantomData;
+
+/// Options and flags which can be used to configure how a file is opened.
+///
+/// This corresponds to [`std::fs::OpenOptions`]. Invalid combinations
+/// of options are rejected when opening, see [`OpenOptions::check`].
+/// For options that are valid by construction, see
+/// [`OpenOptions::read_only`] and [`OpenOptions::writable`].
+#[derive(Clone, Debug, Default)]
+pub struct OpenOptions {
+    pub(crate) read: bool,
+    pub(crate) write: bool,
+    pub(crate) append: bool,
+    pub(crate) truncate: bool,
+    pub(crate) create: bool,
+    pub(crate) create_new: bool,
+}
+
+impl OpenOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns options for opening an existing file for reading only.
+    pub fn read_only() -> CheckedOpenOptions<ReadAccess> {
+        CheckedOpenOptions::new(Self::new().read(true).clone())
+    }
+
+    /// Returns options for opening a file for writing. Reading,
+    /// truncating and creating the file can be enabled further.
+    pub fn writable() -> CheckedOpenOptions<WriteAccess> {
+        CheckedOpenOptions::new(Self::new().write(true).clone())
+    }
+
+    /// Checks that the options are valid, which is done by
+    /// [`Bijou::open_file`] and [`Bijou::open_file_direct`].
+    ///
+    /// Truncating requires write access. Other options only
+    /// meaningful for writing are accepted without it, as they are
+    /// by POSIX `open` (e.g. `O_CREAT` with `O_RDONLY`), which FUSE
+    /// relies on.
+    ///
+    /// [`Bijou::open_file`]: crate::Bijou::open_file
+    /// [`Bijou::open_file_direct`]: crate::Bijou::open_file_direct
+    pub fn check(&self) -> Result<()> {
+        if self.truncate && !self.write {
+            bail!(@InvalidInput? "cannot specify truncate without write");
+        }
+        Ok(())
+    }
+
+    /// Sets the option for read access.
+    ///
+    /// See also [`std::fs::OpenOptions::read`].
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    /// Sets the option for write access.
+    ///
+    /// See also [`std::fs::OpenOptions::write`].
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    /// Sets the option for append mode.
+    ///
+    /// See also [`std::fs::OpenOptions::append`].
+    pub fn append(&mut self, append: bool) -> &mut Self {
+        self.append = append;
+        self
+    }
+
+    /// Sets the option for truncating a previous file.
+    ///
+    /// Truncation happens once when opening, as if by `set_len(0)`
+    /// under the file's write lock. Other handles of the same file
+    /// observe it either entirely before or entirely after any of
+    /// their own reads and writes, regardless of the storage backend.
+    ///
+    /// See also [`std::fs::OpenOptions::truncate`].
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    /// Sets the option to create a new file, or open it if it already exists.
+    ///
+    /// See also [`std::fs::OpenOptions::create`].
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    /// Sets the option to always create a new file.
+    ///
+    /// See also [`std::fs::OpenOptions::create_new`].
+    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.create_new = create_new;
+        self
+    }
+
+    #[doc(hidden)]
+    pub fn to_flags(&self) -> FileFlags {
+        let mut flags = FileFlags::EMPTY;
+
+        if self.read {
+            flags = flags | FileFlags::READ;
+        }
+        if self.write {
+            flags = flags | FileFlags::WRITE;
+        }
+        if self.truncate {
+            flags = flags | FileFlags::TRUNCATE;
+        }
+
+        flags
+    }
+
+    /// Opens a low level file at `path` with the options specified by `self`.
+    #[cfg(feature = "rocksdb")]
+    pub fn open_low_level(&self, bijou: &Bijou, path: impl AsRef<Path>) -> Result<LowLevelFile> {
+        self.open_in(bijou, FileId::ROOT, path.as_ref())
+    }
+
+    /// Opens a low level file at `path`, treating the directory
+    /// `root` as the root.
+    #[cfg(feature = "rocksdb")]
+    fn open_in(&self, bijou: &Bijou, root: FileId, path: &Path) -> Result<LowLevelFile> {
+        Ok(if !(self.create || self.create_new) {
+            bijou.open_file_direct(bijou.resolve_in(root, path)?, self)?
+        } else {
+            let (parent, name) = bijou.resolve_parent_nonroot_in(root, path)?;
+            bijou.open_file(parent, name, self, None)?
+        })
+    }
+
+    /// Opens a file at path `with` the options specified by `self`.
+    ///
+    /// This corresponds to [`std::fs::OpenOptions::open`].
+    #[cfg(feature = "rocksdb")]
+    pub fn open(&self, fs: &BijouFs, path: impl AsRef<Path>) -> Result<File> {
+        self.open_in(&fs.bijou, fs.root, path.as_ref())
+            .map(File::new)
+    }
+}
+
+impl AsRef<OpenOptions> for OpenOptions {
+    fn as_ref(&self) -> &OpenOptions {
+        self
+    }
+}
+
+/// Access mode of [`CheckedOpenOptions`] that only allows reading.
+#[derive(Clone, Copy, Debug)]
+pub struct ReadAccess;
+
+/// Access mode of [`CheckedOpenOptions`] that allows writing.
+#[derive(Clone, Copy, Debug)]
+pub struct WriteAccess;
+
+/// [`OpenOptions`] that are valid by construction.
+///
+/// Options only meaningful for writing (appending, truncating and
+/// creating the file) are only available with [`WriteAccess`], so
+/// that invalid combinations are caught at compile time:
+///
+/// ```compile_fail
+/// # use bijou::OpenOptions;
+/// OpenOptions::read_only().truncate(true);
+/// ```
+///
+/// Created by [`OpenOptions::read_only`] and
+/// [`OpenOptions::writable`]. Can be passed wherever [`OpenOptions`]
+/// are expected.
+#[derive(Clone, Debug)]
+pub struct CheckedOpenOptions<A> {
+    options: OpenOptions,
+    access: PhantomData<A>,
+}
+
+impl<A> CheckedOpenOptions<A> {
+    fn new(options: OpenOptions) -> Self {
+        Self {
+            options,
+            access: PhantomData,
+        }
+    }
+
+    /// See [`OpenOptions::open_low_level`].
+    #[cfg(feature = "rocksdb")]
+    pub fn open_low_level(&self, bijou: &Bijou, path: impl AsRef<Path>) -> Result<LowLevelFile> {
+        self.options.open_low_level(bijou, path)
+    }
+
+    /// See [`OpenOptions::open`].
+    #[cfg(feature = "rocksdb")]
+    pub fn open(&self, fs: &BijouFs, path: impl AsRef<Path>) -> Result<File> {
+        self.options.open(fs, path)
+    }
+}
+
+impl CheckedOpenOptions<WriteAccess> {
+    /// See [`OpenOptions::read`].
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.options.read(read);
+        self
+    }
+
+    /// See [`OpenOptions::append`].
+    pub fn append(&mut self, append: bool) -> &mut Self {
+        self.options.append(append);
+        self
+    }
+
+    /// See [`OpenOptions::truncate`].
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.options.truncate(truncate);
+        self
+    }
+
+    /// See [`OpenOptions::create`].
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.options.create(create);
+        self
+    }
+
+    /// See [`OpenOptions::create_new`].
+    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.options.create_new(create_new);
+        self
+    }
+}
+
+impl<A> AsRef<OpenOptions> for CheckedOpenOptions<A> {
+    fn as_ref(&self) -> &OpenOptions {
+        &self.options
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileFlags(u8);
+impl FileFlags {
+    pub const EMPTY: FileFlags = FileFlags(0);
+    pub const READ: FileFlags = FileFlags(1 << 0);
+    pub const WRITE: FileFlags = FileFlags(1 << 1);
+    pub const TRUNCATE: FileFlags = FileFlags(1 << 2);
+
+    pub fn has(&self, flag: Self) -> bool {
+        self.0 & flag.0 != 0
+    }
+
+    pub fn remove(&self, flag: Self) -> Self {
+        Self(self.0 & !flag.0)
+    }
+
+    pub fn to_std(self) -> std::fs::OpenOptions {
+        let mut opts = std::fs::OpenOptions::new();
+        opts.read(self.has(Self::READ))
+            .write(self.has(Self::WRITE))
+            .truncate(self.has(Self::TRUNCATE));
+        opts
+    }
+}
+impl std::ops::BitOr for FileFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
@@ -13,6 +13,7 @@
 // limitations under the License.
 //
 
+use crate::{bail, Result};
 use std::{borrow::Borrow, fmt, ops, sync::Arc};
 
 /// A single component of a [`Path`].
@@ -139,7 +140,13 @@ impl<'a> DoubleEndedIterator for Components<'a> {
 /// A slice of path (akin to [`str`]).
 ///
 /// Different from [`std::path::Path`], this type is always
-/// UTF-8 encoded.
+/// UTF-8 encoded, and `/` is the only separator on every platform.
+/// Backslashes are never treated as separators; since a path
+/// containing them is most likely a Windows-style path passed by
+/// mistake, [`Path::check`] rejects them, along with embedded NULs.
+/// Paths are checked when resolved by [`Bijou`].
+///
+/// [`Bijou`]: crate::Bijou
 #[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Path {
     inner: str,
@@ -154,6 +161,17 @@ impl Path {
         &self.inner
     }
 
+    /// Checks that this path contains neither NULs nor backslashes.
+    pub fn check(&self) -> Result<()> {
+        if self.inner.contains('\0') {
+            bail!(@InvalidInput? "path contains NUL: {:?}", &self.inner);
+        }
+        if self.inner.contains('\\') {
+            bail!(@InvalidInput? "path contains backslash: {:?}", &self.inner);
+        }
+        Ok(())
+    }
+
     pub fn components(&self) -> Components {
         Components {
             path: &self.inner,
@@ -191,9 +209,16 @@ impl Path {
         })
     }
 
-    pub fn to_relative(&self) -> PathBuf {
+    /// Lexically normalizes this absolute path, returning it
+    /// relative to the root.
+    ///
+    /// `..` at the root stays at the root, as in POSIX. Fails if the
+    /// path is not absolute.
+    pub fn to_relative(&self) -> Result<PathBuf> {
         let mut comps = self.components();
-        assert_eq!(Some(Component::RootDir), comps.next());
+        if comps.next() != Some(Component::RootDir) {
+            bail!(@InvalidInput? "expected absolute path, got `{self}`");
+        }
         let mut parts = Vec::new();
         for comp in comps {
             match comp {
@@ -201,13 +226,13 @@ impl Path {
                     parts.push(p);
                 }
                 Component::ParentDir => {
-                    parts.pop().unwrap();
+                    parts.pop();
                 }
-                _ => unreachable!(),
+                Component::RootDir | Component::CurDir => {}
             }
         }
         if parts.is_empty() {
-            return PathBuf::new(String::new());
+            return Ok(PathBuf::new(String::new()));
         }
 
         let mut buf = String::with_capacity(parts.iter().map(|it| it.len() + 1).sum());
@@ -217,7 +242,7 @@ impl Path {
         }
         buf.pop();
 
-        PathBuf { inner: buf }
+        Ok(PathBuf { inner: buf })
     }
 }
 
@@ -378,4 +403,22 @@ mod test {
         assert_eq!(Some("b"), Path::new("a/b/.").file_name());
         assert_eq!(None, Path::new("a/..").file_name());
     }
+
+    #[test]
+    fn test_to_relative() {
+        let relative = |path: &str| Path::new(path).to_relative().map(|p| p.inner);
+        assert_eq!("a/c", relative("/a/./b/../c/").unwrap());
+        assert_eq!("a", relative("/../../a").unwrap());
+        assert_eq!("", relative("//").unwrap());
+        assert!(relative("a/b").is_err());
+        assert!(relative("../a").is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        assert!(Path::new("/a b/c").check().is_ok());
+        assert!(Path::new("/a\\b").check().is_err());
+        assert!(Path::new("C:\\a").check().is_err());
+        assert!(Path::new("/a\0b").check().is_err());
+    }
 }
@@ -13,14 +13,28 @@
 // limitations under the License.
 //
 
+#[cfg(feature = "flaky")]
+mod flaky;
 mod local;
+#[cfg(feature = "rocksdb")]
+mod migrating;
+#[cfg(feature = "rocksdb")]
 mod rocksdb;
+#[cfg(feature = "rocksdb")]
 mod split;
+#[cfg(feature = "rocksdb")]
 mod tracking;
 
+#[cfg(feature = "rocksdb")]
 pub use self::rocksdb::RocksDBFileSystem;
+#[cfg(feature = "flaky")]
+pub use flaky::FlakyFileSystem;
 pub use local::LocalFileSystem;
+#[cfg(feature = "rocksdb")]
+pub use migrating::MigratingFileSystem;
+#[cfg(feature = "rocksdb")]
 pub use split::SplitFileSystem;
+#[cfg(feature = "rocksdb")]
 pub use tracking::TrackingFileSystem;
 
 #[cfg(feature = "opendal")]
@@ -29,15 +43,31 @@ mod opendal;
 pub use self::opendal::OpenDALFileSystem;
 
 use super::{time, FileFlags, FileId};
-use crate::Result;
+use crate::{
+    algo::{BlockIndex, CipherSize},
+    bail, Result,
+};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
 type ArcRawFileSystem = Arc<dyn RawFileSystem + Send + Sync>;
 
+pub(crate) mod sealed {
+    /// Prevents [`RawFileSystem`] and [`RawFile`] from being
+    /// implemented outside this crate.
+    ///
+    /// [`RawFileSystem`]: super::RawFileSystem
+    /// [`RawFile`]: super::RawFile
+    pub trait Sealed {}
+}
+use sealed::Sealed;
+
 /// A type abstracting over different low-level filesystems.
-pub trait RawFileSystem {
+///
+/// This trait is sealed, so that methods can be added to it without
+/// breaking changes.
+pub trait RawFileSystem: Sealed {
     /// Opens a file.
     ///
     /// The caller should make sure that the file exists.
@@ -73,33 +103,79 @@ pub trait RawFileSystem {
     /// in the algorithm.
     fn write(&self, id: FileId, data: &[u8]) -> Result<()> {
         self.open(id, FileFlags::WRITE | FileFlags::TRUNCATE)?
-            .write_block(data, data.len(), 0)
+            .write_block(data, data.len(), BlockIndex(0))
+    }
+
+    /// Persists pending metadata updates, if any.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Checks that the storage is reachable, e.g. that a remote
+    /// service responds. Local filesystems can keep the default,
+    /// which does nothing.
+    fn probe(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Copies all stored files into `dest`, which should not exist.
+    ///
+    /// The caller should make sure that no files are modified
+    /// during the backup.
+    fn backup(&self, _dest: &std::path::Path) -> Result<()> {
+        bail!(@Unsupported "this filesystem does not support backup")
+    }
+
+    /// Calls `f` with the ID of every stored file, so that the ones
+    /// no file refers to can be found (see [`Bijou::check`]). Stops
+    /// at the first error returned by `f`.
+    ///
+    /// [`Bijou::check`]: crate::Bijou::check
+    fn list(&self, _f: &mut dyn FnMut(FileId) -> Result<()>) -> Result<()> {
+        bail!(@Unsupported "this filesystem does not support listing files")
+    }
+
+    /// Returns this filesystem if it is a [`MigratingFileSystem`].
+    #[cfg(feature = "rocksdb")]
+    fn migrating(&self) -> Option<&MigratingFileSystem> {
+        None
     }
 }
 
 /// File created by a [`RawFileSystem`].
-pub trait RawFile {
+///
+/// This trait is sealed like [`RawFileSystem`].
+pub trait RawFile: Sealed {
     /// Reads a block of data from the file, returning the
     /// number of bytes read.
     ///
     /// The length of `data` should be the block size.
     ///
     /// The caller should make sure that the file is opened with read permission.
-    fn read_block(&self, data: &mut [u8], block: u64) -> Result<u64>;
+    fn read_block(&self, data: &mut [u8], block: BlockIndex) -> Result<u64>;
 
-    /// Writes a block of data tchildo the file.
+    /// Writes a block of data to the file.
     ///
     /// `block_end` indicates the number of bytes to write, and
     /// the length of `data` should be the block size.
     ///
     /// The caller should make sure that the file is opened with write permission.
-    fn write_block(&mut self, data: &[u8], block_end: usize, block: u64) -> Result<()>;
+    fn write_block(&mut self, data: &[u8], block_end: usize, block: BlockIndex) -> Result<()>;
 
     /// Resizes the file.
     ///
     /// If the original file is larger than `len`, extra content
     /// got truncated; otherwise, the file is extended with zeros.
-    fn set_len(&mut self, len: u64, block_size: u64) -> Result<()>;
+    fn set_len(&mut self, len: CipherSize, block_size: u64) -> Result<()>;
+
+    /// Hints that the file is going to grow to `len` bytes, so that
+    /// space can be allocated ahead, e.g. to reduce fragmentation.
+    ///
+    /// The size of the file is not changed. Filesystems without such
+    /// a notion can keep the default, which does nothing.
+    fn reserve(&mut self, _len: CipherSize, _block_size: u64) -> Result<()> {
+        Ok(())
+    }
 
     /// Sets the metadata.
     ///
@@ -114,12 +190,32 @@ pub trait RawFile {
         panic!("This filesystem does not support persisting metadata. You should wrap it in a TrackingFileSystem.");
     }
 
+    /// Explicitly sets the access and modification times.
+    ///
+    /// Different from [`set_metadata`], filesystems capable of
+    /// automatically persisting metadata should apply the times.
+    ///
+    /// [`set_metadata`]: RawFile::set_metadata
+    fn set_times(&self, meta: &RawFileMeta) -> Result<()> {
+        self.set_metadata(meta.clone())
+    }
+
     /// Returns the metadata of the file.
     fn metadata(&self) -> Result<RawFileMeta> {
         unimplemented!()
     }
+
+    /// Flushes written data to durable storage.
+    ///
+    /// Metadata kept by the filesystem (see [`RawFileSystem::flush`])
+    /// is not covered. Filesystems without such a notion (e.g.
+    /// in-memory ones) can keep the default, which does nothing.
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
+impl Sealed for ArcRawFileSystem {}
 impl RawFileSystem for ArcRawFileSystem {
     fn open(&self, id: FileId, flags: FileFlags) -> Result<Box<dyn RawFile + Send + Sync>> {
         self.as_ref().open(id, flags)
@@ -144,6 +240,27 @@ impl RawFileSystem for ArcRawFileSystem {
     fn write(&self, id: FileId, data: &[u8]) -> Result<()> {
         self.as_ref().write(id, data)
     }
+
+    fn flush(&self) -> Result<()> {
+        self.as_ref().flush()
+    }
+
+    fn probe(&self) -> Result<()> {
+        self.as_ref().probe()
+    }
+
+    fn backup(&self, dest: &std::path::Path) -> Result<()> {
+        self.as_ref().backup(dest)
+    }
+
+    fn list(&self, f: &mut dyn FnMut(FileId) -> Result<()>) -> Result<()> {
+        self.as_ref().list(f)
+    }
+
+    #[cfg(feature = "rocksdb")]
+    fn migrating(&self) -> Option<&MigratingFileSystem> {
+        self.as_ref().migrating()
+    }
 }
 
 /// Raw file metadata.
@@ -156,7 +273,7 @@ impl RawFileSystem for ArcRawFileSystem {
 /// [`FileMeta`]: crate::FileMeta
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct RawFileMeta {
-    pub size: u64,
+    pub size: CipherSize,
 
     #[serde(with = "time::opt_compact_date_time")]
     pub accessed: Option<DateTime<Utc>>,
@@ -168,7 +285,7 @@ impl RawFileMeta {
     pub fn create() -> Self {
         let now = Utc::now();
         Self {
-            size: 0,
+            size: CipherSize(0),
 
             accessed: Some(now),
             modified: Some(now),
@@ -177,7 +294,7 @@ impl RawFileMeta {
 
     pub fn from_std(meta: std::fs::Metadata) -> Self {
         Self {
-            size: meta.len(),
+            size: CipherSize(meta.len()),
 
             accessed: meta
                 .accessed()
@@ -195,7 +312,7 @@ impl RawFileMeta {
     #[cfg(feature = "opendal")]
     pub fn from_opendal(meta: ::opendal::Metadata) -> Self {
         Self {
-            size: meta.content_length(),
+            size: CipherSize(meta.content_length()),
 
             accessed: None,
             modified: meta.last_modified(),
@@ -207,10 +324,11 @@ impl RawFileMeta {
 /// [`RawFile::write_block`] in non-random-write filesystems,
 /// where file content is fully loaded into memory in order to
 /// be edited.
-fn write_vec_at(vec: &mut Vec<u8>, data: &[u8], block_end: usize, block: u64) {
-    let offset = data.len() * block as usize;
-    if offset > vec.len() {
+#[cfg(any(feature = "rocksdb", feature = "opendal"))]
+fn write_vec_at(vec: &mut Vec<u8>, data: &[u8], block_end: usize, block: BlockIndex) {
+    let offset = block.offset(data.len() as u64).0 as usize;
+    if offset + block_end > vec.len() {
         vec.resize(offset + block_end, 0);
     }
-    vec[offset..offset + block_end].copy_from_slice(data);
+    vec[offset..offset + block_end].copy_from_slice(&data[..block_end]);
 }
@@ -0,0 +1,181 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::{RawFile, RawFileMeta, RawFileSystem, Sealed};
+use crate::{
+    algo::{BlockIndex, CipherSize},
+    error::ErrorExt,
+    fs::{FileFlags, FileId},
+    Result,
+};
+use rand::{seq::SliceRandom, Rng};
+use std::{io, sync::Arc, time::Duration};
+
+/// Kinds of injected errors, which are mapped to different errnos.
+const ERROR_KINDS: &[io::ErrorKind] = &[
+    io::ErrorKind::TimedOut,
+    io::ErrorKind::ConnectionReset,
+    io::ErrorKind::Interrupted,
+    io::ErrorKind::Other,
+];
+
+struct Chaos {
+    failure_rate: f64,
+    latency: Duration,
+}
+impl Chaos {
+    /// Waits for a random time up to `latency`, and then fails
+    /// randomly.
+    fn strike(&self, op: &str) -> Result<()> {
+        let mut rng = rand::thread_rng();
+        if !self.latency.is_zero() {
+            std::thread::sleep(self.latency.mul_f64(rng.gen()));
+        }
+        if rng.gen_bool(self.failure_rate) {
+            let kind = *ERROR_KINDS.choose(&mut rng).unwrap();
+            return Err(io::Error::new(kind, format!("injected failure of {op}")).wrap());
+        }
+        Ok(())
+    }
+}
+
+/// A filesystem that injects random failures and delays into
+/// operations of the underlying filesystem, for testing how they are
+/// handled.
+///
+/// Every operation (on files as well) is delayed by up to `latency`,
+/// and fails before reaching the underlying filesystem with a
+/// probability of `failure_rate`.
+///
+/// This requires the `flaky` feature.
+pub struct FlakyFileSystem<FS: RawFileSystem> {
+    inner: FS,
+    chaos: Arc<Chaos>,
+}
+impl<FS: RawFileSystem> FlakyFileSystem<FS> {
+    /// Creates a flaky filesystem. `failure_rate` should be between 0
+    /// and 1.
+    pub fn new(inner: FS, failure_rate: f64, latency: Duration) -> Self {
+        assert!((0.0..=1.0).contains(&failure_rate));
+        Self {
+            inner,
+            chaos: Arc::new(Chaos {
+                failure_rate,
+                latency,
+            }),
+        }
+    }
+}
+impl<FS: RawFileSystem> Sealed for FlakyFileSystem<FS> {}
+impl<FS: RawFileSystem> RawFileSystem for FlakyFileSystem<FS> {
+    fn open(&self, id: FileId, flags: FileFlags) -> Result<Box<dyn RawFile + Send + Sync>> {
+        self.chaos.strike("open")?;
+        Ok(Box::new(FlakyFile {
+            inner: self.inner.open(id, flags)?,
+            chaos: Arc::clone(&self.chaos),
+        }))
+    }
+
+    fn create(&self, id: FileId) -> Result<()> {
+        self.chaos.strike("create")?;
+        self.inner.create(id)
+    }
+
+    fn exists(&self, id: FileId) -> Result<bool> {
+        self.chaos.strike("exists")?;
+        self.inner.exists(id)
+    }
+
+    fn unlink(&self, id: FileId) -> Result<()> {
+        self.chaos.strike("unlink")?;
+        self.inner.unlink(id)
+    }
+
+    fn stat(&self, id: FileId) -> Result<RawFileMeta> {
+        self.chaos.strike("stat")?;
+        self.inner.stat(id)
+    }
+
+    fn write(&self, id: FileId, data: &[u8]) -> Result<()> {
+        self.chaos.strike("write")?;
+        self.inner.write(id, data)
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.chaos.strike("flush")?;
+        self.inner.flush()
+    }
+
+    fn probe(&self) -> Result<()> {
+        self.chaos.strike("probe")?;
+        self.inner.probe()
+    }
+
+    fn backup(&self, dest: &std::path::Path) -> Result<()> {
+        self.inner.backup(dest)
+    }
+
+    fn list(&self, f: &mut dyn FnMut(FileId) -> Result<()>) -> Result<()> {
+        self.chaos.strike("list")?;
+        self.inner.list(f)
+    }
+}
+
+struct FlakyFile {
+    inner: Box<dyn RawFile + Send + Sync>,
+    chaos: Arc<Chaos>,
+}
+impl Sealed for FlakyFile {}
+impl RawFile for FlakyFile {
+    fn read_block(&self, data: &mut [u8], block: BlockIndex) -> Result<u64> {
+        self.chaos.strike("read_block")?;
+        self.inner.read_block(data, block)
+    }
+
+    fn write_block(&mut self, data: &[u8], block_end: usize, block: BlockIndex) -> Result<()> {
+        self.chaos.strike("write_block")?;
+        self.inner.write_block(data, block_end, block)
+    }
+
+    fn set_len(&mut self, len: CipherSize, block_size: u64) -> Result<()> {
+        self.chaos.strike("set_len")?;
+        self.inner.set_len(len, block_size)
+    }
+
+    fn reserve(&mut self, len: CipherSize, block_size: u64) -> Result<()> {
+        self.chaos.strike("reserve")?;
+        self.inner.reserve(len, block_size)
+    }
+
+    fn set_metadata(&self, meta: RawFileMeta) -> Result<()> {
+        // no IO for most filesystems
+        self.inner.set_metadata(meta)
+    }
+
+    fn set_times(&self, meta: &RawFileMeta) -> Result<()> {
+        self.chaos.strike("set_times")?;
+        self.inner.set_times(meta)
+    }
+
+    fn metadata(&self) -> Result<RawFileMeta> {
+        self.chaos.strike("metadata")?;
+        self.inner.metadata()
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.chaos.strike("sync")?;
+        self.inner.sync()
+    }
+}
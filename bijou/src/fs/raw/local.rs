@@ -13,10 +13,11 @@
 // limitations under the License.
 //
 
-use super::{RawFile, RawFileMeta, RawFileSystem};
+use super::{RawFile, RawFileMeta, RawFileSystem, Sealed};
 use crate::{
+    algo::{BlockIndex, CipherSize},
     error::{bail, ErrorExt},
-    fs::{FileFlags, FileId},
+    fs::{time, FileFlags, FileId},
     Context, ErrorKind, Result,
 };
 use std::{fs, io, path};
@@ -40,6 +41,7 @@ impl LocalFileSystem {
         Ok(dir.join(name))
     }
 }
+impl Sealed for LocalFileSystem {}
 impl RawFileSystem for LocalFileSystem {
     fn open(&self, id: FileId, flags: FileFlags) -> Result<Box<dyn RawFile + Send + Sync>> {
         Ok(Box::new(LocalFile::new(
@@ -83,6 +85,62 @@ impl RawFileSystem for LocalFileSystem {
             .kind(ErrorKind::IOError)?;
         Ok(())
     }
+
+    fn probe(&self) -> Result<()> {
+        fs::metadata(&self.root)
+            .context("local storage is unreachable")
+            .kind(ErrorKind::IOError)?;
+        Ok(())
+    }
+
+    fn backup(&self, dest: &path::Path) -> Result<()> {
+        // Files are modified in place, so hard links are not an option.
+        // `fs::copy` will make use of reflinks where supported.
+        fn copy_dir(from: &path::Path, to: &path::Path) -> io::Result<()> {
+            fs::create_dir(to)?;
+            for entry in fs::read_dir(from)? {
+                let entry = entry?;
+                let to = to.join(entry.file_name());
+                if entry.file_type()?.is_dir() {
+                    copy_dir(&entry.path(), &to)?;
+                } else {
+                    fs::copy(entry.path(), to)?;
+                }
+            }
+            Ok(())
+        }
+        copy_dir(&self.root, dest)
+            .context("failed to copy local files")
+            .kind(ErrorKind::IOError)
+    }
+
+    fn list(&self, f: &mut dyn FnMut(FileId) -> Result<()>) -> Result<()> {
+        fn wrap<T>(result: io::Result<T>) -> Result<T> {
+            result
+                .context("failed to list local files")
+                .kind(ErrorKind::IOError)
+        }
+
+        for dir in wrap(fs::read_dir(&self.root))? {
+            let dir = wrap(dir)?;
+            if !wrap(dir.file_type())?.is_dir() {
+                continue;
+            }
+            let prefix = dir.file_name();
+            for file in wrap(fs::read_dir(dir.path()))? {
+                // the hexadecimal ID split in two, see `path`
+                let name = format!(
+                    "{}{}",
+                    prefix.to_string_lossy(),
+                    wrap(file)?.file_name().to_string_lossy()
+                );
+                if let Ok(id) = name.parse() {
+                    f(id)?;
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 #[cfg(any(unix, windows))]
@@ -156,20 +214,20 @@ impl LocalFile {
     }
 }
 
+impl Sealed for LocalFile {}
 impl RawFile for LocalFile {
-    fn read_block(&self, data: &mut [u8], block: u64) -> Result<u64> {
+    fn read_block(&self, data: &mut [u8], block: BlockIndex) -> Result<u64> {
+        let offset = block.offset(data.len() as u64).0;
         #[allow(clippy::needless_borrow)]
         #[allow(clippy::unnecessary_mut_passed)]
-        Ok(
-            Self::read_at(&mut self.get_file(), data, block * data.len() as u64)
-                .context("failed to read from local file")
-                .kind(ErrorKind::IOError)? as u64,
-        )
+        Ok(Self::read_at(&mut self.get_file(), data, offset)
+            .context("failed to read from local file")
+            .kind(ErrorKind::IOError)? as u64)
     }
 
-    fn write_block(&mut self, data: &[u8], block_end: usize, block: u64) -> Result<()> {
+    fn write_block(&mut self, data: &[u8], block_end: usize, block: BlockIndex) -> Result<()> {
         let mut file = self.get_file();
-        let mut offset = block * data.len() as u64;
+        let mut offset = block.offset(data.len() as u64).0;
         let mut data = &data[..block_end];
         while !data.is_empty() {
             #[allow(clippy::needless_borrow)]
@@ -195,18 +253,62 @@ impl RawFile for LocalFile {
         Ok(())
     }
 
-    fn set_len(&mut self, len: u64, _block_size: u64) -> Result<()> {
+    fn set_len(&mut self, len: CipherSize, _block_size: u64) -> Result<()> {
         self.get_file()
-            .set_len(len)
+            .set_len(len.0)
             .context("failed to resize local file")
             .kind(ErrorKind::IOError)?;
         Ok(())
     }
 
+    #[cfg(target_os = "linux")]
+    fn reserve(&mut self, len: CipherSize, _block_size: u64) -> Result<()> {
+        use std::os::fd::AsRawFd;
+
+        let Ok(len) = libc::off_t::try_from(len.0) else {
+            bail!(@FileTooLarge "reserving too much space: {len}");
+        };
+        // the size is only changed by writes and `set_len`
+        let res = unsafe {
+            libc::fallocate(
+                self.get_file().as_raw_fd(),
+                libc::FALLOC_FL_KEEP_SIZE,
+                0,
+                len,
+            )
+        };
+        if res != 0 {
+            let err = io::Error::last_os_error();
+            // only a hint, which not every filesystem supports
+            if err.raw_os_error() != Some(libc::EOPNOTSUPP) {
+                return Err(err
+                    .wrap()
+                    .context("failed to reserve space for local file")
+                    .with_kind(ErrorKind::IOError));
+            }
+        }
+        Ok(())
+    }
+
     fn set_metadata(&self, _meta: RawFileMeta) -> Result<()> {
         Ok(())
     }
 
+    fn set_times(&self, meta: &RawFileMeta) -> Result<()> {
+        let mut times = fs::FileTimes::new();
+        if let Some(accessed) = &meta.accessed {
+            times = times.set_accessed(time::date_time_to_system_time(accessed));
+        }
+        if let Some(modified) = &meta.modified {
+            times = times.set_modified(time::date_time_to_system_time(modified));
+        }
+        self.get_file()
+            .set_times(times)
+            .context("failed to set times of local file")
+            .kind(ErrorKind::IOError)?;
+        Ok(())
+    }
+
     fn metadata(&self) -> Result<RawFileMeta> {
         Ok(RawFileMeta::from_std(
             self.get_file()
@@ -215,4 +317,11 @@ impl RawFile for LocalFile {
                 .kind(ErrorKind::IOError)?,
         ))
     }
+
+    fn sync(&self) -> Result<()> {
+        self.get_file()
+            .sync_data()
+            .context("failed to sync local file")
+            .kind(ErrorKind::IOError)
+    }
 }
@@ -0,0 +1,200 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::{ArcRawFileSystem, RawFile, RawFileMeta, RawFileSystem, Sealed};
+use crate::{
+    bail,
+    db::{consts, Database, DatabaseKey},
+    fs::{FileFlags, FileId},
+    Result,
+};
+use std::sync::Arc;
+
+/// Copies the raw file `id`, replacing any partial copy left by an
+/// interrupted run. Returns the number of bytes copied.
+fn copy(
+    from: &dyn RawFileSystem,
+    to: &dyn RawFileSystem,
+    id: FileId,
+    buffer: &mut [u8],
+) -> Result<u64> {
+    if to.exists(id)? {
+        to.unlink(id)?;
+    }
+    to.create(id)?;
+
+    let meta = from.stat(id)?;
+    let block_size = buffer.len() as u64;
+    let src = from.open(id, FileFlags::READ)?;
+    let mut dst = to.open(id, FileFlags::WRITE)?;
+    for block in meta.size.blocks(block_size) {
+        let block_end = src.read_block(buffer, block)? as usize;
+        // gaps are left as is
+        if block_end != 0 {
+            dst.write_block(buffer, block_end, block)?;
+        }
+    }
+    dst.set_len(meta.size, block_size)?;
+    dst.set_metadata(meta.clone())?;
+    dst.set_times(&meta)?;
+    dst.sync()?;
+    Ok(meta.size.0)
+}
+
+/// Checks that the raw file `id` is the same in both filesystems.
+fn compare(
+    from: &dyn RawFileSystem,
+    to: &dyn RawFileSystem,
+    id: FileId,
+    buffers: (&mut [u8], &mut [u8]),
+) -> Result<bool> {
+    let size = from.stat(id)?.size;
+    if to.stat(id)?.size != size {
+        return Ok(false);
+    }
+
+    let block_size = buffers.0.len() as u64;
+    let src = from.open(id, FileFlags::READ)?;
+    let dst = to.open(id, FileFlags::READ)?;
+    for block in size.blocks(block_size) {
+        let src_end = src.read_block(buffers.0, block)? as usize;
+        let dst_end = dst.read_block(buffers.1, block)? as usize;
+        if buffers.0[..src_end] != buffers.1[..dst_end] {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// A filesystem moving files from one filesystem to another.
+///
+/// Files are moved one by one with [`MigratingFileSystem::move_file`],
+/// which marks them as moved in the database. Moved files, as well as
+/// the ones created since, are only looked up in the new filesystem.
+/// Others are looked up in the old filesystem, falling back to the
+/// new one, but never read from the new one while it may hold a
+/// partial copy.
+pub struct MigratingFileSystem {
+    from: ArcRawFileSystem,
+    to: ArcRawFileSystem,
+    db: Arc<Database>,
+}
+impl MigratingFileSystem {
+    pub(crate) fn new(from: ArcRawFileSystem, to: ArcRawFileSystem, db: Arc<Database>) -> Self {
+        Self { from, to, db }
+    }
+
+    /// Returns the filesystem files are moved from.
+    pub fn from(&self) -> &ArcRawFileSystem {
+        &self.from
+    }
+
+    /// Returns the filesystem files are moved to.
+    pub fn to(&self) -> &ArcRawFileSystem {
+        &self.to
+    }
+
+    pub(crate) fn moved_key(db: &Database, id: FileId) -> DatabaseKey {
+        db.key(consts::Root::File)
+            .derive(id)
+            .derive(consts::Derive::Moved)
+    }
+
+    /// Checks if the file `id` has been moved to the new filesystem.
+    pub fn is_moved(&self, id: FileId) -> Result<bool> {
+        Self::moved_key(&self.db, id).exists()
+    }
+
+    /// Copies the file `id` to the new filesystem and verifies the
+    /// copy, after which the file is only looked up there. The file
+    /// in the old filesystem is left as is.
+    ///
+    /// `buffers` should both be of the block size. Returns the number
+    /// of bytes copied, or `None` if the file has already been moved.
+    pub fn move_file(&self, id: FileId, buffers: (&mut [u8], &mut [u8])) -> Result<Option<u64>> {
+        if self.is_moved(id)? {
+            return Ok(None);
+        }
+        let size = copy(&*self.from, &*self.to, id, buffers.0)?;
+        if !compare(&*self.from, &*self.to, id, buffers)? {
+            bail!(@IOError "copy of raw file {id} differs from the original");
+        }
+        // the copy must be persisted before the mark is
+        self.to.flush()?;
+        Self::moved_key(&self.db, id).write(b"")?;
+        Ok(Some(size))
+    }
+
+    /// Returns the filesystem holding the file `id`.
+    fn locate(&self, id: FileId) -> Result<&dyn RawFileSystem> {
+        Ok(if self.is_moved(id)? || !self.from.exists(id)? {
+            &*self.to
+        } else {
+            &*self.from
+        })
+    }
+}
+impl Sealed for MigratingFileSystem {}
+impl RawFileSystem for MigratingFileSystem {
+    fn open(&self, id: FileId, flags: FileFlags) -> Result<Box<dyn RawFile + Send + Sync>> {
+        self.locate(id)?.open(id, flags)
+    }
+
+    fn create(&self, id: FileId) -> Result<()> {
+        self.to.create(id)?;
+        Self::moved_key(&self.db, id).write(b"")
+    }
+
+    fn exists(&self, id: FileId) -> Result<bool> {
+        Ok(self.is_moved(id)? || self.from.exists(id)?)
+    }
+
+    fn unlink(&self, id: FileId) -> Result<()> {
+        if self.is_moved(id)? {
+            self.to.unlink(id)?;
+            return Self::moved_key(&self.db, id).delete();
+        }
+        if self.from.exists(id)? {
+            self.from.unlink(id)?;
+        }
+        // partial copy
+        if self.to.exists(id)? {
+            self.to.unlink(id)?;
+        }
+        Ok(())
+    }
+
+    fn stat(&self, id: FileId) -> Result<RawFileMeta> {
+        self.locate(id)?.stat(id)
+    }
+
+    fn write(&self, id: FileId, data: &[u8]) -> Result<()> {
+        self.locate(id)?.write(id, data)
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.from.flush()?;
+        self.to.flush()
+    }
+
+    fn probe(&self) -> Result<()> {
+        self.from.probe()?;
+        self.to.probe()
+    }
+
+    fn migrating(&self) -> Option<&MigratingFileSystem> {
+        Some(self)
+    }
+}
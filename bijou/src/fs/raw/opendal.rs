@@ -13,21 +13,76 @@
 // limitations under the License.
 //
 
-use super::{RawFile, RawFileMeta, RawFileSystem};
+use super::{RawFile, RawFileMeta, RawFileSystem, Sealed};
 use crate::{
+    algo::{BlockIndex, CipherSize},
     fs::{raw::write_vec_at, FileFlags, FileId},
     Result,
 };
+use dashmap::DashMap;
 use opendal::BlockingOperator;
-use std::sync::Arc;
-use tracing::warn;
+use std::{
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+
+/// Size of parts uploaded by multipart uploads.
+const PART_SIZE: usize = 8 << 20;
+/// Bytes read ahead of the block being read.
+const READ_AHEAD: u64 = 4 << 20;
+/// The number of ranges read-aheads are split into, which are read
+/// concurrently.
+const PARALLEL_READS: u64 = 4;
+/// Minimum interval between warnings about rewritten objects.
+const WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Warns about objects being rewritten in whole, at most once per
+/// [`WARNING_INTERVAL`].
+#[derive(Default)]
+struct RewriteWarning {
+    /// When the last warning was emitted, and the number of warnings
+    /// suppressed since.
+    state: Mutex<(Option<Instant>, u64)>,
+}
+impl RewriteWarning {
+    fn warn(&self, path: &str) {
+        let mut state = self.state.lock().unwrap();
+        let (last, suppressed) = &mut *state;
+        if last.is_some_and(|last| last.elapsed() < WARNING_INTERVAL) {
+            *suppressed += 1;
+            return;
+        }
+        warn!(
+            path,
+            suppressed = *suppressed,
+            "object rewritten in whole since OpenDAL does not support random writes, consider wrapping it in split storage with cluster_size 1"
+        );
+        *state = (Some(Instant::now()), 0);
+    }
+}
 
 /// A filesystem that uses OpenDAL as backend.
 ///
+/// Since objects can't be modified in place, a file being written is
+/// loaded into memory and uploaded once it's synced or closed, with
+/// multipart uploads if the service supports them. Reads fetch
+/// several blocks ahead with concurrent ranged reads. Handles of the
+/// same file share their state, so that they see each other's
+/// writes.
+///
+/// As the whole content of a file being written is held in memory,
+/// files larger than a few MB need this to be wrapped in
+/// [`SplitFileSystem`], which keeps each object to one cluster.
+///
 /// This is experimental and not recommended for production use.
+///
+/// [`SplitFileSystem`]: super::SplitFileSystem
 pub struct OpenDALFileSystem {
     operator: Arc<BlockingOperator>,
     prefix: String,
+    rewrite_warning: Arc<RewriteWarning>,
+    states: Arc<DashMap<FileId, Weak<Mutex<FileState>>>>,
 }
 
 impl OpenDALFileSystem {
@@ -35,14 +90,28 @@ impl OpenDALFileSystem {
         Self {
             operator: Arc::new(operator),
             prefix,
+            rewrite_warning: Arc::default(),
+            states: Arc::default(),
         }
     }
 
     fn path(&self, id: FileId) -> String {
         format!("{}{id}", self.prefix)
     }
+
+    /// Returns the state shared by the opened handles of `id`.
+    fn state(&self, id: FileId) -> Arc<Mutex<FileState>> {
+        let mut entry = self.states.entry(id).or_default();
+        if let Some(state) = entry.upgrade() {
+            return state;
+        }
+        let state = Arc::default();
+        *entry = Arc::downgrade(&state);
+        state
+    }
 }
 
+impl Sealed for OpenDALFileSystem {}
 impl RawFileSystem for OpenDALFileSystem {
     fn open(&self, id: FileId, flags: FileFlags) -> Result<Box<dyn RawFile + Send + Sync>> {
         if flags.has(FileFlags::TRUNCATE) {
@@ -50,7 +119,11 @@ impl RawFileSystem for OpenDALFileSystem {
         }
         Ok(Box::new(OpenDALFile {
             operator: Arc::clone(&self.operator),
+            id,
             path: self.path(id),
+            rewrite_warning: Arc::clone(&self.rewrite_warning),
+            states: Arc::clone(&self.states),
+            state: self.state(id),
         }))
     }
 
@@ -74,48 +147,218 @@ impl RawFileSystem for OpenDALFileSystem {
     }
 
     fn write(&self, id: FileId, data: &[u8]) -> Result<()> {
-        // TODO cache
-        self.operator.write(&self.path(id), data.to_vec())?;
+        upload(&self.operator, &self.path(id), data)?;
+        if let Some(state) = self.states.get(&id).and_then(|state| state.upgrade()) {
+            state.lock().unwrap().reset(data.len() as u64);
+        }
+        Ok(())
+    }
+
+    fn probe(&self) -> Result<()> {
+        // a missing prefix is fine, as long as the service answers
+        self.operator.is_exist(&self.prefix)?;
+        Ok(())
+    }
+}
+
+/// Uploads `data` as the whole content of `path`.
+fn upload(operator: &BlockingOperator, path: &str, data: &[u8]) -> Result<()> {
+    let multipart =
+        data.len() > PART_SIZE && operator.info().capability().write_without_content_length;
+    debug!(path, size = data.len(), multipart, "uploading object");
+    if !multipart {
+        operator.write(path, data.to_vec())?;
+        return Ok(());
+    }
+
+    let mut writer = operator.writer(path)?;
+    for part in data.chunks(PART_SIZE) {
+        writer.write(part.to_vec())?;
+    }
+    writer.close()?;
+    Ok(())
+}
+
+#[derive(Default)]
+struct FileState {
+    /// The whole content, loaded when the file is first written.
+    content: Option<Vec<u8>>,
+    /// Whether `content` differs from the object.
+    dirty: bool,
+    /// The size of the object, fetched when first needed.
+    size: Option<u64>,
+    /// The offset and content of the last read-ahead.
+    read_ahead: Option<(u64, Vec<u8>)>,
+    /// The size `content` is expected to grow to, see
+    /// [`RawFile::reserve`].
+    reserved: usize,
+}
+
+impl FileState {
+    /// Forgets everything cached after the object is replaced by one
+    /// of `size` bytes.
+    fn reset(&mut self, size: u64) {
+        *self = FileState {
+            size: Some(size),
+            reserved: self.reserved,
+            ..FileState::default()
+        };
+    }
+
+    /// Returns the content to be modified, loading it if needed.
+    fn content_mut(&mut self, operator: &BlockingOperator, path: &str) -> Result<&mut Vec<u8>> {
+        if self.content.is_none() {
+            // no need to download an empty object
+            let content = if self.size == Some(0) {
+                Vec::new()
+            } else {
+                operator.read(path)?
+            };
+            debug!(path, size = content.len(), "loaded object");
+            self.content = Some(content);
+            self.reserve();
+        }
+        self.read_ahead = None;
+        self.dirty = true;
+        Ok(self.content.as_mut().unwrap())
+    }
+
+    /// Allocates the memory of `content` ahead, so that it is not
+    /// reallocated over and over while growing.
+    fn reserve(&mut self) {
+        if let Some(content) = &mut self.content {
+            content.reserve(self.reserved.saturating_sub(content.len()));
+        }
+    }
+
+    fn flush(&mut self, operator: &BlockingOperator, path: &str) -> Result<()> {
+        if let Some(content) = self.content.as_ref().filter(|_| self.dirty) {
+            upload(operator, path, content)?;
+            self.size = Some(content.len() as u64);
+            self.dirty = false;
+        }
         Ok(())
     }
 }
 
 pub struct OpenDALFile {
     operator: Arc<BlockingOperator>,
+    id: FileId,
     path: String,
+    rewrite_warning: Arc<RewriteWarning>,
+    states: Arc<DashMap<FileId, Weak<Mutex<FileState>>>>,
+    state: Arc<Mutex<FileState>>,
 }
+impl OpenDALFile {
+    /// Reads `len` bytes from `offset` or until the end of the object,
+    /// splitting them into ranges that are read concurrently.
+    fn read_range(&self, state: &mut FileState, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let size = match state.size {
+            Some(size) => size,
+            None => *state
+                .size
+                .insert(self.operator.stat(&self.path)?.content_length()),
+        };
+        let end = size.min(offset + len);
+        if offset >= end {
+            return Ok(Vec::new());
+        }
+        debug!(path = %self.path, offset, len = end - offset, "reading ahead");
+
+        let step = (end - offset).div_ceil(PARALLEL_READS);
+        let parts = std::thread::scope(|scope| {
+            let handles = (offset..end)
+                .step_by(step as usize)
+                .map(|start| {
+                    let range = start..end.min(start + step);
+                    scope.spawn(move || self.operator.range_read(&self.path, range))
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+        Ok(parts.concat())
+    }
+}
+impl Sealed for OpenDALFile {}
 impl RawFile for OpenDALFile {
-    fn read_block(&self, data: &mut [u8], block: u64) -> Result<u64> {
+    fn read_block(&self, data: &mut [u8], block: BlockIndex) -> Result<u64> {
         let len = data.len() as u64;
-        let mut reader = self
-            .operator
-            .range_reader(&self.path, block * len..(block + 1) * len)?;
-        let res = reader.read(data)?;
-        dbg!(&data[..res]);
-        Ok(res as u64)
-    }
+        let offset = block.offset(len).0;
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
 
-    fn write_block(&mut self, data: &[u8], block_end: usize, block: u64) -> Result<()> {
-        warn!(
-            "OpenDAL does not support random write and thus is recommended to wrap it with SplitFileSystem with cluster_size=1"
-        );
+        let read_ahead = state.read_ahead.as_ref().is_some_and(|(start, content)| {
+            (*start..*start + content.len() as u64).contains(&offset)
+        });
+        if state.content.is_none() && !read_ahead {
+            let content = self.read_range(state, offset, READ_AHEAD.max(len))?;
+            state.read_ahead = Some((offset, content));
+        }
 
-        let mut vec = self.operator.read(&self.path)?;
-        write_vec_at(&mut vec, data, block_end, block);
-        self.operator.write(&self.path, vec)?;
+        let (start, content) = match &state.content {
+            Some(content) => (0, content),
+            None => {
+                let (start, content) = state.read_ahead.as_ref().unwrap();
+                (*start, content)
+            }
+        };
 
+        let content = content.get((offset - start) as usize..).unwrap_or_default();
+        let res = content.len().min(data.len());
+        data[..res].copy_from_slice(&content[..res]);
+        Ok(res as u64)
+    }
+
+    fn write_block(&mut self, data: &[u8], block_end: usize, block: BlockIndex) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let loaded = state.content.is_some();
+        let content = state.content_mut(&self.operator, &self.path)?;
+        if !loaded && !content.is_empty() {
+            self.rewrite_warning.warn(&self.path);
+        }
+        write_vec_at(content, data, block_end, block);
         Ok(())
     }
 
-    fn set_len(&mut self, len: u64, _block_size: u64) -> Result<()> {
-        let data = self.operator.range_read(&self.path, 0..len)?;
-        self.operator.write(&self.path, data)?;
+    fn set_len(&mut self, len: CipherSize, _block_size: u64) -> Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .content_mut(&self.operator, &self.path)?
+            .resize(len.0 as usize, 0);
+        Ok(())
+    }
 
+    fn reserve(&mut self, len: CipherSize, _block_size: u64) -> Result<()> {
+        // objects are uploaded in whole, so only the memory holding
+        // them can be allocated ahead
+        let mut state = self.state.lock().unwrap();
+        state.reserved = state.reserved.max(len.0 as usize);
+        state.reserve();
         Ok(())
     }
 
     fn metadata(&self) -> Result<RawFileMeta> {
+        self.sync()?;
         let meta = self.operator.stat(&self.path)?;
         Ok(RawFileMeta::from_opendal(meta))
     }
+
+    fn sync(&self) -> Result<()> {
+        self.state.lock().unwrap().flush(&self.operator, &self.path)
+    }
+}
+impl Drop for OpenDALFile {
+    fn drop(&mut self) {
+        // only a last resort, errors are reported by `sync`, which is
+        // called when the file is flushed
+        if let Err(err) = self.sync() {
+            warn!(path = %self.path, "failed to upload file: {err}");
+        }
+        self.states
+            .remove_if(&self.id, |_, state| state.strong_count() <= 1);
+    }
 }
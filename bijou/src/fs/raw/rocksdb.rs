@@ -13,11 +13,12 @@
 // limitations under the License.
 //
 
-use super::{RawFile, RawFileSystem};
+use super::{RawFile, RawFileMeta, RawFileSystem, Sealed};
 use crate::{
+    algo::{BlockIndex, CipherSize},
     db::{Database, DatabaseKey},
     fs::{raw::write_vec_at, FileFlags, FileId},
-    Result,
+    Context, ErrorKind, Result,
 };
 use std::sync::Arc;
 use tracing::warn;
@@ -30,11 +31,23 @@ pub struct RocksDBFileSystem {
 }
 
 impl RocksDBFileSystem {
-    pub fn new(db: Arc<Database>) -> Self {
+    /// Key of the key store of embedded Bijous (see
+    /// [`Config::embedded`]).
+    ///
+    /// Raw files are keyed by their 8-byte IDs, so keys of other
+    /// lengths are reserved.
+    ///
+    /// [`Config::embedded`]: crate::config::Config::embedded
+    pub(crate) const KEYSTORE_KEY: &'static [u8] = b"@keystore";
+    /// Key of the config of embedded Bijous.
+    pub(crate) const CONFIG_KEY: &'static [u8] = b"@config";
+
+    pub(crate) fn new(db: Arc<Database>) -> Self {
         Self { db }
     }
 }
 
+impl Sealed for RocksDBFileSystem {}
 impl RawFileSystem for RocksDBFileSystem {
     fn open(&self, id: FileId, flags: FileFlags) -> Result<Box<dyn RawFile + Send + Sync>> {
         if flags.has(FileFlags::TRUNCATE) {
@@ -57,29 +70,44 @@ impl RawFileSystem for RocksDBFileSystem {
         self.db.key(id).exists()
     }
 
+    fn stat(&self, id: FileId) -> Result<RawFileMeta> {
+        let size = self.db.key(id).read()?.kind(ErrorKind::NotFound)?.len();
+        // times are not recorded
+        Ok(RawFileMeta {
+            size: CipherSize(size as u64),
+            accessed: None,
+            modified: None,
+        })
+    }
+
     fn write(&self, id: FileId, data: &[u8]) -> Result<()> {
         self.db.key(id).write(data)
     }
+
+    fn backup(&self, dest: &std::path::Path) -> Result<()> {
+        self.db.checkpoint(dest)
+    }
 }
 
 pub struct RocksDBFile {
     key: DatabaseKey,
 }
+impl Sealed for RocksDBFile {}
 impl RawFile for RocksDBFile {
-    fn read_block(&self, data: &mut [u8], block: u64) -> Result<u64> {
+    fn read_block(&self, data: &mut [u8], block: BlockIndex) -> Result<u64> {
         let Some(slice) = self.key.read()? else {
             return Ok(0);
         };
-        let offset = data.len() * block as usize;
+        let offset = block.offset(data.len() as u64).0 as usize;
         if offset > slice.len() {
             return Ok(0);
         }
-        let len = slice.len().saturating_sub(offset);
+        let len = (slice.len() - offset).min(data.len());
         data[..len].copy_from_slice(&slice[offset..offset + len]);
         Ok(len as u64)
     }
 
-    fn write_block(&mut self, data: &[u8], block_end: usize, block: u64) -> Result<()> {
+    fn write_block(&mut self, data: &[u8], block_end: usize, block: BlockIndex) -> Result<()> {
         warn!(
             "RocksDB does not support random write and thus is recommended to wrap it with SplitFileSystem with cluster_size=1"
         );
@@ -89,8 +117,14 @@ impl RawFile for RocksDBFile {
         self.key.write(&vec)
     }
 
-    fn set_len(&mut self, len: u64, _block_size: u64) -> Result<()> {
-        let slice = self.key.read()?.unwrap();
-        self.key.write(&slice[..len as usize])
+    fn set_len(&mut self, len: CipherSize, _block_size: u64) -> Result<()> {
+        let mut vec = self.key.read_owned()?.unwrap();
+        vec.resize(len.0 as usize, 0);
+        self.key.write(&vec)
+    }
+
+    fn set_metadata(&self, _meta: RawFileMeta) -> Result<()> {
+        // the size is derived from the value
+        Ok(())
     }
 }
@@ -13,34 +13,29 @@
 // limitations under the License.
 //
 
-use super::{RawFile, RawFileSystem};
+use super::{RawFile, RawFileMeta, RawFileSystem, Sealed};
 use crate::{
+    algo::{BlockIndex, CipherSize},
     cache::{CachedStorage, CachedStorageKey},
+    config::ClusterNaming,
     db::{consts, Database},
+    format::FileClusters,
     fs::{FileFlags, FileId},
+    refcount::RefCounter,
+    sodium::generic_hash,
     Result,
 };
-use serde::{Deserialize, Serialize};
-use std::{
-    collections::BTreeMap,
-    sync::{Arc, Mutex, MutexGuard},
-};
+use std::sync::{Arc, Mutex, MutexGuard};
 
-// TODO optimize
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
-struct FileClusters {
-    ids: Vec<FileId>,
-    sparse: BTreeMap<u64, FileId>,
-}
 impl FileClusters {
-    pub fn get(&self, block: u64) -> Option<FileId> {
+    pub(crate) fn get(&self, block: u64) -> Option<FileId> {
         self.ids
             .get(block as usize)
             .or_else(|| self.sparse.get(&block))
             .copied()
     }
 
-    pub fn insert(&mut self, block: u64, id: FileId) {
+    pub(crate) fn insert(&mut self, block: u64, id: FileId) {
         if self.ids.len() == block as usize {
             self.ids.push(id);
             while self.sparse.first_key_value().map(|it| *it.0) == Some(self.ids.len() as u64) {
@@ -52,15 +47,25 @@ impl FileClusters {
         }
     }
 
-    pub fn truncate(&mut self, blocks: u64) -> impl Iterator<Item = FileId> + '_ {
+    pub(crate) fn truncate(&mut self, blocks: u64) -> impl Iterator<Item = FileId> + '_ {
         self.ids
             .drain(self.ids.len().min(blocks as usize)..)
             .chain(self.sparse.split_off(&blocks).into_values())
     }
 
-    pub fn into_values(self) -> impl Iterator<Item = FileId> {
+    pub(crate) fn into_values(self) -> impl Iterator<Item = FileId> {
         self.ids.into_iter().chain(self.sparse.into_values())
     }
+
+    /// Returns the index and the ID of the last cluster.
+    pub(crate) fn last(&self) -> Option<(u64, FileId)> {
+        match self.sparse.last_key_value() {
+            Some((cluster, id)) => Some((*cluster, *id)),
+            None => (self.ids.len() as u64)
+                .checked_sub(1)
+                .map(|cluster| (cluster, self.ids[cluster as usize])),
+        }
+    }
 }
 
 /// A filesystem that splits files into clusters.
@@ -71,33 +76,109 @@ impl FileClusters {
 ///
 /// Lower `cluster_size` implies better file size obfuscation, but also
 /// a higher overhead (both performance and storage).
+///
+/// Clusters are named randomly by default. See [`ClusterNaming`].
+///
+/// The size of a file is computed from the number of its clusters and
+/// the size of the last one. Times are the ones of the last cluster,
+/// which are only accurate when set explicitly, so this should be
+/// wrapped in [`TrackingFileSystem`] if times matter.
+///
+/// [`TrackingFileSystem`]: super::TrackingFileSystem
 pub struct SplitFileSystem<FS: RawFileSystem> {
     inner: Arc<FS>,
     cluster_size: u64,
+    block_size: u64,
+    naming: Arc<ClusterNaming>,
     clusters: Arc<CachedStorage<FileClusters>>,
+    refs: Arc<RefCounter>,
 }
 impl<FS: RawFileSystem> SplitFileSystem<FS> {
-    pub fn new(inner: FS, db: Arc<Database>, cluster_size: u64) -> Self {
+    /// Creates a filesystem with clusters of `cluster_size` blocks,
+    /// each of which is `block_size` bytes long.
+    pub(crate) fn new(inner: FS, db: Arc<Database>, cluster_size: u64, block_size: u64) -> Self {
         Self {
             inner: Arc::new(inner),
             cluster_size,
-            clusters: Arc::new(CachedStorage::new(db, consts::BLOCKS_DERIVE)),
+            block_size,
+            naming: Arc::default(),
+            clusters: Arc::new(CachedStorage::new(Arc::clone(&db), consts::Derive::Blocks)),
+            refs: Arc::new(RefCounter::new(db)),
         }
     }
+
+    /// Sets how clusters are named. This must not change once
+    /// clusters are stored.
+    pub fn naming(mut self, naming: ClusterNaming) -> Self {
+        self.naming = Arc::new(naming);
+        self
+    }
 }
+
+/// Derives the ID of a cluster from the file it belongs to and its
+/// index, see [`ClusterNaming::Keyed`].
+fn keyed_cluster_id(key: &[u8], file: FileId, cluster: u64) -> Result<FileId> {
+    let mut input = [0; 17];
+    input[..8].copy_from_slice(file.as_ref());
+    input[8..16].copy_from_slice(&cluster.to_le_bytes());
+    loop {
+        // the minimum output length of BLAKE2b in libsodium
+        let mut output = [0; 16];
+        generic_hash::hash(&mut output, &input, Some(key))?;
+        let id = u64::from_le_bytes(output[..8].try_into().unwrap());
+        // reserved IDs, see `FileId::gen`
+        if !matches!(id, 0 | 1 | u64::MAX) {
+            return Ok(FileId::from_bytes(&output[..8]));
+        }
+        input[16] += 1;
+    }
+}
+
+/// Returns the metadata of a file made of `clusters`, each of which
+/// is `cluster_len` bytes long except the last one.
+fn stat_clusters(
+    fs: &impl RawFileSystem,
+    clusters: &FileClusters,
+    cluster_len: u64,
+) -> Result<RawFileMeta> {
+    let Some((cluster, id)) = clusters.last() else {
+        return Ok(RawFileMeta::default());
+    };
+    let meta = fs.stat(id)?;
+    Ok(RawFileMeta {
+        size: CipherSize(cluster * cluster_len) + meta.size,
+        ..meta
+    })
+}
+
+/// Drops a reference to a cluster, removing it if there are
+/// no more references.
+fn release_cluster(fs: &impl RawFileSystem, refs: &RefCounter, id: FileId) -> Result<()> {
+    if refs.release(id)? {
+        fs.unlink(id)?;
+    }
+    Ok(())
+}
+impl<FS: RawFileSystem> Sealed for SplitFileSystem<FS> {}
 impl<FS: RawFileSystem + Send + Sync + 'static> RawFileSystem for SplitFileSystem<FS> {
     fn open(&self, id: FileId, flags: FileFlags) -> Result<Box<dyn RawFile + Send + Sync>> {
         let key = self.clusters.key(id)?;
         if flags.has(FileFlags::TRUNCATE) {
             let mut blocks = key.write();
-            *blocks = FileClusters::default();
+            for id in std::mem::take(&mut *blocks).into_values() {
+                release_cluster(self.inner.as_ref(), &self.refs, id)?;
+            }
             key.update(blocks);
         }
 
         Ok(Box::new(SplitFile {
+            id,
             fs: Arc::clone(&self.inner),
+            naming: Arc::clone(&self.naming),
+            refs: Arc::clone(&self.refs),
             flags: flags.remove(FileFlags::TRUNCATE),
             cluster_size: self.cluster_size,
+            block_size: self.block_size,
             key,
             current_file: Mutex::default(),
         }))
@@ -112,24 +193,50 @@ impl<FS: RawFileSystem + Send + Sync + 'static> RawFileSystem for SplitFileSyste
         self.clusters.exists(id)
     }
 
+    fn stat(&self, id: FileId) -> Result<RawFileMeta> {
+        let clusters = self.clusters.stat(id)?;
+        stat_clusters(
+            self.inner.as_ref(),
+            &clusters,
+            self.cluster_size * self.block_size,
+        )
+    }
+
     fn unlink(&self, id: FileId) -> Result<()> {
         let clusters = self.clusters.stat(id)?;
         self.clusters.delete(id)?;
         for id in clusters.into_values() {
-            self.inner.unlink(id)?;
+            release_cluster(self.inner.as_ref(), &self.refs, id)?;
         }
 
         Ok(())
     }
+
+    fn flush(&self) -> Result<()> {
+        self.clusters.flush()?;
+        self.inner.flush()
+    }
+
+    fn probe(&self) -> Result<()> {
+        self.inner.probe()
+    }
+
+    fn backup(&self, dest: &std::path::Path) -> Result<()> {
+        self.inner.backup(dest)
+    }
 }
 
 type BoxRawFile = Box<dyn RawFile + Send + Sync>;
 type CurrentFile = Option<(u64, BoxRawFile)>;
 
 struct SplitFile<FS: RawFileSystem> {
+    id: FileId,
     fs: Arc<FS>,
+    naming: Arc<ClusterNaming>,
+    refs: Arc<RefCounter>,
     flags: FileFlags,
     cluster_size: u64,
+    block_size: u64,
     key: CachedStorageKey<FileClusters>,
     // TODO better cache
     current_file: Mutex<CurrentFile>,
@@ -140,10 +247,16 @@ impl<FS: RawFileSystem> SplitFile<FS> {
         Ok(if let Some(id) = clusters.get(cluster) {
             id
         } else {
-            let mut id = FileId::gen();
-            while self.fs.exists(id)? {
-                id = FileId::gen();
-            }
+            let id = match &*self.naming {
+                ClusterNaming::Random => {
+                    let mut id = FileId::gen();
+                    while self.fs.exists(id)? {
+                        id = FileId::gen();
+                    }
+                    id
+                }
+                ClusterNaming::Keyed { key } => keyed_cluster_id(key, self.id, cluster)?,
+            };
             self.fs.create(id)?;
             clusters.insert(cluster, id);
             self.key.update(clusters);
@@ -151,9 +264,9 @@ impl<FS: RawFileSystem> SplitFile<FS> {
         })
     }
 
-    fn open(&self, block: u64) -> Result<(MutexGuard<CurrentFile>, u64)> {
-        let cluster = block / self.cluster_size;
-        let block = block % self.cluster_size;
+    fn open(&self, block: BlockIndex) -> Result<(MutexGuard<'_, CurrentFile>, BlockIndex)> {
+        let cluster = block.0 / self.cluster_size;
+        let block = BlockIndex(block.0 % self.cluster_size);
         let mut current_file = self.current_file.lock().unwrap();
         if current_file.as_ref().map(|it| it.0) != Some(cluster) {
             let file = self.fs.open(self.cluster_id(cluster)?, self.flags)?;
@@ -164,33 +277,90 @@ impl<FS: RawFileSystem> SplitFile<FS> {
     }
 }
 
+impl<FS: RawFileSystem> Sealed for SplitFile<FS> {}
 impl<FS: RawFileSystem> RawFile for SplitFile<FS> {
-    fn read_block(&self, data: &mut [u8], block: u64) -> Result<u64> {
+    fn read_block(&self, data: &mut [u8], block: BlockIndex) -> Result<u64> {
         let (mut file, block) = self.open(block)?;
         file.as_mut().unwrap().1.read_block(data, block)
     }
 
-    fn write_block(&mut self, data: &[u8], block_end: usize, block: u64) -> Result<()> {
+    fn write_block(&mut self, data: &[u8], block_end: usize, block: BlockIndex) -> Result<()> {
         if self.cluster_size == 1 {
-            return self.fs.write(self.cluster_id(block)?, &data[..block_end]);
+            return self.fs.write(self.cluster_id(block.0)?, &data[..block_end]);
         }
         let (mut file, block) = self.open(block)?;
         file.as_mut().unwrap().1.write_block(data, block_end, block)
     }
 
-    fn set_len(&mut self, len: u64, block_size: u64) -> Result<()> {
-        let blocks = len / block_size;
-        let offset = len % block_size;
+    fn set_len(&mut self, len: CipherSize, block_size: u64) -> Result<()> {
+        let cluster_len = self.cluster_size * block_size;
+        let count = len.0.div_ceil(cluster_len);
 
         let mut clusters = self.key.write();
-        for id in clusters.truncate(blocks + 1) {
-            self.fs.unlink(id)?;
-        }
-        if let Some(id) = clusters.get(blocks) {
-            self.fs.open(id, self.flags)?.set_len(offset, block_size)?;
+        for id in clusters.truncate(count) {
+            release_cluster(self.fs.as_ref(), &self.refs, id)?;
         }
         self.key.update(clusters);
+        // might have been released
+        *self.current_file.get_mut().unwrap() = None;
+
+        // the last cluster determines the size, so it's created if
+        // missing
+        if let Some(last) = count.checked_sub(1) {
+            self.fs
+                .open(self.cluster_id(last)?, self.flags)?
+                .set_len(len - CipherSize(last * cluster_len), block_size)?;
+        }
+
+        Ok(())
+    }
+
+    fn reserve(&mut self, len: CipherSize, block_size: u64) -> Result<()> {
+        // Clusters can't be created ahead since the last one
+        // determines the size, so only the last one is filled up.
+        let cluster_len = self.cluster_size * block_size;
+        let Some((last, id)) = self.key.write().last() else {
+            return Ok(());
+        };
+        let start = CipherSize(last * cluster_len);
+        if start < len {
+            self.fs
+                .open(id, self.flags)?
+                .reserve((len - start).min(CipherSize(cluster_len)), block_size)?;
+        }
+        Ok(())
+    }
+
+    fn set_metadata(&self, _meta: RawFileMeta) -> Result<()> {
+        // the size is derived from clusters
+        Ok(())
+    }
 
+    fn set_times(&self, meta: &RawFileMeta) -> Result<()> {
+        let Some((_, id)) = self.key.write().last() else {
+            return Ok(());
+        };
+        let mut cluster_meta = self.fs.stat(id)?;
+        cluster_meta.accessed = meta.accessed;
+        cluster_meta.modified = meta.modified;
+        self.fs.open(id, FileFlags::READ)?.set_times(&cluster_meta)
+    }
+
+    fn metadata(&self) -> Result<RawFileMeta> {
+        let clusters = self.key.write().clone();
+        stat_clusters(
+            self.fs.as_ref(),
+            &clusters,
+            self.cluster_size * self.block_size,
+        )
+    }
+
+    fn sync(&self) -> Result<()> {
+        // clusters written through other handles are synced as well
+        let clusters = self.key.write().clone();
+        for id in clusters.into_values() {
+            self.fs.open(id, FileFlags::READ)?.sync()?;
+        }
         Ok(())
     }
 }
@@ -13,8 +13,9 @@
 // limitations under the License.
 //
 
-use super::{RawFile, RawFileMeta, RawFileSystem};
+use super::{RawFile, RawFileMeta, RawFileSystem, Sealed};
 use crate::{
+    algo::{BlockIndex, CipherSize},
     cache::{CachedStorage, CachedStorageKey},
     db::{consts, Database},
     fs::{FileFlags, FileId},
@@ -34,19 +35,20 @@ pub struct TrackingFileSystem<FS: RawFileSystem> {
     metas: Arc<CachedStorage<RawFileMeta>>,
 }
 impl<FS: RawFileSystem> TrackingFileSystem<FS> {
-    pub fn new(inner: FS, db: Arc<Database>) -> Self {
+    pub(crate) fn new(inner: FS, db: Arc<Database>) -> Self {
         Self {
             inner,
-            metas: Arc::new(CachedStorage::new(db, consts::TRACKING_DERIVE)),
+            metas: Arc::new(CachedStorage::new(db, consts::Derive::Tracking)),
         }
     }
 }
+impl<FS: RawFileSystem> Sealed for TrackingFileSystem<FS> {}
 impl<FS: RawFileSystem> RawFileSystem for TrackingFileSystem<FS> {
     fn open(&self, id: FileId, flags: FileFlags) -> Result<Box<dyn RawFile + Send + Sync>> {
         let key = self.metas.key(id)?;
         let mut meta = key.write();
         if flags.has(FileFlags::TRUNCATE) {
-            meta.size = 0;
+            meta.size = CipherSize(0);
         }
         if flags.has(FileFlags::READ) {
             meta.accessed = Some(Utc::now());
@@ -87,31 +89,53 @@ impl<FS: RawFileSystem> RawFileSystem for TrackingFileSystem<FS> {
 
         let key = self.metas.key(id)?;
         let mut meta = key.write();
-        meta.size = data.len() as u64;
+        meta.size = CipherSize(data.len() as u64);
         meta.modified = Some(Utc::now());
         key.update(meta);
 
         Ok(())
     }
+
+    fn flush(&self) -> Result<()> {
+        self.metas.flush()?;
+        self.inner.flush()
+    }
+
+    fn probe(&self) -> Result<()> {
+        self.inner.probe()
+    }
+
+    fn backup(&self, dest: &std::path::Path) -> Result<()> {
+        self.inner.backup(dest)
+    }
+
+    fn list(&self, f: &mut dyn FnMut(FileId) -> Result<()>) -> Result<()> {
+        self.inner.list(f)
+    }
 }
 
 struct TrackingFile {
     inner: Box<dyn RawFile + Send + Sync>,
     key: CachedStorageKey<RawFileMeta>,
 }
+impl Sealed for TrackingFile {}
 impl RawFile for TrackingFile {
-    fn read_block(&self, data: &mut [u8], block: u64) -> Result<u64> {
+    fn read_block(&self, data: &mut [u8], block: BlockIndex) -> Result<u64> {
         self.inner.read_block(data, block)
     }
 
-    fn write_block(&mut self, data: &[u8], block_end: usize, block: u64) -> Result<()> {
+    fn write_block(&mut self, data: &[u8], block_end: usize, block: BlockIndex) -> Result<()> {
         self.inner.write_block(data, block_end, block)
     }
 
-    fn set_len(&mut self, len: u64, block_size: u64) -> Result<()> {
+    fn set_len(&mut self, len: CipherSize, block_size: u64) -> Result<()> {
         self.inner.set_len(len, block_size)
     }
 
+    fn reserve(&mut self, len: CipherSize, block_size: u64) -> Result<()> {
+        self.inner.reserve(len, block_size)
+    }
+
     fn set_metadata(&self, its_meta: RawFileMeta) -> Result<()> {
         let mut meta = self.key.write();
         *meta = its_meta;
@@ -122,4 +146,8 @@ impl RawFile for TrackingFile {
     fn metadata(&self) -> Result<RawFileMeta> {
         Ok(self.key.write().clone())
     }
+
+    fn sync(&self) -> Result<()> {
+        self.inner.sync()
+    }
 }
@@ -0,0 +1,215 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::{raw::sealed::Sealed, FileId, RawFile, RawFileMeta};
+use crate::{
+    algo::{BlockIndex, CipherSize},
+    Result,
+};
+use serde::Serialize;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+/// Statistics of a single file handle, returned by
+/// [`LowLevelFile::stats`].
+///
+/// Latencies are of whole reads and writes, in microseconds, and
+/// rounded up to one less than a power of two (but never beyond the
+/// maximum).
+///
+/// [`LowLevelFile::stats`]: crate::low_level::LowLevelFile::stats
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileStats {
+    pub reads: u64,
+    pub bytes_read: u64,
+    pub writes: u64,
+    pub bytes_written: u64,
+    pub latency_p50: u64,
+    pub latency_p90: u64,
+    pub latency_p99: u64,
+    pub latency_max: u64,
+}
+
+/// Atomic counters behind [`FileStats`].
+#[derive(Debug, Default)]
+pub(crate) struct FileCounters {
+    reads: AtomicU64,
+    bytes_read: AtomicU64,
+    writes: AtomicU64,
+    bytes_written: AtomicU64,
+    /// Number of operations whose latency in microseconds has `i`
+    /// significant bits, at index `i`. The last bucket also counts
+    /// longer operations.
+    latencies: [AtomicU64; Self::BUCKETS],
+    max_latency: AtomicU64,
+}
+
+impl FileCounters {
+    const BUCKETS: usize = 32;
+
+    fn record_latency(&self, elapsed: Duration) {
+        let micros = elapsed.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = ((u64::BITS - micros.leading_zeros()) as usize).min(Self::BUCKETS - 1);
+        self.latencies[bucket].fetch_add(1, Ordering::Relaxed);
+        self.max_latency.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    pub fn record_read(&self, bytes: u64, elapsed: Duration) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+        self.record_latency(elapsed);
+    }
+
+    pub fn record_write(&self, bytes: u64, elapsed: Duration) {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+        self.record_latency(elapsed);
+    }
+
+    pub fn snapshot(&self) -> FileStats {
+        let buckets: Vec<u64> = self
+            .latencies
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect();
+        let max = self.max_latency.load(Ordering::Relaxed);
+        let total: u64 = buckets.iter().sum();
+        let percentile = |p: u64| {
+            let rank = (total * p).div_ceil(100).max(1);
+            let mut seen = 0;
+            for (bucket, count) in buckets.iter().enumerate() {
+                seen += count;
+                if seen >= rank {
+                    let bound = if bucket == Self::BUCKETS - 1 {
+                        u64::MAX
+                    } else {
+                        (1 << bucket) - 1
+                    };
+                    return bound.min(max);
+                }
+            }
+            0
+        };
+        FileStats {
+            reads: self.reads.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            latency_p50: percentile(50),
+            latency_p90: percentile(90),
+            latency_p99: percentile(99),
+            latency_max: max,
+        }
+    }
+}
+
+/// A raw file logging block IO slower than a threshold, along with
+/// the file and the storage backend. See
+/// [`Config::slow_op_threshold`].
+///
+/// [`Config::slow_op_threshold`]: crate::Config::slow_op_threshold
+pub(crate) struct SlowOpLog {
+    inner: Box<dyn RawFile + Send + Sync>,
+    id: FileId,
+    backend: Arc<str>,
+    threshold: Duration,
+}
+
+impl SlowOpLog {
+    pub fn new(
+        inner: Box<dyn RawFile + Send + Sync>,
+        id: FileId,
+        backend: Arc<str>,
+        threshold: Duration,
+    ) -> Self {
+        Self {
+            inner,
+            id,
+            backend,
+            threshold,
+        }
+    }
+
+    /// Logs the operation `op` started at `start` if it is slow.
+    fn check(&self, op: &str, block: Option<u64>, start: Instant) {
+        let elapsed = start.elapsed();
+        if elapsed >= self.threshold {
+            warn!(
+                id = %self.id,
+                backend = %self.backend,
+                ?block,
+                ?elapsed,
+                "slow {op}"
+            );
+        }
+    }
+}
+
+impl Sealed for SlowOpLog {}
+impl RawFile for SlowOpLog {
+    fn read_block(&self, data: &mut [u8], block: BlockIndex) -> Result<u64> {
+        let start = Instant::now();
+        let result = self.inner.read_block(data, block);
+        self.check("read", Some(block.0), start);
+        result
+    }
+
+    fn write_block(&mut self, data: &[u8], block_end: usize, block: BlockIndex) -> Result<()> {
+        let start = Instant::now();
+        let result = self.inner.write_block(data, block_end, block);
+        self.check("write", Some(block.0), start);
+        result
+    }
+
+    fn set_len(&mut self, len: CipherSize, block_size: u64) -> Result<()> {
+        let start = Instant::now();
+        let result = self.inner.set_len(len, block_size);
+        self.check("resize", None, start);
+        result
+    }
+
+    fn reserve(&mut self, len: CipherSize, block_size: u64) -> Result<()> {
+        let start = Instant::now();
+        let result = self.inner.reserve(len, block_size);
+        self.check("reserve", None, start);
+        result
+    }
+
+    fn set_metadata(&self, meta: RawFileMeta) -> Result<()> {
+        self.inner.set_metadata(meta)
+    }
+
+    fn set_times(&self, meta: &RawFileMeta) -> Result<()> {
+        self.inner.set_times(meta)
+    }
+
+    fn metadata(&self) -> Result<RawFileMeta> {
+        self.inner.metadata()
+    }
+
+    fn sync(&self) -> Result<()> {
+        let start = Instant::now();
+        let result = self.inner.sync();
+        self.check("sync", None, start);
+        result
+    }
+}
@@ -13,6 +13,10 @@
 // limitations under the License.
 //
 
+#[cfg(feature = "rocksdb")]
+pub use bijou_types::time::compact_date_time;
+pub use bijou_types::time::opt_compact_date_time;
+
 use chrono::{DateTime, TimeZone, Utc};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
@@ -52,49 +56,3 @@ pub fn date_time_to_system_time(t: &DateTime<Utc>) -> SystemTime {
     let (secs, nsecs) = (t.timestamp(), t.timestamp_subsec_nanos());
     tuple_to_system_time((secs, nsecs))
 }
-
-pub fn unix_epoch_date_time() -> DateTime<Utc> {
-    Utc.timestamp_opt(0, 0).unwrap()
-}
-
-pub mod compact_date_time {
-    use chrono::{DateTime, TimeZone, Utc};
-    use serde::{Deserialize, Deserializer, Serialize, Serializer};
-
-    pub fn serialize<S>(val: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let tuple = (val.timestamp(), val.timestamp_subsec_nanos());
-        tuple.serialize(serializer)
-    }
-
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let (secs, nsecs) = <(i64, u32)>::deserialize(deserializer)?;
-        Ok(Utc.timestamp_opt(secs, nsecs).unwrap())
-    }
-}
-
-pub mod opt_compact_date_time {
-    use chrono::{DateTime, TimeZone, Utc};
-    use serde::{Deserialize, Deserializer, Serialize, Serializer};
-
-    pub fn serialize<S>(val: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let tuple = val.map(|val| (val.timestamp(), val.timestamp_subsec_nanos()));
-        tuple.serialize(serializer)
-    }
-
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let tuple = <Option<(i64, u32)>>::deserialize(deserializer)?;
-        Ok(tuple.map(|it| Utc.timestamp_opt(it.0, it.1).unwrap()))
-    }
-}
@@ -0,0 +1,109 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Allocation of IDs for new files.
+//!
+//! IDs are 64 bits wide. Collisions with existing files are detected
+//! and retried, so the birthday bound only affects how often a retry
+//! is needed, not correctness.
+
+use crate::{bail, fs::Inode, FileId, Result};
+use std::{collections::HashSet, sync::Mutex};
+
+/// Generates candidate IDs for new files, see
+/// [`Bijou::set_id_generator`].
+///
+/// Candidates are not required to be unique: collisions are checked
+/// and another candidate is requested. Candidates reserved for
+/// internal use (see [`Inode::from_file_id`]) are skipped as well.
+///
+/// [`Bijou::set_id_generator`]: crate::Bijou::set_id_generator
+pub trait IdGenerator: Send + Sync {
+    fn generate(&self) -> FileId;
+}
+
+/// The default [`IdGenerator`], returning random IDs.
+pub struct RandomIds;
+impl IdGenerator for RandomIds {
+    fn generate(&self) -> FileId {
+        FileId::gen()
+    }
+}
+
+/// Hands out IDs that are neither used by existing files nor
+/// reserved by concurrent creators.
+pub struct IdAllocator {
+    generator: Box<dyn IdGenerator>,
+    reserved: Mutex<HashSet<FileId>>,
+}
+
+impl IdAllocator {
+    /// The maximum number of candidates tried before giving up.
+    const MAX_ATTEMPTS: usize = 64;
+
+    pub fn new(generator: Box<dyn IdGenerator>) -> Self {
+        Self {
+            generator,
+            reserved: Mutex::default(),
+        }
+    }
+
+    pub fn set_generator(&mut self, generator: Box<dyn IdGenerator>) {
+        self.generator = generator;
+    }
+
+    /// Reserves an ID for which `is_used` returns `false`.
+    ///
+    /// The ID will not be handed out again until the returned
+    /// [`Reservation`] is dropped, which should happen after the
+    /// file has been persisted, so that `is_used` sees it.
+    pub fn reserve(&self, is_used: impl Fn(FileId) -> Result<bool>) -> Result<Reservation<'_>> {
+        for _ in 0..Self::MAX_ATTEMPTS {
+            let id = self.generator.generate();
+            if id == FileId::ROOT || Inode::from_file_id(id).is_none() {
+                continue;
+            }
+            if !self.reserved.lock().unwrap().insert(id) {
+                continue;
+            }
+            let reservation = Reservation {
+                allocator: self,
+                id,
+            };
+            if !is_used(id)? {
+                return Ok(reservation);
+            }
+        }
+        bail!(@AlreadyExists "failed to allocate a file ID after {} attempts", Self::MAX_ATTEMPTS);
+    }
+}
+
+/// An ID reserved by [`IdAllocator::reserve`], released on drop.
+pub struct Reservation<'a> {
+    allocator: &'a IdAllocator,
+    id: FileId,
+}
+
+impl Reservation<'_> {
+    pub fn id(&self) -> FileId {
+        self.id
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        self.allocator.reserved.lock().unwrap().remove(&self.id);
+    }
+}
@@ -17,7 +17,7 @@ use crate::fs::FileId;
 use dashmap::{mapref::entry::Entry, DashMap};
 use std::{
     ops::Deref,
-    sync::{Arc, RwLock},
+    sync::{Arc, RwLock, RwLockWriteGuard},
 };
 
 /// A concurrent map from `FileId` to `Arc<RwLock<V>>`.
@@ -76,4 +76,26 @@ impl<V: Default> IdLock<V> {
     pub fn get_opt(&self, id: FileId) -> Option<Arc<RwLock<V>>> {
         self.0.get(&id).map(|it| Arc::clone(&it))
     }
+
+    /// Get the values associated with the given `ids` as a
+    /// [`LockSet`]. Duplicated IDs are ignored.
+    pub fn get_all(&self, ids: impl IntoIterator<Item = FileId>) -> LockSet<V> {
+        let mut ids: Vec<_> = ids.into_iter().collect();
+        ids.sort();
+        ids.dedup();
+        LockSet(ids.into_iter().map(|id| self.get(id)).collect())
+    }
+}
+
+/// A set of locks from an [`IdLock`], sorted by ID.
+///
+/// Whenever multiple locks are held at the same time, they should be
+/// acquired through this, so that they are always acquired in
+/// ascending order of IDs and cannot deadlock each other.
+pub struct LockSet<V>(Vec<Arc<RwLock<V>>>);
+impl<V> LockSet<V> {
+    /// Acquires all locks for writing.
+    pub fn write(&self) -> Vec<RwLockWriteGuard<'_, V>> {
+        self.0.iter().map(|lock| lock.write().unwrap()).collect()
+    }
 }
@@ -14,30 +14,70 @@
 //
 
 pub mod algo;
+#[cfg(feature = "rocksdb")]
 mod bijou;
+#[cfg(feature = "rocksdb")]
+mod buffer;
+#[cfg(feature = "rocksdb")]
 mod cache;
+mod clock;
 mod crypto;
+#[cfg(feature = "rocksdb")]
 mod db;
+#[cfg(feature = "rocksdb")]
+mod dir_times;
 mod error;
+#[cfg(feature = "rocksdb")]
+pub mod format;
 mod fs;
+#[cfg(feature = "rocksdb")]
+mod id_alloc;
+#[cfg(feature = "rocksdb")]
 mod id_lock;
+pub mod low_level;
+pub mod mime;
+pub mod password;
+pub mod prelude;
+#[cfg(feature = "rocksdb")]
+mod quota;
+#[cfg(feature = "rocksdb")]
+mod refcount;
 mod secret;
+#[cfg(feature = "rocksdb")]
 mod serde_ext;
 mod sodium;
 
 pub(crate) use error::{anyhow, bail, Context};
 
-pub use bijou::{Bijou, BijouFs, DirIterator, File};
-pub use error::{Error, ErrorKind, Result};
+#[cfg(feature = "rocksdb")]
+pub use bijou::{
+    AppStorage, BackgroundScrub, BackgroundScrubState, Bijou, BijouBuilder, BijouFs, CheckEvent,
+    CheckIssue, CheckOptions, CheckReport, DirIterator, Embedded, ExtraTime, File, FileIterator,
+    HashAlgorithm, KeyStore, MasterKey, NewNode, ProbeInfo, ReconcileReport, ScrubEvent,
+    ScrubFailure, ScrubOptions, ScrubProgress, ScrubReport, Separate, TaskFailure, TaskKind,
+    TaskState, TreeOptions,
+};
+pub use bijou_types::report::{ExportStats, ImportStats, TreeEntry, UsageSnapshot, UsageStats};
+pub use clock::{Clock, MockClock, SystemClock};
+#[cfg(feature = "rocksdb")]
+pub use db::{DbCounters, DbStats};
+pub use error::{
+    clear_error_hook, set_error_hook, Error, ErrorEvent, ErrorKind, ErrorOrigin, Result,
+};
 pub use fs::{
     config::{self, Config},
-    path, raw as raw_fs, FileId, FileKind, FileMeta, LowLevelFile, OpenOptions,
+    path, CheckedOpenOptions, FileAttributes, FileId, FileKind, FileMeta, OpenOptions, ReadAccess,
+    RenameFlags, UnixPerms, WriteAccess,
 };
+#[cfg(feature = "rocksdb")]
+pub use id_alloc::{IdGenerator, RandomIds};
+#[cfg(feature = "rocksdb")]
+pub use quota::{QuotaInfo, QuotaLimits};
 pub use secret::SecretBytes;
 pub use sodium::pwhash::Limit;
 
 #[cfg(feature = "fuse")]
-pub use bijou::BijouFuse;
+pub use bijou::{BijouFuse, OpClass, OwnerPolicy};
 #[cfg(feature = "fuse")]
 pub use fuser::MountOption;
 
@@ -46,20 +86,21 @@ pub use fuser::MountOption;
 /// Should be called before any use of this library.
 pub fn init() -> Result<()> {
     unsafe {
-        if libsodium_sys::sodium_init() != 0 {
+        // 1 means libsodium was already initialized
+        if libsodium_sys::sodium_init() < 0 {
             bail!(@CryptoError "failed to initialize libsodium");
         }
     }
     Ok(())
 }
 
-#[cfg(debug_assertions)]
+#[cfg(all(feature = "fuse", debug_assertions))]
 struct TimeSpan(String, std::time::Instant);
-#[cfg(debug_assertions)]
+#[cfg(all(feature = "fuse", debug_assertions))]
 fn begin_span(name: impl Into<String>) -> TimeSpan {
     TimeSpan(name.into(), std::time::Instant::now())
 }
-#[cfg(debug_assertions)]
+#[cfg(all(feature = "fuse", debug_assertions))]
 impl Drop for TimeSpan {
     fn drop(&mut self) {
         let elapsed = self.1.elapsed();
@@ -67,5 +108,5 @@ impl Drop for TimeSpan {
     }
 }
 
-#[cfg(not(debug_assertions))]
+#[cfg(all(feature = "fuse", not(debug_assertions)))]
 fn begin_span(_name: impl Into<String>) {}
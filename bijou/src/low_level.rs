@@ -0,0 +1,28 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Lower-level access to Bijous, for when [`Bijou`] and [`BijouFs`]
+//! are not enough.
+//!
+//! Internals of the database are not exposed. The traits of raw
+//! filesystems are sealed, so that they can evolve without breaking
+//! changes.
+//!
+//! [`Bijou`]: crate::Bijou
+//! [`BijouFs`]: crate::BijouFs
+
+pub use crate::fs::raw as raw_fs;
+#[cfg(feature = "rocksdb")]
+pub use crate::fs::{FileStats, LowLevelFile};
@@ -0,0 +1,131 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Detection of file types from their first bytes.
+//!
+//! If [`Config::sniff_mime_types`] is enabled, the type of a file is
+//! detected whenever its first [`SNIFF_LEN`] bytes are written or
+//! truncated, and kept in its [`MIME_TYPE_XATTR`] xattr (see
+//! [`Bijou::mime_type`]).
+//!
+//! [`Config::sniff_mime_types`]: crate::Config::sniff_mime_types
+//! [`Bijou::mime_type`]: crate::Bijou::mime_type
+
+/// The xattr in which the detected MIME type of a file is kept.
+pub const MIME_TYPE_XATTR: &str = "user.mime_type";
+
+/// The number of leading bytes looked at by [`sniff`].
+pub const SNIFF_LEN: usize = 512;
+
+/// Signatures at the start of files, checked in order.
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"II*\0", "image/tiff"),
+    (b"MM\0*", "image/tiff"),
+    (b"\0\0\x01\0", "image/x-icon"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"PK\x05\x06", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"BZh", "application/x-bzip2"),
+    (b"\xfd7zXZ\0", "application/x-xz"),
+    (b"\x28\xb5\x2f\xfd", "application/zstd"),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (b"Rar!\x1a\x07", "application/vnd.rar"),
+    (b"\x7fELF", "application/x-executable"),
+    (b"SQLite format 3\0", "application/vnd.sqlite3"),
+    (b"ID3", "audio/mpeg"),
+    (b"fLaC", "audio/flac"),
+    (b"OggS", "audio/ogg"),
+    (b"\x1a\x45\xdf\xa3", "video/x-matroska"),
+    (b"%!PS", "application/postscript"),
+    (b"{\\rtf", "application/rtf"),
+];
+
+/// Returns the MIME type of a file starting with `head`, which
+/// should be its first [`SNIFF_LEN`] bytes or the whole file if
+/// shorter, or `None` if it is empty or not recognized.
+///
+/// Only common formats are recognized by their signatures. Other
+/// files are `text/plain` if they look like text, or not recognized.
+pub fn sniff(head: &[u8]) -> Option<&'static str> {
+    if head.is_empty() {
+        return None;
+    }
+    if let Some((_, mime)) = SIGNATURES
+        .iter()
+        .find(|(signature, _)| head.starts_with(signature))
+    {
+        return Some(mime);
+    }
+    if head.len() >= 12 && head.starts_with(b"RIFF") {
+        match &head[8..12] {
+            b"WEBP" => return Some("image/webp"),
+            b"WAVE" => return Some("audio/wav"),
+            b"AVI " => return Some("video/x-msvideo"),
+            _ => {}
+        }
+    }
+    if head.len() >= 12 && &head[4..8] == b"ftyp" {
+        return Some(match &head[8..12] {
+            b"qt  " => "video/quicktime",
+            b"heic" | b"heix" => "image/heic",
+            b"M4A " => "audio/mp4",
+            _ => "video/mp4",
+        });
+    }
+    if head.len() >= 262 && &head[257..262] == b"ustar" {
+        return Some("application/x-tar");
+    }
+    sniff_text(head)
+}
+
+/// Recognizes markup and plain text, which is valid UTF-8 (except
+/// for a character cut at the end, and with an optional BOM)
+/// without control characters.
+fn sniff_text(head: &[u8]) -> Option<&'static str> {
+    let head = head.strip_prefix(b"\xef\xbb\xbf").unwrap_or(head);
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        // cut in the middle of a character
+        Err(err) if err.error_len().is_none() => {
+            std::str::from_utf8(&head[..err.valid_up_to()]).unwrap()
+        }
+        Err(_) => return None,
+    };
+    if text
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c'))
+    {
+        return None;
+    }
+
+    let start = text.trim_start().as_bytes();
+    let starts_with = |prefix: &str| {
+        start.len() >= prefix.len() && start[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+    };
+    Some(if starts_with("<!doctype html") || starts_with("<html") {
+        "text/html"
+    } else if starts_with("<svg") {
+        "image/svg+xml"
+    } else if starts_with("<?xml") {
+        "application/xml"
+    } else {
+        "text/plain"
+    })
+}
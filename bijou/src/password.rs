@@ -0,0 +1,206 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Password strength estimation and policies, and sources of
+//! passwords.
+
+use crate::{bail, sodium::utils, Context, ErrorKind, Result, SecretBytes};
+use std::{
+    io::BufRead,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+/// A policy that passwords must satisfy when creating a Bijou.
+///
+/// See [`Bijou::create_with_policy`].
+///
+/// [`Bijou::create_with_policy`]: crate::Bijou::create_with_policy
+pub trait PasswordPolicy {
+    /// Checks the password, returning an error if it is rejected.
+    fn check(&self, password: &[u8]) -> Result<()>;
+}
+
+impl<F> PasswordPolicy for F
+where
+    F: Fn(&[u8]) -> Result<()>,
+{
+    fn check(&self, password: &[u8]) -> Result<()> {
+        self(password)
+    }
+}
+
+/// The estimated strength of a password.
+#[derive(Clone, Debug)]
+pub struct Strength {
+    /// The score of the password, from 0 (weakest) to 4 (strongest).
+    pub score: u8,
+    /// Human-readable warnings and suggestions.
+    pub feedback: Vec<String>,
+}
+
+/// Estimates the strength of a password using zxcvbn.
+///
+/// Returns `None` if the password is not valid UTF-8.
+pub fn estimate_strength(password: &[u8]) -> Option<Strength> {
+    let password = std::str::from_utf8(password).ok()?;
+    let Ok(entropy) = zxcvbn::zxcvbn(password, &[]) else {
+        // blank password
+        return Some(Strength {
+            score: 0,
+            feedback: vec!["Password is empty.".to_owned()],
+        });
+    };
+
+    let mut feedback = Vec::new();
+    if let Some(fb) = entropy.feedback() {
+        if let Some(warning) = fb.warning() {
+            feedback.push(warning.to_string());
+        }
+        feedback.extend(fb.suggestions().iter().map(ToString::to_string));
+    }
+
+    Some(Strength {
+        score: entropy.score(),
+        feedback,
+    })
+}
+
+/// A [`PasswordPolicy`] that requires the estimated strength
+/// (see [`estimate_strength`]) to be at least the given score.
+///
+/// Passwords that are not valid UTF-8 are rejected since their
+/// strength cannot be estimated.
+#[derive(Clone, Copy, Debug)]
+pub struct MinimumStrength(pub u8);
+
+impl PasswordPolicy for MinimumStrength {
+    fn check(&self, password: &[u8]) -> Result<()> {
+        let Some(strength) = estimate_strength(password) else {
+            bail!(@InvalidInput "cannot estimate strength of non UTF-8 password");
+        };
+        if strength.score < self.0 {
+            bail!(
+                @InvalidInput "password too weak (score {} < {}): {}",
+                strength.score,
+                self.0,
+                strength.feedback.join(" ")
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A source of passwords, so that frontends share how passwords are
+/// obtained instead of each reimplementing it.
+///
+/// Interactive prompts are left to frontends. Keyrings and password
+/// managers can be queried with [`CommandSecret`].
+pub trait SecretProvider {
+    /// Obtains a secret. `prompt` tells the user what it is for, e.g.
+    /// `Enter password: `, if the provider is interactive.
+    fn secret(&self, prompt: &str) -> Result<SecretBytes>;
+
+    /// Obtains a new secret, e.g. when creating a Bijou. Interactive
+    /// providers should ask for it twice.
+    ///
+    /// Defaults to [`SecretProvider::secret`].
+    fn new_secret(&self, prompt: &str) -> Result<SecretBytes> {
+        self.secret(prompt)
+    }
+}
+
+impl<F> SecretProvider for F
+where
+    F: Fn(&str) -> Result<SecretBytes>,
+{
+    fn secret(&self, prompt: &str) -> Result<SecretBytes> {
+        self(prompt)
+    }
+}
+
+/// Keeps the first line of `bytes` as the secret, zeroing out the
+/// rest.
+fn first_line(mut bytes: Vec<u8>) -> SecretBytes {
+    let end = bytes
+        .iter()
+        .position(|&b| b == b'\n')
+        .unwrap_or(bytes.len());
+    let secret = SecretBytes::move_from(&mut bytes[..end]);
+    utils::memzero(&mut bytes);
+    secret
+}
+
+/// Reads the secret from an environment variable.
+#[derive(Clone, Debug)]
+pub struct EnvSecret(pub String);
+
+impl SecretProvider for EnvSecret {
+    fn secret(&self, _prompt: &str) -> Result<SecretBytes> {
+        let Some(value) = std::env::var_os(&self.0) else {
+            bail!(@NotFound "environment variable {} is not set", self.0);
+        };
+        let Some(value) = value.to_str() else {
+            bail!(@InvalidInput "environment variable {} is not valid UTF-8", self.0);
+        };
+        Ok(value.as_bytes().to_vec().into())
+    }
+}
+
+/// Reads the secret from the first line of a file, or of stdin if
+/// the path is `-`, like `cryptsetup --key-file`.
+#[derive(Clone, Debug)]
+pub struct FileSecret(pub PathBuf);
+
+impl SecretProvider for FileSecret {
+    fn secret(&self, _prompt: &str) -> Result<SecretBytes> {
+        let mut bytes = Vec::new();
+        if self.0.as_os_str() == "-" {
+            std::io::stdin()
+                .lock()
+                .read_until(b'\n', &mut bytes)
+                .context("failed to read secret from stdin")
+                .kind(ErrorKind::IOError)?;
+        } else {
+            bytes = std::fs::read(&self.0)
+                .context("failed to read secret file")
+                .kind(ErrorKind::IOError)?;
+        }
+        Ok(first_line(bytes))
+    }
+}
+
+/// Runs a command with `sh -c` and takes the first line of its output
+/// as the secret, e.g. `secret-tool lookup bijou home` to query a
+/// keyring.
+#[derive(Clone, Debug)]
+pub struct CommandSecret(pub String);
+
+impl SecretProvider for CommandSecret {
+    fn secret(&self, _prompt: &str) -> Result<SecretBytes> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&self.0)
+            .stdin(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .output()
+            .context("failed to run secret command")
+            .kind(ErrorKind::IOError)?;
+        if !output.status.success() {
+            bail!("secret command failed ({})", output.status);
+        }
+        Ok(first_line(output.stdout))
+    }
+}
@@ -0,0 +1,354 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::{bail, fs::FileId, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, MutexGuard,
+    },
+};
+
+/// Limits of a directory quota. `None` means unlimited.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaLimits {
+    /// The maximum total size of files, in bytes.
+    pub bytes: Option<u64>,
+    /// The maximum number of files, directories and symlinks.
+    pub inodes: Option<u64>,
+}
+
+/// A directory quota and its current usage, returned by
+/// [`Bijou::quota`].
+///
+/// [`Bijou::quota`]: crate::Bijou::quota
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaInfo {
+    pub limits: QuotaLimits,
+    pub bytes: u64,
+    pub inodes: u64,
+}
+
+/// A file to be charged by [`Quotas::transfer`].
+pub struct Charged {
+    pub id: FileId,
+    /// The size of the file, used if it is not charged yet.
+    pub bytes: u64,
+    /// Whether the file is only reachable from one place, i.e. it is
+    /// a directory or a file without other hard links. Only these
+    /// files are uncharged from the quotas they are moved out of.
+    pub exclusive: bool,
+}
+
+/// A group of files moved by [`Quotas::transfer_many`].
+pub struct Transfer<'a> {
+    pub files: &'a [Charged],
+    pub from: &'a [FileId],
+    pub to: &'a [FileId],
+}
+
+/// The quota roots a file is charged to.
+struct Charge {
+    roots: Vec<FileId>,
+    bytes: u64,
+}
+
+#[derive(Default)]
+struct State {
+    roots: HashMap<FileId, QuotaInfo>,
+    charges: HashMap<FileId, Charge>,
+}
+
+/// Usage of directory quotas, kept in memory.
+///
+/// The usage is computed when the Bijou is opened and kept up to
+/// date as files are created, resized, moved and removed. Every
+/// file under a quota root remembers which roots it is charged to,
+/// so that writes through an open handle can be checked without
+/// knowing where the file is.
+///
+/// A file with several hard links stays charged to every quota it
+/// has been linked under, until it is removed.
+#[derive(Default)]
+pub struct Quotas {
+    enabled: AtomicBool,
+    state: Mutex<State>,
+}
+
+impl Quotas {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn update_enabled(&self, state: &State) {
+        self.enabled
+            .store(!state.roots.is_empty(), Ordering::Relaxed);
+    }
+
+    /// Returns whether there are any quotas at all.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn is_root(&self, id: FileId) -> bool {
+        self.is_enabled() && self.state().roots.contains_key(&id)
+    }
+
+    pub fn get(&self, id: FileId) -> Option<QuotaInfo> {
+        self.state().roots.get(&id).copied()
+    }
+
+    pub fn all(&self) -> Vec<(FileId, QuotaInfo)> {
+        let mut quotas: Vec<_> = self
+            .state()
+            .roots
+            .iter()
+            .map(|(id, info)| (*id, *info))
+            .collect();
+        quotas.sort_by_key(|(id, _)| *id);
+        quotas
+    }
+
+    /// Passes the limits of all quotas to `f` to be stored. Changes
+    /// are blocked meanwhile, so that stores happen in order.
+    pub fn save(&self, f: impl FnOnce(&BTreeMap<FileId, QuotaLimits>) -> Result<()>) -> Result<()> {
+        let state = self.state();
+        let limits = state
+            .roots
+            .iter()
+            .map(|(id, info)| (*id, info.limits))
+            .collect();
+        f(&limits)
+    }
+
+    /// Sets the limits of the quota rooted at `root`, creating it
+    /// with the given files (everything under `root`) if it does not
+    /// exist yet.
+    pub fn insert(&self, root: FileId, limits: QuotaLimits, files: &[Charged]) {
+        let mut state = self.state();
+        if let Some(info) = state.roots.get_mut(&root) {
+            info.limits = limits;
+            return;
+        }
+
+        let mut info = QuotaInfo {
+            limits,
+            ..QuotaInfo::default()
+        };
+        for file in files {
+            let charge = state.charges.entry(file.id).or_insert_with(|| Charge {
+                roots: Vec::new(),
+                bytes: file.bytes,
+            });
+            if !charge.roots.contains(&root) {
+                charge.roots.push(root);
+                info.bytes += charge.bytes;
+                info.inodes += 1;
+            }
+        }
+        state.roots.insert(root, info);
+        self.update_enabled(&state);
+    }
+
+    /// Removes the quota rooted at `root`, returning whether it
+    /// existed.
+    pub fn remove(&self, root: FileId) -> bool {
+        let mut state = self.state();
+        if state.roots.remove(&root).is_none() {
+            return false;
+        }
+        state.charges.retain(|_, charge| {
+            charge.roots.retain(|it| *it != root);
+            !charge.roots.is_empty()
+        });
+        self.update_enabled(&state);
+        true
+    }
+
+    /// Moves files out of the quotas `from` and into the quotas `to`,
+    /// failing with [`ErrorKind::QuotaExceeded`] if any quota in `to`
+    /// would be exceeded. Nothing is changed on failure.
+    ///
+    /// New files are charged by passing an empty `from`.
+    ///
+    /// [`ErrorKind::QuotaExceeded`]: crate::ErrorKind::QuotaExceeded
+    pub fn transfer(&self, files: &[Charged], from: &[FileId], to: &[FileId]) -> Result<()> {
+        self.transfer_many(&[Transfer { files, from, to }])
+    }
+
+    /// Same as [`transfer`], but for several groups of files at once,
+    /// e.g. for exchanging two directories.
+    ///
+    /// [`transfer`]: Quotas::transfer
+    pub fn transfer_many(&self, transfers: &[Transfer]) -> Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        let mut state = self.state();
+        let mut deltas = HashMap::<FileId, (i128, i128)>::new();
+        let mut updates = HashMap::<FileId, (Vec<FileId>, u64)>::new();
+        for &Transfer { files, from, to } in transfers {
+            for file in files {
+                let (roots, bytes) = match updates.remove(&file.id) {
+                    Some(update) => update,
+                    None => match state.charges.get(&file.id) {
+                        Some(charge) => (charge.roots.clone(), charge.bytes),
+                        None => (Vec::new(), file.bytes),
+                    },
+                };
+                let roots = Self::move_file(&state, &mut deltas, file, roots, bytes, from, to);
+                updates.insert(file.id, (roots, bytes));
+            }
+        }
+
+        for (root, (bytes, inodes)) in &deltas {
+            if let Some(info) = state.roots.get(root) {
+                Self::check(root, info, *bytes, *inodes)?;
+            }
+        }
+
+        for (root, (bytes, inodes)) in deltas {
+            if let Some(info) = state.roots.get_mut(&root) {
+                info.bytes = (info.bytes as i128 + bytes).max(0) as u64;
+                info.inodes = (info.inodes as i128 + inodes).max(0) as u64;
+            }
+        }
+        for (id, (roots, bytes)) in updates {
+            if roots.is_empty() {
+                state.charges.remove(&id);
+            } else {
+                state.charges.insert(id, Charge { roots, bytes });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the roots `file` is charged to after being moved,
+    /// recording the changes of usage in `deltas`.
+    fn move_file(
+        state: &State,
+        deltas: &mut HashMap<FileId, (i128, i128)>,
+        file: &Charged,
+        mut roots: Vec<FileId>,
+        bytes: u64,
+        from: &[FileId],
+        to: &[FileId],
+    ) -> Vec<FileId> {
+        if file.exclusive {
+            roots.retain(|root| {
+                if !from.contains(root) {
+                    return true;
+                }
+                let delta = deltas.entry(*root).or_default();
+                delta.0 -= bytes as i128;
+                delta.1 -= 1;
+                false
+            });
+        }
+        for root in to {
+            if !roots.contains(root) && state.roots.contains_key(root) {
+                roots.push(*root);
+                let delta = deltas.entry(*root).or_default();
+                delta.0 += bytes as i128;
+                delta.1 += 1;
+            }
+        }
+        roots
+    }
+
+    fn check(root: &FileId, info: &QuotaInfo, bytes: i128, inodes: i128) -> Result<()> {
+        let exceeds = |used: u64, delta: i128, limit: Option<u64>| {
+            delta > 0 && limit.is_some_and(|limit| used as i128 + delta > limit as i128)
+        };
+        if exceeds(info.bytes, bytes, info.limits.bytes) {
+            bail!(@QuotaExceeded? "byte quota of {root} exceeded");
+        }
+        if exceeds(info.inodes, inodes, info.limits.inodes) {
+            bail!(@QuotaExceeded? "inode quota of {root} exceeded");
+        }
+        Ok(())
+    }
+
+    /// Uncharges a removed file from all quotas.
+    pub fn uncharge(&self, id: FileId) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let mut state = self.state();
+        let Some(charge) = state.charges.remove(&id) else {
+            return;
+        };
+        for root in charge.roots {
+            if let Some(info) = state.roots.get_mut(&root) {
+                info.bytes = info.bytes.saturating_sub(charge.bytes);
+                info.inodes = info.inodes.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Makes sure that the file can grow to `size` bytes, charging
+    /// it in advance. The charge should be corrected with
+    /// [`settle`] afterwards.
+    ///
+    /// [`settle`]: Quotas::settle
+    pub fn reserve(&self, id: FileId, size: u64) -> Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        let mut state = self.state();
+        let Some(charge) = state.charges.get(&id) else {
+            return Ok(());
+        };
+        if size <= charge.bytes {
+            return Ok(());
+        }
+        let delta = (size - charge.bytes) as i128;
+        for root in &charge.roots {
+            if let Some(info) = state.roots.get(root) {
+                Self::check(root, info, delta, 0)?;
+            }
+        }
+        Self::resize(&mut state, id, size);
+        Ok(())
+    }
+
+    /// Sets the charged size of the file to `size`.
+    pub fn settle(&self, id: FileId, size: u64) {
+        if self.is_enabled() {
+            Self::resize(&mut self.state(), id, size);
+        }
+    }
+
+    fn resize(state: &mut State, id: FileId, size: u64) {
+        let State { roots, charges } = state;
+        let Some(charge) = charges.get_mut(&id) else {
+            return;
+        };
+        for root in &charge.roots {
+            if let Some(info) = roots.get_mut(root) {
+                info.bytes = info.bytes.saturating_add(size).saturating_sub(charge.bytes);
+            }
+        }
+        charge.bytes = size;
+    }
+}
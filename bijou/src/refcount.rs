@@ -0,0 +1,96 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use crate::{
+    db::{consts, Database, DatabaseKey},
+    fs::FileId,
+    id_lock::IdLock,
+    Result,
+};
+use bijou_rocksdb::WriteBatch;
+use std::sync::{Arc, RwLock};
+
+/// Reference counts of raw files, stored in the database.
+///
+/// A raw file without a stored count is owned by exactly one
+/// reference, so only shared raw files take up space here.
+///
+/// Raw files that might be shared should never be removed
+/// directly. Instead, [`release`] should be called and the
+/// raw file removed only if it was the last reference.
+///
+/// [`release`]: RefCounter::release
+pub struct RefCounter {
+    db: Arc<Database>,
+    lock: IdLock,
+}
+
+impl RefCounter {
+    pub fn new(db: Arc<Database>) -> Self {
+        Self {
+            db,
+            lock: IdLock::new(),
+        }
+    }
+
+    fn key(&self, id: FileId) -> DatabaseKey<u32> {
+        self.db.key(consts::Root::Refs).derive(id).typed()
+    }
+
+    /// Returns the lock of the given raw file. This should be held
+    /// when modifying its reference count in a batch, until the
+    /// batch is committed.
+    pub fn lock(&self, id: FileId) -> Arc<RwLock<()>> {
+        self.lock.get(id)
+    }
+
+    /// Returns the number of references to the given raw file.
+    pub fn count(&self, id: FileId) -> Result<u32> {
+        Ok(self.key(id).get()?.unwrap_or(1))
+    }
+
+    /// Adds a reference to the given raw file.
+    pub fn acquire_batch(&self, batch: &mut WriteBatch, id: FileId) -> Result<()> {
+        let count = self.count(id)?;
+        self.key(id).put_batch(batch, &(count + 1))
+    }
+
+    /// Drops a reference to the given raw file, returning whether
+    /// it has no more references and should be removed.
+    pub fn release_batch(&self, batch: &mut WriteBatch, id: FileId) -> Result<bool> {
+        let key = self.key(id);
+        let count = self.count(id)?;
+        if count <= 2 {
+            key.delete_batch(batch);
+        } else {
+            key.put_batch(batch, &(count - 1))?;
+        }
+        Ok(count <= 1)
+    }
+
+    /// Same as [`release_batch`], but commits immediately.
+    ///
+    /// [`release_batch`]: RefCounter::release_batch
+    pub fn release(&self, id: FileId) -> Result<bool> {
+        let lock = self.lock(id);
+        let _guard = lock.write().unwrap();
+
+        let mut batch = self.db.batch();
+        let released = self.release_batch(&mut batch, id)?;
+        batch.commit()?;
+
+        Ok(released)
+    }
+}
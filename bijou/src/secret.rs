@@ -14,20 +14,30 @@
 //
 
 use crate::sodium::utils;
-use std::ops::{Deref, DerefMut};
+use std::{
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, Ordering},
+};
+use tracing::warn;
 
 /// A wrapper around a byte array that is locked in memory
 /// and is automatically zeroed out when dropped.
 ///
 /// Please note that this is not aligned, and thus cannot
 /// be used under some circumstances.
-pub struct SecretBytes(Box<[u8]>);
+pub struct SecretBytes {
+    bytes: Box<[u8]>,
+    locked: bool,
+}
 impl SecretBytes {
     /// Creates a new [`SecretBytes`] from a byte array.
     pub fn new(mut bytes: Box<[u8]>) -> Self {
         // TODO handle this error
         utils::mlock(&mut bytes).unwrap();
-        Self(bytes)
+        Self {
+            bytes,
+            locked: true,
+        }
     }
 
     /// Creates a new [`SecretBytes`] from a byte slice,
@@ -43,28 +53,56 @@ impl SecretBytes {
         Self::new(vec![0; len].into_boxed_slice())
     }
 
+    /// Allocates a new [`SecretBytes`] like [`SecretBytes::allocate`],
+    /// but falls back to memory that is not locked if locking fails
+    /// (e.g. when `RLIMIT_MEMLOCK` is exhausted). The content is
+    /// still zeroed out when dropped.
+    pub fn allocate_lenient(len: usize) -> Self {
+        static WARNED: AtomicBool = AtomicBool::new(false);
+
+        let mut bytes = vec![0; len].into_boxed_slice();
+        let locked = match utils::mlock(&mut bytes) {
+            Ok(()) => true,
+            Err(err) => {
+                if !WARNED.swap(true, Ordering::Relaxed) {
+                    warn!("{err}, falling back to unlocked memory");
+                }
+                false
+            }
+        };
+        Self { bytes, locked }
+    }
+
+    /// Whether the memory is locked, i.e. will not be swapped out.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.bytes.len()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.bytes.is_empty()
     }
 
     pub fn as_ptr(&self) -> *const u8 {
-        self.0.as_ptr()
+        self.bytes.as_ptr()
     }
 }
 
 impl Clone for SecretBytes {
     fn clone(&self) -> Self {
-        Self::new(self.0.clone())
+        Self::new(self.bytes.clone())
     }
 }
 
 impl From<Vec<u8>> for SecretBytes {
     fn from(bytes: Vec<u8>) -> Self {
-        Self(bytes.into_boxed_slice())
+        Self {
+            bytes: bytes.into_boxed_slice(),
+            locked: false,
+        }
     }
 }
 
@@ -72,17 +110,21 @@ impl Deref for SecretBytes {
     type Target = [u8];
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.bytes
     }
 }
 impl DerefMut for SecretBytes {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.bytes
     }
 }
 
 impl Drop for SecretBytes {
     fn drop(&mut self) {
-        utils::munlock(&mut self.0).unwrap();
+        if self.locked {
+            utils::munlock(&mut self.bytes).unwrap();
+        } else {
+            utils::memzero(&mut self.bytes);
+        }
     }
 }
@@ -13,37 +13,35 @@
 // limitations under the License.
 //
 
-use serde::de::Visitor;
+pub use bijou_types::serde_ext::base64;
 
-struct BytesVisitor<const N: usize>();
-impl<'de, const N: usize> Visitor<'de> for BytesVisitor<N> {
-    type Value = [u8; N];
+/// Serializes a value with its [`Display`] implementation.
+///
+/// [`Display`]: std::fmt::Display
+pub mod display {
+    use serde::Serializer;
+    use std::fmt::Display;
 
-    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(formatter, "a byte array of length {N}")
+    pub fn serialize<S: Serializer, T: Display>(v: &T, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(v)
     }
 }
 
-pub mod base64 {
-    use super::BytesVisitor;
-    use base64::{engine::general_purpose::STANDARD, Engine};
-    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+/// Serializes a value as a JSON string, for values that can't be
+/// handled by formats that are not self-describing (e.g. internally
+/// tagged enums with postcard).
+pub mod json {
+    use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
 
-    pub fn serialize<S: Serializer, const N: usize>(v: &[u8; N], s: S) -> Result<S::Ok, S::Error> {
-        let base64 = STANDARD.encode(v);
-        String::serialize(&base64, s)
+    pub fn serialize<S: Serializer, T: Serialize>(v: &T, s: S) -> Result<S::Ok, S::Error> {
+        let json = serde_json::to_string(v).map_err(serde::ser::Error::custom)?;
+        s.serialize_str(&json)
     }
 
-    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+    pub fn deserialize<'de, D: Deserializer<'de>, T: DeserializeOwned>(
         d: D,
-    ) -> Result<[u8; N], D::Error> {
-        let base64 = String::deserialize(d)?;
-        let decoded = STANDARD
-            .decode(base64.as_bytes())
-            .map_err(serde::de::Error::custom)?;
-
-        <[u8; N]>::try_from(decoded).map_err(|decoded| {
-            serde::de::Error::invalid_length(decoded.len(), &BytesVisitor::<N>())
-        })
+    ) -> Result<T, D::Error> {
+        let json = String::deserialize(d)?;
+        serde_json::from_str(&json).map_err(serde::de::Error::custom)
     }
 }
@@ -16,7 +16,9 @@
 //! Bindings to libsodium
 
 pub mod aead;
+#[cfg(feature = "rocksdb")]
 pub mod generic_hash;
+#[cfg(feature = "rocksdb")]
 pub mod kdf;
 pub mod pwhash;
 pub mod stream;
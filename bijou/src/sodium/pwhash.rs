@@ -13,8 +13,10 @@
 // limitations under the License.
 //
 
+#[cfg(feature = "rocksdb")]
+use crate::{error::anyhow, Result};
+#[cfg(feature = "rocksdb")]
 use libsodium_sys::*;
-use crate::{Result, error::anyhow};
 
 #[derive(Debug, Clone, Copy)]
 pub enum Limit {
@@ -35,6 +37,7 @@ impl Limit {
     }
 }
 
+#[cfg(feature = "rocksdb")]
 pub struct Algorithm {
     pub salt_len: usize,
 
@@ -53,6 +56,7 @@ pub struct Algorithm {
     ) -> libc::c_int,
 }
 
+#[cfg(feature = "rocksdb")]
 impl Algorithm {
     fn check(&self, salt: &[u8]) {
         assert_eq!(self.salt_len, salt.len());
@@ -87,6 +91,7 @@ impl Algorithm {
     }
 }
 
+#[cfg(feature = "rocksdb")]
 pub const ARGON2_ID13: Algorithm = Algorithm {
     salt_len: crypto_pwhash_argon2id_SALTBYTES as _,
 
@@ -68,6 +68,7 @@ pub const XSALSA20: Algorithm = Algorithm {
     xor_inplace_ic: crypto_stream_xsalsa20_xor_ic,
 };
 
+#[cfg(feature = "rocksdb")]
 pub const XCHACHA20: Algorithm = Algorithm {
     nonce_len: crypto_stream_xchacha20_NONCEBYTES as _,
     key_len: crypto_stream_xchacha20_KEYBYTES as _,
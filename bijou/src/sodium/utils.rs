@@ -13,7 +13,9 @@
 // limitations under the License.
 //
 
-use crate::{error::anyhow, Result, SecretBytes};
+#[cfg(feature = "rocksdb")]
+use crate::SecretBytes;
+use crate::{error::anyhow, Result};
 use libsodium_sys::*;
 
 pub fn memzero(bytes: &mut [u8]) {
@@ -42,6 +44,7 @@ pub fn munlock(bytes: &mut [u8]) -> Result<()> {
     }
 }
 
+#[cfg(feature = "rocksdb")]
 pub fn memcmp(x: &[u8], y: &[u8]) -> bool {
     if x.len() != y.len() {
         return false;
@@ -58,12 +61,14 @@ pub fn rand_bytes(buf: &mut [u8]) {
     }
 }
 
+#[cfg(feature = "rocksdb")]
 pub fn gen_secret(len: usize) -> SecretBytes {
     let mut result = SecretBytes::allocate(len);
     rand_bytes(&mut result);
     result
 }
 
+#[cfg(feature = "rocksdb")]
 pub fn gen_rand_bytes<const N: usize>() -> [u8; N] {
     let mut result = [0; N];
     rand_bytes(&mut result);
@@ -0,0 +1,122 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Immutable and append-only files.
+
+mod common;
+
+use bijou::{ErrorKind, FileAttributes, FileId, FileKind, OpenOptions, Result};
+use common::TempBijou;
+
+fn error_kind<T>(result: Result<T>) -> ErrorKind {
+    result.map(drop).unwrap_err().kind()
+}
+
+#[test]
+fn immutable() {
+    let bijou = TempBijou::new("attributes-immutable");
+    let file = bijou
+        .make_node(FileId::ROOT, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    bijou
+        .set_attributes(file, FileAttributes::IMMUTABLE)
+        .unwrap();
+    assert_eq!(
+        bijou.get_meta(file).unwrap().attributes,
+        FileAttributes::IMMUTABLE
+    );
+
+    let denied = ErrorKind::PermissionDenied;
+    assert_eq!(
+        error_kind(bijou.open_file_direct(file, OpenOptions::writable())),
+        denied
+    );
+    assert_eq!(error_kind(bijou.set_xattr(file, "user.a", b"b")), denied);
+    assert_eq!(error_kind(bijou.link(file, FileId::ROOT, "link")), denied);
+    assert_eq!(error_kind(bijou.unlink(FileId::ROOT, "file")), denied);
+    assert_eq!(
+        error_kind(bijou.rename(FileId::ROOT, "file", FileId::ROOT, "moved")),
+        denied
+    );
+    bijou
+        .open_file_direct(file, OpenOptions::read_only())
+        .unwrap();
+
+    bijou.set_attributes(file, FileAttributes::EMPTY).unwrap();
+    bijou
+        .open_file_direct(file, OpenOptions::writable())
+        .unwrap();
+    bijou.unlink(FileId::ROOT, "file").unwrap();
+}
+
+#[test]
+fn immutable_directory() {
+    let bijou = TempBijou::new("attributes-dir");
+    let dir = bijou
+        .make_node(FileId::ROOT, "dir", FileKind::Directory, None, None)
+        .unwrap()
+        .id;
+    bijou
+        .make_node(dir, "file", FileKind::File, None, None)
+        .unwrap();
+    bijou
+        .set_attributes(dir, FileAttributes::IMMUTABLE)
+        .unwrap();
+
+    let denied = ErrorKind::PermissionDenied;
+    assert_eq!(
+        error_kind(bijou.make_node(dir, "new", FileKind::File, None, None)),
+        denied
+    );
+    assert_eq!(error_kind(bijou.unlink(dir, "file")), denied);
+}
+
+#[test]
+fn append_only() {
+    let bijou = TempBijou::new("attributes-append");
+    let file = bijou
+        .make_node(FileId::ROOT, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    bijou
+        .open_file_direct(file, OpenOptions::writable())
+        .unwrap()
+        .write(b"hello", 0)
+        .unwrap();
+    bijou
+        .set_attributes(file, FileAttributes::APPEND_ONLY)
+        .unwrap();
+
+    let denied = ErrorKind::PermissionDenied;
+    assert_eq!(
+        error_kind(bijou.open_file_direct(file, OpenOptions::writable())),
+        denied
+    );
+    assert_eq!(
+        error_kind(
+            bijou.open_file_direct(file, OpenOptions::writable().append(true).truncate(true))
+        ),
+        denied
+    );
+    assert_eq!(error_kind(bijou.unlink(FileId::ROOT, "file")), denied);
+
+    bijou
+        .open_file_direct(file, OpenOptions::writable().append(true))
+        .unwrap()
+        .write(b" world", 5)
+        .unwrap();
+    assert_eq!(bijou.get_meta(file).unwrap().size, 11);
+}
@@ -0,0 +1,77 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+mod common;
+
+use bijou::{config::ScrubSchedule, FileId, FileKind, OpenOptions};
+use common::TempBijou;
+use std::time::{Duration, Instant};
+
+#[test]
+fn scrubs_while_idle() {
+    let bijou = TempBijou::with("background-scrub", |builder| {
+        builder.background_scrub(Some(ScrubSchedule {
+            interval: 3600,
+            rate_limit: u64::MAX,
+            quick: false,
+        }));
+    });
+    for name in ["a", "b"] {
+        let file = bijou
+            .make_node(FileId::ROOT, name, FileKind::File, None, None)
+            .unwrap()
+            .id;
+        bijou
+            .open_file_direct(file, OpenOptions::new().write(true))
+            .unwrap()
+            .write(b"hello", 0)
+            .unwrap();
+    }
+
+    // nothing is scrubbed while a file is open
+    let open = bijou
+        .open_file_direct(
+            bijou.lookup(FileId::ROOT, "a").unwrap(),
+            OpenOptions::read_only(),
+        )
+        .unwrap();
+    let scrub = bijou.arc().spawn_background_scrub().unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    let state = bijou.background_scrub_state().unwrap();
+    let task = state.task.unwrap();
+    assert_eq!(bijou.tasks().unwrap()[0].files, 0);
+    assert!(state.last.is_none());
+
+    drop(open);
+    let start = Instant::now();
+    let state = loop {
+        let state = bijou.background_scrub_state().unwrap();
+        if state.last.is_some() {
+            break state;
+        }
+        assert!(start.elapsed() < Duration::from_secs(10));
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    drop(scrub);
+
+    let last = state.last.unwrap();
+    assert_eq!(last.id, task);
+    // the root and both files
+    assert_eq!(last.files, 3);
+    assert!(last.failures.is_empty());
+    // the next pass is not due yet
+    assert_eq!(state.task, None);
+    assert!(bijou.tasks().unwrap().is_empty());
+}
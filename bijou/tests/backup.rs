@@ -0,0 +1,60 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Backups can be taken while the Bijou is in use, and are Bijous
+//! themselves.
+
+mod common;
+
+use bijou::{Bijou, FileId, FileKind, OpenOptions};
+use common::TempBijou;
+
+#[test]
+fn backup_in_use() {
+    let bijou = TempBijou::new("backup");
+    let file = bijou
+        .make_node(FileId::ROOT, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    let mut handle = bijou
+        .open_file_direct(file, OpenOptions::writable())
+        .unwrap();
+    handle.write(b"hello", 0).unwrap();
+
+    let backup = std::env::temp_dir().join(format!("bijou-backup-dest-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&backup);
+    bijou.backup(&backup).unwrap();
+    assert!(backup.join("manifest.json").is_file());
+
+    // not seen by the backup
+    handle.write(b"world", 0).unwrap();
+    drop(handle);
+
+    let restored = Bijou::open(&backup, b"password".to_vec()).unwrap();
+    assert_eq!(restored.lookup(FileId::ROOT, "file").unwrap(), file);
+    let reader = restored
+        .open_file_direct(file, OpenOptions::read_only())
+        .unwrap();
+    let mut buffer = [0; 5];
+    reader.read(&mut buffer, 0).unwrap();
+    assert_eq!(&buffer, b"hello");
+    drop(reader);
+    drop(restored);
+
+    // the destination has to be empty
+    assert!(bijou.backup(&backup).is_err());
+
+    std::fs::remove_dir_all(&backup).unwrap();
+}
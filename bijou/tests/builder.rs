@@ -0,0 +1,148 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Presets of [`bijou::BijouBuilder`] and the validation of its
+//! configuration.
+
+mod common;
+
+use bijou::{
+    config::{ClusterNaming, FileEncryption, FileStorage},
+    Bijou, BijouBuilder, ErrorKind, FileId, FileKind, Limit, OpenOptions,
+};
+use common::TempBijou;
+
+fn round_trip(bijou: &mut TempBijou) {
+    let file = bijou
+        .make_node(FileId::ROOT, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    let data = b"hello, world";
+    let mut writer = bijou
+        .open_file_direct(file, OpenOptions::writable())
+        .unwrap();
+    writer.write(data, 0).unwrap();
+    drop(writer);
+
+    bijou.reopen(|_| {});
+    let reader = bijou
+        .open_file_direct(file, OpenOptions::read_only())
+        .unwrap();
+    let mut buffer = [0; 32];
+    let read = reader.read(&mut buffer, 0).unwrap();
+    assert_eq!(&buffer[..read as usize], data);
+}
+
+#[test]
+fn paranoid() {
+    let mut bijou = TempBijou::with("builder-paranoid", |builder| {
+        builder.paranoid();
+        let config = builder.config();
+        assert!(matches!(
+            config.file_encryption,
+            FileEncryption::XChaCha20Poly1305IETF
+        ));
+        assert!(config.encrypt_db);
+        assert!(config.encrypt_file_name);
+        // the sensitive limits take too long for tests
+        builder
+            .ops_limit(Limit::Interactive)
+            .mem_limit(Limit::Interactive);
+    });
+    assert!(bijou.config().encrypt_db);
+    assert!(Bijou::probe(bijou.path()).unwrap().unwrap().throttle_unlock);
+    round_trip(&mut bijou);
+}
+
+#[test]
+fn fast() {
+    let mut bijou = TempBijou::with("builder-fast", |builder| {
+        builder.fast();
+    });
+    let config = bijou.config();
+    assert!(matches!(config.file_encryption, FileEncryption::Aes256Gcm));
+    assert_eq!(config.block_size, 16384);
+    assert!(!config.encrypt_file_name);
+    assert!(config.disable_xattr_gets);
+    round_trip(&mut bijou);
+}
+
+#[test]
+fn remote() {
+    let mut bijou = TempBijou::with("builder-remote", |builder| {
+        builder.remote();
+    });
+    let config = bijou.config();
+    assert!(config.encrypt_file_name);
+    let FileStorage::Tracking { inner } = &config.storage else {
+        panic!("unexpected storage: {:?}", config.storage);
+    };
+    let FileStorage::Split {
+        inner,
+        cluster_size,
+        naming,
+    } = &**inner
+    else {
+        panic!("unexpected storage: {inner:?}");
+    };
+    assert_eq!(**inner, FileStorage::Local);
+    assert_eq!(*cluster_size, 256);
+    assert!(matches!(naming, ClusterNaming::Keyed { .. }));
+    round_trip(&mut bijou);
+}
+
+#[test]
+fn compact() {
+    let mut bijou = TempBijou::compact("builder-compact");
+    assert!(bijou.config().embedded);
+    assert_eq!(bijou.config().storage, FileStorage::RocksDB);
+    round_trip(&mut bijou);
+}
+
+#[test]
+fn invalid() {
+    bijou::init().unwrap();
+    let path = std::env::temp_dir().join(format!("bijou-builder-invalid-{}", std::process::id()));
+
+    let mut builder = BijouBuilder::new(&path);
+    builder.block_size(0);
+    assert_eq!(
+        builder.validate().unwrap_err().kind(),
+        ErrorKind::InvalidInput
+    );
+
+    let mut builder = BijouBuilder::new(&path);
+    builder.storage(FileStorage::Split {
+        inner: Box::new(FileStorage::Local),
+        cluster_size: 0,
+        naming: ClusterNaming::Random,
+    });
+    assert_eq!(
+        builder.validate().unwrap_err().kind(),
+        ErrorKind::InvalidInput
+    );
+
+    // the layout is only checked at runtime for configs given as is
+    let config = bijou::config::Config {
+        embedded: true,
+        ..Default::default()
+    };
+    let builder = BijouBuilder::from_config(&path, config);
+    assert_eq!(
+        builder.create(b"password".to_vec()).unwrap_err().kind(),
+        ErrorKind::InvalidInput
+    );
+    assert!(!path.exists());
+}
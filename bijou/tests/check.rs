@@ -0,0 +1,116 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Consistency checks of the whole Bijou.
+
+mod common;
+
+use bijou::{CheckEvent, CheckIssue, CheckOptions, FileId, FileKind, OpenOptions};
+use common::TempBijou;
+use std::sync::Mutex;
+
+#[test]
+fn orphaned_content() {
+    let bijou = TempBijou::new("check");
+    let dir = bijou
+        .make_node(FileId::ROOT, "dir", FileKind::Directory, None, None)
+        .unwrap()
+        .id;
+    let file = bijou
+        .make_node(dir, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    bijou
+        .open_file_direct(file, OpenOptions::new().write(true))
+        .unwrap()
+        .write(b"hello", 0)
+        .unwrap();
+    bijou.link(file, FileId::ROOT, "link").unwrap();
+
+    let report = bijou.check(&CheckOptions::default()).unwrap();
+    assert!(report.is_ok(), "{:?}", report.issues);
+    assert_eq!((report.files, report.directories), (1, 2));
+    assert!(report.orphans_checked);
+
+    // as if left behind by a crash
+    let orphan: FileId = "abcdef0123456789".parse().unwrap();
+    let path = bijou.path().join("data/ab/cdef0123456789");
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(&path, b"junk").unwrap();
+
+    let report = bijou.check(&CheckOptions::default()).unwrap();
+    assert!(!report.is_ok());
+    assert!(matches!(
+        report.issues[..],
+        [CheckIssue::OrphanedContent { id }] if id == orphan
+    ));
+    assert!(path.exists());
+
+    let options = CheckOptions {
+        repair: true,
+        ..Default::default()
+    };
+    let report = bijou.check(&options).unwrap();
+    assert_eq!(report.repaired, 1);
+    assert!(report.is_ok());
+    assert!(!path.exists());
+    assert!(bijou.check(&options).unwrap().issues.is_empty());
+}
+
+#[test]
+fn content_of_clones() {
+    let bijou = TempBijou::new("check-clones");
+    let fs = bijou.fs();
+    fs.write("/file", "hello").unwrap();
+    fs.clone_file("/file", "/clone").unwrap();
+    // the content is still stored under the ID of the removed file
+    fs.remove("/file").unwrap();
+    drop(fs);
+
+    let report = bijou.check(&CheckOptions::default()).unwrap();
+    assert!(report.orphans_checked);
+    assert!(report.is_ok(), "{:?}", report.issues);
+    assert_eq!(report.files, 1);
+}
+
+#[test]
+fn issues_as_found() {
+    let bijou = TempBijou::new("check-events");
+    bijou.fs().write("/file", "hello").unwrap();
+    let path = bijou.path().join("data/ab/cdef0123456789");
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(&path, b"junk").unwrap();
+
+    let found = Mutex::new(Vec::new());
+    let options = CheckOptions {
+        threads: 2,
+        ..Default::default()
+    };
+    let report = bijou
+        .check_with(&options, &|event| {
+            if let CheckEvent::Issue(issue) = event {
+                found.lock().unwrap().push(issue);
+            }
+        })
+        .unwrap();
+    assert!(matches!(
+        found.into_inner().unwrap()[..],
+        [CheckIssue::OrphanedContent { .. }]
+    ));
+    assert!(report.issues.is_empty());
+    assert_eq!(report.issue_count, 1);
+    assert!(!report.is_ok());
+    assert_eq!(report.files, 1);
+}
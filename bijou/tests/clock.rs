@@ -0,0 +1,54 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Times of files and delays are taken from the clock set on the
+//! Bijou.
+
+mod common;
+
+use bijou::{config::DirTimePolicy, Clock, FileId, FileKind, MockClock};
+use chrono::{TimeZone, Utc};
+use common::TempBijou;
+use std::{sync::Arc, time::Duration};
+
+#[test]
+fn mocked_times() {
+    let mut bijou = TempBijou::with("clock", |builder| {
+        builder.dir_time_policy(DirTimePolicy::Relaxed);
+    });
+    let clock = Arc::new(MockClock::new(
+        Utc.with_ymd_and_hms(2000, 1, 1, 0, 0, 0).unwrap(),
+    ));
+    bijou.get_mut().set_clock(Arc::clone(&clock));
+
+    let make_dir = |name: &str| {
+        let dir = bijou
+            .make_node(FileId::ROOT, name, FileKind::Directory, None, None)
+            .unwrap();
+        assert_eq!(dir.modified, clock.now());
+        assert_eq!(bijou.get_meta(FileId::ROOT).unwrap().modified, clock.now());
+    };
+
+    make_dir("a");
+    // deferred
+    clock.advance(Duration::from_secs(1));
+    make_dir("b");
+    // written, as the update has been deferred for too long
+    clock.advance(Duration::from_secs(3600));
+    make_dir("c");
+
+    bijou.flush_dir_times().unwrap();
+    assert_eq!(bijou.get_meta(FileId::ROOT).unwrap().modified, clock.now());
+}
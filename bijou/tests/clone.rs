@@ -0,0 +1,115 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Copy-on-write clones sharing content with the original file.
+
+mod common;
+
+use bijou::{Bijou, ErrorKind, FileId, FileKind, OpenOptions};
+use common::TempBijou;
+
+fn write(bijou: &Bijou, file: FileId, data: &[u8]) {
+    let mut handle = bijou
+        .open_file_direct(file, OpenOptions::writable().truncate(true))
+        .unwrap();
+    handle.write(data, 0).unwrap();
+}
+
+fn read(bijou: &Bijou, file: FileId) -> Vec<u8> {
+    let handle = bijou
+        .open_file_direct(file, OpenOptions::read_only())
+        .unwrap();
+    let mut buffer = vec![0; 64];
+    let len = handle.read(&mut buffer, 0).unwrap() as usize;
+    buffer.truncate(len);
+    buffer
+}
+
+fn setup(name: &str) -> (TempBijou, FileId, FileId) {
+    let bijou = TempBijou::new(name);
+    let file = bijou
+        .make_node(FileId::ROOT, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    write(&bijou, file, b"original");
+    let clone = bijou
+        .clone_file(file, FileId::ROOT, "clone", None)
+        .unwrap()
+        .id;
+    (bijou, file, clone)
+}
+
+#[test]
+fn write_through_both() {
+    let (bijou, file, clone) = setup("clone-write");
+    assert_eq!(read(&bijou, clone), b"original");
+
+    write(&bijou, file, b"changed");
+    assert_eq!(read(&bijou, file), b"changed");
+    assert_eq!(read(&bijou, clone), b"original");
+
+    write(&bijou, clone, b"cloned");
+    assert_eq!(read(&bijou, file), b"changed");
+    assert_eq!(read(&bijou, clone), b"cloned");
+}
+
+#[test]
+fn unlink_original() {
+    let (bijou, _, clone) = setup("clone-unlink-original");
+    bijou.unlink(FileId::ROOT, "file").unwrap();
+    assert_eq!(read(&bijou, clone), b"original");
+
+    write(&bijou, clone, b"changed");
+    assert_eq!(read(&bijou, clone), b"changed");
+    bijou.unlink(FileId::ROOT, "clone").unwrap();
+}
+
+#[test]
+fn unlink_clone() {
+    let (bijou, file, _) = setup("clone-unlink-clone");
+    bijou.unlink(FileId::ROOT, "clone").unwrap();
+    assert_eq!(read(&bijou, file), b"original");
+
+    write(&bijou, file, b"changed");
+    assert_eq!(read(&bijou, file), b"changed");
+}
+
+#[test]
+fn clone_opened_file() {
+    let (bijou, file, _) = setup("clone-opened");
+    let mut handle = bijou
+        .open_file_direct(file, OpenOptions::writable().truncate(true))
+        .unwrap();
+    assert_eq!(
+        bijou
+            .clone_file(file, FileId::ROOT, "another", None)
+            .unwrap_err()
+            .kind(),
+        ErrorKind::InvalidInput
+    );
+
+    // clones made once the handle is closed see what it has written
+    handle.write(b"changed", 0).unwrap();
+    drop(handle);
+    let another = bijou
+        .clone_file(file, FileId::ROOT, "another", None)
+        .unwrap()
+        .id;
+    assert_eq!(read(&bijou, another), b"changed");
+    assert_eq!(
+        read(&bijou, bijou.lookup(FileId::ROOT, "clone").unwrap()),
+        b"original"
+    );
+}
@@ -0,0 +1,118 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Databases of older versions keep directory entries and xattrs along
+//! with the other records, and are migrated into their own column
+//! families when opened.
+
+mod common;
+
+use bijou::{Bijou, FileId, FileKind};
+use bijou_rocksdb::{IteratorMode, Options, WriteBatch, DB};
+use common::TempBijou;
+use std::path::Path;
+
+const COLUMNS: [&str; 2] = ["dirs", "xattrs"];
+
+type Records = Vec<(Box<[u8]>, Box<[u8]>)>;
+
+/// Moves the records of the column families back into the default
+/// one, as older versions kept them.
+fn downgrade(path: &Path) {
+    let mut db = DB::open_cf(&Options::default(), path, COLUMNS).unwrap();
+    let mut batch = WriteBatch::default();
+    for name in COLUMNS {
+        let cf = db.cf_handle(name).unwrap();
+        for entry in db.iterator_cf(cf, IteratorMode::Start) {
+            let (key, value) = entry.unwrap();
+            batch.put(key, value);
+        }
+    }
+    db.write(batch).unwrap();
+    for name in COLUMNS {
+        db.drop_cf(name).unwrap();
+    }
+}
+
+/// Returns the directory entries and xattrs in the database, with the
+/// ones left in the default column family last.
+fn dump(path: &Path) -> (Vec<Records>, Records) {
+    let db = DB::open_cf(&Options::default(), path, COLUMNS).unwrap();
+    let columns = COLUMNS
+        .iter()
+        .map(|name| {
+            let cf = db.cf_handle(name).unwrap();
+            db.iterator_cf(cf, IteratorMode::Start)
+                .map(Result::unwrap)
+                .collect()
+        })
+        .collect();
+    // the root byte and the file ID, followed by the derivation
+    let left = db
+        .iterator(IteratorMode::Start)
+        .map(Result::unwrap)
+        .filter(|(key, _)| key.first() == Some(&b'f') && matches!(key.get(9), Some(b':' | b'x')))
+        .collect();
+    (columns, left)
+}
+
+fn check(bijou: &Bijou, dir: FileId, file: FileId) {
+    let names = bijou
+        .read_dir(dir)
+        .unwrap()
+        .dots(false)
+        .map(|entry| entry.unwrap().0)
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["file"]);
+    assert_eq!(bijou.lookup(FileId::ROOT, "dir").unwrap(), dir);
+    let value = bijou.get_xattr(file, "user.test", |value| {
+        value.unwrap().map(|value| value.to_vec())
+    });
+    assert_eq!(value.as_deref(), Some(&b"value"[..]));
+}
+
+#[test]
+fn migrate_columns() {
+    let mut bijou = TempBijou::with("columns", |builder| {
+        builder.db_encryption(false).xattr_gets(true);
+    });
+    let dir = bijou
+        .make_node(FileId::ROOT, "dir", FileKind::Directory, None, None)
+        .unwrap()
+        .id;
+    let file = bijou
+        .make_node(dir, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    bijou.set_xattr(file, "user.test", b"value").unwrap();
+
+    bijou.reopen(|path| {
+        let db = path.join("db");
+        downgrade(&db);
+        assert_eq!(DB::list_cf(&Options::default(), &db).unwrap(), ["default"]);
+    });
+    check(&bijou, dir, file);
+
+    let mut migrated = None;
+    bijou.reopen(|path| migrated = Some(dump(&path.join("db"))));
+    let migrated = migrated.unwrap();
+    assert!(migrated.0.iter().all(|records| !records.is_empty()));
+    assert!(migrated.1.is_empty());
+    check(&bijou, dir, file);
+
+    // nothing is left to migrate the second time
+    bijou.reopen(|path| assert_eq!(dump(&path.join("db")), migrated));
+    check(&bijou, dir, file);
+}
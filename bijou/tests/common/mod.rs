@@ -0,0 +1,127 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use bijou::{Bijou, BijouBuilder, BijouFs, Limit};
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// A Bijou in a temporary directory, which is removed on drop.
+pub struct TempBijou {
+    bijou: Option<Arc<Bijou>>,
+    path: PathBuf,
+}
+
+impl TempBijou {
+    #[allow(dead_code)]
+    pub fn new(name: &str) -> Self {
+        Self::with(name, |_| {})
+    }
+
+    /// Creates a Bijou configured by `f`.
+    pub fn with(name: &str, f: impl FnOnce(&mut BijouBuilder)) -> Self {
+        Self::build(name, |mut builder| {
+            f(&mut builder);
+            builder.create(b"password".to_vec())
+        })
+    }
+
+    /// Creates an embedded Bijou, see [`BijouBuilder::compact`].
+    #[allow(dead_code)]
+    pub fn compact(name: &str) -> Self {
+        Self::build(name, |builder| {
+            builder.compact().create(b"password".to_vec())
+        })
+    }
+
+    fn build(name: &str, create: impl FnOnce(BijouBuilder) -> bijou::Result<()>) -> Self {
+        bijou::init().unwrap();
+
+        let path = std::env::temp_dir().join(format!("bijou-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let mut builder = BijouBuilder::new(&path);
+        builder
+            .ops_limit(Limit::Interactive)
+            .mem_limit(Limit::Interactive);
+        create(builder).unwrap();
+        let bijou = Bijou::open(&path, b"password".to_vec()).unwrap();
+
+        Self {
+            bijou: Some(Arc::new(bijou)),
+            path,
+        }
+    }
+
+    /// Returns a [`BijouFs`] of this Bijou, which should be dropped
+    /// before it.
+    #[allow(dead_code)]
+    pub fn fs(&self) -> BijouFs {
+        BijouFs::new(Arc::clone(self.bijou.as_ref().unwrap()))
+    }
+
+    /// Returns the shared Bijou.
+    #[allow(dead_code)]
+    pub fn arc(&self) -> &Arc<Bijou> {
+        self.bijou.as_ref().unwrap()
+    }
+
+    /// Returns a mutable reference to the Bijou, which must not be
+    /// shared by a [`BijouFs`] at the time.
+    #[allow(dead_code)]
+    pub fn get_mut(&mut self) -> &mut Bijou {
+        Arc::get_mut(self.bijou.as_mut().unwrap()).unwrap()
+    }
+
+    /// Closes the Bijou, which must not be shared at the time, runs
+    /// `f` with its path and opens it again.
+    #[allow(dead_code)]
+    pub fn reopen(&mut self, f: impl FnOnce(&Path)) {
+        self.reopen_with(f, |path, password| Bijou::open(path, password));
+    }
+
+    /// Same as [`TempBijou::reopen`], but opens the Bijou read-only.
+    #[allow(dead_code)]
+    pub fn reopen_read_only(&mut self, f: impl FnOnce(&Path)) {
+        self.reopen_with(f, |path, password| Bijou::open_read_only(path, password));
+    }
+
+    fn reopen_with(
+        &mut self,
+        f: impl FnOnce(&Path),
+        open: impl FnOnce(&Path, Vec<u8>) -> bijou::Result<Bijou>,
+    ) {
+        drop(self.bijou.take());
+        f(&self.path);
+        let bijou = open(&self.path, b"password".to_vec()).unwrap();
+        self.bijou = Some(Arc::new(bijou));
+    }
+}
+
+impl Deref for TempBijou {
+    type Target = Bijou;
+
+    fn deref(&self) -> &Bijou {
+        self.bijou.as_ref().unwrap()
+    }
+}
+
+impl Drop for TempBijou {
+    fn drop(&mut self) {
+        drop(self.bijou.take());
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,70 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! A Bijou whose storage is unreachable can still be browsed, and
+//! recovers once the storage is back.
+
+mod common;
+
+use bijou::{config::FileStorage, ErrorKind, FileId, FileKind, MockClock, OpenOptions};
+use chrono::Utc;
+use common::TempBijou;
+use std::{fs, sync::Arc, time::Duration};
+
+#[test]
+fn degraded_mode() {
+    let mut bijou = TempBijou::with("degraded", |builder| {
+        builder
+            .storage(FileStorage::Tracking {
+                inner: Box::new(FileStorage::Local),
+            })
+            .offline_fallback(true);
+    });
+    let file = bijou
+        .make_node(FileId::ROOT, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    bijou
+        .open_file_direct(file, OpenOptions::writable())
+        .unwrap()
+        .write(b"hello", 0)
+        .unwrap();
+    assert!(!bijou.is_degraded());
+
+    // the database is still there, but the storage is gone
+    bijou.reopen_read_only(|path| fs::rename(path.join("data"), path.join("data.away")).unwrap());
+    let clock = Arc::new(MockClock::new(Utc::now()));
+    bijou.get_mut().set_clock(Arc::clone(&clock));
+    assert!(bijou.is_degraded());
+    assert_eq!(bijou.get_meta(file).unwrap().size, 5);
+    let err = bijou
+        .open_file_direct(file, OpenOptions::read_only())
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), ErrorKind::StorageUnavailable);
+
+    fs::rename(bijou.path().join("data.away"), bijou.path().join("data")).unwrap();
+    // not probed again yet
+    assert!(bijou.is_degraded());
+    clock.advance(Duration::from_secs(10));
+    assert!(!bijou.is_degraded());
+    let mut buffer = [0; 5];
+    bijou
+        .open_file_direct(file, OpenOptions::read_only())
+        .unwrap()
+        .read(&mut buffer, 0)
+        .unwrap();
+    assert_eq!(&buffer, b"hello");
+}
@@ -0,0 +1,45 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+mod common;
+
+use bijou::{FileId, FileKind, OpenOptions};
+use common::TempBijou;
+
+#[test]
+fn embedded() {
+    let bijou = TempBijou::compact("embedded");
+    for name in ["keystore.json", "config.json"] {
+        assert!(!bijou.path().join(name).exists());
+    }
+
+    let file = bijou
+        .make_node(FileId::ROOT, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    let data = b"hello, world";
+    let mut writer = bijou
+        .open_file_direct(file, OpenOptions::writable())
+        .unwrap();
+    writer.write(data, 0).unwrap();
+    drop(writer);
+
+    let reader = bijou
+        .open_file_direct(file, OpenOptions::read_only())
+        .unwrap();
+    let mut buffer = [0; 32];
+    let read = reader.read(&mut buffer, 0).unwrap();
+    assert_eq!(&buffer[..read as usize], data);
+}
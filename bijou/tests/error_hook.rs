@@ -0,0 +1,43 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use bijou::{Error, ErrorKind, ErrorOrigin};
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn hook_receives_severe_errors() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    bijou::set_error_hook({
+        let events = Arc::clone(&events);
+        move |event| {
+            events
+                .lock()
+                .unwrap()
+                .push((event.origin, event.error.kind()));
+        }
+    });
+
+    assert_eq!(Error::new(ErrorKind::IOError, None).to_libc(), libc::EIO);
+    let expected = Error::new(ErrorKind::NotFound, None).take_it_easy();
+    assert_eq!(expected.to_libc(), libc::ENOENT);
+    assert_eq!(
+        *events.lock().unwrap(),
+        [(ErrorOrigin::Operation, ErrorKind::IOError)]
+    );
+
+    bijou::clear_error_hook();
+    Error::new(ErrorKind::IOError, None).to_libc();
+    assert_eq!(events.lock().unwrap().len(), 1);
+}
@@ -0,0 +1,54 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Creation and backup times are kept in xattrs.
+
+mod common;
+
+use bijou::{ExtraTime, FileId, FileKind};
+use chrono::{TimeZone, Utc};
+use common::TempBijou;
+
+#[test]
+fn extra_times() {
+    let bijou = TempBijou::new("extra_time");
+    let file = bijou
+        .make_node(FileId::ROOT, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    assert_eq!(bijou.extra_time(file, ExtraTime::Creation).unwrap(), None);
+
+    let created = Utc.timestamp_opt(1_000_000_000, 123_456_789).unwrap();
+    bijou
+        .set_extra_time(file, ExtraTime::Creation, created)
+        .unwrap();
+    assert_eq!(
+        bijou.extra_time(file, ExtraTime::Creation).unwrap(),
+        Some(created)
+    );
+    assert_eq!(bijou.extra_time(file, ExtraTime::Backup).unwrap(), None);
+
+    bijou
+        .set_xattr(file, ExtraTime::Backup.xattr(), b"2001-09-09T01:46:40Z")
+        .unwrap();
+    assert_eq!(
+        bijou.extra_time(file, ExtraTime::Backup).unwrap(),
+        Some(Utc.timestamp_opt(1_000_000_000, 0).unwrap())
+    );
+    bijou
+        .set_xattr(file, ExtraTime::Backup.xattr(), b"yesterday")
+        .unwrap();
+    assert_eq!(bijou.extra_time(file, ExtraTime::Backup).unwrap(), None);
+}
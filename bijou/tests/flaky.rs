@@ -0,0 +1,101 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Failures of the storage and the database must surface as errors,
+//! without corrupting what has been written successfully.
+
+mod common;
+
+use bijou::{config::FileStorage, CheckOptions, FileId, FileKind, OpenOptions, Result};
+use common::TempBijou;
+
+const ATTEMPTS: usize = 100;
+
+fn retry<T>(mut f: impl FnMut() -> Result<T>) -> T {
+    for _ in 1..ATTEMPTS {
+        if let Ok(value) = f() {
+            return value;
+        }
+    }
+    f().unwrap()
+}
+
+#[test]
+fn flaky_storage() {
+    let bijou = TempBijou::with("flaky", |builder| {
+        builder.storage(FileStorage::Flaky {
+            inner: Box::new(FileStorage::Local),
+            failure_rate: 0.2,
+            latency: 1,
+        });
+    });
+
+    let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+    // A failed creation may leave the name taken, so each attempt uses a
+    // fresh one.
+    let mut attempt = 0;
+    let file = retry(|| {
+        attempt += 1;
+        let name = format!("file{attempt}");
+        bijou.make_node(FileId::ROOT, &name, FileKind::File, None, None)
+    })
+    .id;
+    let mut writer = retry(|| bijou.open_file_direct(file, OpenOptions::writable()));
+    for (index, chunk) in data.chunks(4096).enumerate() {
+        retry(|| writer.write(chunk, (index * 4096) as u64));
+    }
+    drop(writer);
+
+    let reader = retry(|| bijou.open_file_direct(file, OpenOptions::read_only()));
+    let mut buffer = vec![0; data.len()];
+    let read = retry(|| reader.read(&mut buffer, 0));
+    assert_eq!(read as usize, data.len());
+    assert_eq!(buffer, data);
+}
+
+#[test]
+fn failed_unlink_keeps_content() {
+    let bijou = TempBijou::new("flaky-unlink");
+
+    let data = b"still here";
+    let file = bijou
+        .make_node(FileId::ROOT, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    bijou
+        .open_file_direct(file, OpenOptions::writable())
+        .unwrap()
+        .write(data, 0)
+        .unwrap();
+    bijou
+        .make_node(FileId::ROOT, "other", FileKind::File, None, None)
+        .unwrap();
+
+    bijou.fail_commits(true);
+    assert!(bijou.unlink(FileId::ROOT, "file").is_err());
+    assert!(bijou
+        .rename(FileId::ROOT, "other", FileId::ROOT, "file")
+        .is_err());
+    bijou.fail_commits(false);
+
+    assert_eq!(bijou.lookup(FileId::ROOT, "file").unwrap(), file);
+    let reader = bijou
+        .open_file_direct(file, OpenOptions::read_only())
+        .unwrap();
+    let mut buffer = vec![0; data.len()];
+    assert_eq!(reader.read(&mut buffer, 0).unwrap() as usize, data.len());
+    assert_eq!(buffer, data);
+    assert!(bijou.check(&CheckOptions::default()).unwrap().is_ok());
+}
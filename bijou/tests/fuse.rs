@@ -0,0 +1,365 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! End-to-end tests driving a real FUSE mount through the kernel,
+//! covering a POSIX conformance subset in the spirit of pjdfstest.
+//!
+//! Requires the `fuse-tests` feature and access to `/dev/fuse`:
+//!
+//! ```bash
+//! cargo test -p bijou --features fuse-tests --test fuse
+//! ```
+//!
+//! On FreeBSD, the `fusefs` kernel module has to be loaded first
+//! (`kldload fusefs`), and `vfs.usermount` set for non-root users.
+
+use bijou::{Bijou, BijouBuilder, BijouFuse, Limit};
+use std::{
+    fs,
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// A Bijou in a temporary directory, mounted at another temporary
+/// directory. Unmounted and removed on drop.
+struct TempMount {
+    unmount: Option<Box<dyn FnOnce()>>,
+    root: PathBuf,
+    mount_point: PathBuf,
+}
+
+impl TempMount {
+    const MOUNT_TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn new(name: &str) -> Self {
+        bijou::init().unwrap();
+
+        let root = std::env::temp_dir().join(format!("bijou-fuse-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let data = root.join("data");
+        let mount_point = root.join("mnt");
+        fs::create_dir_all(&mount_point).unwrap();
+
+        BijouBuilder::new(&data)
+            .ops_limit(Limit::Interactive)
+            .mem_limit(Limit::Interactive)
+            .xattr_gets(true)
+            .create(b"password".to_vec())
+            .unwrap();
+        let bijou = Bijou::open(&data, b"password".to_vec()).unwrap();
+        let mut unmounter = BijouFuse::new(Arc::new(bijou))
+            .mount(&mount_point, &[])
+            .unwrap();
+
+        // mounting is asynchronous, wait until the mount point
+        // belongs to another device
+        let parent_dev = fs::metadata(&root).unwrap().dev();
+        let start = Instant::now();
+        while fs::metadata(&mount_point).unwrap().dev() == parent_dev {
+            assert!(start.elapsed() < Self::MOUNT_TIMEOUT, "mount timed out");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        Self {
+            unmount: Some(Box::new(move || unmounter.unmount().unwrap())),
+            root,
+            mount_point,
+        }
+    }
+
+    fn path(&self, path: &str) -> PathBuf {
+        self.mount_point.join(path)
+    }
+}
+
+impl Drop for TempMount {
+    fn drop(&mut self) {
+        if let Some(unmount) = self.unmount.take() {
+            unmount();
+        }
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+fn error_kind<T: std::fmt::Debug>(result: std::io::Result<T>) -> ErrorKind {
+    result.unwrap_err().kind()
+}
+
+fn raw_error<T: std::fmt::Debug>(result: std::io::Result<T>) -> Option<i32> {
+    result.unwrap_err().raw_os_error()
+}
+
+fn list(dir: &Path) -> Vec<String> {
+    let mut names = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+#[test]
+fn create_write_read() {
+    let mount = TempMount::new("create");
+    let path = mount.path("file");
+
+    fs::write(&path, b"hello").unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"hello");
+    assert_eq!(fs::metadata(&path).unwrap().len(), 5);
+
+    let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(b", world").unwrap();
+    drop(file);
+    assert_eq!(fs::read(&path).unwrap(), b"hello, world");
+
+    // sparse write past the end
+    let mut file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.seek(SeekFrom::Start(1 << 16)).unwrap();
+    file.write_all(b"!").unwrap();
+    drop(file);
+    let content = fs::read(&path).unwrap();
+    assert_eq!(content.len(), (1 << 16) + 1);
+    assert!(content[12..1 << 16].iter().all(|&b| b == 0));
+
+    assert_eq!(
+        error_kind(
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+        ),
+        ErrorKind::AlreadyExists
+    );
+    assert_eq!(
+        error_kind(fs::read(mount.path("missing"))),
+        ErrorKind::NotFound
+    );
+}
+
+#[test]
+fn rename() {
+    let mount = TempMount::new("rename");
+    fs::create_dir(mount.path("a")).unwrap();
+    fs::create_dir(mount.path("a/b")).unwrap();
+    fs::write(mount.path("x"), b"x").unwrap();
+    fs::write(mount.path("y"), b"y").unwrap();
+
+    fs::rename(mount.path("x"), mount.path("a/x")).unwrap();
+    assert_eq!(fs::read(mount.path("a/x")).unwrap(), b"x");
+    assert_eq!(
+        error_kind(fs::metadata(mount.path("x"))),
+        ErrorKind::NotFound
+    );
+
+    // replaces the target
+    fs::rename(mount.path("y"), mount.path("a/x")).unwrap();
+    assert_eq!(fs::read(mount.path("a/x")).unwrap(), b"y");
+
+    assert_eq!(
+        raw_error(fs::rename(mount.path("a"), mount.path("a/b/c"))),
+        Some(libc::EINVAL)
+    );
+    assert_eq!(
+        error_kind(fs::rename(mount.path("a/x"), mount.path("missing/x"))),
+        ErrorKind::NotFound
+    );
+
+    fs::rename(mount.path("a/b"), mount.path("b")).unwrap();
+    assert_eq!(list(&mount.mount_point), ["a", "b"]);
+    assert_eq!(list(&mount.path("a")), ["x"]);
+}
+
+#[test]
+fn unlink() {
+    let mount = TempMount::new("unlink");
+    fs::create_dir(mount.path("dir")).unwrap();
+    fs::write(mount.path("dir/file"), b"").unwrap();
+
+    assert_eq!(
+        raw_error(fs::remove_dir(mount.path("dir"))),
+        Some(libc::ENOTEMPTY)
+    );
+
+    // an opened file stays readable after being unlinked
+    fs::write(mount.path("opened"), b"data").unwrap();
+    let mut file = fs::File::open(mount.path("opened")).unwrap();
+    fs::remove_file(mount.path("opened")).unwrap();
+    let mut content = Vec::new();
+    file.read_to_end(&mut content).unwrap();
+    assert_eq!(content, b"data");
+
+    fs::remove_file(mount.path("dir/file")).unwrap();
+    fs::remove_dir(mount.path("dir")).unwrap();
+    assert!(list(&mount.mount_point).is_empty());
+}
+
+#[test]
+fn permissions() {
+    let mount = TempMount::new("perms");
+    let path = mount.path("file");
+    fs::write(&path, b"secret").unwrap();
+
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().mode() & 0o777, 0o640);
+
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o000)).unwrap();
+    // root bypasses permission checks
+    if unsafe { libc::geteuid() } != 0 {
+        assert_eq!(error_kind(fs::read(&path)), ErrorKind::PermissionDenied);
+    }
+}
+
+#[test]
+fn symlinks_and_hard_links() {
+    let mount = TempMount::new("links");
+    fs::write(mount.path("target"), b"content").unwrap();
+
+    std::os::unix::fs::symlink("target", mount.path("link")).unwrap();
+    assert_eq!(
+        fs::read_link(mount.path("link")).unwrap(),
+        Path::new("target")
+    );
+    assert!(fs::symlink_metadata(mount.path("link"))
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert_eq!(fs::read(mount.path("link")).unwrap(), b"content");
+
+    std::os::unix::fs::symlink("missing", mount.path("dangling")).unwrap();
+    assert_eq!(
+        error_kind(fs::read(mount.path("dangling"))),
+        ErrorKind::NotFound
+    );
+
+    fs::hard_link(mount.path("target"), mount.path("hard")).unwrap();
+    assert_eq!(fs::metadata(mount.path("target")).unwrap().nlink(), 2);
+    assert_eq!(
+        fs::metadata(mount.path("target")).unwrap().ino(),
+        fs::metadata(mount.path("hard")).unwrap().ino()
+    );
+    fs::write(mount.path("hard"), b"changed").unwrap();
+    assert_eq!(fs::read(mount.path("target")).unwrap(), b"changed");
+
+    fs::remove_file(mount.path("target")).unwrap();
+    assert_eq!(fs::metadata(mount.path("hard")).unwrap().nlink(), 1);
+}
+
+#[test]
+fn xattrs() {
+    let mount = TempMount::new("xattrs");
+    let path = mount.path("file");
+    fs::write(&path, b"").unwrap();
+
+    xattr::set(&path, "user.test", b"value").unwrap();
+    assert_eq!(xattr::get(&path, "user.test").unwrap().unwrap(), b"value");
+    assert!(xattr::list(&path).unwrap().any(|name| name == "user.test"));
+    xattr::remove(&path, "user.test").unwrap();
+    assert_eq!(xattr::get(&path, "user.test").unwrap(), None);
+
+    // virtual xattrs of the root are read-only
+    assert!(xattr::get(&mount.mount_point, "user.bijou.stats")
+        .unwrap()
+        .is_some());
+    assert!(xattr::set(&mount.mount_point, "user.bijou.stats", b"").is_err());
+}
+
+#[test]
+fn mmap() {
+    let mount = TempMount::new("mmap");
+    let path = mount.path("file");
+    let content = (0..1 << 16).map(|i| i as u8).collect::<Vec<_>>();
+    fs::write(&path, &content).unwrap();
+
+    // files opened for writing use direct I/O, which not every
+    // kernel can mmap, so only read-only mappings are tested
+    let file = fs::File::open(&path).unwrap();
+    let mapped = unsafe {
+        use std::os::fd::AsRawFd;
+        let ptr = libc::mmap(
+            std::ptr::null_mut(),
+            content.len(),
+            libc::PROT_READ,
+            libc::MAP_PRIVATE,
+            file.as_raw_fd(),
+            0,
+        );
+        assert_ne!(ptr, libc::MAP_FAILED);
+        let mapped = std::slice::from_raw_parts(ptr as *const u8, content.len()).to_vec();
+        libc::munmap(ptr, content.len());
+        mapped
+    };
+    assert_eq!(mapped, content);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn chattr() {
+    use std::os::fd::AsRawFd;
+
+    // See linux/fs.h
+    const FS_IOC_SETFLAGS: libc::c_ulong = 1 << 30
+        | (std::mem::size_of::<libc::c_long>() as libc::c_ulong) << 16
+        | (b'f' as libc::c_ulong) << 8
+        | 2;
+    const FS_IMMUTABLE_FL: libc::c_int = 0x10;
+
+    fn set_flags(file: &fs::File, flags: libc::c_int) -> std::io::Result<()> {
+        if unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_SETFLAGS as _, &flags) } == 0 {
+            Ok(())
+        } else {
+            Err(std::io::Error::last_os_error())
+        }
+    }
+
+    let mount = TempMount::new("chattr");
+    let path = mount.path("file");
+    fs::write(&path, b"content").unwrap();
+    let file = fs::File::open(&path).unwrap();
+
+    // setting the immutable flag requires CAP_LINUX_IMMUTABLE
+    if unsafe { libc::geteuid() } != 0 {
+        assert_eq!(
+            raw_error(set_flags(&file, FS_IMMUTABLE_FL)),
+            Some(libc::EPERM)
+        );
+        return;
+    }
+
+    set_flags(&file, FS_IMMUTABLE_FL).unwrap();
+    assert_eq!(
+        error_kind(fs::write(&path, b"changed")),
+        ErrorKind::PermissionDenied
+    );
+    assert_eq!(raw_error(fs::remove_file(&path)), Some(libc::EPERM));
+
+    // other users may not change the flags
+    let denied = std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                unsafe { libc::setfsuid(65534) };
+                raw_error(set_flags(&file, 0))
+            })
+            .join()
+            .unwrap()
+    });
+    assert_eq!(denied, Some(libc::EPERM));
+
+    set_flags(&file, 0).unwrap();
+    fs::write(&path, b"changed").unwrap();
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,80 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Importing directories of the local filesystem, as done when
+//! migrating from other encrypted filesystems.
+
+#![cfg(unix)]
+
+mod common;
+
+use bijou::{FileId, OpenOptions};
+use common::TempBijou;
+use std::{
+    fs::{self, Permissions},
+    os::unix::fs::{symlink, PermissionsExt},
+    time::{Duration, SystemTime},
+};
+
+#[test]
+fn import() {
+    let bijou = TempBijou::new("import");
+    let src = std::env::temp_dir().join(format!("bijou-import-src-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&src);
+    fs::create_dir_all(src.join("dir")).unwrap();
+    fs::write(src.join("dir/file"), b"hello").unwrap();
+    fs::set_permissions(src.join("dir/file"), Permissions::from_mode(0o640)).unwrap();
+    fs::hard_link(src.join("dir/file"), src.join("hard")).unwrap();
+    symlink("dir/file", src.join("link")).unwrap();
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    fs::File::open(src.join("dir"))
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+
+    let stats = bijou.import(&src, FileId::ROOT).unwrap();
+    fs::remove_dir_all(&src).unwrap();
+    assert_eq!(stats.files, 1);
+    assert_eq!(stats.directories, 1);
+    assert_eq!(stats.symlinks, 1);
+    assert_eq!(stats.hard_links, 1);
+    assert_eq!(stats.bytes, 5);
+
+    let file = bijou.resolve("/dir/file").unwrap();
+    assert_eq!(bijou.resolve("/hard").unwrap(), file);
+    let meta = bijou.get_meta(file).unwrap();
+    assert_eq!(meta.nlinks, 2);
+    assert_eq!(meta.perms.unwrap().mode, 0o640);
+    let reader = bijou
+        .open_file_direct(file, OpenOptions::read_only())
+        .unwrap();
+    let mut buffer = [0; 16];
+    let read = reader.read(&mut buffer, 0).unwrap();
+    assert_eq!(&buffer[..read as usize], b"hello");
+
+    let link = bijou.lookup(FileId::ROOT, "link").unwrap();
+    assert_eq!(bijou.read_link(link).unwrap(), "dir/file");
+
+    let dir = bijou.resolve("/dir").unwrap();
+    let meta = bijou.get_meta(dir).unwrap();
+    assert_eq!(meta.modified.timestamp(), 1_000_000_000);
+}
+
+#[test]
+fn import_read_only() {
+    let mut bijou = TempBijou::new("import-read-only");
+    bijou.reopen_read_only(|_| {});
+    assert!(bijou.import(std::env::temp_dir(), FileId::ROOT).is_err());
+}
@@ -0,0 +1,58 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! The UUID and label of a Bijou can be read without unlocking it, and
+//! bind its config to its key store.
+
+mod common;
+
+use bijou::{Bijou, ErrorKind, KeyStore};
+use common::TempBijou;
+
+#[test]
+fn label() {
+    let mut bijou = TempBijou::with("label", |builder| {
+        builder.label("backups");
+    });
+    let uuid = bijou.config().uuid.clone().unwrap();
+    assert_eq!(uuid.len(), 36);
+    assert_eq!(bijou.config().label.as_deref(), Some("backups"));
+
+    let keystore = KeyStore::load(bijou.path()).unwrap();
+    assert_eq!(keystore.uuid(), Some(uuid.as_str()));
+    assert_eq!(keystore.label(), Some("backups"));
+
+    bijou.get_mut().set_label(None).unwrap();
+    assert_eq!(bijou.config().label, None);
+    let keystore = KeyStore::load(bijou.path()).unwrap();
+    assert_eq!(keystore.uuid(), Some(uuid.as_str()));
+    assert_eq!(keystore.label(), None);
+}
+
+#[test]
+fn mismatched_config() {
+    let first = TempBijou::new("mismatched-config-1");
+    let second = TempBijou::new("mismatched-config-2");
+
+    std::fs::copy(
+        first.path().join("config.json"),
+        second.path().join("config.json"),
+    )
+    .unwrap();
+    let err = Bijou::open(second.path(), b"password".to_vec())
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), ErrorKind::Mismatch);
+}
@@ -0,0 +1,94 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+mod common;
+
+use bijou::{ErrorKind, FileId, FileKind};
+use common::TempBijou;
+
+#[test]
+fn hard_links_to_symlinks() {
+    let bijou = TempBijou::new("link-symlink");
+
+    let symlink = bijou
+        .make_node(
+            FileId::ROOT,
+            "a",
+            FileKind::Symlink,
+            Some("target".to_owned()),
+            None,
+        )
+        .unwrap()
+        .id;
+    let meta = bijou.link(symlink, FileId::ROOT, "b").unwrap();
+    assert_eq!(meta.kind, FileKind::Symlink);
+    assert_eq!(bijou.get_meta(symlink).unwrap().nlinks, 2);
+    assert_eq!(bijou.lookup(FileId::ROOT, "b").unwrap(), symlink);
+
+    // the target is shared, and kept until the last link is removed
+    assert_eq!(bijou.unlink(FileId::ROOT, "a").unwrap(), None);
+    assert_eq!(bijou.get_meta(symlink).unwrap().nlinks, 1);
+    assert_eq!(bijou.read_link(symlink).unwrap(), "target");
+
+    assert_eq!(bijou.unlink(FileId::ROOT, "b").unwrap(), Some(symlink));
+    assert_eq!(
+        bijou.read_link(symlink).unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+    assert_eq!(
+        bijou.get_meta(symlink).unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+}
+
+#[test]
+fn hard_links_to_directories() {
+    let bijou = TempBijou::new("link-dir");
+
+    let dir = bijou
+        .make_node(FileId::ROOT, "dir", FileKind::Directory, None, None)
+        .unwrap()
+        .id;
+    let err = bijou.link(dir, FileId::ROOT, "other").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn paths_of_hard_links() {
+    let bijou = TempBijou::with("link-path-of", |builder| {
+        builder.filename_encryption(true).reverse_index(true);
+    });
+
+    let dir = bijou
+        .make_node(FileId::ROOT, "dir", FileKind::Directory, None, None)
+        .unwrap()
+        .id;
+    let file = bijou
+        .make_node(dir, "a", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    bijou.link(file, FileId::ROOT, "b").unwrap();
+
+    let mut paths = bijou.path_of(file).unwrap();
+    paths.sort();
+    assert_eq!(paths, ["/b", "/dir/a"]);
+
+    bijou
+        .rename(FileId::ROOT, "dir", FileId::ROOT, "moved")
+        .unwrap();
+    bijou.unlink(FileId::ROOT, "b").unwrap();
+    assert_eq!(bijou.path_of(file).unwrap(), ["/moved/a"]);
+    assert_eq!(bijou.path_of(dir).unwrap(), ["/moved"]);
+}
@@ -0,0 +1,54 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! The metadata of a Bijou can be backed up alone, and restored over
+//! data that has changed since.
+
+mod common;
+
+use bijou::{Bijou, FileId, FileKind, OpenOptions};
+use common::TempBijou;
+
+#[test]
+fn export_and_import_meta() {
+    let mut bijou = TempBijou::new("meta-backup");
+    let file = bijou
+        .make_node(FileId::ROOT, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    bijou
+        .open_file_direct(file, OpenOptions::new().write(true))
+        .unwrap()
+        .write(b"hello", 0)
+        .unwrap();
+
+    let backup =
+        std::env::temp_dir().join(format!("bijou-meta-backup-dest-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&backup);
+    bijou.export_meta(&backup).unwrap();
+    assert!(backup.join("db").is_dir());
+    assert!(!backup.join("data").exists());
+
+    // the data is newer than the backup
+    bijou.unlink(FileId::ROOT, "file").unwrap();
+    bijou.reopen(|path| Bijou::import_meta(path, &backup).unwrap());
+    assert_eq!(bijou.lookup(FileId::ROOT, "file").unwrap(), file);
+
+    let report = bijou.reconcile().unwrap();
+    assert_eq!(report.recreated, [file]);
+    assert!(bijou.reconcile().unwrap().is_clean());
+
+    std::fs::remove_dir_all(&backup).unwrap();
+}
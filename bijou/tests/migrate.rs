@@ -0,0 +1,181 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Storage migrations interrupted between files and resumed.
+//!
+//! Local storage keeps every raw file in the data directory, and only
+//! OpenDAL storage does not use it, so these migrate between the two.
+//! The latter is tracked, as it can't keep the metadata of files.
+
+mod common;
+
+use bijou::{
+    config::{FileStorage, OpenDALType},
+    CheckOptions, Clock, FileId, FileKind, MockClock,
+};
+use chrono::{DateTime, Utc};
+use common::TempBijou;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+const NAMES: [&str; 4] = ["a", "b", "c", "clone"];
+
+/// A clock that advances whenever it's looked at, so that tasks
+/// checkpoint after every file.
+#[derive(Default)]
+struct Ticking(MockClock);
+impl Clock for Ticking {
+    fn now(&self) -> DateTime<Utc> {
+        self.0.now()
+    }
+
+    fn instant(&self) -> Instant {
+        self.0.advance(Duration::from_secs(60));
+        self.0.instant()
+    }
+}
+
+fn memory() -> FileStorage {
+    FileStorage::Tracking {
+        inner: Box::new(FileStorage::OpenDAL {
+            ty: OpenDALType::Memory,
+            prefix: String::new(),
+            strict: false,
+        }),
+    }
+}
+
+fn content(name: &str) -> Vec<u8> {
+    let name = if name == "clone" { "a" } else { name };
+    name.repeat(10_000).into_bytes()
+}
+
+/// Where local storage keeps the raw file `id`.
+fn local_path(bijou: &Path, id: FileId) -> PathBuf {
+    let name = id.to_string();
+    let (dir, name) = name.split_at(2);
+    bijou.join("data").join(dir).join(name)
+}
+
+/// Creates the files in [`NAMES`], the last one being a clone of the
+/// first, and returns their content IDs in the order they are moved.
+fn populate(bijou: &TempBijou) -> Vec<FileId> {
+    let fs = bijou.fs();
+    for name in &NAMES[..3] {
+        fs.write(format!("/{name}"), content(name)).unwrap();
+    }
+    fs.clone_file("/a", "/clone").unwrap();
+    drop(fs);
+
+    let mut ids = Vec::new();
+    for meta in bijou.iter_files().kind(FileKind::File) {
+        let id = meta.unwrap().content_id();
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+    assert_eq!(ids.len(), 3);
+    ids
+}
+
+fn verify(bijou: &TempBijou, names: &[&str]) {
+    let fs = bijou.fs();
+    for name in names {
+        assert_eq!(fs.read(format!("/{name}")).unwrap(), content(name));
+    }
+}
+
+#[test]
+fn resume_interrupted_move() {
+    let mut bijou = TempBijou::new("migrate-resume");
+    bijou.get_mut().set_clock(Ticking::default());
+    let ids = populate(&bijou);
+    let paths: Vec<_> = ids.iter().map(|&id| local_path(bijou.path(), id)).collect();
+    let size: u64 = paths
+        .iter()
+        .map(|path| fs::metadata(path).unwrap().len())
+        .sum();
+
+    let task = bijou.start_storage_migration(memory()).unwrap();
+
+    // the second content goes missing, after the first has been moved
+    let hidden = paths[1].with_extension("hidden");
+    fs::rename(&paths[1], &hidden).unwrap();
+    bijou.get_mut().migrate_storage(task, None).unwrap_err();
+    assert!(matches!(
+        bijou.config().storage,
+        FileStorage::Migrating { .. }
+    ));
+    fs::rename(&hidden, &paths[1]).unwrap();
+    // usable in the meantime, with the first content read from the
+    // new storage and the others from the old one
+    verify(&bijou, &NAMES);
+
+    let state = bijou.get_mut().migrate_storage(task, None).unwrap();
+    // once to move them and once to clean up
+    assert_eq!(state.files, 2 * NAMES.len() as u64);
+    // resumed after the first content and copied the shared content
+    // once, so that every byte is only counted once
+    assert_eq!(state.bytes, size);
+    assert!(bijou.tasks().unwrap().is_empty());
+    assert_eq!(bijou.config().storage, memory());
+    verify(&bijou, &NAMES);
+
+    // cleaned up
+    for path in &paths {
+        assert!(!path.exists(), "{} is left", path.display());
+    }
+    assert!(bijou.check(&CheckOptions::default()).unwrap().is_ok());
+}
+
+#[test]
+fn unlink_partial_copy() {
+    let mut bijou = TempBijou::with("migrate-unlink", |builder| {
+        builder.storage(memory());
+    });
+    let ids = populate(&bijou);
+    let task = bijou.start_storage_migration(FileStorage::Local).unwrap();
+
+    // a directory can't be replaced by the copy, which interrupts the
+    // migration after the first content
+    let victim = local_path(bijou.path(), ids[1]);
+    fs::create_dir_all(&victim).unwrap();
+    bijou.get_mut().migrate_storage(task, None).unwrap_err();
+    fs::remove_dir(&victim).unwrap();
+    fs::write(&victim, b"partial").unwrap();
+    // never read from a partial copy
+    verify(&bijou, &NAMES);
+
+    let mut kept = Vec::new();
+    for name in NAMES {
+        let file = bijou.lookup(FileId::ROOT, name).unwrap();
+        if bijou.get_meta(file).unwrap().content_id() == ids[1] {
+            bijou.unlink(FileId::ROOT, name).unwrap();
+        } else {
+            kept.push(name);
+        }
+    }
+    assert!(!victim.exists());
+
+    bijou.get_mut().migrate_storage(task, None).unwrap();
+    assert_eq!(bijou.config().storage, FileStorage::Local);
+    verify(&bijou, &kept);
+    let report = bijou.check(&CheckOptions::default()).unwrap();
+    assert!(report.orphans_checked);
+    assert!(report.is_ok(), "{:?}", report.issues);
+}
@@ -0,0 +1,51 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Types of files are detected when their first bytes are written.
+
+mod common;
+
+use bijou::{mime, FileId, FileKind, OpenOptions};
+use common::TempBijou;
+
+#[test]
+fn sniffed_on_write() {
+    let bijou = TempBijou::with("mime", |builder| {
+        builder.sniff_mime_types(true);
+    });
+    let file = bijou
+        .make_node(FileId::ROOT, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    assert_eq!(bijou.mime_type(file).unwrap(), None);
+
+    let mut handle = bijou
+        .open_file_direct(file, OpenOptions::new().write(true))
+        .unwrap();
+    handle.write(b"\x89PNG\r\n\x1a\n", 0).unwrap();
+    assert_eq!(bijou.mime_type(file).unwrap().as_deref(), Some("image/png"));
+    // beyond the sniffed bytes
+    handle.write(&[0; 100], 4096).unwrap();
+    assert_eq!(bijou.mime_type(file).unwrap().as_deref(), Some("image/png"));
+
+    handle.write(b"<!DOCTYPE html>", 0).unwrap();
+    handle.set_len(15).unwrap();
+    assert_eq!(bijou.mime_type(file).unwrap().as_deref(), Some("text/html"));
+    handle.set_len(0).unwrap();
+    assert_eq!(bijou.mime_type(file).unwrap(), None);
+
+    assert_eq!(mime::sniff(b"hello\n"), Some("text/plain"));
+    assert_eq!(mime::sniff(&[0, 1, 2, 3]), None);
+}
@@ -0,0 +1,46 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+mod common;
+
+use bijou::{config::NormalizationPolicy, ErrorKind, FileId, FileKind};
+use common::TempBijou;
+
+const NFC: &str = "caf\u{e9}";
+const NFD: &str = "cafe\u{301}";
+
+#[test]
+fn reject_normalization_duplicates() {
+    let bijou = TempBijou::with("normalization", |builder| {
+        builder
+            .filename_encryption(true)
+            .normalization_duplicates(NormalizationPolicy::Reject);
+    });
+
+    bijou
+        .make_node(FileId::ROOT, NFD, FileKind::File, None, None)
+        .unwrap();
+    assert_eq!(
+        bijou
+            .make_node(FileId::ROOT, NFC, FileKind::File, None, None)
+            .unwrap_err()
+            .kind(),
+        ErrorKind::AlreadyExists
+    );
+
+    // renaming to the other form is allowed
+    bijou.rename(FileId::ROOT, NFD, FileId::ROOT, NFC).unwrap();
+    bijou.lookup(FileId::ROOT, NFC).unwrap();
+}
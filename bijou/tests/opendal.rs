@@ -0,0 +1,65 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+
+//! Files in OpenDAL storage, whose handles share what they cache.
+
+mod common;
+
+use bijou::{
+    config::{FileStorage, OpenDALType},
+    FileId, FileKind, OpenOptions,
+};
+use common::TempBijou;
+
+#[test]
+fn handles_see_each_other() {
+    let bijou = TempBijou::with("opendal", |builder| {
+        builder.storage(FileStorage::Tracking {
+            inner: Box::new(FileStorage::OpenDAL {
+                ty: OpenDALType::Memory,
+                prefix: String::new(),
+                strict: false,
+            }),
+        });
+    });
+    let file = bijou
+        .make_node(FileId::ROOT, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+
+    let mut writer = bijou
+        .open_file_direct(file, OpenOptions::writable())
+        .unwrap();
+    writer.write(b"hello", 0).unwrap();
+    writer.sync().unwrap();
+
+    let reader = bijou
+        .open_file_direct(file, OpenOptions::read_only())
+        .unwrap();
+    let mut buffer = [0; 16];
+    let read = reader.read(&mut buffer, 0).unwrap();
+    assert_eq!(&buffer[..read as usize], b"hello");
+
+    // the reader has read ahead, which must not be stale
+    writer.write(b"world", 0).unwrap();
+    writer.sync().unwrap();
+    let read = reader.read(&mut buffer, 0).unwrap();
+    assert_eq!(&buffer[..read as usize], b"world");
+
+    drop(writer);
+    let read = reader.read(&mut buffer, 0).unwrap();
+    assert_eq!(&buffer[..read as usize], b"world");
+}
@@ -0,0 +1,163 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+mod common;
+
+use bijou::{
+    password::{CommandSecret, EnvSecret, FileSecret, SecretProvider},
+    Bijou, ErrorKind, FileId, FileKind, KeyStore, Limit,
+};
+use common::TempBijou;
+
+#[test]
+fn change_password() {
+    let bijou = TempBijou::new("password");
+    bijou
+        .make_node(FileId::ROOT, "file", FileKind::File, None, None)
+        .unwrap();
+
+    assert!(bijou
+        .change_password(b"wrong".to_vec(), b"new".to_vec(), None, None)
+        .is_err());
+    bijou
+        .change_password(b"password".to_vec(), b"new".to_vec(), None, None)
+        .unwrap();
+    // the open instance keeps working
+    bijou
+        .make_node(FileId::ROOT, "other", FileKind::File, None, None)
+        .unwrap();
+
+    let keystore = KeyStore::load(bijou.path()).unwrap();
+    assert!(keystore.unlock(b"password".to_vec()).is_err());
+    keystore.unlock(b"new".to_vec()).unwrap();
+}
+
+#[test]
+fn change_key_derivation_cost() {
+    let bijou = TempBijou::new("password-cost");
+    let mem_limit = Bijou::probe(bijou.path()).unwrap().unwrap().mem_limit;
+
+    // weaker than the interactive limits
+    for (ops_limit, mem_limit) in [
+        (Some(Limit::Custom(1)), None),
+        (None, Some(Limit::Custom(8192))),
+    ] {
+        let err = bijou
+            .change_password(b"password".to_vec(), b"new".to_vec(), ops_limit, mem_limit)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    bijou
+        .change_password(
+            b"password".to_vec(),
+            b"new".to_vec(),
+            Some(Limit::Custom(3)),
+            None,
+        )
+        .unwrap();
+    let info = Bijou::probe(bijou.path()).unwrap().unwrap();
+    assert_eq!((info.ops_limit, info.mem_limit), (3, mem_limit));
+    KeyStore::load(bijou.path())
+        .unwrap()
+        .unlock(b"new".to_vec())
+        .unwrap();
+
+    // the read-only password is derived with the same cost
+    bijou
+        .set_read_only_password(b"new".to_vec(), Some(b"read only".to_vec().into()))
+        .unwrap();
+    let err = bijou
+        .change_password(
+            b"new".to_vec(),
+            b"newer".to_vec(),
+            Some(Limit::Custom(4)),
+            None,
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    bijou
+        .change_password(
+            b"new".to_vec(),
+            b"newer".to_vec(),
+            Some(Limit::Custom(3)),
+            None,
+        )
+        .unwrap();
+}
+
+#[test]
+fn read_only_password() {
+    let bijou = TempBijou::with("read-only-password", |builder| {
+        builder.read_only_password(b"read only".to_vec());
+    });
+    bijou
+        .make_node(FileId::ROOT, "file", FileKind::File, None, None)
+        .unwrap();
+
+    let keystore = KeyStore::load(bijou.path()).unwrap();
+    assert!(!keystore
+        .unlock(b"password".to_vec())
+        .unwrap()
+        .is_read_only());
+    assert!(keystore
+        .unlock(b"read only".to_vec())
+        .unwrap()
+        .is_read_only());
+
+    let reader = Bijou::open(bijou.path(), b"read only".to_vec()).unwrap();
+    assert!(reader.is_read_only());
+    assert!(reader.lookup(FileId::ROOT, "file").is_ok());
+    drop(reader);
+
+    // it cannot be used to gain full access
+    let err = bijou
+        .change_password(b"read only".to_vec(), b"new".to_vec(), None, None)
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    assert!(bijou
+        .set_read_only_password(b"read only".to_vec(), None)
+        .is_err());
+
+    bijou
+        .set_read_only_password(b"password".to_vec(), None)
+        .unwrap();
+    let keystore = KeyStore::load(bijou.path()).unwrap();
+    assert!(keystore.unlock(b"read only".to_vec()).is_err());
+}
+
+#[test]
+fn secret_providers() {
+    let path = std::env::temp_dir().join(format!("bijou-secret-{}", std::process::id()));
+    std::fs::write(&path, "from file\nignored\n").unwrap();
+    let secret = FileSecret(path.clone()).secret("").unwrap();
+    assert_eq!(&secret[..], b"from file");
+    std::fs::remove_file(&path).unwrap();
+
+    std::env::set_var("BIJOU_TEST_SECRET", "from env");
+    let secret = EnvSecret("BIJOU_TEST_SECRET".to_owned())
+        .secret("")
+        .unwrap();
+    assert_eq!(&secret[..], b"from env");
+
+    #[cfg(unix)]
+    {
+        let secret = CommandSecret("echo from command".to_owned())
+            .secret("")
+            .unwrap();
+        assert_eq!(&secret[..], b"from command");
+        assert!(CommandSecret("false".to_owned()).secret("").is_err());
+    }
+}
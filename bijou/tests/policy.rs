@@ -0,0 +1,137 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Password strength estimation, and policies rejecting passwords
+//! before the key is derived.
+
+mod common;
+
+use bijou::{
+    password::{estimate_strength, MinimumStrength, PasswordPolicy},
+    Bijou, BijouBuilder, Config, Error, ErrorKind, FileId, Limit,
+};
+use common::TempBijou;
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+fn temp_path(name: &str) -> PathBuf {
+    bijou::init().unwrap();
+    std::env::temp_dir().join(format!("bijou-{name}-{}", std::process::id()))
+}
+
+const WEAK: &[u8] = b"password";
+const STRONG: &[u8] = b"quartz lantern oyster 7 gravel umbrella";
+
+#[test]
+fn strength() {
+    let weak = estimate_strength(WEAK).unwrap();
+    assert!(weak.score <= 1);
+    assert!(!weak.feedback.is_empty());
+    assert_eq!(estimate_strength(STRONG).unwrap().score, 4);
+    assert_eq!(estimate_strength(b"").unwrap().score, 0);
+    assert!(estimate_strength(b"\xff\xfe").is_none());
+}
+
+#[test]
+fn minimum_strength() {
+    let policy = MinimumStrength(3);
+    assert_eq!(
+        policy.check(WEAK).unwrap_err().kind(),
+        ErrorKind::InvalidInput
+    );
+    policy.check(STRONG).unwrap();
+    // the strength of non UTF-8 passwords is unknown
+    assert_eq!(
+        policy.check(b"\xff\xfe").unwrap_err().kind(),
+        ErrorKind::InvalidInput
+    );
+}
+
+#[test]
+fn create_with_policy() {
+    let path = temp_path("policy-create");
+    let _ = std::fs::remove_dir_all(&path);
+
+    // embedders can enforce their own policies
+    let policy = |password: &[u8]| {
+        if password.len() < 12 {
+            return Err(Error::new(ErrorKind::InvalidInput, None));
+        }
+        Ok(())
+    };
+    let err = Bijou::create_with_policy(
+        &path,
+        WEAK.to_vec(),
+        Config::default(),
+        Limit::Interactive,
+        Limit::Interactive,
+        &policy,
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(!path.exists());
+
+    Bijou::create_with_policy(
+        &path,
+        STRONG.to_vec(),
+        Config::default(),
+        Limit::Interactive,
+        Limit::Interactive,
+        &policy,
+    )
+    .unwrap();
+    Bijou::open(&path, STRONG.to_vec()).unwrap();
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn builder_policy() {
+    let path = temp_path("policy-builder");
+    let _ = std::fs::remove_dir_all(&path);
+
+    let mut builder = BijouBuilder::new(&path);
+    builder
+        .ops_limit(Limit::Interactive)
+        .mem_limit(Limit::Interactive)
+        .password_policy(MinimumStrength(3));
+    let err = builder.create(WEAK.to_vec()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    // the read-only password is checked as well
+    builder.read_only_password(WEAK.to_vec());
+    let err = builder.create(STRONG.to_vec()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(!path.exists());
+}
+
+#[test]
+fn builder_policy_accepted() {
+    let checked = Arc::new(AtomicBool::new(false));
+    let bijou = TempBijou::with("policy-accepted", |builder| {
+        let checked = Arc::clone(&checked);
+        builder.password_policy(move |password: &[u8]| {
+            assert_eq!(password, b"password");
+            checked.store(true, Ordering::Relaxed);
+            Ok(())
+        });
+    });
+    assert!(checked.load(Ordering::Relaxed));
+    assert!(bijou.lookup(FileId::ROOT, ".").is_ok());
+}
@@ -0,0 +1,37 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+mod common;
+
+use bijou::Bijou;
+use common::TempBijou;
+
+#[test]
+fn probe() {
+    let bijou = TempBijou::with("probe", |builder| {
+        builder.label("probed");
+    });
+    let info = Bijou::probe(bijou.path()).unwrap().unwrap();
+    assert_eq!(info.version, 0);
+    assert!(!info.embedded);
+    assert!(info.ops_limit > 0 && info.mem_limit > 0);
+    assert_eq!(info.uuid, bijou.config().uuid);
+    assert_eq!(info.label.as_deref(), Some("probed"));
+
+    let dir = std::env::temp_dir().join(format!("bijou-not-probe-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    assert!(Bijou::probe(&dir).unwrap().is_none());
+    std::fs::remove_dir_all(dir).unwrap();
+}
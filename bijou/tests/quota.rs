@@ -0,0 +1,111 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+mod common;
+
+use bijou::{ErrorKind, FileId, FileKind, OpenOptions, QuotaLimits};
+use common::TempBijou;
+
+#[test]
+fn limits_are_enforced() {
+    let bijou = TempBijou::new("quota");
+    let home = bijou
+        .make_node(FileId::ROOT, "home", FileKind::Directory, None, None)
+        .unwrap()
+        .id;
+    bijou
+        .set_quota(
+            home,
+            QuotaLimits {
+                bytes: Some(1000),
+                inodes: Some(2),
+            },
+        )
+        .unwrap();
+
+    let file = bijou
+        .make_node(home, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    let mut handle = bijou
+        .open_file_direct(file, OpenOptions::new().write(true))
+        .unwrap();
+    handle.write(&[1; 600], 0).unwrap();
+    let err = handle.write(&[1; 600], 600).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::QuotaExceeded);
+    let err = bijou.set_len(file, 1001).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::QuotaExceeded);
+
+    let info = bijou.quota(home).unwrap();
+    assert_eq!((info.bytes, info.inodes), (600, 1));
+
+    // moving a file in is charged as well
+    let outside = bijou
+        .make_node(FileId::ROOT, "outside", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    bijou.set_len(outside, 500).unwrap();
+    let err = bijou
+        .rename(FileId::ROOT, "outside", home, "outside")
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::QuotaExceeded);
+    bijou.set_len(outside, 400).unwrap();
+    bijou
+        .rename(FileId::ROOT, "outside", home, "outside")
+        .unwrap();
+
+    let err = bijou
+        .make_node(home, "third", FileKind::File, None, None)
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::QuotaExceeded);
+
+    let info = bijou.quota(home).unwrap();
+    assert_eq!((info.bytes, info.inodes), (1000, 2));
+}
+
+#[test]
+fn usage_is_released() {
+    let bijou = TempBijou::new("quota-release");
+    let home = bijou
+        .make_node(FileId::ROOT, "home", FileKind::Directory, None, None)
+        .unwrap()
+        .id;
+    let dir = bijou
+        .make_node(home, "dir", FileKind::Directory, None, None)
+        .unwrap()
+        .id;
+    let file = bijou
+        .make_node(dir, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    bijou.set_len(file, 100).unwrap();
+
+    // existing files are counted
+    bijou.set_quota(home, QuotaLimits::default()).unwrap();
+    let info = bijou.quota(home).unwrap();
+    assert_eq!((info.bytes, info.inodes), (100, 2));
+
+    bijou.rename(home, "dir", FileId::ROOT, "dir").unwrap();
+    let info = bijou.quota(home).unwrap();
+    assert_eq!((info.bytes, info.inodes), (0, 0));
+
+    bijou.rename(FileId::ROOT, "dir", home, "dir").unwrap();
+    bijou.unlink(dir, "file").unwrap();
+    let info = bijou.quota(home).unwrap();
+    assert_eq!((info.bytes, info.inodes), (0, 1));
+
+    assert!(bijou.remove_quota(home).unwrap());
+    assert!(bijou.quota(home).is_none());
+}
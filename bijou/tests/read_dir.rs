@@ -0,0 +1,44 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+mod common;
+
+use bijou::{FileId, FileKind};
+use common::TempBijou;
+
+#[test]
+fn sorted_entries() {
+    let bijou = TempBijou::with("read-dir-sorted", |builder| {
+        builder.filename_encryption(true);
+    });
+
+    let mut names: Vec<_> = (0..50).map(|i| format!("file{i}")).collect();
+    for name in &names {
+        bijou
+            .make_node(FileId::ROOT, name, FileKind::File, None, None)
+            .unwrap();
+    }
+    names.sort();
+
+    let mut iter = bijou.read_dir(FileId::ROOT).unwrap();
+    iter.sorted(true);
+    let listed: Vec<_> = iter.by_ref().map(|entry| entry.unwrap().0).collect();
+    assert_eq!(listed[..2], [".", ".."]);
+    assert_eq!(listed[2..], names);
+
+    iter.seek("file3").unwrap();
+    assert_eq!(iter.next().unwrap().unwrap().0, "file3");
+    assert_eq!(iter.next().unwrap().unwrap().0, "file30");
+}
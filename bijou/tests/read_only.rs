@@ -0,0 +1,48 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+mod common;
+
+use bijou::{Bijou, ErrorKind, FileId, FileKind, OpenOptions};
+use common::TempBijou;
+
+#[test]
+fn read_only() {
+    let bijou = TempBijou::new("read-only");
+    let fs = bijou.fs();
+    fs.write("/file", "hello").unwrap();
+    drop(fs);
+
+    let ro = Bijou::open_read_only(bijou.path(), b"password".to_vec()).unwrap();
+    assert!(ro.is_read_only());
+    let file = ro.lookup(FileId::ROOT, "file").unwrap();
+    let reader = ro.open_file_direct(file, OpenOptions::read_only()).unwrap();
+    let mut buffer = [0; 16];
+    assert_eq!(reader.read(&mut buffer, 0).unwrap(), 5);
+    assert_eq!(&buffer[..5], b"hello");
+
+    let errors = [
+        ro.make_node(FileId::ROOT, "new", FileKind::File, None, None)
+            .unwrap_err(),
+        ro.open_file_direct(file, OpenOptions::writable())
+            .map(drop)
+            .unwrap_err(),
+        ro.unlink(FileId::ROOT, "file").unwrap_err(),
+        ro.set_xattr(file, "user.test", b"value").unwrap_err(),
+    ];
+    for err in errors {
+        assert_eq!(err.kind(), ErrorKind::ReadOnly);
+    }
+}
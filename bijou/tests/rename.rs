@@ -0,0 +1,87 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+mod common;
+
+use bijou::{Bijou, ErrorKind, FileId, FileKind};
+use common::TempBijou;
+
+fn mkdir(bijou: &Bijou, parent: FileId, name: &str) -> FileId {
+    bijou
+        .make_node(parent, name, FileKind::Directory, None, None)
+        .unwrap()
+        .id
+}
+
+/// Counts directories reachable from the root.
+fn count_dirs(bijou: &Bijou, dir: FileId) -> usize {
+    bijou
+        .read_dir(dir)
+        .unwrap()
+        .dots(false)
+        .map(Result::unwrap)
+        .filter(|(_, item)| item.kind == FileKind::Directory)
+        .map(|(_, item)| 1 + count_dirs(bijou, item.id))
+        .sum()
+}
+
+#[test]
+fn rename_into_descendant() {
+    let bijou = TempBijou::new("rename-descendant");
+
+    let mut dirs = vec![FileId::ROOT];
+    for depth in 0..50 {
+        let dir = mkdir(&bijou, *dirs.last().unwrap(), &format!("d{depth}"));
+        dirs.push(dir);
+    }
+
+    for (from, to) in [(1, 50), (1, 1), (10, 20), (49, 50)] {
+        let err = bijou
+            .rename(dirs[from - 1], &format!("d{}", from - 1), dirs[to], "x")
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput, "d{from} into d{to}");
+    }
+    assert_eq!(count_dirs(&bijou, FileId::ROOT), 50);
+
+    // moving upwards is fine
+    bijou.rename(dirs[20], "d20", FileId::ROOT, "d20").unwrap();
+    assert_eq!(bijou.resolve("/d20/d21/..").unwrap(), dirs[21]);
+    assert_eq!(count_dirs(&bijou, FileId::ROOT), 50);
+}
+
+#[test]
+fn concurrent_cross_renames() {
+    let bijou = TempBijou::new("rename-concurrent");
+
+    let a = mkdir(&bijou, FileId::ROOT, "a");
+    let b = mkdir(&bijou, FileId::ROOT, "b");
+    for _ in 0..100 {
+        // exactly one of them succeeds
+        let (ra, rb) = std::thread::scope(|scope| {
+            let ra = scope.spawn(|| bijou.rename(FileId::ROOT, "a", b, "a"));
+            let rb = scope.spawn(|| bijou.rename(FileId::ROOT, "b", a, "b"));
+            (ra.join().unwrap(), rb.join().unwrap())
+        });
+        assert_ne!(ra.is_ok(), rb.is_ok());
+        assert_eq!(count_dirs(&bijou, FileId::ROOT), 2);
+
+        if ra.is_ok() {
+            bijou.rename(b, "a", FileId::ROOT, "a").unwrap();
+        }
+        if rb.is_ok() {
+            bijou.rename(a, "b", FileId::ROOT, "b").unwrap();
+        }
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Property test of path resolution: random trees with symlink
+//! cycles and `..` chains are resolved by [`Bijou`] and by a simple
+//! recursive model, and the results are compared.
+
+mod common;
+
+use bijou::{
+    path::{Component, Path},
+    Bijou, ErrorKind, FileId, FileKind,
+};
+use common::TempBijou;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::collections::BTreeMap;
+
+const NAMES: &[&str] = &["a", "b", "c", "d"];
+const MAX_LINKS: u32 = 40;
+
+enum Node {
+    Dir(BTreeMap<String, usize>),
+    File,
+    Symlink(String),
+}
+
+/// An in-memory tree, where node 0 is the root.
+struct Model {
+    nodes: Vec<Node>,
+}
+
+impl Model {
+    /// Resolves `path` recursively in POSIX semantics.
+    fn resolve(
+        &self,
+        stack: &mut Vec<usize>,
+        path: &str,
+        last: bool,
+        links: &mut u32,
+    ) -> Result<(), ErrorKind> {
+        let comps: Vec<_> = Path::new(path).components().collect();
+        for (index, comp) in comps.iter().enumerate() {
+            let is_last = last && index == comps.len() - 1;
+            match comp {
+                Component::RootDir => stack.truncate(1),
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    if stack.len() > 1 {
+                        stack.pop();
+                    }
+                }
+                Component::Normal(name) => {
+                    let Node::Dir(entries) = &self.nodes[*stack.last().unwrap()] else {
+                        unreachable!()
+                    };
+                    let Some(&child) = entries.get(*name) else {
+                        return Err(ErrorKind::NotFound);
+                    };
+                    match &self.nodes[child] {
+                        Node::Symlink(target) => {
+                            *links += 1;
+                            if *links > MAX_LINKS {
+                                return Err(ErrorKind::FilesystemLoop);
+                            }
+                            self.resolve(stack, target, is_last, links)?;
+                        }
+                        Node::File if !is_last => return Err(ErrorKind::NotADirectory),
+                        _ => stack.push(child),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn random_path(rng: &mut StdRng) -> String {
+    let len = rng.gen_range(1..=5);
+    let comps: Vec<_> = (0..len)
+        .map(|_| {
+            if rng.gen_bool(0.25) {
+                ".."
+            } else if rng.gen_bool(0.1) {
+                "."
+            } else {
+                *NAMES.choose(rng).unwrap()
+            }
+        })
+        .collect();
+    let path = comps.join("/");
+    if rng.gen_bool(0.3) {
+        format!("/{path}")
+    } else {
+        path
+    }
+}
+
+/// Builds a random tree in both the model and `bijou`, returning
+/// the mapping from model nodes to file IDs.
+fn build(rng: &mut StdRng, bijou: &Bijou, model: &mut Model) -> Vec<FileId> {
+    let mut ids = vec![FileId::ROOT];
+    let mut dirs = vec![0];
+    for _ in 0..30 {
+        let parent = *dirs.choose(rng).unwrap();
+        let name = *NAMES.choose(rng).unwrap();
+        let Node::Dir(entries) = &model.nodes[parent] else {
+            unreachable!()
+        };
+        if entries.contains_key(name) {
+            continue;
+        }
+        let (node, kind, target) = match rng.gen_range(0..3) {
+            0 => (Node::Dir(BTreeMap::new()), FileKind::Directory, None),
+            1 => (Node::File, FileKind::File, None),
+            _ => {
+                let target = random_path(rng);
+                (
+                    Node::Symlink(target.clone()),
+                    FileKind::Symlink,
+                    Some(target),
+                )
+            }
+        };
+        let id = bijou
+            .make_node(ids[parent], name, kind, target, None)
+            .unwrap()
+            .id;
+
+        let index = model.nodes.len();
+        if kind == FileKind::Directory {
+            dirs.push(index);
+        }
+        model.nodes.push(node);
+        let Node::Dir(entries) = &mut model.nodes[parent] else {
+            unreachable!()
+        };
+        entries.insert(name.to_owned(), index);
+        ids.push(id);
+    }
+    ids
+}
+
+#[test]
+fn resolve_matches_model() {
+    for seed in 0..8 {
+        let bijou = TempBijou::new(&format!("resolve-{seed}"));
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut model = Model {
+            nodes: vec![Node::Dir(BTreeMap::new())],
+        };
+        let ids = build(&mut rng, &bijou, &mut model);
+
+        for _ in 0..200 {
+            let path = format!("/{}", random_path(&mut rng));
+            let mut stack = vec![0];
+            let expected = model
+                .resolve(&mut stack, &path, true, &mut 0)
+                .map(|_| ids[*stack.last().unwrap()]);
+            let actual = bijou.resolve(path.as_str()).map_err(|err| err.kind());
+            assert_eq!(expected, actual, "seed {seed}, path `{path}`");
+        }
+    }
+}
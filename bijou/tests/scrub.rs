@@ -0,0 +1,81 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Scrubs verify files on several threads, and report what they find
+//! as they go.
+
+mod common;
+
+use bijou::{FileId, FileKind, OpenOptions, ScrubEvent, ScrubOptions};
+use common::TempBijou;
+use std::sync::Mutex;
+
+#[test]
+fn parallel_scrub() {
+    let bijou = TempBijou::new("scrub");
+    let mut files = Vec::new();
+    for dir in 0..4 {
+        let dir = bijou
+            .make_node(
+                FileId::ROOT,
+                &format!("dir{dir}"),
+                FileKind::Directory,
+                None,
+                None,
+            )
+            .unwrap()
+            .id;
+        for i in 0..8 {
+            let file = bijou
+                .make_node(dir, &format!("file{i}"), FileKind::File, None, None)
+                .unwrap()
+                .id;
+            bijou
+                .open_file_direct(file, OpenOptions::new().write(true))
+                .unwrap()
+                .write(&[i; 5000], 0)
+                .unwrap();
+            files.push(file);
+        }
+    }
+    // verified once
+    bijou.link(files[0], FileId::ROOT, "link").unwrap();
+
+    let report = bijou.scrub(&ScrubOptions {
+        threads: 4,
+        ..Default::default()
+    });
+    assert!(report.is_ok(), "{report:?}");
+    assert_eq!(report.files, 32);
+    assert_eq!(report.directories, 5);
+    assert_eq!(report.pinned, 32);
+    assert_eq!(report.unreachable_count, 0);
+
+    let events = Mutex::new(Vec::new());
+    let report = bijou.scrub_with(
+        &ScrubOptions {
+            quick: true,
+            threads: 2,
+            ..Default::default()
+        },
+        &|event| events.lock().unwrap().push(event),
+    );
+    assert_eq!(report.pin_checked, 32);
+    assert!(events
+        .into_inner()
+        .unwrap()
+        .iter()
+        .all(|event| matches!(event, ScrubEvent::Progress(_))));
+}
@@ -0,0 +1,56 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+mod common;
+
+use bijou::{ErrorKind, FileId, FileKind, OpenOptions};
+use common::TempBijou;
+
+#[test]
+fn shred() {
+    let bijou = TempBijou::new("shred");
+
+    let file = bijou
+        .make_node(FileId::ROOT, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    let mut writer = bijou
+        .open_file_direct(file, OpenOptions::writable())
+        .unwrap();
+    writer.write(&[42; 10000], 0).unwrap();
+
+    // refused while opened or linked elsewhere
+    assert_eq!(
+        bijou.shred(FileId::ROOT, "file").unwrap_err().kind(),
+        ErrorKind::InvalidInput
+    );
+    drop(writer);
+    bijou.link(file, FileId::ROOT, "link").unwrap();
+    assert_eq!(
+        bijou.shred(FileId::ROOT, "file").unwrap_err().kind(),
+        ErrorKind::InvalidInput
+    );
+    bijou.unlink(FileId::ROOT, "link").unwrap();
+
+    bijou.shred(FileId::ROOT, "file").unwrap();
+    assert_eq!(
+        bijou.lookup(FileId::ROOT, "file").unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+    assert_eq!(
+        bijou.get_meta(file).unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+}
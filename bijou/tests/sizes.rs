@@ -0,0 +1,135 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! The mapping between plaintext and ciphertext sizes must be exact
+//! for every algorithm and block size, since file sizes, truncation
+//! and `statfs` all depend on it.
+
+mod common;
+
+use bijou::{
+    algo::{Algorithm, CipherSize, PlainSize},
+    config::FileEncryption,
+    Config, ErrorKind, FileId, FileKind, OpenOptions,
+};
+use common::TempBijou;
+use std::sync::Arc;
+
+const BLOCK_SIZES: [u64; 6] = [1, 2, 3, 16, 4096, 65536];
+
+fn algorithms() -> impl Iterator<Item = Arc<dyn Algorithm + Send + Sync>> {
+    bijou::init().unwrap();
+    let ciphers = [
+        FileEncryption::Aes256Gcm,
+        FileEncryption::ChaCha20Poly1305,
+        FileEncryption::XChaCha20Poly1305IETF,
+        FileEncryption::XSalsa20,
+    ];
+    ciphers.into_iter().flat_map(|cipher| {
+        BLOCK_SIZES.into_iter().map(move |block_size| {
+            let config = Config {
+                file_encryption: cipher.clone(),
+                block_size,
+                ..Default::default()
+            };
+            bijou::algo::from_config(&config).unwrap()
+        })
+    })
+}
+
+/// Sizes from zero to a few blocks of `block_size` bytes: all of them
+/// for small blocks, and the ones around block boundaries otherwise.
+fn sizes(block_size: u64) -> Vec<u64> {
+    if block_size <= 64 {
+        return (0..=4 * block_size + 2).collect();
+    }
+    let mut sizes: Vec<_> = (0..=4)
+        .flat_map(|k| (k * block_size).saturating_sub(2)..=k * block_size + 2)
+        .collect();
+    sizes.dedup();
+    sizes
+}
+
+#[test]
+fn plaintext_round_trip() {
+    for algo in algorithms() {
+        let mut last = None;
+        for size in sizes(algo.content_size()) {
+            let cipher = algo.ciphertext_size(PlainSize(size)).unwrap();
+            assert_eq!(algo.plaintext_size(cipher), PlainSize(size));
+            assert!(last < Some(cipher), "not increasing at {size}");
+            last = Some(cipher);
+        }
+        assert_eq!(algo.ciphertext_size(PlainSize(0)).unwrap(), CipherSize(0));
+    }
+}
+
+#[test]
+fn ciphertext_round_trip() {
+    for algo in algorithms() {
+        let block_size = algo.block_size();
+        let mut last = PlainSize(0);
+        for size in sizes(block_size) {
+            let plain = algo.plaintext_size(CipherSize(size));
+            assert!(last <= plain, "decreasing at {size}");
+            last = plain;
+
+            // a trailing block too short to hold any content is
+            // invalid, and mapped back to the largest valid size
+            // below it
+            let rem = size % block_size;
+            let valid = rem == 0 || rem > algo.metadata_size();
+            let cipher = algo.ciphertext_size(plain).unwrap();
+            if valid {
+                assert_eq!(cipher, CipherSize(size));
+            } else {
+                assert_eq!(cipher, CipherSize(size - rem));
+            }
+        }
+    }
+}
+
+#[test]
+fn maximum_size() {
+    for algo in algorithms() {
+        let max = algo.max_plaintext_size();
+        let limit = CipherSize(i64::MAX as u64);
+        assert!(algo.ciphertext_size(max).unwrap() <= limit);
+        assert!(algo.ciphertext_size(max + PlainSize(1)).unwrap() > limit);
+
+        let err = algo.ciphertext_size(PlainSize(u64::MAX)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::FileTooLarge);
+    }
+}
+
+#[test]
+fn file_sizes() {
+    let bijou = TempBijou::with("sizes", |builder| {
+        builder.block_size(16);
+    });
+    let file = bijou
+        .make_node(FileId::ROOT, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    let mut handle = bijou
+        .open_file_direct(file, OpenOptions::new().write(true))
+        .unwrap();
+
+    // growing and shrinking across block boundaries
+    for size in sizes(16).into_iter().chain(sizes(16).into_iter().rev()) {
+        handle.set_len(size).unwrap();
+        assert_eq!(bijou.get_meta(file).unwrap().size, size);
+    }
+}
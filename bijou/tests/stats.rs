@@ -0,0 +1,51 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+mod common;
+
+use bijou::{FileId, FileKind, OpenOptions};
+use common::TempBijou;
+
+#[test]
+fn per_handle_stats() {
+    let bijou = TempBijou::with("stats", |builder| {
+        builder.slow_op_threshold(Some(0));
+    });
+
+    let file = bijou
+        .make_node(FileId::ROOT, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    let mut handle = bijou
+        .open_file_direct(file, OpenOptions::writable().read(true))
+        .unwrap();
+    for offset in [0, 5000, 10000] {
+        assert_eq!(handle.write(&[1; 100], offset).unwrap(), 100);
+    }
+    let mut buffer = [0; 64];
+    assert_eq!(handle.read(&mut buffer, 10050).unwrap(), 50);
+
+    let stats = handle.stats();
+    assert_eq!((stats.writes, stats.bytes_written), (3, 300));
+    assert_eq!((stats.reads, stats.bytes_read), (1, 50));
+    assert!(stats.latency_p50 <= stats.latency_p99);
+    assert!(stats.latency_p99 <= stats.latency_max);
+
+    // other handles have their own counters
+    let other = bijou
+        .open_file_direct(file, OpenOptions::read_only())
+        .unwrap();
+    assert_eq!(other.stats().reads, 0);
+}
@@ -0,0 +1,101 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Stress test hammering directory operations concurrently, checking
+//! for deadlocks and for consistency of the resulting tree.
+
+mod common;
+
+use bijou::{Bijou, FileId, FileKind, RenameFlags};
+use common::TempBijou;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::{
+    sync::{mpsc, Arc},
+    time::Duration,
+};
+
+const THREADS: u64 = 8;
+const OPS: usize = 500;
+const NAMES: &[&str] = &["x", "y", "z"];
+const DIRS: &[&str] = &["/", "/x", "/y", "/x/y", "/y/x", "/x/x", "/y/z/x"];
+
+fn random_op(bijou: &Bijou, rng: &mut StdRng) {
+    let mut random_entry = || {
+        let dir = bijou.resolve(*DIRS.choose(rng).unwrap()).ok()?;
+        Some((dir, *NAMES.choose(rng).unwrap()))
+    };
+    let (Some((parent, name)), Some((new_parent, new_name))) = (random_entry(), random_entry())
+    else {
+        return;
+    };
+    // errors are expected, e.g. when the file is already removed
+    let _ = match rng.gen_range(0..5) {
+        0 => bijou
+            .make_node(parent, name, FileKind::Directory, None, None)
+            .map(drop),
+        1 => bijou
+            .make_node(parent, name, FileKind::File, None, None)
+            .map(drop),
+        2 => bijou.unlink(parent, name).map(drop),
+        3 => bijou.rename(parent, name, new_parent, new_name).map(drop),
+        _ => bijou
+            .rename_with_flags(parent, name, new_parent, new_name, RenameFlags::EXCHANGE)
+            .map(drop),
+    };
+}
+
+/// Checks `..` entries and `nlinks` of directories, returning the
+/// number of directories in `dir`.
+fn check_tree(bijou: &Bijou, dir: FileId, parent: FileId) -> u32 {
+    let mut subdirs = 0;
+    for entry in bijou.read_dir(dir).unwrap() {
+        let (name, item) = entry.unwrap();
+        match name.as_str() {
+            "." => assert_eq!(item.id, dir),
+            ".." => assert_eq!(item.id, parent),
+            _ if item.kind == FileKind::Directory => {
+                subdirs += 1;
+                check_tree(bijou, item.id, dir);
+            }
+            _ => {}
+        }
+    }
+    assert_eq!(bijou.get_meta(dir).unwrap().nlinks, 2 + subdirs, "{dir}");
+    subdirs
+}
+
+#[test]
+fn concurrent_directory_operations() {
+    let bijou = Arc::new(TempBijou::new("stress"));
+
+    let (tx, rx) = mpsc::channel();
+    for seed in 0..THREADS {
+        let bijou = Arc::clone(&bijou);
+        let tx = tx.clone();
+        std::thread::spawn(move || {
+            let mut rng = StdRng::seed_from_u64(seed);
+            for _ in 0..OPS {
+                random_op(&bijou, &mut rng);
+            }
+            tx.send(()).unwrap();
+        });
+    }
+    for _ in 0..THREADS {
+        rx.recv_timeout(Duration::from_secs(120))
+            .expect("possible deadlock");
+    }
+
+    check_tree(&bijou, FileId::ROOT, FileId::ROOT);
+}
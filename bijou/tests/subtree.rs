@@ -0,0 +1,57 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+mod common;
+
+use bijou::ErrorKind;
+use common::TempBijou;
+
+#[test]
+fn subtree_cannot_escape() {
+    let bijou = TempBijou::new("subtree");
+    let fs = bijou.fs();
+    fs.create_dir_all("/jail/dir").unwrap();
+    fs.write("/secret", "secret").unwrap();
+    fs.write("/jail/file", "file").unwrap();
+
+    let jail = fs.subtree("/jail").unwrap();
+    assert_eq!(jail.read_to_string("/file").unwrap(), "file");
+    assert_eq!(jail.read_to_string("dir/../../../file").unwrap(), "file");
+    assert_eq!(
+        jail.read("/../secret").unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+
+    // symlink targets are resolved within the view as well
+    jail.soft_link("/secret", "/dir/link").unwrap();
+    jail.soft_link("../../secret", "/dir/relative").unwrap();
+    for link in ["/dir/link", "/dir/relative"] {
+        assert_eq!(jail.read(link).unwrap_err().kind(), ErrorKind::NotFound);
+    }
+    jail.write("/secret", "not so secret").unwrap();
+    assert_eq!(jail.read_to_string("/dir/link").unwrap(), "not so secret");
+    assert_eq!(fs.read_to_string("/secret").unwrap(), "secret");
+
+    let nested = jail.subtree("dir").unwrap();
+    assert_eq!(
+        nested.read("/../file").unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+    assert!(nested.remove("/").is_err());
+    assert_eq!(
+        jail.subtree("/file").err().unwrap().kind(),
+        ErrorKind::NotADirectory
+    );
+}
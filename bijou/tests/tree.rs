@@ -0,0 +1,67 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+mod common;
+
+use bijou::{FileId, FileKind, OpenOptions, TreeOptions};
+use common::TempBijou;
+
+#[test]
+fn depth_and_metadata() {
+    let bijou = TempBijou::new("tree");
+    let dir = bijou
+        .make_node(FileId::ROOT, "dir", FileKind::Directory, None, None)
+        .unwrap()
+        .id;
+    let file = bijou
+        .make_node(dir, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    bijou
+        .open_file_direct(file, OpenOptions::new().write(true))
+        .unwrap()
+        .write(b"hello", 0)
+        .unwrap();
+
+    let tree = bijou.tree(FileId::ROOT).unwrap();
+    let children = tree[0].children.as_ref().unwrap();
+    assert_eq!(children[0].name, "file");
+    assert_eq!(children[0].size, None);
+
+    let tree = bijou
+        .tree_with(
+            FileId::ROOT,
+            &TreeOptions {
+                max_depth: Some(1),
+                metadata: true,
+            },
+        )
+        .unwrap();
+    assert_eq!(tree.len(), 1);
+    assert_eq!(tree[0].name, "dir");
+    assert!(tree[0].children.is_none());
+    assert!(tree[0].size.is_some());
+
+    let tree = bijou
+        .tree_with(
+            FileId::ROOT,
+            &TreeOptions {
+                max_depth: None,
+                metadata: true,
+            },
+        )
+        .unwrap();
+    assert_eq!(tree[0].children.as_ref().unwrap()[0].size, Some(5));
+}
@@ -0,0 +1,195 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Reads racing with truncations of the same file must observe
+//! either the state before or after each truncation, never a
+//! partially rewritten block. The same holds for truncation when
+//! opening a file.
+
+mod common;
+
+use bijou::{config::FileStorage, ErrorKind, FileId, FileKind, OpenOptions};
+use common::TempBijou;
+
+const LEN: usize = 20_000;
+const ROUNDS: usize = 200;
+
+fn pattern() -> Vec<u8> {
+    (0..LEN).map(|i| (i % 251) as u8).collect()
+}
+
+#[test]
+fn truncate_while_reading() {
+    let bijou = TempBijou::new("truncate");
+    let file = bijou
+        .make_node(FileId::ROOT, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    let pattern = pattern();
+
+    let mut writer = bijou
+        .open_file_direct(file, OpenOptions::new().write(true))
+        .unwrap();
+    writer.write(&pattern, 0).unwrap();
+
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            // the content is always a prefix of the pattern, since
+            // the file is only shrunk and then rewritten as a whole
+            for round in 0..ROUNDS {
+                writer.set_len((round * 97 % LEN) as u64).unwrap();
+                writer.write(&pattern, 0).unwrap();
+            }
+        });
+
+        let reader = bijou
+            .open_file_direct(file, OpenOptions::new().read(true))
+            .unwrap();
+        let mut buffer = vec![0; LEN + 100];
+        for _ in 0..ROUNDS {
+            let read = reader.read(&mut buffer, 0).unwrap() as usize;
+            assert!(read <= LEN);
+            assert!(buffer[..read] == pattern[..read], "garbage read");
+        }
+    });
+}
+
+#[test]
+fn beyond_maximum_size() {
+    let bijou = TempBijou::new("max-size");
+    let file = bijou
+        .make_node(FileId::ROOT, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+
+    let err = bijou.set_len(file, u64::MAX).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::FileTooLarge);
+
+    let mut handle = bijou
+        .open_file_direct(file, OpenOptions::new().write(true))
+        .unwrap();
+    let err = handle.write(b"x", u64::MAX - 1).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::FileTooLarge);
+    assert_eq!(bijou.get_meta(file).unwrap().size, 0);
+}
+
+fn split_bijou(name: &str) -> TempBijou {
+    TempBijou::with(name, |builder| {
+        builder.storage(FileStorage::Split {
+            inner: Box::new(FileStorage::Local),
+            cluster_size: 2,
+            naming: Default::default(),
+        });
+    })
+}
+
+#[test]
+fn truncate_on_open_with_open_handle() {
+    let bijou = split_bijou("truncate-open");
+    let file = bijou
+        .make_node(FileId::ROOT, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    let pattern = pattern();
+
+    let mut writer = bijou
+        .open_file_direct(file, OpenOptions::new().read(true).write(true))
+        .unwrap();
+    writer.write(&pattern, 0).unwrap();
+
+    // the handle opened before must see the truncation, not a stale
+    // size pointing to released clusters
+    drop(
+        bijou
+            .open_file_direct(file, OpenOptions::new().write(true).truncate(true))
+            .unwrap(),
+    );
+    let mut buffer = vec![0; LEN];
+    assert_eq!(writer.read(&mut buffer, 0).unwrap(), 0);
+
+    writer.write(b"hello", 0).unwrap();
+    assert_eq!(writer.read(&mut buffer, 0).unwrap(), 5);
+    assert_eq!(&buffer[..5], b"hello");
+    assert_eq!(bijou.get_meta(file).unwrap().size, 5);
+}
+
+#[test]
+fn concurrent_truncating_opens() {
+    let bijou = split_bijou("truncate-concurrent");
+    let file = bijou
+        .make_node(FileId::ROOT, "file", FileKind::File, None, None)
+        .unwrap()
+        .id;
+    let pattern = pattern();
+
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            for _ in 0..ROUNDS / 4 {
+                let mut handle = bijou
+                    .open_file_direct(file, OpenOptions::new().write(true).truncate(true))
+                    .unwrap();
+                handle.write(&pattern, 0).unwrap();
+            }
+        });
+        s.spawn(|| {
+            let mut handle = bijou
+                .open_file_direct(file, OpenOptions::new().write(true))
+                .unwrap();
+            for _ in 0..ROUNDS / 4 {
+                handle.write(&pattern, 0).unwrap();
+            }
+        });
+
+        let reader = bijou
+            .open_file_direct(file, OpenOptions::new().read(true))
+            .unwrap();
+        let mut buffer = vec![0; LEN + 100];
+        for _ in 0..ROUNDS {
+            let read = reader.read(&mut buffer, 0).unwrap() as usize;
+            assert!(read <= LEN);
+            assert!(buffer[..read] == pattern[..read], "garbage read");
+        }
+    });
+}
+
+#[test]
+fn reserve_and_extend() {
+    for bijou in [TempBijou::new("reserve"), split_bijou("reserve-split")] {
+        let file = bijou
+            .make_node(FileId::ROOT, "file", FileKind::File, None, None)
+            .unwrap()
+            .id;
+        let pattern = pattern();
+        let mut handle = bijou
+            .open_file_direct(file, OpenOptions::new().read(true).write(true))
+            .unwrap();
+        handle.write(&pattern, 0).unwrap();
+
+        // reserving keeps the size
+        handle.reserve(LEN as u64 * 10).unwrap();
+        assert_eq!(bijou.get_meta(file).unwrap().size, LEN as u64);
+
+        // extending never shrinks
+        handle.extend(LEN as u64 / 2).unwrap();
+        assert_eq!(bijou.get_meta(file).unwrap().size, LEN as u64);
+        handle.extend(LEN as u64 * 2).unwrap();
+        assert_eq!(bijou.get_meta(file).unwrap().size, LEN as u64 * 2);
+
+        let mut buf = vec![0; LEN * 2];
+        assert_eq!(handle.read(&mut buf, 0).unwrap(), LEN as u64 * 2);
+        assert_eq!(&buf[..LEN], pattern);
+        assert!(buf[LEN..].iter().all(|&b| b == 0));
+    }
+}
@@ -0,0 +1,63 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Failed unlock attempts are only throttled if enabled when
+//! creating the Bijou.
+
+mod common;
+
+use bijou::{Bijou, KeyStore};
+use common::TempBijou;
+use std::time::{Duration, Instant};
+
+#[test]
+fn disabled() {
+    let bijou = TempBijou::new("unlock-disabled");
+    assert!(!Bijou::probe(bijou.path()).unwrap().unwrap().throttle_unlock);
+
+    let keystore = KeyStore::load(bijou.path()).unwrap();
+    let first = Instant::now();
+    assert!(keystore.unlock(b"wrong".to_vec()).is_err());
+    let cost = first.elapsed();
+    for _ in 0..5 {
+        assert!(keystore.unlock(b"wrong".to_vec()).is_err());
+    }
+    assert!(!bijou.path().join("unlock.json").exists());
+
+    // the delay would be at least 4 seconds by now
+    let start = Instant::now();
+    keystore.unlock(b"password".to_vec()).unwrap();
+    assert!(start.elapsed() < cost + Duration::from_secs(1));
+}
+
+#[test]
+fn enabled() {
+    let bijou = TempBijou::with("unlock-enabled", |builder| {
+        builder.throttle_unlock(true);
+    });
+    assert!(Bijou::probe(bijou.path()).unwrap().unwrap().throttle_unlock);
+
+    let keystore = KeyStore::load(bijou.path()).unwrap();
+    for _ in 0..3 {
+        assert!(keystore.unlock(b"wrong".to_vec()).is_err());
+    }
+    let record = bijou.path().join("unlock.json");
+    assert!(record.exists());
+
+    let start = Instant::now();
+    keystore.unlock(b"password".to_vec()).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(500));
+    assert!(!record.exists());
+}
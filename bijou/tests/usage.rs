@@ -0,0 +1,69 @@
+// Copyright 2023 Mivik
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+//! Usage snapshots are kept by day.
+
+mod common;
+
+use bijou::{FileId, FileKind, MockClock, OpenOptions, UsageStats};
+use chrono::{TimeZone, Utc};
+use common::TempBijou;
+use std::{sync::Arc, time::Duration};
+
+#[test]
+fn usage_history() {
+    let mut bijou = TempBijou::new("usage");
+    let clock = Arc::new(MockClock::new(
+        Utc.with_ymd_and_hms(2000, 1, 1, 12, 0, 0).unwrap(),
+    ));
+    bijou.get_mut().set_clock(Arc::clone(&clock));
+
+    let write = |name: &str, len: usize| {
+        let file = bijou
+            .make_node(FileId::ROOT, name, FileKind::File, None, None)
+            .unwrap()
+            .id;
+        bijou
+            .open_file_direct(file, OpenOptions::new().write(true))
+            .unwrap()
+            .write(&vec![0; len], 0)
+            .unwrap();
+    };
+    write("a", 1000);
+    bijou
+        .make_node(FileId::ROOT, "dir", FileKind::Directory, None, None)
+        .unwrap();
+    let first = bijou.record_usage().unwrap();
+    assert_eq!(first.stats.files, 1);
+    assert_eq!(first.stats.bytes, 1000);
+    // the root and "dir"
+    assert_eq!(first.stats.directories, 2);
+    assert_eq!(first.stats.sizes[UsageStats::size_bucket(1000)], 1);
+
+    // replaces the snapshot of the same day
+    write("b", 0);
+    bijou.record_usage().unwrap();
+    clock.advance(Duration::from_secs(24 * 3600));
+    write("c", 3000);
+    bijou.record_usage().unwrap();
+
+    let history = bijou.usage_history().unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].date.to_string(), "2000-01-01");
+    assert_eq!(history[0].stats.files, 2);
+    assert_eq!(history[0].stats.sizes[0], 1);
+    assert_eq!(history[1].date.to_string(), "2000-01-02");
+    assert_eq!(history[1].stats.bytes, 4000);
+}